@@ -0,0 +1,75 @@
+//! Hash, copy and import a wide tree on one thread and on the default
+//! rayon pool.
+//!
+//! Run with `cargo bench --features rayon --bench parallel`.
+
+use std::{
+    fs, process,
+    time::{Duration, Instant},
+};
+
+use vfs::{Algo, Vfs};
+
+const DIRS: usize = 64;
+const FILES: usize = 16;
+const FILE_SIZE: usize = 64 * 1024;
+
+/// Time `f` on a pool of `threads` threads.
+fn timed(threads: usize, f: impl FnOnce() + Send) -> Duration {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .unwrap();
+    let start = Instant::now();
+    pool.install(f);
+    start.elapsed()
+}
+
+fn report(name: &str, run: impl Fn(usize) -> Duration) {
+    let threads = rayon::current_num_threads();
+    let sequential = run(1);
+    let parallel = run(threads);
+    println!(
+        "{} {} files: 1 thread {:?}, {} threads {:?} ({:.1}x)",
+        name,
+        DIRS * FILES,
+        sequential,
+        threads,
+        parallel,
+        sequential.as_secs_f64() / parallel.as_secs_f64()
+    );
+}
+
+fn main() {
+    let mut vfs = Vfs::new();
+    let data: Vec<u8> = (0..FILE_SIZE).map(|i| (i * 31 % 251) as u8).collect();
+    let host = std::env::temp_dir().join(format!("vfs-bench-parallel-{}", process::id()));
+    vfs.mkdir("/tree").unwrap();
+    for d in 0..DIRS {
+        vfs.mkdir(&format!("/tree/{}", d)).unwrap();
+        fs::create_dir_all(host.join(d.to_string())).unwrap();
+        for f in 0..FILES {
+            vfs.write_file(&format!("/tree/{}/{}", d, f), &data)
+                .unwrap();
+            fs::write(host.join(d.to_string()).join(f.to_string()), &data).unwrap();
+        }
+    }
+
+    report("hash_tree", |threads| {
+        timed(threads, || {
+            let sums = vfs.hash_tree("/tree", Algo::Sha256).unwrap();
+            assert_eq!(sums.len(), DIRS * FILES);
+        })
+    });
+    report("copy_recursive", |threads| {
+        let mut vfs = vfs.clone();
+        timed(threads, || vfs.copy_recursive("/tree", "/copy").unwrap())
+    });
+    report("import_dir", |threads| {
+        let mut vfs = Vfs::new();
+        timed(threads, || {
+            vfs.import_dir(&host, "/import").unwrap();
+        })
+    });
+    fs::remove_dir_all(&host).unwrap();
+}
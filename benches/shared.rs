@@ -0,0 +1,63 @@
+//! Throughput of `SharedVfs` with several threads, each overwriting its
+//! own file and reading the file of the next thread, with every call under
+//! the exclusive tree lock against reads under the shared tree lock and
+//! overwrites in place through `write_at`.
+//!
+//! Run with `cargo bench --bench shared`.
+
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use vfs::{SharedVfs, Vfs};
+
+const ROUNDS: usize = 32;
+const FILE_SIZE: usize = 64 * 1024;
+
+fn run(vfs: &SharedVfs, threads: usize, exclusive: bool) {
+    thread::scope(|scope| {
+        for i in 0..threads {
+            scope.spawn(move || {
+                let own = format!("/{}", i);
+                let next = format!("/{}", (i + 1) % threads);
+                let data = vec![i as u8; FILE_SIZE];
+                for _ in 0..ROUNDS {
+                    let (written, read) = match exclusive {
+                        true => vfs.write(|vfs| {
+                            let oid = vfs.open(&own).unwrap();
+                            let written = vfs.write(oid, &data);
+                            vfs.close(oid).unwrap();
+                            (written, vfs.read_file(&next))
+                        }),
+                        false => (vfs.write_at(&own, 0, &data), vfs.read_file(&next)),
+                    };
+                    assert_eq!(written.unwrap(), FILE_SIZE);
+                    assert_eq!(read.unwrap().len(), FILE_SIZE);
+                }
+            });
+        }
+    })
+}
+
+fn shared(c: &mut Criterion) {
+    let mut group = c.benchmark_group("shared");
+    group.sample_size(20);
+    for threads in [1, 2, 4, 8] {
+        let mut vfs = Vfs::new();
+        for i in 0..threads {
+            vfs.write_file(&format!("/{}", i), &vec![0; FILE_SIZE])
+                .unwrap();
+        }
+        let vfs = SharedVfs::new(vfs);
+        group.throughput(Throughput::Bytes((2 * threads * ROUNDS * FILE_SIZE) as u64));
+        for exclusive in [true, false] {
+            let name = if exclusive { "exclusive" } else { "sharded" };
+            group.bench_with_input(BenchmarkId::new(name, threads), &threads, |b, &threads| {
+                b.iter(|| run(&vfs, threads, exclusive))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, shared);
+criterion_main!(benches);
@@ -0,0 +1,31 @@
+//! The workloads of the `bench` command under criterion.
+//!
+//! Run with `cargo bench --bench workloads`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use vfs::{Vfs, Workload};
+
+fn workloads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("vfs");
+    group.sample_size(20);
+    for workload in Workload::standard() {
+        let mut vfs = Vfs::new();
+        workload.prepare(&mut vfs).unwrap();
+        let ops = workload.run(&mut vfs.clone()).unwrap();
+        group.throughput(match workload.bytes() {
+            0 => Throughput::Elements(ops as u64),
+            bytes => Throughput::Bytes(bytes as u64),
+        });
+        group.bench_function(workload.to_string(), |b| {
+            b.iter_batched(
+                || vfs.clone(),
+                |mut vfs| workload.run(&mut vfs).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, workloads);
+criterion_main!(benches);
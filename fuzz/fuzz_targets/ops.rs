@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vfs::model::{check_against_model, ops_from_bytes};
+
+fuzz_target!(|data: &[u8]| {
+    if let Err(err) = check_against_model(&ops_from_bytes(data)) {
+        panic!("{}", err);
+    }
+});
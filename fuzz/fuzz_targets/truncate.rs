@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vfs::model::torture_truncate;
+
+fuzz_target!(|data: &[u8]| {
+    if let Err(err) = torture_truncate(data) {
+        panic!("{}", err);
+    }
+});
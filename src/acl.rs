@@ -0,0 +1,447 @@
+use std::{fmt, str::FromStr};
+
+use crate::{ErrorKind, FileDescriptor, FileType, Op, Vfs, VfsError, ATTR_IMMUTABLE};
+
+/// Permission bits, as for `access(2)`.
+pub const R_OK: u32 = 4;
+pub const W_OK: u32 = 2;
+pub const X_OK: u32 = 1;
+
+/// The sticky bit: entries of a directory with it can only be removed or
+/// renamed by their owner, the directory's owner or the superuser.
+pub const S_ISVTX: u32 = 0o1000;
+
+/// Whom an ACL entry applies to, as in POSIX.1e access ACLs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AclTag {
+    /// The owner of the file.
+    UserObj,
+    User(u32),
+    /// The owning group of the file.
+    GroupObj,
+    Group(u32),
+    /// Upper bound of what named users and all groups are granted.
+    Mask,
+    Other,
+}
+
+/// One entry of an access ACL, in `getfacl` text form `user:1000:rw-`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AclEntry {
+    pub tag: AclTag,
+    pub perms: u32,
+}
+
+impl AclEntry {
+    pub fn new(tag: AclTag, perms: u32) -> Self {
+        Self {
+            tag,
+            perms: perms & 0o7,
+        }
+    }
+}
+
+fn perms_string(perms: u32) -> String {
+    [(R_OK, 'r'), (W_OK, 'w'), (X_OK, 'x')]
+        .iter()
+        .map(|&(bit, c)| if perms & bit != 0 { c } else { '-' })
+        .collect()
+}
+
+/// The `ls -l` form of permission bits, e.g. `rwxr-x---`, with the
+/// sticky bit shown as `t` (or `T` without search permission for others).
+pub fn mode_string(mode: u32) -> String {
+    let mut string: String = [6, 3, 0]
+        .iter()
+        .map(|shift| perms_string(mode >> shift))
+        .collect();
+    if mode & S_ISVTX != 0 {
+        let sticky = if mode & X_OK != 0 { 't' } else { 'T' };
+        string.replace_range(8.., &sticky.to_string());
+    }
+    string
+}
+
+impl fmt::Display for AclEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let perms = perms_string(self.perms);
+        match self.tag {
+            AclTag::UserObj => write!(f, "user::{}", perms),
+            AclTag::User(uid) => write!(f, "user:{}:{}", uid, perms),
+            AclTag::GroupObj => write!(f, "group::{}", perms),
+            AclTag::Group(gid) => write!(f, "group:{}:{}", gid, perms),
+            AclTag::Mask => write!(f, "mask::{}", perms),
+            AclTag::Other => write!(f, "other::{}", perms),
+        }
+    }
+}
+
+/// Accepts the `setfacl` forms `u:1000:rw`, `user::rwx`, `g::r-x`,
+/// `m::r` and `o::-`.
+impl FromStr for AclEntry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid ACL entry: {}", s);
+        let mut fields = s.split(':');
+        let (kind, qualifier, perms) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(kind), Some(qualifier), Some(perms)) if fields.next().is_none() => {
+                (kind, qualifier, perms)
+            }
+            _ => return Err(invalid()),
+        };
+        let id = || qualifier.parse::<u32>().map_err(|_| invalid());
+        let tag = match (kind, qualifier.is_empty()) {
+            ("u" | "user", true) => AclTag::UserObj,
+            ("u" | "user", false) => AclTag::User(id()?),
+            ("g" | "group", true) => AclTag::GroupObj,
+            ("g" | "group", false) => AclTag::Group(id()?),
+            ("m" | "mask", true) => AclTag::Mask,
+            ("o" | "other", true) => AclTag::Other,
+            _ => return Err(invalid()),
+        };
+        let mut bits = 0;
+        for c in perms.chars() {
+            bits |= match c {
+                'r' => R_OK,
+                'w' => W_OK,
+                'x' => X_OK,
+                '-' => 0,
+                _ => return Err(invalid()),
+            };
+        }
+        Ok(AclEntry::new(tag, bits))
+    }
+}
+
+impl FileType {
+    /// Permission bits of a new file of this type before the umask.
+    pub(crate) fn base_mode(&self) -> u32 {
+        match self {
+            FileType::Directory(_) | FileType::Symlink(_) => 0o777,
+            FileType::Proc(_) => 0o444,
+            _ => 0o666,
+        }
+    }
+}
+
+impl FileDescriptor {
+    /// The access ACL, derived from the permission bits unless the file
+    /// has an extended one.
+    pub(crate) fn acl_entries(&self) -> Vec<AclEntry> {
+        if !self.acl.is_empty() {
+            return self.acl.clone();
+        }
+        vec![
+            AclEntry::new(AclTag::UserObj, self.mode >> 6),
+            AclEntry::new(AclTag::GroupObj, self.mode >> 3),
+            AclEntry::new(AclTag::Other, self.mode),
+        ]
+    }
+
+    /// Evaluate the access ACL for `want` permissions as POSIX.1e does:
+    /// the first class that matches the caller decides, and entries other
+    /// than the owner and others are limited by the mask.
+    pub(crate) fn permits(&self, uid: u32, gid: u32, want: u32) -> bool {
+        if uid == 0 {
+            return true;
+        }
+        let acl = self.acl_entries();
+        let mask = acl
+            .iter()
+            .find(|entry| entry.tag == AclTag::Mask)
+            .map_or(0o7, |entry| entry.perms);
+        let grants = |perms: u32| perms & want == want;
+        if uid == self.uid {
+            return acl
+                .iter()
+                .any(|entry| entry.tag == AclTag::UserObj && grants(entry.perms));
+        }
+        if let Some(entry) = acl.iter().find(|entry| entry.tag == AclTag::User(uid)) {
+            return grants(entry.perms & mask);
+        }
+        let groups: Vec<_> = acl
+            .iter()
+            .filter(|entry| {
+                (entry.tag == AclTag::GroupObj && gid == self.gid)
+                    || entry.tag == AclTag::Group(gid)
+            })
+            .collect();
+        if !groups.is_empty() {
+            return groups.iter().any(|entry| grants(entry.perms & mask));
+        }
+        acl.iter()
+            .any(|entry| entry.tag == AclTag::Other && grants(entry.perms))
+    }
+
+    fn set_mode(&mut self, mode: u32) {
+        self.mode = mode & 0o7777;
+        for entry in &mut self.acl {
+            entry.perms = match entry.tag {
+                AclTag::UserObj => (mode >> 6) & 0o7,
+                AclTag::Mask => (mode >> 3) & 0o7,
+                AclTag::Other => mode & 0o7,
+                _ => entry.perms,
+            };
+        }
+    }
+}
+
+/// Check that `entries` form a valid access ACL and put it in canonical
+/// order, adding a mask that covers all group-class entries if named
+/// entries need one.
+fn normalize(entries: &[AclEntry]) -> Option<Vec<AclEntry>> {
+    let mut acl = entries.to_vec();
+    acl.sort_by_key(|entry| entry.tag);
+    if acl.windows(2).any(|pair| pair[0].tag == pair[1].tag) {
+        return None;
+    }
+    let has = |tag| acl.iter().any(|entry: &AclEntry| entry.tag == tag);
+    if !has(AclTag::UserObj) || !has(AclTag::GroupObj) || !has(AclTag::Other) {
+        return None;
+    }
+    let named = acl
+        .iter()
+        .any(|entry| matches!(entry.tag, AclTag::User(_) | AclTag::Group(_)));
+    if named && !has(AclTag::Mask) {
+        let perms = acl
+            .iter()
+            .filter(|entry| !matches!(entry.tag, AclTag::UserObj | AclTag::Other))
+            .fold(0, |perms, entry| perms | entry.perms);
+        acl.push(AclEntry::new(AclTag::Mask, perms));
+        acl.sort_by_key(|entry| entry.tag);
+    }
+    Some(acl)
+}
+
+impl Vfs {
+    /// Fail with `Permission denied` unless the current session may
+    /// access inode `id` with all of the `want` permissions.
+    pub(crate) fn check_access<F>(&self, id: usize, want: u32, context: F) -> Result<(), VfsError>
+    where
+        F: FnOnce() -> String,
+    {
+        let session = &self.session;
+        if !self.fds[id].permits(session.uid(), session.gid(), want) {
+            return Err(VfsError::new(
+                ErrorKind::PermissionDenied,
+                format!("{}: Permission denied", context()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Fail with `Operation not permitted` unless the current session owns
+    /// inode `id` or is the superuser.
+    fn check_owner<F>(&self, id: usize, context: F) -> Result<(), VfsError>
+    where
+        F: FnOnce() -> String,
+    {
+        let uid = self.session.uid();
+        if uid != 0 && uid != self.fds[id].uid {
+            return Err(VfsError::new(
+                ErrorKind::NotPermitted,
+                format!("{}: Operation not permitted", context()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Fail with `Operation not permitted` if directory `dir_id` is sticky
+    /// and the current session may not remove its entry for inode `id`.
+    pub(crate) fn check_sticky<F>(
+        &self,
+        dir_id: usize,
+        id: usize,
+        context: F,
+    ) -> Result<(), VfsError>
+    where
+        F: FnOnce() -> String,
+    {
+        let uid = self.session.uid();
+        let dir = &self.fds[dir_id];
+        if dir.mode & S_ISVTX != 0 && uid != 0 && uid != dir.uid && uid != self.fds[id].uid {
+            return Err(VfsError::new(
+                ErrorKind::NotPermitted,
+                format!("{}: Operation not permitted", context()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check whether the current session may access `pathname` with the
+    /// `want` permissions (`R_OK`, `W_OK`, `X_OK`), like `access(2)`.
+    pub fn access(&self, pathname: &str, want: u32) -> Result<(), VfsError> {
+        let context = || format!("access: cannot access '{}'", pathname);
+        match self.resolve(pathname) {
+            Some((_, id, _)) => Ok(self.check_access(id, want, context)?),
+            None => Err(self.error_at(
+                VfsError::new(
+                    ErrorKind::NotFound,
+                    format!("{}: No such file or directory", context()),
+                ),
+                pathname,
+            )),
+        }
+    }
+
+    /// Change the permission bits of `pathname`, including the sticky bit;
+    /// with an extended ACL the group bits set its mask.
+    pub fn chmod(&mut self, pathname: &str, mode: u32) -> Result<(), VfsError> {
+        let result = self.chmod_unaudited(pathname, mode);
+        self.audit(
+            || Op::Chmod {
+                pathname: pathname.to_string(),
+                mode,
+            },
+            &result,
+        );
+        result.map_err(|err| self.error_at(err, pathname))
+    }
+
+    fn chmod_unaudited(&mut self, pathname: &str, mode: u32) -> Result<(), VfsError> {
+        let context = || format!("chmod: cannot change permissions of '{}'", pathname);
+        self.check_writable_at(pathname, context)?;
+        let id = match self.resolve(pathname) {
+            Some((_, id, _)) => id,
+            None => {
+                return Err(VfsError::new(
+                    ErrorKind::NotFound,
+                    format!("{}: No such file or directory", context()),
+                ))
+            }
+        };
+        self.check_owner(id, context)?;
+        self.check_attrs(id, ATTR_IMMUTABLE, context)?;
+        self.fds[id].set_mode(mode);
+        self.touch_changed(id);
+        Ok(())
+    }
+
+    /// Change the owner and group of `pathname`, leaving either unchanged
+    /// if `None`. Only the superuser may give a file away; its owner may
+    /// only change the group to the session's own.
+    pub fn chown(
+        &mut self,
+        pathname: &str,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> Result<(), VfsError> {
+        let result = self.chown_unaudited(pathname, uid, gid);
+        self.audit(
+            || Op::Chown {
+                pathname: pathname.to_string(),
+                uid,
+                gid,
+            },
+            &result,
+        );
+        result.map_err(|err| self.error_at(err, pathname))
+    }
+
+    fn chown_unaudited(
+        &mut self,
+        pathname: &str,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> Result<(), VfsError> {
+        let context = || format!("chown: changing ownership of '{}'", pathname);
+        self.check_writable_at(pathname, context)?;
+        let id = match self.resolve(pathname) {
+            Some((_, id, _)) => id,
+            None => {
+                return Err(VfsError::new(
+                    ErrorKind::NotFound,
+                    format!("{}: No such file or directory", context()),
+                ))
+            }
+        };
+        self.check_owner(id, context)?;
+        let fd = &self.fds[id];
+        let session = &self.session;
+        if session.uid() != 0
+            && (uid.is_some_and(|uid| uid != fd.uid)
+                || gid.is_some_and(|gid| gid != fd.gid && gid != session.gid()))
+        {
+            return Err(VfsError::new(
+                ErrorKind::NotPermitted,
+                format!("{}: Operation not permitted", context()),
+            ));
+        }
+        self.check_attrs(id, ATTR_IMMUTABLE, context)?;
+        let fd = &mut self.fds[id];
+        fd.uid = uid.unwrap_or(fd.uid);
+        fd.gid = gid.unwrap_or(fd.gid);
+        self.touch_changed(id);
+        Ok(())
+    }
+
+    /// The access ACL of `pathname`, with the owner, group and other
+    /// entries derived from its mode when it has no extended ACL.
+    pub fn get_acl(&self, pathname: &str) -> Result<Vec<AclEntry>, VfsError> {
+        match self.resolve(pathname) {
+            Some((fd, _, _)) => Ok(fd.acl_entries()),
+            None => {
+                let message = VfsError::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "getfacl: cannot access '{}': No such file or directory",
+                        pathname
+                    ),
+                );
+                Err(self.error_at(message, pathname))
+            }
+        }
+    }
+
+    /// Replace the access ACL of `pathname`. It needs exactly one owner,
+    /// group and other entry; a mask is computed if named entries are
+    /// given without one. The mode follows the ACL, with the group bits
+    /// taken from the mask.
+    pub fn set_acl(&mut self, pathname: &str, entries: &[AclEntry]) -> Result<(), VfsError> {
+        let result = self.set_acl_unaudited(pathname, entries);
+        self.audit(
+            || Op::SetAcl {
+                pathname: pathname.to_string(),
+                acl: entries.to_vec(),
+            },
+            &result,
+        );
+        result.map_err(|err| self.error_at(err, pathname))
+    }
+
+    fn set_acl_unaudited(&mut self, pathname: &str, entries: &[AclEntry]) -> Result<(), VfsError> {
+        let context = || format!("setfacl: cannot set ACL of '{}'", pathname);
+        self.check_writable_at(pathname, context)?;
+        let id = match self.resolve(pathname) {
+            Some((_, id, _)) => id,
+            None => {
+                return Err(VfsError::new(
+                    ErrorKind::NotFound,
+                    format!("{}: No such file or directory", context()),
+                ))
+            }
+        };
+        self.check_owner(id, context)?;
+        self.check_attrs(id, ATTR_IMMUTABLE, context)?;
+        let acl = normalize(entries).ok_or_else(|| {
+            VfsError::new(
+                ErrorKind::InvalidInput,
+                format!("{}: Invalid argument", context()),
+            )
+        })?;
+        let perms = |tag| {
+            acl.iter()
+                .find(|entry: &&AclEntry| entry.tag == tag)
+                .map(|entry| entry.perms)
+        };
+        let group = perms(AclTag::Mask).or(perms(AclTag::GroupObj)).unwrap();
+        let mode =
+            perms(AclTag::UserObj).unwrap() << 6 | group << 3 | perms(AclTag::Other).unwrap();
+        let fd = &mut self.fds[id];
+        fd.acl = if acl.len() > 3 { acl } else { Vec::new() };
+        fd.set_mode(mode | (fd.mode & !0o777));
+        self.touch_changed(id);
+        Ok(())
+    }
+}
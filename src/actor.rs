@@ -0,0 +1,96 @@
+use std::{
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+use crate::{ErrorKind, Reply, Request, Vfs, VfsError};
+
+type Command = Box<dyn FnOnce(&mut Vfs) + Send>;
+
+/// A `Vfs` owned by a thread of its own, which runs commands one at a
+/// time as clients send them. Clients on any number of threads share it
+/// without locks, and since sending does not block, an async runtime can
+/// await the reply on its own terms.
+#[derive(Debug)]
+pub struct VfsActor {
+    client: VfsClient,
+    thread: JoinHandle<Vfs>,
+}
+
+/// A cloneable handle for sending commands to a `VfsActor`.
+#[derive(Debug, Clone)]
+pub struct VfsClient {
+    commands: Sender<Command>,
+}
+
+impl VfsActor {
+    /// Move `vfs` to a new thread and start taking commands.
+    pub fn spawn(mut vfs: Vfs) -> Self {
+        let (commands, received) = mpsc::channel::<Command>();
+        let thread = thread::spawn(move || {
+            for command in received {
+                command(&mut vfs);
+            }
+            vfs
+        });
+        Self {
+            client: VfsClient { commands },
+            thread,
+        }
+    }
+
+    pub fn client(&self) -> VfsClient {
+        self.client.clone()
+    }
+
+    /// Wait for every client to be dropped and every command sent to
+    /// finish, then take the `Vfs` back.
+    pub fn join(self) -> Result<Vfs, VfsError> {
+        drop(self.client);
+        self.thread.join().map_err(|_| {
+            VfsError::new(
+                ErrorKind::Other,
+                "actor: the Vfs thread panicked".to_string(),
+            )
+        })
+    }
+}
+
+impl VfsClient {
+    /// Run `f` on the actor's thread once the commands sent before it are
+    /// done, and return a receiver for its result.
+    pub fn send<T, F>(&self, f: F) -> Result<Receiver<T>, VfsError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Vfs) -> T + Send + 'static,
+    {
+        let (reply, received) = mpsc::sync_channel(1);
+        self.commands
+            .send(Box::new(move |vfs| {
+                let _ = reply.send(f(vfs));
+            }))
+            .map_err(|_| stopped())?;
+        Ok(received)
+    }
+
+    /// Run `f` on the actor's thread and wait for its result.
+    pub fn call<T, F>(&self, f: F) -> Result<T, VfsError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Vfs) -> T + Send + 'static,
+    {
+        self.send(f)?.recv().map_err(|_| stopped())
+    }
+
+    /// Run one `Request`, as `Vfs::submit` would, and wait for its reply.
+    pub fn request(&self, request: Request) -> Result<Reply, VfsError> {
+        self.call(move |vfs| vfs.execute(request))?
+    }
+}
+
+fn stopped() -> VfsError {
+    VfsError::new(
+        ErrorKind::Other,
+        "actor: the Vfs thread has stopped".to_string(),
+    )
+}
@@ -0,0 +1,914 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::{
+    error::host_error, image::Mount, namei::join, ErrorKind, FileDescriptor, FileType, Monitor,
+    MountOptions, Times, Vfs, VfsError, DOT, DOTDOT,
+};
+
+const TAR_BLOCK: usize = 512;
+const ZIP_LOCAL_HEADER: u32 = 0x0403_4b50;
+const ZIP_CENTRAL_HEADER: u32 = 0x0201_4b50;
+const ZIP_END: u32 = 0x0605_4b50;
+/// The end of central directory record and the longest comment after it.
+const ZIP_END_MAX: u64 = 22 + 0xffff;
+
+const S_IFMT: u32 = 0o170_000;
+const S_IFDIR: u32 = 0o040_000;
+const S_IFLNK: u32 = 0o120_000;
+
+/// The contents of a file in a mounted archive, read from the host file
+/// each time instead of being copied into blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Member {
+    archive: Arc<Path>,
+    /// Where the data starts in a tar archive, or where the local header
+    /// starts in a zip archive.
+    offset: u64,
+    size: usize,
+    encoding: Encoding,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Tar,
+    ZipStored { crc: u32 },
+    ZipDeflated { compressed_size: usize, crc: u32 },
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Why an archive member could not be read, after `context`: a damaged
+/// archive is an I/O error, like a bad sector would be.
+pub(crate) fn archive_error(context: String, err: &io::Error) -> VfsError {
+    match err.kind() {
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+            VfsError::new(ErrorKind::Other, format!("{}: Input/output error", context))
+        }
+        _ => host_error(context, err),
+    }
+}
+
+impl Member {
+    /// Up to `len` bytes of the contents from `offset` on. Only deflated
+    /// members are decoded as a whole.
+    pub(crate) fn read_at(&self, offset: usize, len: usize) -> io::Result<Vec<u8>> {
+        let offset = offset.min(self.size);
+        let len = len.min(self.size - offset);
+        let mut file = File::open(&self.archive)?;
+        let start = self.data_start(&mut file)?;
+        match self.encoding {
+            Encoding::Tar | Encoding::ZipStored { .. } => {
+                file.seek(SeekFrom::Start(start + offset as u64))?;
+                let mut data = vec![0; len];
+                file.read_exact(&mut data)?;
+                Ok(data)
+            }
+            Encoding::ZipDeflated {
+                compressed_size,
+                crc,
+            } => {
+                file.seek(SeekFrom::Start(start))?;
+                let mut compressed = vec![0; compressed_size];
+                file.read_exact(&mut compressed)?;
+                let data = inflate(&compressed).ok_or_else(|| invalid("corrupt deflate stream"))?;
+                if data.len() != self.size || crc32fast::hash(&data) != crc {
+                    return Err(invalid("checksum mismatch"));
+                }
+                Ok(data[offset..offset + len].to_vec())
+            }
+        }
+    }
+
+    /// The whole contents, checked against the archive's checksum if it
+    /// has one.
+    pub(crate) fn contents(&self) -> io::Result<Vec<u8>> {
+        let data = self.read_at(0, self.size)?;
+        if let Encoding::ZipStored { crc } = self.encoding {
+            if crc32fast::hash(&data) != crc {
+                return Err(invalid("checksum mismatch"));
+            }
+        }
+        Ok(data)
+    }
+
+    fn data_start(&self, file: &mut File) -> io::Result<u64> {
+        if self.encoding == Encoding::Tar {
+            return Ok(self.offset);
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut header = [0; 30];
+        file.read_exact(&mut header)?;
+        if le32(&header, 0) != ZIP_LOCAL_HEADER {
+            return Err(invalid("bad local header"));
+        }
+        let extra = le16(&header, 26) + le16(&header, 28);
+        Ok(self.offset + 30 + extra as u64)
+    }
+}
+
+/// One path of an archive's index.
+#[derive(Debug)]
+enum Entry {
+    Dir {
+        mode: u32,
+    },
+    File {
+        mode: u32,
+        member: Member,
+    },
+    Symlink(String),
+    /// A hard link to the file at another path of the archive.
+    Link(Vec<String>),
+}
+
+fn le16(data: &[u8], at: usize) -> usize {
+    u16::from_le_bytes([data[at], data[at + 1]]) as usize
+}
+
+fn le32(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+/// `name` split into components, or `None` if it would leave the
+/// archive's root.
+fn components(name: &str) -> Option<Vec<String>> {
+    let mut components = Vec::new();
+    for component in name.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return None,
+            component => components.push(component.to_string()),
+        }
+    }
+    Some(components)
+}
+
+/// The NUL-terminated string at the start of `field`.
+fn c_string(field: &[u8]) -> String {
+    let end = field
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn octal(field: &[u8]) -> Option<usize> {
+    let digits = c_string(field);
+    let digits = digits.trim_matches(|c: char| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Some(0);
+    }
+    usize::from_str_radix(digits, 8).ok()
+}
+
+/// Read the headers of a ustar, GNU or pax tar archive, skipping over
+/// the file data.
+fn tar_index(archive: &Arc<Path>, file: &mut File) -> io::Result<Vec<(String, Entry)>> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    let mut long_name = None;
+    let mut long_link = None;
+    let mut header = [0; TAR_BLOCK];
+    loop {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header)?;
+        if header.iter().all(|&byte| byte == 0) {
+            return Ok(entries);
+        }
+        let checksum: usize = header
+            .iter()
+            .enumerate()
+            .map(|(i, &byte)| {
+                if (148..156).contains(&i) {
+                    32
+                } else {
+                    byte as usize
+                }
+            })
+            .sum();
+        if octal(&header[148..156]) != Some(checksum) {
+            return Err(match offset {
+                0 => invalid("not a tar or zip archive"),
+                _ => invalid("bad tar header checksum"),
+            });
+        }
+        let size = octal(&header[124..136]).ok_or_else(|| invalid("bad tar member size"))?;
+        let mode = octal(&header[100..108]).unwrap_or(0) as u32 & 0o7777;
+        let data = offset + TAR_BLOCK as u64;
+        offset = data + size.div_ceil(TAR_BLOCK) as u64 * TAR_BLOCK as u64;
+        let read_data = |file: &mut File| -> io::Result<String> {
+            let mut buffer = vec![0; size];
+            file.read_exact(&mut buffer)?;
+            Ok(c_string(&buffer))
+        };
+        let mut name = c_string(&header[..100]);
+        if &header[257..262] == b"ustar" {
+            let prefix = c_string(&header[345..500]);
+            if !prefix.is_empty() {
+                name = format!("{}/{}", prefix, name);
+            }
+        }
+        let name = long_name.take().unwrap_or(name);
+        let link = long_link
+            .take()
+            .unwrap_or_else(|| c_string(&header[157..257]));
+        let entry = match header[156] {
+            b'0' | b'\0' if name.ends_with('/') => Entry::Dir { mode },
+            b'0' | b'\0' | b'7' => Entry::File {
+                mode,
+                member: Member {
+                    archive: archive.clone(),
+                    offset: data,
+                    size,
+                    encoding: Encoding::Tar,
+                },
+            },
+            b'5' => Entry::Dir { mode },
+            b'2' => Entry::Symlink(link),
+            b'1' => match components(&link) {
+                Some(target) => Entry::Link(target),
+                None => return Err(invalid("hard link leaves the archive")),
+            },
+            b'L' => {
+                long_name = Some(read_data(file)?);
+                continue;
+            }
+            b'K' => {
+                long_link = Some(read_data(file)?);
+                continue;
+            }
+            b'x' => {
+                for record in read_data(file)?.lines() {
+                    let Some((_, field)) = record.split_once(' ') else {
+                        continue;
+                    };
+                    match field.split_once('=') {
+                        Some(("path", path)) => long_name = Some(path.to_string()),
+                        Some(("linkpath", path)) => long_link = Some(path.to_string()),
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+            // Global pax headers, devices and FIFOs.
+            _ => continue,
+        };
+        entries.push((name, entry));
+    }
+}
+
+/// Read the central directory of a zip archive.
+fn zip_index(archive: &Arc<Path>, file: &mut File) -> io::Result<Vec<(String, Entry)>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let tail_start = len.saturating_sub(ZIP_END_MAX);
+    file.seek(SeekFrom::Start(tail_start))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&at| le32(&tail, at) == ZIP_END)
+        .ok_or_else(|| invalid("no zip end of central directory"))?;
+    let count = le16(&tail, end + 10);
+    let size = le32(&tail, end + 12);
+    let start = le32(&tail, end + 16);
+    if count == 0xffff || size == u32::MAX || start == u32::MAX {
+        return Err(invalid("zip64 archives are not supported"));
+    }
+    file.seek(SeekFrom::Start(start as u64))?;
+    let mut directory = vec![0; size as usize];
+    file.read_exact(&mut directory)?;
+    let mut entries = Vec::new();
+    let mut at = 0;
+    for _ in 0..count {
+        if directory.len() < at + 46 || le32(&directory, at) != ZIP_CENTRAL_HEADER {
+            return Err(invalid("bad zip central directory"));
+        }
+        let header = &directory[at..];
+        let name_len = le16(header, 28);
+        let name = header
+            .get(46..46 + name_len)
+            .ok_or_else(|| invalid("bad zip central directory"))?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        at += 46 + name_len + le16(header, 30) + le16(header, 32);
+        if le16(header, 8) & 1 != 0 {
+            return Err(invalid("encrypted zip members are not supported"));
+        }
+        let crc = le32(header, 16);
+        let compressed_size = le32(header, 20) as usize;
+        let encoding = match le16(header, 10) {
+            0 => Encoding::ZipStored { crc },
+            8 => Encoding::ZipDeflated {
+                compressed_size,
+                crc,
+            },
+            _ => return Err(invalid("unsupported zip compression method")),
+        };
+        let is_unix = le16(header, 4) >> 8 == 3;
+        let unix_mode = if is_unix { le32(header, 38) >> 16 } else { 0 };
+        let member = Member {
+            archive: archive.clone(),
+            offset: le32(header, 42) as u64,
+            size: le32(header, 24) as usize,
+            encoding,
+        };
+        let entry = if name.ends_with('/') || unix_mode & S_IFMT == S_IFDIR {
+            Entry::Dir {
+                mode: if is_unix { unix_mode & 0o7777 } else { 0o755 },
+            }
+        } else if unix_mode & S_IFMT == S_IFLNK {
+            let target = member.contents()?;
+            Entry::Symlink(String::from_utf8_lossy(&target).into_owned())
+        } else {
+            Entry::File {
+                mode: if is_unix { unix_mode & 0o7777 } else { 0o644 },
+                member,
+            }
+        };
+        entries.push((name, entry));
+    }
+    Ok(entries)
+}
+
+/// The index of the tar or zip archive at `path`, by path within the
+/// archive. Parents missing from the archive are added as directories,
+/// and a later entry for a path replaces an earlier one.
+fn read_index(path: &Path) -> io::Result<BTreeMap<Vec<String>, Entry>> {
+    let archive: Arc<Path> = Arc::from(path);
+    let mut file = File::open(path)?;
+    let mut magic = [0; 4];
+    let is_zip =
+        file.read(&mut magic)? == 4 && matches!(le32(&magic, 0), ZIP_LOCAL_HEADER | ZIP_END);
+    let entries = match is_zip {
+        true => zip_index(&archive, &mut file),
+        false => tar_index(&archive, &mut file),
+    }
+    .map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => invalid("truncated archive"),
+        _ => err,
+    })?;
+    let mut index = BTreeMap::new();
+    for (name, entry) in entries {
+        let components = components(&name).ok_or_else(|| invalid("member leaves the archive"))?;
+        if components.is_empty() {
+            continue;
+        }
+        for len in 1..components.len() {
+            index
+                .entry(components[..len].to_vec())
+                .or_insert(Entry::Dir { mode: 0o755 });
+        }
+        index.insert(components, entry);
+    }
+    Ok(index)
+}
+
+/// Fill `field` with `value` in zero-padded octal and a final NUL.
+fn put_octal(field: &mut [u8], value: u64) {
+    let width = field.len() - 1;
+    let digits = format!("{:0width$o}", value, width = width);
+    field[..width].copy_from_slice(&digits.as_bytes()[digits.len() - width..]);
+    field[width] = 0;
+}
+
+/// A ustar header for a member of `kind` with `size` bytes of data; the
+/// name and link target are cut to fit, see `write_tar_header`.
+fn tar_header(
+    name: &[u8],
+    kind: u8,
+    size: usize,
+    link: &[u8],
+    fd: Option<&FileDescriptor>,
+) -> [u8; TAR_BLOCK] {
+    let mut header = [0; TAR_BLOCK];
+    let name = &name[..name.len().min(100)];
+    header[..name.len()].copy_from_slice(name);
+    let (mode, uid, gid, mtime) = fd.map_or((0o644, 0, 0, 0), |fd| {
+        (fd.mode & 0o7777, fd.uid, fd.gid, fd.times.mtime.sec.max(0))
+    });
+    put_octal(&mut header[100..108], mode as u64);
+    put_octal(&mut header[108..116], uid as u64);
+    put_octal(&mut header[116..124], gid as u64);
+    put_octal(&mut header[124..136], size as u64);
+    put_octal(&mut header[136..148], mtime as u64);
+    header[156] = kind;
+    let link = &link[..link.len().min(100)];
+    header[157..157 + link.len()].copy_from_slice(link);
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[148..156].fill(b' ');
+    let checksum: u64 = header.iter().map(|&byte| byte as u64).sum();
+    put_octal(&mut header[148..155], checksum);
+    header
+}
+
+/// Write `data` padded to whole tar blocks.
+fn write_tar_data(out: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    out.write_all(data)?;
+    out.write_all(&[0; TAR_BLOCK][..data.len().next_multiple_of(TAR_BLOCK) - data.len()])
+}
+
+/// Write the header of one member, preceded by GNU long name and long
+/// link members if its name or link target is too long for the header.
+fn write_tar_header(
+    out: &mut impl Write,
+    name: &str,
+    kind: u8,
+    size: usize,
+    link: &str,
+    fd: &FileDescriptor,
+) -> io::Result<()> {
+    for (long_kind, value) in [(b'L', name), (b'K', link)] {
+        if value.len() > 100 {
+            let mut data = value.as_bytes().to_vec();
+            data.push(0);
+            out.write_all(&tar_header(
+                b"././@LongLink",
+                long_kind,
+                data.len(),
+                b"",
+                None,
+            ))?;
+            write_tar_data(out, &data)?;
+        }
+    }
+    out.write_all(&tar_header(
+        name.as_bytes(),
+        kind,
+        size,
+        link.as_bytes(),
+        Some(fd),
+    ))
+}
+
+impl Vfs {
+    /// Mount the tar or zip archive at `host_path` on the host read-only
+    /// on an empty directory, like `archivemount(1)`. Only the index is
+    /// read up front: file contents stay in the archive and are read, and
+    /// inflated if compressed, each time they are read. Uncompressed tar,
+    /// and zip with stored or deflated members, are supported.
+    ///
+    /// Like importing from the host, this is not recorded by the audit
+    /// log.
+    pub fn mount_archive<P: AsRef<Path>>(
+        &mut self,
+        host_path: P,
+        mountpoint: &str,
+        options: MountOptions,
+    ) -> Result<(), VfsError> {
+        let host_path = host_path.as_ref();
+        let context = || {
+            format!(
+                "mount: cannot mount '{}' on '{}'",
+                host_path.display(),
+                mountpoint
+            )
+        };
+        self.check_writable_at(mountpoint, context)?;
+        let root_id = match self.resolve(&format!("{}/{}", mountpoint, DOT)) {
+            Some((fd, id, _)) if fd.file_type.is_dir() => {
+                if fd.file_type.as_dir().len() > 2 {
+                    return Err(VfsError::new(
+                        ErrorKind::DirectoryNotEmpty,
+                        format!("{}: Directory not empty", context()),
+                    ));
+                }
+                id
+            }
+            Some(_) => {
+                return Err(VfsError::new(
+                    ErrorKind::NotADirectory,
+                    format!("{}: Not a directory", context()),
+                ))
+            }
+            None => {
+                return Err(VfsError::new(
+                    ErrorKind::NotFound,
+                    format!("{}: No such file or directory", context()),
+                ))
+            }
+        };
+        if self.mounts.iter().any(|mount| mount.root_id == root_id) {
+            return Err(VfsError::new(
+                ErrorKind::Busy,
+                format!("{}: Device or resource busy", context()),
+            ));
+        }
+        let index = read_index(host_path).map_err(|err| host_error(context(), &err))?;
+        let inodes = index
+            .values()
+            .filter(|entry| !matches!(entry, Entry::Link(_)))
+            .count();
+        self.check_free_inodes(inodes, context)?;
+        let mut ids = HashMap::from([(Vec::new(), root_id)]);
+        let mut links = Vec::new();
+        for (path, entry) in index {
+            // Below a path the archive has as something else than a
+            // directory.
+            let Some(&parent_id) = ids
+                .get(&path[..path.len() - 1])
+                .filter(|&&id| self.fds[id].file_type.is_dir())
+            else {
+                continue;
+            };
+            let (id, mode) = match entry {
+                Entry::Dir { mode } => (
+                    self.alloc_fd(|id| FileDescriptor::new_dir(id, parent_id)),
+                    mode,
+                ),
+                Entry::File { mode, member } => {
+                    let size = member.size;
+                    let id = self.alloc_fd(|_| FileDescriptor {
+                        file_type: FileType::Archive(member),
+                        size,
+                        links: 1,
+                        refs: 0,
+                        generation: 0,
+                        uid: 0,
+                        gid: 0,
+                        mode: 0o644,
+                        acl: Vec::new(),
+                        attrs: 0,
+                        project: 0,
+                        times: Times::default(),
+                    });
+                    (id, mode)
+                }
+                Entry::Symlink(target) => (
+                    self.alloc_fd(|_| FileDescriptor::new_symlink(&target)),
+                    0o777,
+                ),
+                Entry::Link(target) => {
+                    links.push((path, target));
+                    continue;
+                }
+            };
+            self.fds[id].mode = mode;
+            self.fds[id].project = self.fds[root_id].project;
+            let name = path.last().unwrap().clone();
+            self.fds[parent_id].file_type.as_dir_mut().insert(name, id);
+            ids.insert(path, id);
+        }
+        // Hard links to files the archive does not have are left out.
+        for (path, target) in links {
+            let parent_id = ids.get(&path[..path.len() - 1]).copied();
+            let id = ids.get(&target).copied();
+            if let (Some(parent_id), Some(id)) = (parent_id, id) {
+                if !self.fds[parent_id].file_type.is_dir() || self.fds[id].file_type.is_dir() {
+                    continue;
+                }
+                self.fds[id].links += 1;
+                let name = path.last().unwrap().clone();
+                self.fds[parent_id].file_type.as_dir_mut().insert(name, id);
+            }
+        }
+        self.mounts.push(Mount {
+            image: host_path.display().to_string(),
+            mountpoint: self.realpath(mountpoint).unwrap(),
+            root_id,
+            options: MountOptions {
+                read_only: true,
+                ..options
+            },
+            archive: true,
+        });
+        if self.is_name_indexed() {
+            self.set_name_index(true);
+        }
+        Ok(())
+    }
+
+    /// Write `pathname` and everything below it to the host file
+    /// `host_file` as a ustar archive, like `tar -cf`, with member names
+    /// starting at the last component of `pathname`. Hard links are kept;
+    /// FIFOs, devices, generated files and ring buffers are left out.
+    pub fn export_tar<P: AsRef<Path>>(&self, pathname: &str, host_file: P) -> Result<(), VfsError> {
+        self.export_tar_with(pathname, host_file, &mut Monitor::new())
+    }
+
+    /// Like `export_tar`, reporting each block and entry written to
+    /// `monitor` and stopping if it is cancelled, which removes the
+    /// unfinished archive.
+    pub fn export_tar_with<P: AsRef<Path>>(
+        &self,
+        pathname: &str,
+        host_file: P,
+        monitor: &mut Monitor,
+    ) -> Result<(), VfsError> {
+        let host = host_file.as_ref();
+        let host_err =
+            |err: io::Error| host_error(format!("export: cannot write '{}'", host.display()), &err);
+        let (Some(realpath), Some((_, id, _))) = (self.realpath(pathname), self.resolve(pathname))
+        else {
+            return Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!(
+                    "export: cannot access '{}': No such file or directory",
+                    pathname
+                ),
+            ));
+        };
+        let mut members = Vec::new();
+        self.tar_members(Vfs::basename(&realpath), realpath, id, &mut members);
+        let mut out = BufWriter::new(File::create(host).map_err(host_err)?);
+        let result = self
+            .write_tar(&mut out, &members, monitor, pathname, host_err)
+            .and_then(|()| out.flush().map_err(host_err));
+        if result.is_err() {
+            let _ = fs::remove_file(host);
+        }
+        result
+    }
+
+    /// Collect the member name, path and inode of `id` and of everything
+    /// below it, each directory before its entries.
+    fn tar_members(
+        &self,
+        name: String,
+        path: String,
+        id: usize,
+        members: &mut Vec<(String, String, usize)>,
+    ) {
+        let entries = match &self.fds[id].file_type {
+            FileType::Directory(entries) => Some(entries),
+            _ => None,
+        };
+        members.push((name.clone(), path.clone(), id));
+        for (child, &child_id) in entries.into_iter().flatten() {
+            if child == DOT || child == DOTDOT {
+                continue;
+            }
+            let child_name = match name.is_empty() {
+                true => child.clone(),
+                false => format!("{}/{}", name, child),
+            };
+            self.tar_members(child_name, join(&path, child), child_id, members);
+        }
+    }
+
+    fn write_tar<F>(
+        &self,
+        out: &mut impl Write,
+        members: &[(String, String, usize)],
+        monitor: &mut Monitor,
+        pathname: &str,
+        host_err: F,
+    ) -> Result<(), VfsError>
+    where
+        F: Fn(io::Error) -> VfsError + Copy,
+    {
+        let context = || format!("export: cannot export '{}'", pathname);
+        // The first member of each file with several links; the others
+        // become hard links to it.
+        let mut linked: HashMap<usize, &str> = HashMap::new();
+        for (name, path, id) in members {
+            let fd = &self.fds[*id];
+            match &fd.file_type {
+                // The root has no name of its own.
+                FileType::Directory(_) if name.is_empty() => continue,
+                FileType::Directory(_) => {
+                    write_tar_header(out, &format!("{}/", name), b'5', 0, "", fd)
+                        .map_err(host_err)?
+                }
+                FileType::Symlink(target) => {
+                    write_tar_header(out, name, b'2', 0, target, fd).map_err(host_err)?
+                }
+                FileType::Regular(_) | FileType::Archive(_) => match linked.get(id) {
+                    Some(first) => {
+                        write_tar_header(out, name, b'1', 0, first, fd).map_err(host_err)?
+                    }
+                    None => {
+                        if fd.links > 1 {
+                            linked.insert(*id, name);
+                        }
+                        write_tar_header(out, name, b'0', fd.size, "", fd).map_err(host_err)?;
+                        self.write_tar_contents(out, path, *id, monitor, context, host_err)?;
+                    }
+                },
+                _ => continue,
+            }
+            monitor.advance(0, 1, context)?;
+        }
+        out.write_all(&[0; 2 * TAR_BLOCK]).map_err(host_err)
+    }
+
+    /// Write the contents of file `id` at `path` a block at a time, padded
+    /// to whole tar blocks.
+    fn write_tar_contents<C, F>(
+        &self,
+        out: &mut impl Write,
+        path: &str,
+        id: usize,
+        monitor: &mut Monitor,
+        context: C,
+        host_err: F,
+    ) -> Result<(), VfsError>
+    where
+        C: Fn() -> String + Copy,
+        F: Fn(io::Error) -> VfsError + Copy,
+    {
+        if let FileType::Archive(member) = &self.fds[id].file_type {
+            let data = member
+                .contents()
+                .map_err(|err| archive_error(format!("export: cannot read '{}'", path), &err))?;
+            write_tar_data(out, &data).map_err(host_err)?;
+            return monitor.advance(data.len(), 0, context);
+        }
+        for chunk in self.file_chunks(id) {
+            let chunk = chunk.map_err(|block_ref| self.corrupted("export", path, block_ref))?;
+            out.write_all(&chunk).map_err(host_err)?;
+            monitor.advance(chunk.len(), 0, context)?;
+        }
+        let size = self.fds[id].size;
+        out.write_all(&[0; TAR_BLOCK][..size.next_multiple_of(TAR_BLOCK) - size])
+            .map_err(host_err)
+    }
+}
+
+/// Reads the bits of a deflate stream, least significant first.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl Bits<'_> {
+    fn bits(&mut self, n: u32) -> Option<u32> {
+        while self.count < n {
+            self.buffer |= (*self.data.get(self.pos)? as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Some(value)
+    }
+}
+
+/// A canonical Huffman code, as counts of codes per length and the
+/// symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Option<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return self.symbols.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order code length code lengths are stored in.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Decode a raw deflate stream (RFC 1951), or `None` if it is corrupt.
+fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    let mut bits = Bits {
+        data,
+        pos: 0,
+        buffer: 0,
+        count: 0,
+    };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                // Whole bytes already buffered belong to the stored block.
+                bits.pos -= (bits.count / 8) as usize;
+                bits.buffer = 0;
+                bits.count = 0;
+                let header = bits.data.get(bits.pos..bits.pos + 4)?;
+                let len = le16(header, 0);
+                if len != !le16(header, 2) & 0xffff {
+                    return None;
+                }
+                bits.pos += 4;
+                out.extend_from_slice(bits.data.get(bits.pos..bits.pos + len)?);
+                bits.pos += len;
+            }
+            1 => {
+                let mut lengths = [8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                let literals = Huffman::new(&lengths);
+                let distances = Huffman::new(&[5; 30]);
+                inflate_block(&mut bits, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let literal_count = bits.bits(5)? as usize + 257;
+                let distance_count = bits.bits(5)? as usize + 1;
+                let code_count = bits.bits(4)? as usize + 4;
+                let mut code_lengths = [0; 19];
+                for &symbol in &CODE_LENGTH_ORDER[..code_count] {
+                    code_lengths[symbol] = bits.bits(3)? as u8;
+                }
+                let code = Huffman::new(&code_lengths);
+                let mut lengths = Vec::new();
+                while lengths.len() < literal_count + distance_count {
+                    let (len, repeat) = match code.decode(&mut bits)? {
+                        symbol @ 0..=15 => (symbol as u8, 1),
+                        16 => (*lengths.last()?, 3 + bits.bits(2)?),
+                        17 => (0, 3 + bits.bits(3)?),
+                        18 => (0, 11 + bits.bits(7)?),
+                        _ => return None,
+                    };
+                    lengths.extend(std::iter::repeat_n(len, repeat as usize));
+                }
+                if lengths.len() != literal_count + distance_count {
+                    return None;
+                }
+                let literals = Huffman::new(&lengths[..literal_count]);
+                let distances = Huffman::new(&lengths[literal_count..]);
+                inflate_block(&mut bits, &mut out, &literals, &distances)?;
+            }
+            _ => return None,
+        }
+        if last {
+            return Some(out);
+        }
+    }
+}
+
+fn inflate_block(
+    bits: &mut Bits,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Option<()> {
+    loop {
+        match literals.decode(bits)? {
+            symbol @ 0..=255 => out.push(symbol as u8),
+            256 => return Some(()),
+            symbol => {
+                let symbol = symbol as usize - 257;
+                let len = *LENGTH_BASE.get(symbol)? as usize
+                    + bits.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+                let symbol = distances.decode(bits)? as usize;
+                let distance = *DISTANCE_BASE.get(symbol)? as usize
+                    + bits.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+                let start = out.len().checked_sub(distance)?;
+                for i in 0..len {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{ErrorKind, Op, Vfs, VfsError};
+
+/// The file cannot be modified, removed, renamed or linked to, and no
+/// entries can be added to or removed from it if it is a directory.
+pub const ATTR_IMMUTABLE: u32 = 0x10;
+/// The file can only be written at its end and not truncated, removed,
+/// renamed or linked to; a directory can gain entries but not lose them.
+pub const ATTR_APPEND: u32 = 0x20;
+
+const LETTERS: [(u32, char); 2] = [(ATTR_IMMUTABLE, 'i'), (ATTR_APPEND, 'a')];
+
+/// The `lsattr` form of attribute flags: one column per flag, e.g. `-a`.
+pub fn attr_string(attrs: u32) -> String {
+    LETTERS
+        .iter()
+        .map(|&(flag, c)| if attrs & flag != 0 { c } else { '-' })
+        .collect()
+}
+
+/// Parse flags written as letters, ignoring `-` placeholders, so that
+/// `attr_string` round-trips.
+pub fn parse_attrs(s: &str) -> Option<u32> {
+    s.chars().try_fold(0, |attrs, c| match c {
+        '-' => Some(attrs),
+        c => LETTERS
+            .iter()
+            .find(|&&(_, letter)| letter == c)
+            .map(|&(flag, _)| attrs | flag),
+    })
+}
+
+impl Vfs {
+    /// Fail with `Operation not permitted` if inode `id` has any of the
+    /// attribute flags in `attrs`. Checking `ATTR_IMMUTABLE` also fails
+    /// with `Read-only file system` below a read-only mount.
+    pub(crate) fn check_attrs<F>(&self, id: usize, attrs: u32, context: F) -> Result<(), VfsError>
+    where
+        F: FnOnce() -> String,
+    {
+        if self.fds[id].attrs & attrs != 0 {
+            return Err(VfsError::new(
+                ErrorKind::NotPermitted,
+                format!("{}: Operation not permitted", context()),
+            ));
+        }
+        if attrs & ATTR_IMMUTABLE != 0 {
+            self.check_mount_writable(id, context)?;
+        }
+        Ok(())
+    }
+
+    /// Attribute flags of `pathname` (`ATTR_IMMUTABLE`, `ATTR_APPEND`).
+    pub fn attrs(&self, pathname: &str) -> Result<u32, VfsError> {
+        match self.resolve(pathname) {
+            Some((fd, _, _)) => Ok(fd.attrs),
+            None => {
+                let message = VfsError::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "lsattr: cannot access '{}': No such file or directory",
+                        pathname
+                    ),
+                );
+                Err(self.error_at(message, pathname))
+            }
+        }
+    }
+
+    /// Replace the attribute flags of `pathname`, like `chattr(1)`. Only
+    /// the superuser may change them.
+    pub fn set_attrs(&mut self, pathname: &str, attrs: u32) -> Result<(), VfsError> {
+        let result = self.set_attrs_unaudited(pathname, attrs);
+        self.audit(
+            || Op::Chattr {
+                pathname: pathname.to_string(),
+                attrs,
+            },
+            &result,
+        );
+        result.map_err(|err| self.error_at(err, pathname))
+    }
+
+    fn set_attrs_unaudited(&mut self, pathname: &str, attrs: u32) -> Result<(), VfsError> {
+        let context = || format!("chattr: cannot set flags on '{}'", pathname);
+        self.check_writable_at(pathname, context)?;
+        let id = match self.resolve(pathname) {
+            Some((_, id, _)) => id,
+            None => {
+                return Err(VfsError::new(
+                    ErrorKind::NotFound,
+                    format!("{}: No such file or directory", context()),
+                ))
+            }
+        };
+        if attrs & !(ATTR_IMMUTABLE | ATTR_APPEND) != 0 {
+            return Err(VfsError::new(
+                ErrorKind::InvalidInput,
+                format!("{}: Invalid argument", context()),
+            ));
+        }
+        if self.session.uid() != 0 {
+            return Err(VfsError::new(
+                ErrorKind::NotPermitted,
+                format!("{}: Operation not permitted", context()),
+            ));
+        }
+        self.check_mount_writable(id, context)?;
+        self.fds[id].attrs = attrs;
+        self.touch_changed(id);
+        Ok(())
+    }
+}
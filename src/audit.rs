@@ -0,0 +1,136 @@
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fmt, str::FromStr};
+
+use crate::{
+    op::{tokenize, Op},
+    ErrorKind, Vfs, VfsError,
+};
+
+/// One mutating operation recorded by the audit log.
+///
+/// Serialized as a single line: milliseconds since the Unix epoch (the
+/// record's position with a seed or without the `std` feature), `ok` or
+/// `err`, the operation and, for failures, the quoted error message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub timestamp: u64,
+    pub op: Op,
+    pub result: Result<(), String>,
+}
+
+impl fmt::Display for AuditRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.result {
+            Ok(()) => write!(f, "{} ok {}", self.timestamp, self.op),
+            Err(err) => write!(f, "{} err {} {:?}", self.timestamp, self.op, err),
+        }
+    }
+}
+
+impl FromStr for AuditRecord {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid audit record: {}", line);
+        let tokens = tokenize(line).ok_or_else(invalid)?;
+        if tokens.len() < 3 {
+            return Err(invalid());
+        }
+        let timestamp = tokens[0].parse().map_err(|_| invalid())?;
+        let (op, consumed) = Op::from_tokens(&tokens[2..])?;
+        let rest = &tokens[2 + consumed..];
+        let result = match (tokens[1].as_str(), rest) {
+            ("ok", []) => Ok(()),
+            ("err", [err]) => Err(err.clone()),
+            _ => return Err(invalid()),
+        };
+        Ok(Self {
+            timestamp,
+            op,
+            result,
+        })
+    }
+}
+
+impl Vfs {
+    /// Start or stop recording mutating operations; stopping discards the
+    /// recorded log.
+    pub fn set_audit(&mut self, enabled: bool) {
+        match (enabled, &self.audit) {
+            (true, None) => self.audit = Some(Vec::new()),
+            (false, _) => self.audit = None,
+            _ => {}
+        }
+    }
+
+    pub fn audit_log(&self) -> &[AuditRecord] {
+        self.audit.as_deref().unwrap_or_default()
+    }
+
+    /// Whether anything, the audit log, watchers or an index, has to see
+    /// each operation.
+    pub(crate) fn is_observed(&self) -> bool {
+        self.audit.is_some()
+            || !self.watches.is_empty()
+            || self.name_index.is_some()
+            || self.content_index.is_some()
+    }
+
+    /// Record `op` in the audit log, if enabled, and if it succeeded,
+    /// report it to watchers and update the indexes.
+    pub(crate) fn audit<T, F>(&mut self, op: F, result: &Result<T, VfsError>)
+    where
+        F: FnOnce() -> Op,
+    {
+        if !self.is_observed() {
+            return;
+        }
+        let op = op();
+        if result.is_ok() {
+            self.notify(&op);
+            self.index_op(&op);
+            self.index_content(&op);
+        }
+        if let Some(log) = &mut self.audit {
+            let timestamp = match self.seed {
+                #[cfg(feature = "std")]
+                None => SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_millis() as u64),
+                _ => log.len() as u64,
+            };
+            log.push(AuditRecord {
+                timestamp,
+                op,
+                result: result
+                    .as_ref()
+                    .map(|_| ())
+                    .map_err(|err| err.message.clone()),
+            });
+        }
+    }
+
+    /// Re-apply recorded operations, failing at the first one whose outcome
+    /// differs from the recorded one.
+    pub fn replay(&mut self, records: &[AuditRecord]) -> Result<(), VfsError> {
+        for (i, record) in records.iter().enumerate() {
+            let result = self.apply_op(&record.op);
+            if result.is_ok() != record.result.is_ok() {
+                let message = format!(
+                    "replay: record {} '{}' diverged: expected {}, got {}",
+                    i + 1,
+                    record.op,
+                    record
+                        .result
+                        .as_ref()
+                        .err()
+                        .map_or("success", String::as_str),
+                    result.as_ref().err().map_or("success", |err| &err.message),
+                );
+                return Err(VfsError::new(ErrorKind::Other, message));
+            }
+        }
+        Ok(())
+    }
+}
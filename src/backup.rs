@@ -0,0 +1,252 @@
+use std::{
+    fmt,
+    io::{Read, Write},
+};
+
+use crate::{
+    disk::{Inode, Layout, INCOMPAT_SUPPORTED},
+    error::host_error,
+    image::{put_u64, Reader},
+    ErrorKind, FileType, Vfs, VfsError, BLOCK_SIZE,
+};
+
+const MAGIC: &[u8; 4] = b"VFSB";
+const VERSION: u32 = 5;
+
+const KIND_FULL: u8 = 0;
+const KIND_INCREMENTAL: u8 = 1;
+
+const TAG_END: u8 = 0;
+const TAG_INODE: u8 = 1;
+const TAG_REMOVED: u8 = 2;
+const TAG_BLOCK: u8 = 3;
+
+#[derive(Debug, Default)]
+pub struct BackupStats {
+    pub inodes: usize,
+    pub removed: usize,
+    pub blocks: usize,
+}
+
+impl fmt::Display for BackupStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Inodes: {} \tRemoved: {} \tBlocks: {}",
+            self.inodes, self.removed, self.blocks
+        )
+    }
+}
+
+fn capture(cmd: &str, vfs: &Vfs) -> Result<Layout, VfsError> {
+    Layout::capture(vfs).map_err(|block_ref| {
+        VfsError::new(
+            ErrorKind::DataCorruption,
+            format!(
+                "{}: cannot read block {}: Data corruption detected",
+                cmd, block_ref
+            ),
+        )
+    })
+}
+
+impl Vfs {
+    /// Write a backup of every inode and block, restorable onto any
+    /// filesystem with `apply_incremental`.
+    pub fn backup<W: Write>(&self, writer: W) -> Result<BackupStats, VfsError> {
+        self.write_backup(None, writer)
+    }
+
+    /// Write a backup of only the inodes and blocks changed since `base`,
+    /// an earlier snapshot of this filesystem taken with `clone`. It can
+    /// only be applied onto a filesystem in the state of `base`.
+    pub fn backup_incremental<W: Write>(
+        &self,
+        base: &Vfs,
+        writer: W,
+    ) -> Result<BackupStats, VfsError> {
+        self.write_backup(Some(base), writer)
+    }
+
+    fn write_backup<W: Write>(
+        &self,
+        base: Option<&Vfs>,
+        mut writer: W,
+    ) -> Result<BackupStats, VfsError> {
+        let layout = capture("backup", self)?;
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        let old = match base {
+            Some(base) => {
+                let old = capture("backup", base)?;
+                out.push(KIND_INCREMENTAL);
+                out.extend_from_slice(&old.digest());
+                old
+            }
+            None => {
+                out.push(KIND_FULL);
+                Layout::default()
+            }
+        };
+        let mut stats = BackupStats::default();
+        for (&id, inode) in &layout.inodes {
+            if old.inodes.get(&id) != Some(inode) {
+                out.push(TAG_INODE);
+                put_u64(&mut out, id);
+                inode.encode(&mut out);
+                stats.inodes += 1;
+            }
+        }
+        for &id in old.inodes.keys() {
+            if !layout.inodes.contains_key(&id) {
+                out.push(TAG_REMOVED);
+                put_u64(&mut out, id);
+                stats.removed += 1;
+            }
+        }
+        for (&id, plain) in &layout.blocks {
+            if old.blocks.get(&id) != Some(plain) {
+                out.push(TAG_BLOCK);
+                put_u64(&mut out, id);
+                out.extend_from_slice(plain);
+                stats.blocks += 1;
+            }
+        }
+        out.push(TAG_END);
+        out.extend_from_slice(&layout.digest());
+        writer
+            .write_all(&out)
+            .map_err(|err| host_error("backup: cannot write backup".to_string(), &err))?;
+        Ok(stats)
+    }
+
+    /// Restore a backup written by `backup` or `backup_incremental`,
+    /// replacing the whole tree. Device nodes and generated files are not
+    /// part of backups and stay where they are if their directory still
+    /// exists. Like `mknod`, this is not recorded by the audit log.
+    pub fn apply_incremental<R: Read>(&mut self, mut reader: R) -> Result<BackupStats, VfsError> {
+        let context = "restore: cannot restore backup";
+        self.check_writable(|| context.to_string())?;
+        if !self.open_fds.is_empty() || !self.mounts.is_empty() || !self.binds.is_empty() {
+            return Err(VfsError::new(
+                ErrorKind::Busy,
+                format!("{}: Device or resource busy", context),
+            ));
+        }
+        let mut data = Vec::new();
+        reader
+            .read_to_end(&mut data)
+            .map_err(|err| host_error(context.to_string(), &err))?;
+        let truncated = || {
+            VfsError::new(
+                ErrorKind::DataCorruption,
+                format!("{}: truncated backup", context),
+            )
+        };
+        let invalid = |reason: String| {
+            VfsError::new(ErrorKind::InvalidInput, format!("{}: {}", context, reason))
+        };
+        let corrupted = || {
+            VfsError::new(
+                ErrorKind::DataCorruption,
+                format!("{}: corrupted backup", context),
+            )
+        };
+        let mut reader = Reader::new(&data);
+        if reader.take(MAGIC.len()) != Some(MAGIC) {
+            return Err(invalid("not a backup".to_string()));
+        }
+        match reader.u32().ok_or_else(truncated)? {
+            VERSION => {}
+            version => return Err(invalid(format!("unsupported backup version {}", version))),
+        }
+        let current = capture("restore", self)?;
+        let mut layout = match reader.u8().ok_or_else(truncated)? {
+            KIND_FULL => Layout {
+                limit: current.limit,
+                inode_limit: current.inode_limit,
+                compat: current.compat,
+                ..Default::default()
+            },
+            KIND_INCREMENTAL => {
+                let base = reader.take(32).ok_or_else(truncated)?;
+                if base != current.digest() {
+                    return Err(invalid(
+                        "backup was taken against another state".to_string(),
+                    ));
+                }
+                current
+            }
+            _ => return Err(invalid("not a backup".to_string())),
+        };
+        let mut stats = BackupStats::default();
+        let target = loop {
+            match reader.u8().ok_or_else(truncated)? {
+                TAG_INODE => {
+                    let id = reader.u64().ok_or_else(truncated)?;
+                    let inode =
+                        Inode::decode(&mut reader, INCOMPAT_SUPPORTED).ok_or_else(truncated)?;
+                    layout.inodes.insert(id, inode);
+                    stats.inodes += 1;
+                }
+                TAG_REMOVED => {
+                    let id = reader.u64().ok_or_else(truncated)?;
+                    layout.inodes.remove(&id);
+                    stats.removed += 1;
+                }
+                TAG_BLOCK => {
+                    let id = reader.u64().ok_or_else(truncated)?;
+                    let plain = reader.take(BLOCK_SIZE).ok_or_else(truncated)?;
+                    layout.blocks.insert(id, plain.to_vec());
+                    stats.blocks += 1;
+                }
+                TAG_END => break reader.take(32).ok_or_else(truncated)?,
+                _ => return Err(corrupted()),
+            }
+        };
+        layout.collect_garbage();
+        if !reader.is_empty() || target != layout.digest() || layout.validate().is_err() {
+            return Err(corrupted());
+        }
+
+        let paths = self.tree_paths();
+        let live: Vec<_> = paths
+            .iter()
+            .filter(|(_, id)| {
+                matches!(
+                    self.fds[*id].file_type,
+                    FileType::Device(_) | FileType::Proc(_)
+                )
+            })
+            .map(|(path, id)| (path.clone(), self.fds[*id].clone()))
+            .collect();
+        let proc_fds = self
+            .proc_fds
+            .and_then(|fds_id| paths.iter().find(|(_, id)| *id == fds_id))
+            .map(|(path, _)| path.clone());
+        self.install(layout)
+            .map_err(|err| VfsError::new(err.kind, format!("{}: {}", context, err)))?;
+        for (path, fd) in live {
+            let basename = Vfs::basename(&path);
+            let dir_id = match self.resolve(&Vfs::dirname(&path)) {
+                Some((dir, id, _))
+                    if dir.file_type.is_dir()
+                        && !dir.file_type.as_dir().contains_key(&basename) =>
+                {
+                    id
+                }
+                _ => continue,
+            };
+            let id = self.alloc_fd(|_| fd);
+            self.fds[dir_id]
+                .file_type
+                .as_dir_mut()
+                .insert(basename.to_string(), id);
+        }
+        self.proc_fds = proc_fds
+            .and_then(|path| self.resolve(&path))
+            .filter(|(fd, _, _)| fd.file_type.is_dir())
+            .map(|(_, id, _)| id);
+        Ok(stats)
+    }
+}
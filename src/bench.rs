@@ -0,0 +1,211 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::{format_size, Device, Urandom, Vfs, VfsError};
+
+const CHUNK: usize = 4096;
+const ROOT: &str = "/.bench";
+const FILE: &str = "/.bench/file";
+const LOOKUPS: usize = 1000;
+
+/// A synthetic workload, shared by the `bench` command and the benchmark
+/// suite so that both measure the same thing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Workload {
+    /// Write a file of this many bytes from the start, a chunk at a time.
+    SeqWrite(usize),
+    /// Read a file of this many bytes from the start, a chunk at a time.
+    SeqRead(usize),
+    /// Overwrite as many chunks as a file of this many bytes holds, at
+    /// pseudo-random chunk-aligned offsets.
+    RandWrite(usize),
+    /// Read as many chunks as a file of this many bytes holds, at
+    /// pseudo-random chunk-aligned offsets.
+    RandRead(usize),
+    /// Look up a file this many directories deep.
+    DeepPath(usize),
+    /// Create and then remove this many files in one directory.
+    DirChurn(usize),
+}
+
+impl fmt::Display for Workload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Workload::SeqWrite(size) => write!(f, "seq-write {}", format_size(*size)),
+            Workload::SeqRead(size) => write!(f, "seq-read {}", format_size(*size)),
+            Workload::RandWrite(size) => write!(f, "rand-write {}", format_size(*size)),
+            Workload::RandRead(size) => write!(f, "rand-read {}", format_size(*size)),
+            Workload::DeepPath(depth) => write!(f, "deep-path {}", depth),
+            Workload::DirChurn(count) => write!(f, "dir-churn {}", count),
+        }
+    }
+}
+
+impl Workload {
+    /// The workloads run by default.
+    pub fn standard() -> Vec<Workload> {
+        vec![
+            Workload::SeqWrite(64 * 1024),
+            Workload::SeqWrite(4 * 1024 * 1024),
+            Workload::SeqRead(64 * 1024),
+            Workload::SeqRead(4 * 1024 * 1024),
+            Workload::RandWrite(4 * 1024 * 1024),
+            Workload::RandRead(4 * 1024 * 1024),
+            Workload::DeepPath(32),
+            Workload::DirChurn(1000),
+        ]
+    }
+
+    /// Bytes of file data one run moves, 0 for metadata workloads.
+    pub fn bytes(&self) -> usize {
+        match self {
+            Workload::SeqWrite(size)
+            | Workload::SeqRead(size)
+            | Workload::RandWrite(size)
+            | Workload::RandRead(size) => size.next_multiple_of(CHUNK),
+            Workload::DeepPath(_) | Workload::DirChurn(_) => 0,
+        }
+    }
+
+    fn deep_path(depth: usize) -> String {
+        format!("{}{}/file", ROOT, "/d".repeat(depth))
+    }
+
+    /// Create what `run` needs under a scratch directory of `vfs`.
+    pub fn prepare(&self, vfs: &mut Vfs) -> Result<(), VfsError> {
+        vfs.mkdir(ROOT)?;
+        match *self {
+            Workload::SeqWrite(_) => vfs.create(FILE)?,
+            Workload::SeqRead(size) | Workload::RandWrite(size) | Workload::RandRead(size) => {
+                vfs.write_file(FILE, &vec![0xa5; size.next_multiple_of(CHUNK)])?
+            }
+            Workload::DeepPath(depth) => {
+                let mut dirname = ROOT.to_string();
+                for _ in 0..depth {
+                    dirname.push_str("/d");
+                    vfs.mkdir(&dirname)?;
+                }
+                vfs.create(&Workload::deep_path(depth))?
+            }
+            Workload::DirChurn(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Run the workload on a filesystem set up by `prepare`, returning the
+    /// number of operations performed.
+    pub fn run(&self, vfs: &mut Vfs) -> Result<usize, VfsError> {
+        let chunks = self.bytes() / CHUNK;
+        match *self {
+            Workload::SeqWrite(_) | Workload::SeqRead(_) => {
+                let oid = vfs.open(FILE)?;
+                let chunk = [0x5a; CHUNK];
+                for _ in 0..chunks {
+                    if let Workload::SeqWrite(_) = self {
+                        vfs.write_all(oid, &chunk)?;
+                    } else {
+                        vfs.read(oid, CHUNK)?;
+                    }
+                }
+                vfs.close(oid)?;
+                Ok(chunks)
+            }
+            Workload::RandWrite(_) | Workload::RandRead(_) => {
+                let oid = vfs.open(FILE)?;
+                let chunk = [0x5a; CHUNK];
+                let mut random = Urandom::with_seed(chunks as u64);
+                for _ in 0..chunks {
+                    let bytes: [u8; 8] = random.read(8).try_into().unwrap();
+                    let offset = (u64::from_le_bytes(bytes) as usize % chunks) * CHUNK;
+                    vfs.seek(oid, offset)?;
+                    if let Workload::RandWrite(_) = self {
+                        vfs.write_all(oid, &chunk)?;
+                    } else {
+                        vfs.read(oid, CHUNK)?;
+                    }
+                }
+                vfs.close(oid)?;
+                Ok(chunks)
+            }
+            Workload::DeepPath(depth) => {
+                let pathname = Workload::deep_path(depth);
+                for _ in 0..LOOKUPS {
+                    vfs.stat(&pathname)?;
+                }
+                Ok(LOOKUPS)
+            }
+            Workload::DirChurn(count) => {
+                for i in 0..count {
+                    vfs.create(&format!("{}/{}", ROOT, i))?;
+                }
+                for i in 0..count {
+                    vfs.unlink(&format!("{}/{}", ROOT, i))?;
+                }
+                Ok(count * 2)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub workload: Workload,
+    pub ops: usize,
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    /// Bytes per second for data workloads, operations per second
+    /// otherwise.
+    pub fn rate(&self) -> f64 {
+        let done = match self.workload.bytes() {
+            0 => self.ops,
+            bytes => bytes,
+        };
+        done as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rate = match self.workload.bytes() {
+            0 => format!("{:.0} ops/s", self.rate()),
+            _ => format!("{}/s", format_size(self.rate() as usize)),
+        };
+        write!(
+            f,
+            "{:<16} {:>8} {:>12.3?} {:>14}",
+            self.workload.to_string(),
+            self.ops,
+            self.elapsed,
+            rate
+        )
+    }
+}
+
+impl Vfs {
+    /// Time `workloads` on copies of this filesystem, so that its block
+    /// limit, encryption, deduplication and throttle apply while its
+    /// contents stay untouched.
+    pub fn bench(&self, workloads: &[Workload]) -> Result<Vec<BenchResult>, VfsError> {
+        let mut results = Vec::new();
+        for &workload in workloads {
+            let mut vfs = self.clone();
+            vfs.set_read_only(false);
+            vfs.audit = None;
+            let error =
+                |err: VfsError| VfsError::new(err.kind, format!("bench: {}: {}", workload, err));
+            workload.prepare(&mut vfs).map_err(error)?;
+            let start = Instant::now();
+            let ops = workload.run(&mut vfs).map_err(error)?;
+            results.push(BenchResult {
+                workload,
+                ops,
+                elapsed: start.elapsed(),
+            });
+        }
+        Ok(results)
+    }
+}
@@ -0,0 +1,272 @@
+use std::{fmt, str::FromStr};
+
+use crate::{ErrorKind, Op, Vfs, VfsError, DOT, DOTDOT};
+
+/// A directory made to appear at a second place in the tree: resolving
+/// `target` leads to the inodes of `source` instead of those of the
+/// directory that was there, which stay hidden until it is unmounted.
+#[derive(Debug, Clone)]
+pub(crate) struct Bind {
+    pub(crate) source: String,
+    pub(crate) target: String,
+    pub(crate) source_id: usize,
+    pub(crate) target_id: usize,
+    pub(crate) options: BindOptions,
+}
+
+/// How a directory is bound, as `mount --bind` and `mount --rbind` take
+/// it, e.g. `rbind,ro`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BindOptions {
+    /// Also carry over the bind mounts below the source; otherwise their
+    /// hidden directories show through.
+    pub recursive: bool,
+    /// Refuse changes made through the target with `Read-only file
+    /// system`; the source stays writable.
+    pub read_only: bool,
+}
+
+impl fmt::Display for BindOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", if self.recursive { "rbind" } else { "bind" })?;
+        if self.read_only {
+            write!(f, ",ro")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for BindOptions {
+    type Err = String;
+
+    fn from_str(options: &str) -> Result<Self, Self::Err> {
+        let mut parsed = BindOptions::default();
+        for option in options.split(',') {
+            match option {
+                "bind" => parsed.recursive = false,
+                "rbind" => parsed.recursive = true,
+                "ro" => parsed.read_only = true,
+                "rw" => parsed.read_only = false,
+                _ => return Err(format!("invalid bind option: '{}'", option)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+impl Vfs {
+    pub fn bind_mount(&mut self, source: &str, target: &str) -> Result<(), VfsError> {
+        self.bind_mount_with(source, target, BindOptions::default())
+    }
+
+    /// Make directory `source` appear at directory `target` as well, like
+    /// `mount --bind`: both paths lead to the same inodes, and whatever
+    /// was in `target` is hidden until `unmount(target)`. Binds are kept
+    /// in memory, not in images.
+    pub fn bind_mount_with(
+        &mut self,
+        source: &str,
+        target: &str,
+        options: BindOptions,
+    ) -> Result<(), VfsError> {
+        let result = self.bind_mount_unaudited(source, target, options);
+        self.audit(
+            || Op::Bind {
+                source: source.to_string(),
+                target: target.to_string(),
+                options,
+            },
+            &result,
+        );
+        result
+    }
+
+    fn bind_mount_unaudited(
+        &mut self,
+        source: &str,
+        target: &str,
+        options: BindOptions,
+    ) -> Result<(), VfsError> {
+        let context = || format!("mount: cannot bind '{}' on '{}'", source, target);
+        self.check_writable(context)?;
+        let source_id = self.bind_dir(source, context)?;
+        let source = self.realpath(source).unwrap();
+        self.add_bind(source, source_id, target, options, context)
+    }
+
+    fn bind_dir<F>(&self, pathname: &str, context: F) -> Result<usize, VfsError>
+    where
+        F: FnOnce() -> String,
+    {
+        match self.resolve(&format!("{}/{}", pathname, DOT)) {
+            Some((fd, id, _)) if fd.file_type.is_dir() => Ok(id),
+            Some(_) => Err(VfsError::new(
+                ErrorKind::NotADirectory,
+                format!("{}: Not a directory", context()),
+            )),
+            None => Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!("{}: No such file or directory", context()),
+            )),
+        }
+    }
+
+    /// Bind directory `source_id`, known as `source`, on directory
+    /// `target`.
+    pub(crate) fn add_bind<F>(
+        &mut self,
+        source: String,
+        source_id: usize,
+        target: &str,
+        options: BindOptions,
+        context: F,
+    ) -> Result<(), VfsError>
+    where
+        F: FnOnce() -> String + Copy,
+    {
+        let target_id = self.bind_dir(target, context)?;
+        let busy = target_id == 0
+            || self.is_proc_fds(target_id)
+            || self.binds.iter().any(|bind| bind.target_id == target_id)
+            || self.mounts.iter().any(|mount| mount.root_id == target_id);
+        if busy {
+            return Err(VfsError::new(
+                ErrorKind::Busy,
+                format!("{}: Device or resource busy", context()),
+            ));
+        }
+        // A recursive bind below its own source would lead into itself
+        // forever.
+        if options.recursive && self.is_ancestor(source_id, target_id) {
+            return Err(VfsError::new(
+                ErrorKind::InvalidInput,
+                format!("{}: Invalid argument", context()),
+            ));
+        }
+        let target = self.realpath(target).unwrap();
+        self.binds.push(Bind {
+            source,
+            target,
+            source_id,
+            target_id,
+            options,
+        });
+        Ok(())
+    }
+
+    /// Remove the bind mount at `target`, which `unmount` does for paths
+    /// that are bind mount targets. Returns `None` if there is none.
+    pub(crate) fn unbind(&mut self, target: &str) -> Option<Result<(), VfsError>> {
+        let realpath = self.realpath(target);
+        let idx = self
+            .binds
+            .iter()
+            .rposition(|bind| Some(&bind.target) == realpath.as_ref())?;
+        let prefix = format!("{}/", self.binds[idx].target.trim_end_matches('/'));
+        let busy = format!("{}/", self.session.cwd).starts_with(&prefix)
+            || self.binds.iter().any(|other| {
+                other.target.starts_with(&prefix) || other.source.starts_with(&prefix)
+            })
+            || self
+                .mounts
+                .iter()
+                .any(|mount| mount.mountpoint.starts_with(&prefix));
+        if busy {
+            return Some(Err(VfsError::new(
+                ErrorKind::Busy,
+                format!(
+                    "umount: cannot unmount '{}': Device or resource busy",
+                    target
+                ),
+            )));
+        }
+        self.binds.remove(idx);
+        Some(Ok(()))
+    }
+
+    /// The bind mounts as source, target and options, oldest first.
+    pub fn binds(&self) -> impl Iterator<Item = (&str, &str, BindOptions)> {
+        self.binds
+            .iter()
+            .map(|bind| (bind.source.as_str(), bind.target.as_str(), bind.options))
+    }
+
+    /// Whether directory `id` is the source or the target of a bind mount,
+    /// so that it cannot be removed or moved.
+    pub(crate) fn is_bound(&self, id: usize) -> bool {
+        self.binds
+            .iter()
+            .any(|bind| bind.source_id == id || bind.target_id == id)
+    }
+
+    /// Whether a bind mount source or target is below the absolute path
+    /// `prefix`, which ends with a separator.
+    pub(crate) fn has_binds_below(&self, prefix: &str) -> bool {
+        self.binds
+            .iter()
+            .any(|bind| bind.source.starts_with(prefix) || bind.target.starts_with(prefix))
+    }
+
+    /// The bind mount to follow on entering directory `id`, given the
+    /// ones `passed` on the way there, innermost last.
+    pub(crate) fn bind_at<'a>(&'a self, id: usize, passed: &[&Bind]) -> Option<&'a Bind> {
+        if !passed.iter().all(|bind| bind.options.recursive) {
+            return None;
+        }
+        self.binds.iter().rev().find(|bind| bind.target_id == id)
+    }
+
+    /// The parent of directory `id`: the parent of the target of the bind
+    /// mount it was reached through if it is that bind's source, and its
+    /// `..` entry otherwise. The bind is popped off `passed` then.
+    pub(crate) fn bind_parent(&self, id: usize, passed: &mut Vec<&Bind>) -> usize {
+        match passed.last() {
+            Some(bind) if bind.source_id == id => {
+                let parent_id = self.fds[bind.target_id].file_type.as_dir()[DOTDOT];
+                passed.pop();
+                parent_id
+            }
+            _ => self.fds[id].file_type.as_dir()[DOTDOT],
+        }
+    }
+
+    /// `pathname` made absolute if bind mounts might make the inode of the
+    /// working directory lead elsewhere than its path does.
+    pub(crate) fn bind_path(&self, pathname: &str) -> Option<String> {
+        (!self.binds.is_empty() && !Vfs::is_absolute(pathname))
+            .then(|| format!("{}/{}", self.session.cwd, pathname))
+    }
+
+    /// Like `check_writable`, also failing with `Read-only file system` if
+    /// `pathname` or its directory is reached through a read-only bind
+    /// mount.
+    pub(crate) fn check_writable_at<F>(&self, pathname: &str, context: F) -> Result<(), VfsError>
+    where
+        F: FnOnce() -> String + Copy,
+    {
+        self.check_writable(context)?;
+        self.check_bind_writable(pathname, context)
+    }
+
+    pub(crate) fn check_bind_writable<F>(&self, pathname: &str, context: F) -> Result<(), VfsError>
+    where
+        F: FnOnce() -> String,
+    {
+        if self.binds.iter().any(|bind| bind.options.read_only)
+            && [pathname.to_string(), Vfs::dirname(pathname)]
+                .iter()
+                .any(|pathname| self.is_read_only_bind_path(pathname))
+        {
+            return Err(VfsError::new(
+                ErrorKind::ReadOnly,
+                format!("{}: Read-only file system", context()),
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_read_only_bind_path(&self, pathname: &str) -> bool {
+        self.resolve_bound(pathname)
+            .is_some_and(|(.., read_only)| read_only)
+    }
+}
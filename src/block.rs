@@ -0,0 +1,783 @@
+#[cfg(feature = "std")]
+use std::sync::RwLockWriteGuard;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Deref,
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard},
+};
+
+use chacha20poly1305::{
+    aead::{AeadCore, AeadInPlace, KeyInit, OsRng},
+    Tag, XChaCha20Poly1305, XNonce,
+};
+
+use crate::{Device, ErrorKind, Urandom, VfsError, BLOCK_SIZE};
+
+const ZERO_BLOCK: [u8; BLOCK_SIZE] = [0; BLOCK_SIZE];
+const NONCE_SIZE: usize = 24;
+const TAG_SIZE: usize = 16;
+/// Size of an encrypted block as stored in images: nonce, tag and
+/// ciphertext.
+pub(crate) const SEALED_SIZE: usize = NONCE_SIZE + TAG_SIZE + BLOCK_SIZE;
+/// Blocks per page of block data; pages are shared between clones of a
+/// store and copied on the first write, see `Vfs::fork`.
+const PAGE_BLOCKS: usize = 64;
+
+/// A page of zeroes, shared by every page added at once until written.
+fn zero_page() -> Arc<Vec<u8>> {
+    Arc::new(vec![0; PAGE_BLOCKS * BLOCK_SIZE])
+}
+
+/// The blocks of a page as stored and their checksums.
+#[derive(Debug, Clone)]
+struct PageData {
+    data: Arc<Vec<u8>>,
+    checksums: [u32; PAGE_BLOCKS],
+}
+
+impl PageData {
+    #[cfg(feature = "std")]
+    fn block(&self, id: usize) -> &[u8] {
+        let at = id % PAGE_BLOCKS * BLOCK_SIZE;
+        &self.data[at..at + BLOCK_SIZE]
+    }
+
+    fn store(&mut self, id: usize, block: &[u8]) {
+        let at = id % PAGE_BLOCKS * BLOCK_SIZE;
+        Arc::make_mut(&mut self.data)[at..at + BLOCK_SIZE].copy_from_slice(block);
+        self.checksums[id % PAGE_BLOCKS] = crc32fast::hash(block);
+    }
+}
+
+/// A page behind its own lock, so that `BlockStore::overwrite` can change
+/// blocks of different pages at once; with `&mut BlockStore` the lock is
+/// bypassed.
+#[derive(Debug)]
+struct Page(RwLock<PageData>);
+
+/// The plaintext of a block as `BlockStore::read` returns it: the stored
+/// bytes, kept alive by the page after its lock is released since writers
+/// copy a page that is still referenced, or a decrypted copy.
+#[derive(Debug, Clone)]
+pub(crate) enum Plain {
+    Zero(usize),
+    Stored {
+        page: Arc<Vec<u8>>,
+        at: usize,
+        len: usize,
+    },
+    Owned(Vec<u8>),
+}
+
+impl Plain {
+    pub(crate) fn truncate(&mut self, n: usize) {
+        match self {
+            Plain::Zero(len) | Plain::Stored { len, .. } => *len = n.min(*len),
+            Plain::Owned(block) => block.truncate(n),
+        }
+    }
+}
+
+impl Deref for Plain {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Plain::Zero(len) => &ZERO_BLOCK[..*len],
+            Plain::Stored { page, at, len } => &page[*at..*at + *len],
+            Plain::Owned(block) => block,
+        }
+    }
+}
+
+impl AsRef<[u8]> for Plain {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Page {
+    fn zeroed() -> Self {
+        Self(RwLock::new(PageData {
+            data: zero_page(),
+            checksums: [crc32fast::hash(&ZERO_BLOCK); PAGE_BLOCKS],
+        }))
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, PageData> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[cfg(feature = "std")]
+    fn write(&self) -> RwLockWriteGuard<'_, PageData> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn get_mut(&mut self) -> &mut PageData {
+        self.0.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Clone for Page {
+    fn clone(&self) -> Self {
+        Self(RwLock::new(self.read().clone()))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct DedupIndex {
+    index: HashMap<u64, Vec<usize>>,
+    hashes: HashMap<usize, u64>,
+}
+
+impl DedupIndex {
+    fn insert(&mut self, id: usize, hash: u64) {
+        self.index.entry(hash).or_default().push(id);
+        self.hashes.insert(id, hash);
+    }
+
+    fn remove(&mut self, id: usize) {
+        if let Some(hash) = self.hashes.remove(&id) {
+            if let Some(ids) = self.index.get_mut(&hash) {
+                ids.retain(|&i| i != id);
+                if ids.is_empty() {
+                    self.index.remove(&hash);
+                }
+            }
+        }
+    }
+}
+
+/// Block id that would make block `i` of a file contiguous with the
+/// closest allocated block before it.
+pub(crate) fn hint(blocks_refs: &[usize], i: usize) -> Option<usize> {
+    blocks_refs[..i]
+        .iter()
+        .rposition(|&id| id != 0)
+        .map(|j| blocks_refs[j] + (i - j))
+}
+
+/// Allocation bitmap of the block store; bit `i` is set while block `i`
+/// is in use.
+#[derive(Debug, Clone)]
+struct Bitmap {
+    words: Vec<u64>,
+}
+
+impl Bitmap {
+    fn new(count: usize) -> Self {
+        Self {
+            words: vec![0; count.div_ceil(64)],
+        }
+    }
+
+    fn resize(&mut self, count: usize) {
+        self.words.resize(count.div_ceil(64), 0);
+    }
+
+    fn set(&mut self, id: usize) {
+        self.words[id / 64] |= 1 << (id % 64);
+    }
+
+    fn clear(&mut self, id: usize) {
+        self.words[id / 64] &= !(1 << (id % 64));
+    }
+
+    /// First clear bit in `from..to`, skipping full words at a time.
+    fn find_free(&self, from: usize, to: usize) -> Option<usize> {
+        let mut id = from;
+        while id < to {
+            let word = self.words[id / 64] | ((1 << (id % 64)) - 1);
+            if word != u64::MAX {
+                let free = id / 64 * 64 + (!word).trailing_zeros() as usize;
+                return (free < to).then_some(free);
+            }
+            id = (id / 64 + 1) * 64;
+        }
+        None
+    }
+}
+
+#[derive(Clone)]
+struct Encryption {
+    cipher: XChaCha20Poly1305,
+    nonces: Vec<XNonce>,
+    tags: Vec<Tag>,
+    rng: Option<Urandom>,
+}
+
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Encryption").finish_non_exhaustive()
+    }
+}
+
+impl Encryption {
+    fn new(key: &[u8; 32], count: usize) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(key.into()),
+            nonces: vec![XNonce::default(); count],
+            tags: vec![Tag::default(); count],
+            rng: None,
+        }
+    }
+
+    fn resize(&mut self, count: usize) {
+        self.nonces.resize(count, XNonce::default());
+        self.tags.resize(count, Tag::default());
+    }
+
+    fn encrypt(&mut self, id: usize, block: &mut [u8]) {
+        let nonce = match &mut self.rng {
+            Some(rng) => XNonce::clone_from_slice(&rng.read(NONCE_SIZE)),
+            None => XChaCha20Poly1305::generate_nonce(&mut OsRng),
+        };
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&nonce, &id.to_le_bytes(), block)
+            .expect("error: block encryption failed");
+        self.nonces[id] = nonce;
+        self.tags[id] = tag;
+    }
+
+    fn decrypt(&self, id: usize, block: &mut [u8]) -> bool {
+        self.cipher
+            .decrypt_in_place_detached(&self.nonces[id], &id.to_le_bytes(), block, &self.tags[id])
+            .is_ok()
+    }
+}
+
+/// Why `BlockStore::update` or `BlockStore::reserve` left a block
+/// untouched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UpdateError {
+    /// No block was left to allocate.
+    NoSpace,
+    /// The block failed verification.
+    Corrupted(usize),
+}
+
+impl UpdateError {
+    /// The error of the call that failed to update a block, after
+    /// `context`.
+    pub(crate) fn into_error(self, context: String) -> VfsError {
+        match self {
+            UpdateError::NoSpace => VfsError::new(
+                ErrorKind::NoSpace,
+                format!("{}: No space left on device", context),
+            ),
+            UpdateError::Corrupted(id) => VfsError::new(
+                ErrorKind::DataCorruption,
+                format!("{}: Data corruption detected in block {}", context, id),
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DedupStats {
+    pub logical_blocks: usize,
+    pub physical_blocks: usize,
+    pub saved_bytes: usize,
+}
+
+impl fmt::Display for DedupStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Logical: {} \tPhysical: {} \tSaved: {} bytes",
+            self.logical_blocks, self.physical_blocks, self.saved_bytes
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct ScrubReport {
+    pub checked: usize,
+    pub corrupted: Vec<usize>,
+}
+
+impl fmt::Display for ScrubReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Checked: {} \tCorrupted: {}",
+            self.checked,
+            self.corrupted.len()
+        )?;
+        for id in &self.corrupted {
+            write!(f, "\nblock {}: checksum mismatch", id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Block storage shared by all regular files.
+///
+/// Block `0` is never allocated and stays zeroed, so it doubles as the
+/// reference for sparse holes. Every other block carries a reference count,
+/// which lets the dedup mode share identical blocks between files and
+/// copy them on the next write, and a CRC32 checksum of the stored bytes
+/// verified on read. With an encryption key the stored bytes are
+/// ciphertext and only `read` and `update` ever see the plaintext.
+///
+/// Free blocks are tracked in a bitmap and allocated next to a hint, the
+/// block following the previous one of the same file, so files tend to
+/// stay contiguous; without a hint allocation continues after the last
+/// allocated block.
+#[derive(Debug, Clone)]
+pub(crate) struct BlockStore {
+    pages: Vec<Page>,
+    used: Bitmap,
+    cursor: usize,
+    refs: Vec<usize>,
+    dedup: Option<DedupIndex>,
+    encryption: Option<Encryption>,
+    limit: Option<usize>,
+    fail_alloc: Option<usize>,
+}
+
+impl BlockStore {
+    /// Create a store of `count` blocks that grows on demand, up to `limit`
+    /// blocks if given.
+    pub(crate) fn new(count: usize, key: Option<&[u8; 32]>, limit: Option<usize>) -> Self {
+        let count = limit.map_or(count, |limit| count.min(limit));
+        let mut used = Bitmap::new(count);
+        used.set(0);
+        Self {
+            pages: (0..count.div_ceil(PAGE_BLOCKS))
+                .map(|_| Page::zeroed())
+                .collect(),
+            used,
+            cursor: 1,
+            refs: vec![0; count],
+            dedup: None,
+            encryption: key.map(|key| Encryption::new(key, count)),
+            limit,
+            fail_alloc: None,
+        }
+    }
+
+    /// An empty store with the same key and limit.
+    pub(crate) fn emptied(&self) -> Self {
+        let mut blocks = BlockStore::new(1, None, self.limit);
+        blocks.encryption = self.encryption.as_ref().map(|encryption| Encryption {
+            cipher: encryption.cipher.clone(),
+            nonces: vec![XNonce::default()],
+            tags: vec![Tag::default()],
+            rng: encryption.rng.clone(),
+        });
+        blocks.fail_alloc = self.fail_alloc;
+        blocks
+    }
+
+    fn hash(block: &[u8]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        block.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Block `id` as stored, and its checksum.
+    fn stored(&self, id: usize) -> (Plain, u32) {
+        let page = self.pages[id / PAGE_BLOCKS].read();
+        let block = Plain::Stored {
+            page: Arc::clone(&page.data),
+            at: id % PAGE_BLOCKS * BLOCK_SIZE,
+            len: BLOCK_SIZE,
+        };
+        (block, page.checksums[id % PAGE_BLOCKS])
+    }
+
+    pub(crate) fn raw(&self, id: usize) -> Plain {
+        self.stored(id).0
+    }
+
+    pub(crate) fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Number of blocks the store currently holds, including free ones.
+    pub(crate) fn len(&self) -> usize {
+        self.refs.len()
+    }
+
+    pub(crate) fn refs(&self, id: usize) -> usize {
+        self.refs[id]
+    }
+
+    /// Number of allocatable blocks, excluding the reserved hole block.
+    pub(crate) fn capacity(&self) -> usize {
+        self.limit.unwrap_or(self.refs.len()) - 1
+    }
+
+    /// Number of blocks that can still be allocated, or `None` if the
+    /// store is unlimited.
+    pub(crate) fn available(&self) -> Option<usize> {
+        self.limit
+            .map(|_| self.capacity() - self.dedup_stats().physical_blocks)
+    }
+
+    pub(crate) fn raw_blocks(&self) -> impl Iterator<Item = Plain> + '_ {
+        (0..self.refs.len()).map(|id| self.raw(id))
+    }
+
+    fn store(&mut self, id: usize, mut block: [u8; BLOCK_SIZE]) {
+        if let Some(encryption) = &mut self.encryption {
+            encryption.encrypt(id, &mut block);
+        }
+        self.pages[id / PAGE_BLOCKS].get_mut().store(id, &block);
+    }
+
+    /// Return the plaintext of a block, or `None` if it fails verification.
+    pub(crate) fn read(&self, id: usize) -> Option<Plain> {
+        if id == 0 {
+            return Some(Plain::Zero(BLOCK_SIZE));
+        }
+        let (block, checksum) = self.stored(id);
+        if crc32fast::hash(&block) != checksum {
+            return None;
+        }
+        match &self.encryption {
+            Some(encryption) => {
+                let mut block = block.to_vec();
+                encryption
+                    .decrypt(id, &mut block)
+                    .then_some(Plain::Owned(block))
+            }
+            None => Some(block),
+        }
+    }
+
+    /// Append the plaintext of the blocks `ids` of a file of `size` bytes
+    /// to `out`, copying them under the page locks rather than keeping
+    /// pages alive, and locking a page once for a run of its blocks. Fails
+    /// with the id of the first block that fails verification.
+    pub(crate) fn read_into(
+        &self,
+        ids: &[usize],
+        size: usize,
+        out: &mut Vec<u8>,
+    ) -> Result<(), usize> {
+        let mut page: Option<(usize, RwLockReadGuard<'_, PageData>)> = None;
+        for (i, &id) in ids.iter().take(size.div_ceil(BLOCK_SIZE)).enumerate() {
+            let n = (size - i * BLOCK_SIZE).min(BLOCK_SIZE);
+            if id == 0 {
+                out.extend_from_slice(&ZERO_BLOCK[..n]);
+                continue;
+            }
+            if self.encryption.is_some() {
+                out.extend_from_slice(&self.read(id).ok_or(id)?[..n]);
+                continue;
+            }
+            if page
+                .as_ref()
+                .is_none_or(|(index, _)| *index != id / PAGE_BLOCKS)
+            {
+                drop(page.take());
+                page = Some((id / PAGE_BLOCKS, self.pages[id / PAGE_BLOCKS].read()));
+            }
+            let (_, data) = page.as_ref().unwrap();
+            let at = id % PAGE_BLOCKS * BLOCK_SIZE;
+            let block = &data.data[at..at + BLOCK_SIZE];
+            if crc32fast::hash(block) != data.checksums[id % PAGE_BLOCKS] {
+                return Err(id);
+            }
+            out.extend_from_slice(&block[..n]);
+        }
+        Ok(())
+    }
+
+    /// Write `bytes` at `offset` into block `id` in place, through `&self`
+    /// under the lock of its page, for `SharedVfs`. Returns `None`, leaving
+    /// the block alone, unless the block belongs to one file alone and the
+    /// store neither encrypts nor deduplicates, since those need
+    /// `&mut self` to update nonces, the dedup index or reference counts;
+    /// the caller then goes through `update`.
+    #[cfg(feature = "std")]
+    pub(crate) fn overwrite(
+        &self,
+        id: usize,
+        offset: usize,
+        bytes: &[u8],
+    ) -> Option<Result<(), UpdateError>> {
+        if !self.is_overwritable(id) {
+            return None;
+        }
+        let mut page = self.pages[id / PAGE_BLOCKS].write();
+        let mut block = [0; BLOCK_SIZE];
+        block.copy_from_slice(page.block(id));
+        if crc32fast::hash(&block) != page.checksums[id % PAGE_BLOCKS] {
+            return Some(Err(UpdateError::Corrupted(id)));
+        }
+        block[offset..offset + bytes.len()].copy_from_slice(bytes);
+        page.store(id, &block);
+        Some(Ok(()))
+    }
+
+    /// Whether `overwrite` can write block `id`.
+    #[cfg(feature = "std")]
+    pub(crate) fn is_overwritable(&self, id: usize) -> bool {
+        id != 0 && self.refs[id] == 1 && self.encryption.is_none() && self.dedup.is_none()
+    }
+
+    fn grow(&mut self, count: usize) {
+        self.pages
+            .resize_with(count.div_ceil(PAGE_BLOCKS), Page::zeroed);
+        self.used.resize(count);
+        self.refs.resize(count, 0);
+        if let Some(encryption) = &mut self.encryption {
+            encryption.resize(count);
+        }
+    }
+
+    /// Allocate the first free block at or after `hint`, wrapping around
+    /// before growing the store.
+    fn alloc(&mut self, hint: Option<usize>) -> Option<usize> {
+        match self.fail_alloc {
+            Some(0) => {
+                self.fail_alloc = None;
+                return None;
+            }
+            Some(n) => self.fail_alloc = Some(n - 1),
+            None => {}
+        }
+        let count = self.refs.len();
+        let start = hint.unwrap_or(self.cursor).clamp(1, count);
+        let free = self
+            .used
+            .find_free(start, count)
+            .or_else(|| self.used.find_free(1, start));
+        let id = match free {
+            Some(id) => id,
+            None if self.limit.is_some_and(|limit| count >= limit) => return None,
+            None => {
+                self.grow(count + 1);
+                count
+            }
+        };
+        self.used.set(id);
+        self.refs[id] = 1;
+        self.cursor = id + 1;
+        Some(id)
+    }
+
+    /// Draw encryption nonces from a stream seeded with `seed` instead of
+    /// the operating system.
+    pub(crate) fn seed_nonces(&mut self, seed: u64) {
+        if let Some(encryption) = &mut self.encryption {
+            encryption.rng = Some(Urandom::with_seed(seed));
+        }
+    }
+
+    /// Flip the lowest bit of the first stored byte of block `id`, if it is
+    /// in use, without updating its checksum.
+    pub(crate) fn corrupt(&mut self, id: usize) {
+        if id == 0 || self.refs.get(id).is_none_or(|&refs| refs == 0) {
+            return;
+        }
+        let page = self.pages[id / PAGE_BLOCKS].get_mut();
+        let at = id % PAGE_BLOCKS * BLOCK_SIZE;
+        Arc::make_mut(&mut page.data)[at] ^= 1;
+    }
+
+    /// Make the `n`th allocation from now fail, counting from 1.
+    pub(crate) fn fail_alloc(&mut self, n: Option<usize>) {
+        self.fail_alloc = n.map(|n| n.saturating_sub(1));
+    }
+
+    /// Store the plaintext of block `id` with `refs` references while
+    /// loading an image; fails if the id is beyond the limit.
+    pub(crate) fn load(&mut self, id: usize, plain: &[u8], refs: usize) -> bool {
+        if self.limit.is_some_and(|limit| id >= limit) {
+            return false;
+        }
+        if id >= self.refs.len() {
+            self.grow(id + 1);
+        }
+        self.used.set(id);
+        self.refs[id] = refs;
+        let mut block = [0; BLOCK_SIZE];
+        block.copy_from_slice(plain);
+        self.store(id, block);
+        true
+    }
+
+    /// Whether blocks are stored encrypted.
+    pub(crate) fn is_encrypted(&self) -> bool {
+        self.encryption.is_some()
+    }
+
+    /// Return the nonce, tag and ciphertext of an encrypted block as
+    /// stored, or `None` if the store is not encrypted or the block fails
+    /// its checksum.
+    pub(crate) fn sealed(&self, id: usize) -> Option<Vec<u8>> {
+        let encryption = self.encryption.as_ref()?;
+        let (raw, checksum) = self.stored(id);
+        if crc32fast::hash(&raw) != checksum {
+            return None;
+        }
+        let mut sealed = Vec::with_capacity(SEALED_SIZE);
+        sealed.extend_from_slice(&encryption.nonces[id]);
+        sealed.extend_from_slice(&encryption.tags[id]);
+        sealed.extend_from_slice(&raw);
+        Some(sealed)
+    }
+
+    /// Like `load`, but for a block in the form returned by `sealed`,
+    /// stored as is. The block is not decrypted here: `read` fails on it if
+    /// it was sealed with another key.
+    pub(crate) fn load_sealed(&mut self, id: usize, sealed: &[u8], refs: usize) -> bool {
+        if self.limit.is_some_and(|limit| id >= limit) {
+            return false;
+        }
+        if id >= self.refs.len() {
+            self.grow(id + 1);
+        }
+        let Some(encryption) = &mut self.encryption else {
+            return false;
+        };
+        let (nonce, rest) = sealed.split_at(NONCE_SIZE);
+        let (tag, ciphertext) = rest.split_at(TAG_SIZE);
+        encryption.nonces[id] = XNonce::clone_from_slice(nonce);
+        encryption.tags[id] = Tag::clone_from_slice(tag);
+        self.used.set(id);
+        self.refs[id] = refs;
+        self.pages[id / PAGE_BLOCKS].get_mut().store(id, ciphertext);
+        true
+    }
+
+    pub(crate) fn release(&mut self, id: usize) {
+        if id == 0 {
+            return;
+        }
+        self.refs[id] -= 1;
+        if self.refs[id] == 0 {
+            if let Some(dedup) = &mut self.dedup {
+                dedup.remove(id);
+            }
+            self.used.clear(id);
+        }
+    }
+
+    /// Modify the plaintext of a block, returning the block id the file
+    /// should reference afterwards: holes get a fresh block, shared blocks
+    /// are copied and, in dedup mode, the result may be shared again.
+    /// Fails, leaving the block untouched, when no block is left or the
+    /// block fails verification. A new block is allocated near `hint` if
+    /// given.
+    pub(crate) fn update<F>(
+        &mut self,
+        id: usize,
+        hint: Option<usize>,
+        f: F,
+    ) -> Result<usize, UpdateError>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        let mut block = [0; BLOCK_SIZE];
+        block.copy_from_slice(&self.read(id).ok_or(UpdateError::Corrupted(id))?);
+        let id = if id == 0 || self.refs[id] > 1 {
+            let new_id = self.alloc(hint).ok_or(UpdateError::NoSpace)?;
+            self.release(id);
+            new_id
+        } else {
+            if let Some(dedup) = &mut self.dedup {
+                dedup.remove(id);
+            }
+            id
+        };
+        f(&mut block);
+        if self.dedup.is_none() {
+            self.store(id, block);
+            return Ok(id);
+        }
+        let hash = Self::hash(&block);
+        let dedup = self.dedup.as_ref().unwrap();
+        let duplicate = dedup.index.get(&hash).and_then(|ids| {
+            ids.iter().copied().find(|&other| {
+                other != id
+                    && self
+                        .read(other)
+                        .is_some_and(|plain| plain.as_ref() == block.as_slice())
+            })
+        });
+        match duplicate {
+            Some(other) => {
+                self.refs[other] += 1;
+                self.release(id);
+                Ok(other)
+            }
+            None => {
+                self.store(id, block);
+                self.dedup.as_mut().unwrap().insert(id, hash);
+                Ok(id)
+            }
+        }
+    }
+
+    /// Make block `id` private to one file, returning the block id the
+    /// file should reference afterwards: holes get a fresh zeroed block
+    /// and shared blocks are copied, so that the next `update` of it needs
+    /// no allocation. The copy is left out of the dedup index until it is
+    /// written. A shared block that fails verification is not copied.
+    pub(crate) fn reserve(&mut self, id: usize, hint: Option<usize>) -> Result<usize, UpdateError> {
+        if id != 0 && self.refs[id] == 1 {
+            return Ok(id);
+        }
+        let mut block = [0; BLOCK_SIZE];
+        block.copy_from_slice(&self.read(id).ok_or(UpdateError::Corrupted(id))?);
+        let new_id = self.alloc(hint).ok_or(UpdateError::NoSpace)?;
+        self.release(id);
+        self.store(new_id, block);
+        Ok(new_id)
+    }
+
+    pub(crate) fn scrub(&self) -> ScrubReport {
+        let mut checked = 0;
+        let mut corrupted = Vec::new();
+        for (id, &refs) in self.refs.iter().enumerate().skip(1) {
+            if refs > 0 {
+                checked += 1;
+                if self.read(id).is_none() {
+                    corrupted.push(id);
+                }
+            }
+        }
+        ScrubReport { checked, corrupted }
+    }
+
+    pub(crate) fn set_dedup(&mut self, enabled: bool) {
+        if !enabled {
+            self.dedup = None;
+            return;
+        }
+        if self.dedup.is_some() {
+            return;
+        }
+        let mut dedup = DedupIndex::default();
+        for (id, &refs) in self.refs.iter().enumerate().skip(1) {
+            if refs > 0 {
+                if let Some(plain) = self.read(id) {
+                    dedup.insert(id, Self::hash(&plain));
+                }
+            }
+        }
+        self.dedup = Some(dedup);
+    }
+
+    pub(crate) fn is_dedup(&self) -> bool {
+        self.dedup.is_some()
+    }
+
+    pub(crate) fn dedup_stats(&self) -> DedupStats {
+        let logical_blocks = self.refs.iter().sum();
+        let physical_blocks = self.refs.iter().filter(|&&refs| refs > 0).count();
+        DedupStats {
+            logical_blocks,
+            physical_blocks,
+            saved_bytes: (logical_blocks - physical_blocks) * BLOCK_SIZE,
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::{fmt, str::FromStr};
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::{op::hex, ErrorKind, Monitor, Vfs, VfsError, TRAILING_SEPARATOR};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algo {
+    Sha256,
+    Md5,
+}
+
+impl Algo {
+    /// Name of the coreutils command producing this kind of checksum.
+    pub fn command(&self) -> &'static str {
+        match self {
+            Algo::Sha256 => "sha256sum",
+            Algo::Md5 => "md5sum",
+        }
+    }
+}
+
+impl fmt::Display for Algo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Algo::Sha256 => write!(f, "sha256"),
+            Algo::Md5 => write!(f, "md5"),
+        }
+    }
+}
+
+impl FromStr for Algo {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "sha256" => Ok(Algo::Sha256),
+            "md5" => Ok(Algo::Md5),
+            _ => Err(format!("invalid hash algorithm: {}", name)),
+        }
+    }
+}
+
+/// Digest of a file's contents, displayed as lowercase hex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum(pub Vec<u8>);
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex(&self.0))
+    }
+}
+
+impl Vfs {
+    fn digest<D: Digest>(
+        &self,
+        cmd: &str,
+        pathname: &str,
+        monitor: &mut Monitor,
+    ) -> Result<Checksum, VfsError> {
+        let id = self.regular_file(cmd, pathname)?;
+        let mut hasher = D::new();
+        for chunk in self.file_chunks(id) {
+            let chunk = chunk.map_err(|block_ref| self.corrupted(cmd, pathname, block_ref))?;
+            hasher.update(&chunk);
+            monitor.advance(chunk.len(), 0, || format!("{}: {}", cmd, pathname))?;
+        }
+        monitor.advance(0, 1, || format!("{}: {}", cmd, pathname))?;
+        Ok(Checksum(hasher.finalize().to_vec()))
+    }
+
+    /// Hash the contents of a regular file block by block.
+    pub fn hash_file(&self, pathname: &str, algo: Algo) -> Result<Checksum, VfsError> {
+        self.hash_file_with(pathname, algo, &mut Monitor::new())
+    }
+
+    /// Like `hash_file`, reporting each block hashed to `monitor` and
+    /// stopping if it is cancelled.
+    pub fn hash_file_with(
+        &self,
+        pathname: &str,
+        algo: Algo,
+        monitor: &mut Monitor,
+    ) -> Result<Checksum, VfsError> {
+        match algo {
+            Algo::Sha256 => self.digest::<Sha256>(algo.command(), pathname, monitor),
+            Algo::Md5 => self.digest::<Md5>(algo.command(), pathname, monitor),
+        }
+    }
+
+    /// Hash every regular file below directory `pathname`, in path order.
+    /// With the `rayon` feature files are hashed in parallel.
+    pub fn hash_tree(
+        &self,
+        pathname: &str,
+        algo: Algo,
+    ) -> Result<Vec<(String, Checksum)>, VfsError> {
+        let id = match self.resolve(pathname) {
+            Some((fd, id, _)) if fd.file_type.is_dir() => id,
+            Some(_) => {
+                return Ok(vec![(
+                    pathname.to_string(),
+                    self.hash_file(pathname, algo)?,
+                )])
+            }
+            None => {
+                return Err(VfsError::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "{}: {}: No such file or directory",
+                        algo.command(),
+                        pathname
+                    ),
+                ))
+            }
+        };
+        let mut files: Vec<_> = self
+            .subtree_paths(id, pathname.trim_end_matches(TRAILING_SEPARATOR))
+            .into_iter()
+            .filter(|(_, id)| self.fds[*id].file_type.is_file())
+            .map(|(path, _)| path)
+            .collect();
+        files.sort_unstable();
+        let hash = |path: String| {
+            let checksum = self.hash_file(&path, algo)?;
+            Ok((path, checksum))
+        };
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+            files.into_par_iter().map(hash).collect()
+        }
+        #[cfg(not(feature = "rayon"))]
+        files.into_iter().map(hash).collect()
+    }
+
+    /// Verify a checksum manifest stored at `pathname`, in the
+    /// `<hex digest>  <path>` format written by `sha256sum`. Returns each
+    /// listed path with whether it matched; unreadable files do not match.
+    pub fn check_manifest(
+        &self,
+        pathname: &str,
+        algo: Algo,
+    ) -> Result<Vec<(String, bool)>, VfsError> {
+        let manifest = self.read_file(pathname)?;
+        let manifest = String::from_utf8_lossy(&manifest);
+        let mut results = Vec::new();
+        for (i, line) in manifest.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let (digest, path) = line
+                .split_once(' ')
+                .map(|(digest, rest)| (digest, rest.strip_prefix([' ', '*']).unwrap_or(rest)))
+                .filter(|(_, path)| !path.is_empty())
+                .ok_or_else(|| {
+                    let message = format!(
+                        "{}: {}: {}: improperly formatted {} checksum line",
+                        algo.command(),
+                        pathname,
+                        i + 1,
+                        algo.to_string().to_uppercase()
+                    );
+                    VfsError::new(ErrorKind::InvalidInput, message)
+                })?;
+            let matched = self
+                .hash_file(path, algo)
+                .is_ok_and(|actual| actual.to_string() == digest.to_lowercase());
+            results.push((path.to_string(), matched));
+        }
+        Ok(results)
+    }
+}
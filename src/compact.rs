@@ -0,0 +1,71 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{ErrorKind, FileType, Vfs, VfsError, BLOCK_SIZE};
+
+#[derive(Debug)]
+pub struct CompactReport {
+    pub moved: usize,
+    pub reclaimed_bytes: usize,
+}
+
+impl fmt::Display for CompactReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Moved: {} \tReclaimed: {} bytes",
+            self.moved, self.reclaimed_bytes
+        )
+    }
+}
+
+impl Vfs {
+    /// Renumber blocks so that each file's blocks are contiguous and in
+    /// file order, packed at the start of the store, then shrink the store
+    /// to the blocks in use. Shared blocks stay shared; file contents and
+    /// inode ids are unchanged.
+    pub fn compact(&mut self) -> Result<CompactReport, VfsError> {
+        self.check_writable(|| "defrag: cannot compact filesystem".to_string())?;
+        let live: Vec<_> = (0..self.fds.len())
+            .filter(|id| !self.fds_id.free.contains(id))
+            .collect();
+        let mut mapping = HashMap::new();
+        let mut blocks = self.blocks.emptied();
+        for &id in &live {
+            let FileType::Regular(blocks_refs) = &self.fds[id].file_type else {
+                continue;
+            };
+            for &block_id in blocks_refs.iter().filter(|&&block_id| block_id != 0) {
+                if mapping.contains_key(&block_id) {
+                    continue;
+                }
+                let new_id = mapping.len() + 1;
+                let plain = self.blocks.read(block_id).ok_or_else(|| {
+                    VfsError::new(
+                        ErrorKind::DataCorruption,
+                        format!(
+                            "defrag: cannot read block {}: Data corruption detected",
+                            block_id
+                        ),
+                    )
+                })?;
+                blocks.load(new_id, &plain, self.blocks.refs(block_id));
+                mapping.insert(block_id, new_id);
+            }
+        }
+        blocks.set_dedup(self.blocks.is_dedup());
+        let moved = mapping.iter().filter(|(old, new)| old != new).count();
+        let reclaimed_bytes = (self.blocks.len() - blocks.len()) * BLOCK_SIZE;
+        for id in live {
+            if let FileType::Regular(blocks_refs) = &mut self.fds[id].file_type {
+                for block_id in blocks_refs.iter_mut().filter(|block_id| **block_id != 0) {
+                    *block_id = mapping[block_id];
+                }
+            }
+        }
+        self.blocks = blocks;
+        Ok(CompactReport {
+            moved,
+            reclaimed_bytes,
+        })
+    }
+}
@@ -0,0 +1,161 @@
+use std::fmt;
+
+use crate::{archive_error, ErrorKind, FileType, Vfs, VfsError, BLOCK_SIZE};
+
+const MAGIC: &[(&[u8], ContentType)] = &[
+    (b"\x7fELF", ContentType::Elf),
+    (b"\x89PNG\r\n\x1a\n", ContentType::Png),
+    (b"\xff\xd8\xff", ContentType::Jpeg),
+    (b"GIF87a", ContentType::Gif),
+    (b"GIF89a", ContentType::Gif),
+    (b"\x1f\x8b", ContentType::Gzip),
+    (b"PK\x03\x04", ContentType::Zip),
+    (b"%PDF-", ContentType::Pdf),
+    (b"BZh", ContentType::Bzip2),
+    (b"\xfd7zXZ\x00", ContentType::Xz),
+    (b"\x28\xb5\x2f\xfd", ContentType::Zstd),
+    (b"\0asm", ContentType::Wasm),
+];
+
+/// Kind of a file as guessed from its first block, in the spirit of
+/// `file(1)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContentType {
+    Directory,
+    Symlink(String),
+    Fifo,
+    Device,
+    Empty,
+    Ascii,
+    Utf8,
+    Elf,
+    Png,
+    Jpeg,
+    Gif,
+    Gzip,
+    Zip,
+    Pdf,
+    Bzip2,
+    Xz,
+    Zstd,
+    Wasm,
+    Data,
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContentType::Directory => write!(f, "directory"),
+            ContentType::Symlink(target) => write!(f, "symbolic link to {}", target),
+            ContentType::Fifo => write!(f, "fifo (named pipe)"),
+            ContentType::Device => write!(f, "character special"),
+            ContentType::Empty => write!(f, "empty"),
+            ContentType::Ascii => write!(f, "ASCII text"),
+            ContentType::Utf8 => write!(f, "Unicode text, UTF-8 text"),
+            ContentType::Elf => write!(f, "ELF"),
+            ContentType::Png => write!(f, "PNG image data"),
+            ContentType::Jpeg => write!(f, "JPEG image data"),
+            ContentType::Gif => write!(f, "GIF image data"),
+            ContentType::Gzip => write!(f, "gzip compressed data"),
+            ContentType::Zip => write!(f, "Zip archive data"),
+            ContentType::Pdf => write!(f, "PDF document"),
+            ContentType::Bzip2 => write!(f, "bzip2 compressed data"),
+            ContentType::Xz => write!(f, "XZ compressed data"),
+            ContentType::Zstd => write!(f, "Zstandard compressed data"),
+            ContentType::Wasm => write!(f, "WebAssembly (wasm) binary module"),
+            ContentType::Data => write!(f, "data"),
+        }
+    }
+}
+
+impl ContentType {
+    fn sniff(head: &[u8]) -> Self {
+        if head.is_empty() {
+            return ContentType::Empty;
+        }
+        if let Some((_, content_type)) = MAGIC.iter().find(|(magic, _)| head.starts_with(magic)) {
+            return content_type.clone();
+        }
+        let printable = |byte: &u8| !byte.is_ascii_control() || b"\t\n\r\x0c\x1b".contains(byte);
+        if !head.iter().all(printable) {
+            return ContentType::Data;
+        }
+        if head.is_ascii() {
+            return ContentType::Ascii;
+        }
+        // The first block may end in the middle of a character.
+        match std::str::from_utf8(head) {
+            Ok(_) => ContentType::Utf8,
+            Err(err) if err.error_len().is_none() => ContentType::Utf8,
+            Err(_) => ContentType::Data,
+        }
+    }
+}
+
+/// Line, word and byte counts of a file, as reported by `wc`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileStats {
+    pub lines: usize,
+    pub words: usize,
+    pub bytes: usize,
+}
+
+impl Vfs {
+    /// Count lines, whitespace-separated words and bytes of a regular file
+    /// block by block.
+    pub fn file_stats(&self, pathname: &str) -> Result<FileStats, VfsError> {
+        let id = self.regular_file("wc", pathname)?;
+        let mut stats = FileStats::default();
+        let mut in_word = false;
+        for chunk in self.file_chunks(id) {
+            let chunk = chunk.map_err(|block_ref| self.corrupted("wc", pathname, block_ref))?;
+            for &byte in chunk.iter() {
+                if byte == b'\n' {
+                    stats.lines += 1;
+                }
+                if byte.is_ascii_whitespace() {
+                    in_word = false;
+                } else if !in_word {
+                    in_word = true;
+                    stats.words += 1;
+                }
+            }
+            stats.bytes += chunk.len();
+        }
+        Ok(stats)
+    }
+
+    /// Guess the kind of a file from magic bytes at the start of its
+    /// contents.
+    pub fn detect_type(&self, pathname: &str) -> Result<ContentType, VfsError> {
+        let (fd, id, _) = self.resolve(pathname).ok_or_else(|| {
+            VfsError::new(
+                ErrorKind::NotFound,
+                format!(
+                    "file: cannot open '{}': No such file or directory",
+                    pathname
+                ),
+            )
+        })?;
+        match &fd.file_type {
+            FileType::Directory(_) => Ok(ContentType::Directory),
+            FileType::Symlink(target) => Ok(ContentType::Symlink(target.clone())),
+            FileType::Fifo(_) => Ok(ContentType::Fifo),
+            FileType::Device(_) => Ok(ContentType::Device),
+            FileType::Proc(entry) => Ok(ContentType::sniff(&self.proc_contents(entry))),
+            FileType::Ring(ring) => Ok(ContentType::sniff(&ring.contents())),
+            FileType::Archive(member) => member
+                .read_at(0, BLOCK_SIZE)
+                .map(|head| ContentType::sniff(&head))
+                .map_err(|err| archive_error(format!("file: cannot read '{}'", pathname), &err)),
+            FileType::Regular(_) => match self.file_chunks(id).next() {
+                Some(head) => {
+                    let head =
+                        head.map_err(|block_ref| self.corrupted("file", pathname, block_ref))?;
+                    Ok(ContentType::sniff(&head))
+                }
+                None => Ok(ContentType::Empty),
+            },
+        }
+    }
+}
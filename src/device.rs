@@ -0,0 +1,218 @@
+use std::fmt;
+
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+
+use crate::{
+    ErrorKind, FileDescriptor, FileType, Times, Vfs, VfsError, ATTR_IMMUTABLE, W_OK, X_OK,
+};
+
+/// Read and write behavior of a character device node.
+///
+/// Devices keep no data in the block store: every read and write on an
+/// open descriptor is handed to the device, and seeking is a no-op.
+pub trait Device: fmt::Debug + Send + Sync {
+    /// Short device name, e.g. `null`.
+    fn name(&self) -> &str;
+
+    /// Produce up to `size` bytes.
+    fn read(&mut self, size: usize) -> Vec<u8>;
+
+    /// Consume `data`, returning the number of bytes accepted.
+    fn write(&mut self, data: &[u8]) -> usize;
+
+    fn box_clone(&self) -> Box<dyn Device>;
+}
+
+impl Clone for Box<dyn Device> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// Discards writes and reads end of file, like `/dev/null`.
+#[derive(Debug, Clone, Default)]
+pub struct Null;
+
+impl Device for Null {
+    fn name(&self) -> &str {
+        "null"
+    }
+
+    fn read(&mut self, _size: usize) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn write(&mut self, data: &[u8]) -> usize {
+        data.len()
+    }
+
+    fn box_clone(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
+
+/// Discards writes and reads an endless stream of zeros, like `/dev/zero`.
+#[derive(Debug, Clone, Default)]
+pub struct Zero;
+
+impl Device for Zero {
+    fn name(&self) -> &str {
+        "zero"
+    }
+
+    fn read(&mut self, size: usize) -> Vec<u8> {
+        vec![0; size]
+    }
+
+    fn write(&mut self, data: &[u8]) -> usize {
+        data.len()
+    }
+
+    fn box_clone(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
+
+/// Discards writes and reads a pseudo-random stream (xorshift64*) seeded
+/// from the operating system, like `/dev/urandom`.
+#[derive(Debug, Clone)]
+pub struct Urandom {
+    state: u64,
+}
+
+impl Urandom {
+    pub fn new() -> Self {
+        Self::with_seed(OsRng.next_u64())
+    }
+
+    /// Create a reproducible stream; a zero seed is replaced since
+    /// xorshift never leaves the all-zero state.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            state: if seed == 0 {
+                0x9e37_79b9_7f4a_7c15
+            } else {
+                seed
+            },
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+impl Default for Urandom {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Urandom {
+    fn name(&self) -> &str {
+        "urandom"
+    }
+
+    fn read(&mut self, size: usize) -> Vec<u8> {
+        let mut data = Vec::with_capacity(size.next_multiple_of(8));
+        while data.len() < size {
+            data.extend_from_slice(&self.next().to_le_bytes());
+        }
+        data.truncate(size);
+        data
+    }
+
+    fn write(&mut self, data: &[u8]) -> usize {
+        data.len()
+    }
+
+    fn box_clone(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
+
+impl Vfs {
+    /// Create a device node backed by `device`.
+    ///
+    /// Devices are live objects rather than data, so unlike the other
+    /// operations this one is not recorded by the audit log.
+    pub fn mknod(&mut self, pathname: &str, device: Box<dyn Device>) -> Result<(), VfsError> {
+        self.check_writable_at(pathname, || format!("mknod: cannot create '{}'", pathname))?;
+        let basename = Vfs::basename(pathname);
+        let dirname = Vfs::dirname(pathname);
+        match self.resolve(&dirname) {
+            Some((fd, id, _)) => {
+                if !fd.file_type.is_dir() {
+                    return Err(VfsError::new(
+                        ErrorKind::NotADirectory,
+                        format!("mknod: cannot create '{}': Not a directory", pathname),
+                    ));
+                }
+                let entries = fd.file_type.as_dir();
+                if entries.contains_key(&basename) || basename.is_empty() {
+                    return Err(VfsError::new(
+                        ErrorKind::AlreadyExists,
+                        format!("mknod: cannot create '{}': File exists", pathname),
+                    ));
+                }
+                let context = || format!("mknod: cannot create '{}'", pathname);
+                self.check_access(id, W_OK | X_OK, context)?;
+                self.check_attrs(id, ATTR_IMMUTABLE, context)?;
+                self.check_new_inode(id, context)?;
+                let new_id = self.alloc_fd(|_| FileDescriptor {
+                    file_type: FileType::Device(device),
+                    size: 0,
+                    links: 1,
+                    refs: 0,
+                    generation: 0,
+                    uid: 0,
+                    gid: 0,
+                    mode: 0o666,
+                    acl: Vec::new(),
+                    attrs: 0,
+                    project: 0,
+                    times: Times::default(),
+                });
+                self.inherit_project(id, new_id);
+                let fd = &mut self.fds[id];
+                let entries = fd.file_type.as_dir_mut();
+                entries.insert(basename.to_string(), new_id);
+                self.touch_modified(id);
+                Ok(())
+            }
+            None => Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!(
+                    "mknod: cannot create '{}': No such file or directory",
+                    pathname
+                ),
+            )),
+        }
+    }
+
+    /// Create `dirname` holding the `null`, `zero` and `urandom` devices,
+    /// keeping whatever already exists, as in a loaded image.
+    pub(crate) fn populate_devices(&mut self, dirname: &str) -> Result<(), VfsError> {
+        if self.resolve(dirname).is_none() {
+            self.mkdir(dirname)?;
+        }
+        let devices: [Box<dyn Device>; 3] = [
+            Box::new(Null),
+            Box::new(Zero),
+            Box::new(match self.seed {
+                Some(seed) => Urandom::with_seed(seed),
+                None => Urandom::new(),
+            }),
+        ];
+        for device in devices {
+            let pathname = format!("{}/{}", dirname.trim_end_matches('/'), device.name());
+            if self.resolve(&pathname).is_none() {
+                self.mknod(&pathname, device)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,475 @@
+use std::{collections::BTreeMap, fmt};
+
+use sha2::{Digest, Sha256};
+
+use crate::{mode_string, op::hex, FileType, Vfs, VfsError, DOT, DOTDOT, PATHNAME_SEPARATOR};
+
+/// What tree comparisons look at besides the paths, file types, link
+/// counts and contents, which they always compare.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TreeOptions {
+    /// Mode, owner, group, ACL and attributes.
+    pub metadata: bool,
+    /// Modification times.
+    pub times: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct TreeEntry {
+    file_type: String,
+    links: usize,
+    content: [u8; 32],
+    /// The metadata the options ask for, as `describe_metadata` shows it.
+    metadata: String,
+}
+
+/// A digest of a whole tree by paths rather than inode and block numbers,
+/// so equal trees built in different ways have equal digests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TreeDigest(pub [u8; 32]);
+
+impl fmt::Display for TreeDigest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex(&self.0))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified { content: bool, metadata: bool },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub path: String,
+    pub kind: ChangeKind,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ChangeKind::Added => write!(f, "A {}", self.path),
+            ChangeKind::Removed => write!(f, "D {}", self.path),
+            ChangeKind::Modified { content, metadata } => {
+                let what = match (content, metadata) {
+                    (true, true) => "content, metadata",
+                    (true, false) => "content",
+                    _ => "metadata",
+                };
+                write!(f, "M {} ({})", self.path, what)
+            }
+        }
+    }
+}
+
+/// Paths that differ between two filesystems, sorted by path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Changeset {
+    pub changes: Vec<Change>,
+}
+
+impl Changeset {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl fmt::Display for Changeset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, change) in self.changes.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+impl Vfs {
+    fn content_digest(&self, id: usize) -> [u8; 32] {
+        let fd = &self.fds[id];
+        let mut hasher = Sha256::new();
+        match &fd.file_type {
+            FileType::Regular(_) => match self.file_contents(id) {
+                Ok(data) => hasher.update(&data),
+                Err(block_ref) => {
+                    hasher.update(b"corrupted block");
+                    hasher.update(block_ref.to_le_bytes());
+                }
+            },
+            FileType::Directory(_) => {}
+            FileType::Symlink(target) => hasher.update(target.as_bytes()),
+            FileType::Fifo(_) => {}
+            FileType::Device(device) => hasher.update(device.name().as_bytes()),
+            FileType::Proc(_) => {}
+            FileType::Ring(ring) => hasher.update(ring.contents()),
+            FileType::Archive(member) => match member.contents() {
+                Ok(data) => hasher.update(&data),
+                Err(_) => hasher.update(b"unreadable member"),
+            },
+        }
+        hasher.finalize().into()
+    }
+
+    /// Collect every path below the root without following symlinks.
+    pub(crate) fn tree_paths(&self) -> Vec<(String, usize)> {
+        self.subtree_paths(0, "")
+    }
+
+    /// Collect every path below directory `root_id`, prefixed with `prefix`.
+    pub(crate) fn subtree_paths(&self, root_id: usize, prefix: &str) -> Vec<(String, usize)> {
+        let mut paths = Vec::new();
+        let mut stack = vec![(prefix.to_string(), root_id)];
+        while let Some((prefix, id)) = stack.pop() {
+            for (name, &child_id) in self.fds[id].file_type.as_dir() {
+                if name == DOT || name == DOTDOT {
+                    continue;
+                }
+                let path = format!("{}{}{}", prefix, PATHNAME_SEPARATOR, name);
+                if self.fds[child_id].file_type.is_dir() {
+                    stack.push((path.clone(), child_id));
+                }
+                paths.push((path, child_id));
+            }
+        }
+        paths
+    }
+
+    fn describe_metadata(&self, id: usize, options: TreeOptions) -> String {
+        let fd = &self.fds[id];
+        let mut description = Vec::new();
+        if options.metadata {
+            description.push(format!(
+                "mode {} uid {} gid {} attrs {:#x}",
+                mode_string(fd.mode),
+                fd.uid,
+                fd.gid,
+                fd.attrs
+            ));
+            for entry in &fd.acl {
+                description.push(format!("acl {}", entry));
+            }
+        }
+        if options.times {
+            description.push(format!("mtime {}", fd.times.mtime));
+        }
+        description.join(", ")
+    }
+
+    fn tree(&self, options: TreeOptions) -> BTreeMap<String, TreeEntry> {
+        self.tree_paths()
+            .into_iter()
+            .map(|(path, id)| {
+                let fd = &self.fds[id];
+                let entry = TreeEntry {
+                    file_type: fd.file_type.to_string(),
+                    links: fd.links,
+                    content: self.content_digest(id),
+                    metadata: self.describe_metadata(id, options),
+                };
+                (path, entry)
+            })
+            .collect()
+    }
+
+    /// Digest the paths, types, link counts and contents of the whole
+    /// tree, and the metadata `options` asks for. Two trees have the same
+    /// digest exactly when `assert_same_tree_with` would pass for them.
+    pub fn tree_digest(&self, options: TreeOptions) -> TreeDigest {
+        let mut hasher = Sha256::new();
+        for (path, entry) in self.tree(options) {
+            for field in [&path, &entry.file_type, &entry.metadata] {
+                hasher.update(field.len().to_le_bytes());
+                hasher.update(field.as_bytes());
+            }
+            hasher.update(entry.links.to_le_bytes());
+            hasher.update(entry.content);
+        }
+        TreeDigest(hasher.finalize().into())
+    }
+
+    /// Compare this filesystem against `other`, typically an earlier
+    /// snapshot taken with `clone`. Changes are reported from the point of
+    /// view of `self`: paths only in `self` are added.
+    pub fn diff(&self, other: &Vfs) -> Changeset {
+        self.diff_with(other, TreeOptions::default())
+    }
+
+    /// Like `diff`, also comparing the metadata `options` asks for.
+    pub fn diff_with(&self, other: &Vfs, options: TreeOptions) -> Changeset {
+        let ours = self.tree(options);
+        let mut theirs = other.tree(options);
+        let mut changes = Vec::new();
+        for (path, entry) in ours {
+            let kind = match theirs.remove(&path) {
+                None => ChangeKind::Added,
+                Some(old) if old == entry => continue,
+                Some(old) => ChangeKind::Modified {
+                    content: old.content != entry.content || old.file_type != entry.file_type,
+                    metadata: old.links != entry.links
+                        || old.file_type != entry.file_type
+                        || old.metadata != entry.metadata,
+                },
+            };
+            changes.push(Change { path, kind });
+        }
+        changes.extend(theirs.into_keys().map(|path| Change {
+            path,
+            kind: ChangeKind::Removed,
+        }));
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        Changeset { changes }
+    }
+}
+
+/// Panic if `left` and `right` hold different trees, comparing paths,
+/// types, link counts and contents, like `assert_eq!` for filesystems. The
+/// message lists what changes from `left` to `right`, with a unified diff
+/// of each text file that differs.
+#[track_caller]
+pub fn assert_same_tree(left: &Vfs, right: &Vfs) {
+    assert_same_tree_with(left, right, TreeOptions::default())
+}
+
+/// Like `assert_same_tree`, also comparing the metadata `options` asks
+/// for.
+#[track_caller]
+pub fn assert_same_tree_with(left: &Vfs, right: &Vfs, options: TreeOptions) {
+    let changes = right.diff_with(left, options);
+    if changes.is_empty() {
+        return;
+    }
+    let mut message = format!("trees differ from left to right:\n{}\n", changes);
+    for change in &changes.changes {
+        let ChangeKind::Modified { content, metadata } = change.kind else {
+            continue;
+        };
+        let path = &change.path;
+        if content {
+            let text = |vfs: &Vfs| {
+                let data = vfs.tree_contents(path)?;
+                String::from_utf8(data)
+                    .ok()
+                    .filter(|text| !text.contains('\0'))
+            };
+            message.push('\n');
+            message.push_str(&match (text(left), text(right)) {
+                (Some(old), Some(new)) => unified_diff(
+                    &old,
+                    &new,
+                    &format!("left{}", path),
+                    &format!("right{}", path),
+                ),
+                _ => format!("Binary files left{} and right{} differ\n", path, path),
+            });
+        }
+        if metadata {
+            message.push_str(&format!(
+                "\n{}:\n  left:  {}\n  right: {}\n",
+                path,
+                left.describe_path(path, options),
+                right.describe_path(path, options)
+            ));
+        }
+    }
+    panic!("{}", message.trim_end());
+}
+
+impl Vfs {
+    /// The contents `content_digest` hashes for `path`, if it has any.
+    fn tree_contents(&self, path: &str) -> Option<Vec<u8>> {
+        let (fd, id, _) = self.resolve(path)?;
+        match &fd.file_type {
+            FileType::Regular(_) => self.file_contents(id).ok(),
+            FileType::Symlink(target) => Some(target.as_bytes().to_vec()),
+            FileType::Ring(ring) => Some(ring.contents()),
+            FileType::Archive(member) => member.contents().ok(),
+            _ => None,
+        }
+    }
+
+    fn describe_path(&self, path: &str, options: TreeOptions) -> String {
+        let Some((fd, id, _)) = self.resolve(path) else {
+            return "missing".to_string();
+        };
+        let mut description = format!("{}, links {}", fd.file_type, fd.links);
+        let metadata = self.describe_metadata(id, options);
+        if !metadata.is_empty() {
+            description.push_str(", ");
+            description.push_str(&metadata);
+        }
+        description
+    }
+}
+
+const CONTEXT_LINES: usize = 3;
+
+#[derive(Debug, Clone, Copy)]
+enum Edit {
+    Equal(usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Shortest edit script between two sequences of lines (Myers, 1986).
+fn myers(a: &[&str], b: &[&str]) -> Vec<Edit> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    let at = |k: isize| (k + offset) as usize;
+    let mut v = vec![0isize; 2 * max + 3];
+    let mut trace = Vec::new();
+    'search: for d in 0..=max as isize {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+                v[at(k + 1)]
+            } else {
+                v[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[at(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+    let (mut x, mut y) = (n, m);
+    let mut edits = Vec::new();
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[at(prev_k)];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Equal(x as usize - 1));
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit::Insert(y as usize - 1));
+            } else {
+                edits.push(Edit::Delete(x as usize - 1));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    edits.reverse();
+    edits
+}
+
+fn push_line(out: &mut String, tag: char, line: &str) {
+    out.push(tag);
+    out.push_str(line);
+    if !line.ends_with('\n') {
+        out.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+/// Render a unified diff with three lines of context; empty if the texts
+/// are equal.
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    let a: Vec<_> = old.split_inclusive('\n').collect();
+    let b: Vec<_> = new.split_inclusive('\n').collect();
+    let edits = myers(&a, &b);
+    let changed: Vec<_> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Equal(_)))
+        .map(|(i, _)| i)
+        .collect();
+    if changed.is_empty() {
+        return String::new();
+    }
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for i in changed {
+        let start = i.saturating_sub(CONTEXT_LINES);
+        let end = (i + CONTEXT_LINES + 1).min(edits.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    for (start, end) in hunks {
+        let (mut old_start, mut new_start) = (0, 0);
+        for edit in &edits[..start] {
+            match edit {
+                Edit::Equal(_) => {
+                    old_start += 1;
+                    new_start += 1;
+                }
+                Edit::Delete(_) => old_start += 1,
+                Edit::Insert(_) => new_start += 1,
+            }
+        }
+        let hunk = &edits[start..end];
+        let old_len = hunk
+            .iter()
+            .filter(|e| !matches!(e, Edit::Insert(_)))
+            .count();
+        let new_len = hunk
+            .iter()
+            .filter(|e| !matches!(e, Edit::Delete(_)))
+            .count();
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_start + usize::from(old_len > 0),
+            old_len,
+            new_start + usize::from(new_len > 0),
+            new_len
+        ));
+        for edit in hunk {
+            match *edit {
+                Edit::Equal(i) => push_line(&mut out, ' ', a[i]),
+                Edit::Delete(i) => push_line(&mut out, '-', a[i]),
+                Edit::Insert(j) => push_line(&mut out, '+', b[j]),
+            }
+        }
+    }
+    out
+}
+
+impl Vfs {
+    /// Check whether two regular files have identical contents.
+    pub fn compare_files(&self, pathname1: &str, pathname2: &str) -> Result<bool, VfsError> {
+        Ok(self.read_file(pathname1)? == self.read_file(pathname2)?)
+    }
+
+    /// Produce a unified diff of two text files, or a one-line notice if
+    /// either of them is binary.
+    pub fn diff_files(&self, pathname1: &str, pathname2: &str) -> Result<String, VfsError> {
+        let data1 = self.read_file(pathname1)?;
+        let data2 = self.read_file(pathname2)?;
+        if data1 == data2 {
+            return Ok(String::new());
+        }
+        let text = |data: &[u8]| {
+            std::str::from_utf8(data)
+                .ok()
+                .filter(|text| !text.contains('\0'))
+                .map(str::to_string)
+        };
+        match (text(&data1), text(&data2)) {
+            (Some(text1), Some(text2)) => Ok(unified_diff(&text1, &text2, pathname1, pathname2)),
+            _ => Ok(format!(
+                "Binary files {} and {} differ\n",
+                pathname1, pathname2
+            )),
+        }
+    }
+}
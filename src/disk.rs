@@ -0,0 +1,846 @@
+use std::collections::{btree_map::Entry, BTreeMap, BTreeSet, HashMap, VecDeque};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    block::SEALED_SIZE,
+    image::{put_bytes, put_u64, Image, Reader},
+    ring::Ring,
+    AclEntry, AclTag, ErrorKind, FileDescriptor, FileType, Identity, MerkleTree, Times, Timespec,
+    Vfs, VfsBuilder, VfsError, BLOCK_SIZE, DOT, DOTDOT, PATHNAME_SEPARATOR,
+};
+
+const MAGIC: &[u8; 4] = b"VFSI";
+const VERSION: u32 = 2;
+const SUPERBLOCK_LEN: u32 = 76;
+
+/// Compatible feature: block deduplication is enabled.
+const COMPAT_DEDUP: u32 = 1;
+/// Compatible feature: the image was saved in verity mode and loads back
+/// into it.
+const COMPAT_VERITY: u32 = 2;
+/// Incompatible feature: inodes carry their mode, owner and ACL.
+const INCOMPAT_METADATA: u32 = 1;
+/// Incompatible feature: inodes carry their attribute flags.
+const INCOMPAT_ATTRS: u32 = 2;
+/// Incompatible feature: inodes carry their access, modification, change
+/// and birth times.
+const INCOMPAT_TIMES: u32 = 4;
+/// Incompatible feature: inodes carry their project ID.
+const INCOMPAT_PROJECT: u32 = 8;
+/// Incompatible feature: the data area holds blocks sealed with the
+/// volume's encryption key instead of their plaintext.
+const INCOMPAT_ENCRYPTED: u32 = 16;
+/// Incompatible feature: the inode table holds ring buffers, with their
+/// capacity and current window.
+const INCOMPAT_RING: u32 = 32;
+/// Incompatible features this version knows how to load.
+pub(crate) const INCOMPAT_SUPPORTED: u32 = INCOMPAT_METADATA
+    | INCOMPAT_ATTRS
+    | INCOMPAT_TIMES
+    | INCOMPAT_PROJECT
+    | INCOMPAT_ENCRYPTED
+    | INCOMPAT_RING;
+
+const TAG_FILE: u8 = 0;
+const TAG_DIR: u8 = 1;
+const TAG_SYMLINK: u8 = 2;
+const TAG_FIFO: u8 = 3;
+const TAG_RING: u8 = 4;
+
+/// Geometry and feature flags of an image, stored right after the magic
+/// and version, prefixed by its own length so that newer versions can
+/// append fields without breaking older readers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Superblock {
+    block_size: usize,
+    blocks: usize,
+    inodes: usize,
+    limit: usize,
+    compat: u32,
+    incompat: u32,
+    /// Most inodes the filesystem may have, or 0 for no limit.
+    inode_limit: usize,
+    /// Merkle root of the inode table and data area, see
+    /// `Layout::merkle_root`; `None` in images that predate it.
+    merkle_root: Option<[u8; 32]>,
+}
+
+impl Superblock {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&SUPERBLOCK_LEN.to_le_bytes());
+        out.extend_from_slice(&(self.block_size as u32).to_le_bytes());
+        put_u64(out, self.blocks);
+        put_u64(out, self.inodes);
+        put_u64(out, self.limit);
+        out.extend_from_slice(&self.compat.to_le_bytes());
+        out.extend_from_slice(&self.incompat.to_le_bytes());
+        put_u64(out, self.inode_limit);
+        out.extend_from_slice(&self.merkle_root.unwrap_or_default());
+    }
+
+    fn decode(reader: &mut Reader) -> Option<Self> {
+        let len = reader.u32()? as usize;
+        // Fields past the ones known here were added by a newer version.
+        let mut fields = Reader::new(reader.take(len)?);
+        Some(Self {
+            block_size: fields.u32()? as usize,
+            blocks: fields.u64()?,
+            inodes: fields.u64()?,
+            limit: fields.u64()?,
+            compat: fields.u32()?,
+            incompat: fields.u32()?,
+            // Added after the first version 2 images.
+            inode_limit: fields.u64().unwrap_or(0),
+            merkle_root: fields
+                .take(32)
+                .map(|root| root.try_into().expect("32 bytes")),
+        })
+    }
+}
+
+fn bitmap(used: &[bool]) -> Vec<u8> {
+    let mut bits = vec![0; used.len().div_ceil(8)];
+    for (i, _) in used.iter().enumerate().filter(|(_, &used)| used) {
+        bits[i / 8] |= 1 << (i % 8);
+    }
+    bits
+}
+
+fn is_set(bitmap: &[u8], i: usize) -> bool {
+    bitmap
+        .get(i / 8)
+        .is_some_and(|bits| bits & (1 << (i % 8)) != 0)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum InodeKind {
+    File(Vec<usize>),
+    Dir(Vec<(String, usize)>),
+    Symlink(String),
+    Fifo,
+    Ring { capacity: usize, data: Vec<u8> },
+}
+
+impl InodeKind {
+    /// Mode of inodes from images that predate permissions.
+    fn default_mode(&self) -> u32 {
+        match self {
+            InodeKind::Dir(_) => 0o755,
+            InodeKind::Symlink(_) => 0o777,
+            InodeKind::File(_) | InodeKind::Fifo | InodeKind::Ring { .. } => 0o644,
+        }
+    }
+}
+
+/// An inode as stored in the inode table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Inode {
+    pub(crate) kind: InodeKind,
+    pub(crate) links: usize,
+    pub(crate) size: usize,
+    pub(crate) mode: u32,
+    pub(crate) uid: u32,
+    pub(crate) gid: u32,
+    pub(crate) acl: Vec<AclEntry>,
+    pub(crate) attrs: u32,
+    pub(crate) times: Times,
+    pub(crate) project: u32,
+}
+
+fn put_acl_entry(out: &mut Vec<u8>, entry: &AclEntry) {
+    let (tag, qualifier) = match entry.tag {
+        AclTag::UserObj => (0, 0),
+        AclTag::User(uid) => (1, uid),
+        AclTag::GroupObj => (2, 0),
+        AclTag::Group(gid) => (3, gid),
+        AclTag::Mask => (4, 0),
+        AclTag::Other => (5, 0),
+    };
+    out.push(tag);
+    out.extend_from_slice(&qualifier.to_le_bytes());
+    out.push(entry.perms as u8);
+}
+
+fn read_acl_entry(reader: &mut Reader) -> Option<AclEntry> {
+    let tag = reader.u8()?;
+    let qualifier = reader.u32()?;
+    let tag = match tag {
+        0 => AclTag::UserObj,
+        1 => AclTag::User(qualifier),
+        2 => AclTag::GroupObj,
+        3 => AclTag::Group(qualifier),
+        4 => AclTag::Mask,
+        5 => AclTag::Other,
+        _ => return None,
+    };
+    Some(AclEntry::new(tag, reader.u8()? as u32))
+}
+
+impl Inode {
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        let tag = match &self.kind {
+            InodeKind::File(_) => TAG_FILE,
+            InodeKind::Dir(_) => TAG_DIR,
+            InodeKind::Symlink(_) => TAG_SYMLINK,
+            InodeKind::Fifo => TAG_FIFO,
+            InodeKind::Ring { .. } => TAG_RING,
+        };
+        out.push(tag);
+        put_u64(out, self.links);
+        put_u64(out, self.size);
+        match &self.kind {
+            InodeKind::File(blocks_refs) => {
+                put_u64(out, blocks_refs.len());
+                for &block_id in blocks_refs {
+                    put_u64(out, block_id);
+                }
+            }
+            InodeKind::Dir(entries) => {
+                put_u64(out, entries.len());
+                for (name, child_id) in entries {
+                    put_bytes(out, name.as_bytes());
+                    put_u64(out, *child_id);
+                }
+            }
+            InodeKind::Symlink(target) => put_bytes(out, target.as_bytes()),
+            InodeKind::Fifo => {}
+            InodeKind::Ring { capacity, data } => {
+                put_u64(out, *capacity);
+                put_bytes(out, data);
+            }
+        }
+        out.extend_from_slice(&self.mode.to_le_bytes());
+        out.extend_from_slice(&self.uid.to_le_bytes());
+        out.extend_from_slice(&self.gid.to_le_bytes());
+        put_u64(out, self.acl.len());
+        for entry in &self.acl {
+            put_acl_entry(out, entry);
+        }
+        out.extend_from_slice(&self.attrs.to_le_bytes());
+        let times = &self.times;
+        for time in [times.atime, times.mtime, times.ctime, times.btime] {
+            out.extend_from_slice(&time.sec.to_le_bytes());
+            out.extend_from_slice(&time.nsec.to_le_bytes());
+        }
+        out.extend_from_slice(&self.project.to_le_bytes());
+    }
+
+    /// Decode an inode as written with the `incompat` features, which say
+    /// what follows the fields every version has.
+    pub(crate) fn decode(reader: &mut Reader, incompat: u32) -> Option<Self> {
+        let tag = reader.u8()?;
+        let links = reader.u64()?;
+        let size = reader.u64()?;
+        let kind = match tag {
+            TAG_FILE => {
+                let n = reader.u64()?;
+                let mut blocks_refs = Vec::new();
+                for _ in 0..n {
+                    blocks_refs.push(reader.u64()?);
+                }
+                InodeKind::File(blocks_refs)
+            }
+            TAG_DIR => {
+                let n = reader.u64()?;
+                let mut entries = Vec::new();
+                for _ in 0..n {
+                    entries.push((reader.string()?, reader.u64()?));
+                }
+                InodeKind::Dir(entries)
+            }
+            TAG_SYMLINK => InodeKind::Symlink(reader.string()?),
+            TAG_FIFO => InodeKind::Fifo,
+            TAG_RING if incompat & INCOMPAT_RING != 0 => InodeKind::Ring {
+                capacity: reader.u64()?,
+                data: reader.bytes()?.to_vec(),
+            },
+            _ => return None,
+        };
+        let mut inode = Self {
+            mode: kind.default_mode(),
+            kind,
+            links,
+            size,
+            uid: 0,
+            gid: 0,
+            acl: Vec::new(),
+            attrs: 0,
+            times: Times::default(),
+            project: 0,
+        };
+        if incompat & INCOMPAT_METADATA != 0 {
+            inode.mode = reader.u32()?;
+            inode.uid = reader.u32()?;
+            inode.gid = reader.u32()?;
+            let n = reader.u64()?;
+            for _ in 0..n {
+                inode.acl.push(read_acl_entry(reader)?);
+            }
+        }
+        if incompat & INCOMPAT_ATTRS != 0 {
+            inode.attrs = reader.u32()?;
+        }
+        if incompat & INCOMPAT_TIMES != 0 {
+            let times = &mut inode.times;
+            for time in [
+                &mut times.atime,
+                &mut times.mtime,
+                &mut times.ctime,
+                &mut times.btime,
+            ] {
+                let sec = i64::from_le_bytes(reader.take(8)?.try_into().ok()?);
+                *time = Timespec::new(sec, reader.u32()?);
+            }
+        }
+        if incompat & INCOMPAT_PROJECT != 0 {
+            inode.project = reader.u32()?;
+        }
+        Some(inode)
+    }
+}
+
+/// The persistent state of a filesystem as laid out in an image: used
+/// inodes and the plaintext of used blocks, keyed by id, or their sealed
+/// form if `encrypted`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Layout {
+    pub(crate) limit: usize,
+    pub(crate) inode_limit: usize,
+    pub(crate) compat: u32,
+    pub(crate) encrypted: bool,
+    /// Whether the filesystem is in verity mode.
+    pub(crate) verity: bool,
+    pub(crate) inodes: BTreeMap<usize, Inode>,
+    pub(crate) blocks: BTreeMap<usize, Vec<u8>>,
+}
+
+impl Layout {
+    /// Collect the persistent state of `vfs`; fails with the id of the
+    /// first corrupted block.
+    pub(crate) fn capture(vfs: &Vfs) -> Result<Self, usize> {
+        let persistent: Vec<_> = (0..vfs.fds.len()).map(|id| vfs.is_persistent(id)).collect();
+        let mut layout = Layout {
+            limit: vfs.blocks.limit().unwrap_or(0),
+            inode_limit: vfs.max_inodes.unwrap_or(0),
+            compat: if vfs.blocks.is_dedup() {
+                COMPAT_DEDUP
+            } else {
+                0
+            },
+            verity: vfs.is_verity(),
+            ..Default::default()
+        };
+        for (id, _) in persistent.iter().enumerate().filter(|(_, &used)| used) {
+            let fd = &vfs.fds[id];
+            let kind = match &fd.file_type {
+                FileType::Regular(blocks_refs) => {
+                    for &block_id in blocks_refs.iter().filter(|&&block_id| block_id != 0) {
+                        if let Entry::Vacant(entry) = layout.blocks.entry(block_id) {
+                            let plain = vfs.blocks.read(block_id).ok_or(block_id)?;
+                            entry.insert(plain.to_vec());
+                        }
+                    }
+                    InodeKind::File(blocks_refs.clone())
+                }
+                FileType::Directory(entries) => {
+                    let entries = entries
+                        .iter()
+                        .filter(|(_, &child_id)| persistent[child_id])
+                        .map(|(name, &child_id)| (name.clone(), child_id))
+                        .collect();
+                    InodeKind::Dir(entries)
+                }
+                FileType::Symlink(target) => InodeKind::Symlink(target.clone()),
+                FileType::Ring(ring) => InodeKind::Ring {
+                    capacity: ring.capacity(),
+                    data: ring.contents(),
+                },
+                _ => InodeKind::Fifo,
+            };
+            let inode = Inode {
+                kind,
+                links: fd.links,
+                size: fd.size,
+                mode: fd.mode,
+                uid: fd.uid,
+                gid: fd.gid,
+                acl: fd.acl.clone(),
+                attrs: fd.attrs,
+                times: fd.times,
+                project: fd.project,
+            };
+            layout.inodes.insert(id, inode);
+        }
+        Ok(layout)
+    }
+
+    /// Replace the plaintext of every block with its nonce, tag and
+    /// ciphertext as stored in `vfs`; fails with the id of the first
+    /// corrupted block.
+    fn seal(&mut self, vfs: &Vfs) -> Result<(), usize> {
+        for (&id, block) in &mut self.blocks {
+            *block = vfs.blocks.sealed(id).ok_or(id)?;
+        }
+        self.encrypted = true;
+        Ok(())
+    }
+
+    /// Incompatible features of the image, leaving out encryption and ring
+    /// buffers when unused so that older versions can still load it.
+    fn incompat(&self) -> u32 {
+        let mut incompat = INCOMPAT_SUPPORTED;
+        if !self.encrypted {
+            incompat &= !INCOMPAT_ENCRYPTED;
+        }
+        let is_ring = |inode: &Inode| matches!(inode.kind, InodeKind::Ring { .. });
+        if !self.inodes.values().any(is_ring) {
+            incompat &= !INCOMPAT_RING;
+        }
+        incompat
+    }
+
+    /// Size of each block in the data area.
+    fn block_size(&self) -> usize {
+        match self.encrypted {
+            true => SEALED_SIZE,
+            false => BLOCK_SIZE,
+        }
+    }
+
+    /// Number of block and inode slots, including unused ones.
+    fn geometry(&self) -> (usize, usize) {
+        let blocks = self.blocks.keys().next_back().map_or(1, |id| id + 1);
+        let inodes = self.inodes.keys().next_back().map_or(0, |id| id + 1);
+        (blocks, inodes)
+    }
+
+    /// Bitmaps and inode table.
+    fn encode_metadata(&self, out: &mut Vec<u8>) {
+        let (blocks, inodes) = self.geometry();
+        let used = |count, keys: &mut dyn Iterator<Item = &usize>| {
+            let mut used = vec![false; count];
+            for &id in keys {
+                used[id] = true;
+            }
+            bitmap(&used)
+        };
+        out.extend(used(blocks, &mut self.blocks.keys()));
+        out.extend(used(inodes, &mut self.inodes.keys()));
+        for inode in self.inodes.values() {
+            inode.encode(out);
+        }
+    }
+
+    /// Bitmaps, inode table and data area.
+    fn encode_contents(&self, out: &mut Vec<u8>) {
+        self.encode_metadata(out);
+        for plain in self.blocks.values() {
+            out.extend_from_slice(plain);
+        }
+    }
+
+    /// Merkle root over the blocks as stored, joined with a digest of the
+    /// bitmaps and inode table, so that a tampered image fails to load even
+    /// when its CRC was fixed up.
+    fn merkle_root(&self) -> [u8; 32] {
+        let mut metadata = Vec::new();
+        self.encode_metadata(&mut metadata);
+        let tree = MerkleTree::build(self.blocks.values().map(Vec::as_slice));
+        MerkleTree::node(&Sha256::digest(&metadata).into(), &tree.root())
+    }
+
+    /// Digest of the inodes and blocks, independent of the superblock.
+    pub(crate) fn digest(&self) -> [u8; 32] {
+        let mut contents = Vec::new();
+        self.encode_contents(&mut contents);
+        Sha256::digest(&contents).into()
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let (blocks, inodes) = self.geometry();
+        let superblock = Superblock {
+            block_size: BLOCK_SIZE,
+            blocks,
+            inodes,
+            limit: self.limit,
+            compat: match self.verity {
+                true => self.compat | COMPAT_VERITY,
+                false => self.compat & !COMPAT_VERITY,
+            },
+            incompat: self.incompat(),
+            inode_limit: self.inode_limit,
+            merkle_root: Some(self.merkle_root()),
+        };
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        superblock.encode(&mut out);
+        self.encode_contents(&mut out);
+        let crc = crc32fast::hash(&out);
+        out.extend_from_slice(&crc.to_le_bytes());
+        out
+    }
+
+    /// Decode an image of version 2 or later.
+    fn decode(data: &[u8]) -> Result<Self, VfsError> {
+        let truncated = || VfsError::new(ErrorKind::DataCorruption, "truncated image".to_string());
+        let (body, crc) = data.split_last_chunk::<4>().ok_or_else(truncated)?;
+        if crc32fast::hash(body) != u32::from_le_bytes(*crc) {
+            return Err(VfsError::new(
+                ErrorKind::DataCorruption,
+                "corrupted image: checksum mismatch".to_string(),
+            ));
+        }
+        let mut reader = Reader::new(&body[MAGIC.len()..]);
+        let version = reader.u32().ok_or_else(truncated)?;
+        let superblock = Superblock::decode(&mut reader).ok_or_else(truncated)?;
+        if superblock.incompat & !INCOMPAT_SUPPORTED != 0 {
+            return Err(VfsError::new(
+                ErrorKind::InvalidInput,
+                format!("unsupported features in image version {}", version),
+            ));
+        }
+        if superblock.block_size != BLOCK_SIZE {
+            return Err(VfsError::new(
+                ErrorKind::InvalidInput,
+                format!("unsupported block size {}", superblock.block_size),
+            ));
+        }
+        let block_bitmap = reader
+            .take(superblock.blocks.div_ceil(8))
+            .ok_or_else(truncated)?;
+        let inode_bitmap = reader
+            .take(superblock.inodes.div_ceil(8))
+            .ok_or_else(truncated)?;
+        let mut layout = Layout {
+            limit: superblock.limit,
+            inode_limit: superblock.inode_limit,
+            compat: superblock.compat,
+            encrypted: superblock.incompat & INCOMPAT_ENCRYPTED != 0,
+            verity: superblock.compat & COMPAT_VERITY != 0,
+            ..Default::default()
+        };
+        for id in (0..superblock.inodes).filter(|&id| is_set(inode_bitmap, id)) {
+            let inode = Inode::decode(&mut reader, superblock.incompat).ok_or_else(truncated)?;
+            layout.inodes.insert(id, inode);
+        }
+        for id in (0..superblock.blocks).filter(|&id| is_set(block_bitmap, id)) {
+            let block = reader.take(layout.block_size()).ok_or_else(truncated)?;
+            layout.blocks.insert(id, block.to_vec());
+        }
+        if !reader.is_empty() && version == VERSION {
+            return Err(VfsError::new(
+                ErrorKind::DataCorruption,
+                "trailing data after image".to_string(),
+            ));
+        }
+        if superblock
+            .merkle_root
+            .is_some_and(|root| root != layout.merkle_root())
+        {
+            return Err(VfsError::new(
+                ErrorKind::DataCorruption,
+                "corrupted image: Merkle root mismatch".to_string(),
+            ));
+        }
+        layout.validate()?;
+        Ok(layout)
+    }
+
+    /// Drop blocks no file refers to anymore.
+    pub(crate) fn collect_garbage(&mut self) {
+        let referenced: BTreeSet<_> = self
+            .inodes
+            .values()
+            .filter_map(|inode| match &inode.kind {
+                InodeKind::File(blocks_refs) => Some(blocks_refs.iter().copied()),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        self.blocks.retain(|id, _| referenced.contains(id));
+    }
+
+    /// Check that every reference points to a used inode or block and
+    /// that directories can be walked.
+    pub(crate) fn validate(&self) -> Result<(), VfsError> {
+        let invalid = |reason: &str| {
+            Err(VfsError::new(
+                ErrorKind::DataCorruption,
+                format!("corrupted image: {}", reason),
+            ))
+        };
+        if self.blocks.contains_key(&0) {
+            return invalid("reserved block in use");
+        }
+        if self
+            .blocks
+            .values()
+            .any(|block| block.len() != self.block_size())
+        {
+            return invalid("short block");
+        }
+        let is_dir = |id| {
+            self.inodes
+                .get(id)
+                .is_some_and(|inode| matches!(inode.kind, InodeKind::Dir(_)))
+        };
+        if !is_dir(&0) {
+            return invalid("root is not a directory");
+        }
+        let mut referenced = BTreeSet::new();
+        for (id, inode) in &self.inodes {
+            match &inode.kind {
+                InodeKind::File(blocks_refs) => {
+                    if blocks_refs.len() != inode.size.div_ceil(BLOCK_SIZE) {
+                        return invalid("file size does not match its blocks");
+                    }
+                    for block_id in blocks_refs.iter().filter(|&&block_id| block_id != 0) {
+                        if !self.blocks.contains_key(block_id) {
+                            return invalid("reference to a free block");
+                        }
+                        referenced.insert(*block_id);
+                    }
+                }
+                InodeKind::Dir(entries) => {
+                    if entries
+                        .iter()
+                        .any(|(_, child_id)| !self.inodes.contains_key(child_id))
+                    {
+                        return invalid("dangling directory entry");
+                    }
+                    let lookup = |name: &str| {
+                        entries
+                            .iter()
+                            .find(|(entry, _)| entry == name)
+                            .map(|(_, child_id)| child_id)
+                    };
+                    if lookup(DOT) != Some(id) || !lookup(DOTDOT).is_some_and(is_dir) {
+                        return invalid("directory without '.' or '..'");
+                    }
+                }
+                InodeKind::Ring { capacity, data } => {
+                    if *capacity == 0 || data.len() > *capacity || data.len() != inode.size {
+                        return invalid("ring buffer larger than its capacity");
+                    }
+                }
+                InodeKind::Symlink(_) | InodeKind::Fifo => {}
+            }
+        }
+        if referenced.len() != self.blocks.len() {
+            return invalid("unreferenced block");
+        }
+        Ok(())
+    }
+}
+
+impl Vfs {
+    /// Whether inode `id` is written to images: devices and generated
+    /// files are live objects, and unlinked files are gone once the
+    /// descriptors holding them are.
+    fn is_persistent(&self, id: usize) -> bool {
+        let fd = &self.fds[id];
+        !self.fds_id.free.contains(&id)
+            && (id == 0 || fd.links > 0)
+            && !matches!(
+                fd.file_type,
+                FileType::Device(_) | FileType::Proc(_) | FileType::Archive(_)
+            )
+    }
+
+    /// Replace every inode and block with those of a validated `layout`,
+    /// keeping the block store's key and limit. Sealed blocks are kept as
+    /// they are and must decrypt with that key.
+    pub(crate) fn install(&mut self, layout: Layout) -> Result<(), VfsError> {
+        if layout.encrypted && !self.blocks.is_encrypted() {
+            return Err(VfsError::new(
+                ErrorKind::InvalidInput,
+                "image is encrypted: no encryption key given".to_string(),
+            ));
+        }
+        let mut block_refs = HashMap::new();
+        for inode in layout.inodes.values() {
+            if let InodeKind::File(blocks_refs) = &inode.kind {
+                for &block_id in blocks_refs.iter().filter(|&&block_id| block_id != 0) {
+                    *block_refs.entry(block_id).or_insert(0) += 1;
+                }
+            }
+        }
+        let mut blocks = self.blocks.emptied();
+        for (&id, block) in &layout.blocks {
+            let loaded = match layout.encrypted {
+                true => blocks.load_sealed(id, block, block_refs[&id]),
+                false => blocks.load(id, block, block_refs[&id]),
+            };
+            if !loaded {
+                return Err(VfsError::new(
+                    ErrorKind::NoSpace,
+                    "No space left on device".to_string(),
+                ));
+            }
+            if blocks.read(id).is_none() {
+                return Err(VfsError::new(
+                    ErrorKind::DataCorruption,
+                    format!("cannot decrypt block {}: wrong key or corrupted image", id),
+                ));
+            }
+        }
+        blocks.set_dedup(layout.compat & COMPAT_DEDUP != 0);
+
+        let (_, count) = layout.geometry();
+        let mut fds = vec![FileDescriptor::new_dir(0, 0); count];
+        let mut free = BTreeSet::from_iter(1..count);
+        for (id, inode) in layout.inodes {
+            let file_type = match inode.kind {
+                InodeKind::File(blocks_refs) => FileType::Regular(blocks_refs),
+                InodeKind::Dir(entries) => FileType::Directory(entries.into_iter().collect()),
+                InodeKind::Symlink(target) => FileType::Symlink(target),
+                InodeKind::Fifo => FileType::Fifo(VecDeque::new()),
+                InodeKind::Ring { capacity, data } => FileType::Ring(Ring::new(capacity, &data)),
+            };
+            fds[id] = FileDescriptor {
+                file_type,
+                size: inode.size,
+                links: inode.links,
+                refs: 0,
+                generation: 0,
+                uid: inode.uid,
+                gid: inode.gid,
+                mode: inode.mode,
+                acl: inode.acl,
+                attrs: inode.attrs,
+                project: inode.project,
+                times: inode.times,
+            };
+            free.remove(&id);
+        }
+        self.blocks = blocks;
+        self.fds = fds.into();
+        self.fds_id = Identity { free, next: count };
+        self.set_cwd(0, PATHNAME_SEPARATOR.to_string());
+        Ok(())
+    }
+
+    /// Serialize the whole filesystem into a version 2 image:
+    ///
+    /// ```text
+    /// "VFSI" | version u32 | superblock | block bitmap | inode bitmap
+    ///        | inode table | data area | crc32 u32
+    /// ```
+    ///
+    /// Bit `i` of a bitmap is set when block or inode `i` is in use; the
+    /// inode table holds the used inodes, each with its mode, owner, ACL,
+    /// attribute flags, timestamps and project ID, ring buffers with their
+    /// current window, and the data area the used blocks, both in id order.
+    /// On an encrypted volume each block is stored as its nonce, tag and
+    /// ciphertext, and loading the image needs the same key; otherwise
+    /// blocks are stored as plaintext. Ring buffers are never encrypted, so
+    /// saving an encrypted volume fails while one holds data. The
+    /// superblock carries a Merkle root over the inode table and the data
+    /// area, checked on load, and records whether the filesystem is in
+    /// verity mode. Integers are little endian and the trailing CRC32
+    /// covers everything before it.
+    pub fn to_image(&self) -> Result<Vec<u8>, VfsError> {
+        let corrupted = |block_ref| {
+            VfsError::new(
+                ErrorKind::DataCorruption,
+                format!(
+                    "image: cannot read block {}: Data corruption detected",
+                    block_ref
+                ),
+            )
+        };
+        let mut layout = Layout::capture(self).map_err(corrupted)?;
+        if self.blocks.is_encrypted() {
+            let is_filled_ring = |inode: &Inode| matches!(&inode.kind, InodeKind::Ring { data, .. } if !data.is_empty());
+            if layout.inodes.values().any(is_filled_ring) {
+                return Err(VfsError::new(
+                    ErrorKind::InvalidInput,
+                    "image: cannot store ring buffer contents in an encrypted image".to_string(),
+                ));
+            }
+            layout.seal(self).map_err(corrupted)?;
+        }
+        Ok(layout.encode())
+    }
+
+    /// Build a filesystem from an image produced by `to_image`, migrating
+    /// images written by older versions.
+    pub fn from_image(data: &[u8]) -> Result<Vfs, VfsError> {
+        VfsBuilder::new().build_from_image(data)
+    }
+}
+
+impl VfsBuilder {
+    /// Build a filesystem from an image instead of an empty one. The
+    /// image's size and inode limits apply unless `size` or `max_inodes`
+    /// override them, and an image saved in verity mode loads back into it.
+    pub fn build_from_image(self, data: &[u8]) -> Result<Vfs, VfsError> {
+        let (mut vfs, verity) = self
+            .load(data)
+            .map_err(|err| VfsError::new(err.kind, format!("image: {}", err)))?;
+        let max_inodes = vfs.max_inodes.take();
+        self.populate(&mut vfs);
+        vfs.max_inodes = max_inodes;
+        if verity {
+            vfs.enable_verity(vfs.merkle_root())?;
+        }
+        Ok(vfs)
+    }
+
+    /// Load an image without populating devices or generated files, and
+    /// tell whether it was saved in verity mode.
+    pub(crate) fn load(&self, data: &[u8]) -> Result<(Vfs, bool), VfsError> {
+        let mut reader = Reader::new(data);
+        if reader.take(MAGIC.len()) != Some(MAGIC) {
+            return Err(VfsError::new(
+                ErrorKind::InvalidInput,
+                "not a filesystem image".to_string(),
+            ));
+        }
+        let truncated = || VfsError::new(ErrorKind::DataCorruption, "truncated image".to_string());
+        match reader.u32().ok_or_else(truncated)? {
+            1 => self.migrate_v1(&mut reader).map(|vfs| (vfs, false)),
+            0 => Err(VfsError::new(
+                ErrorKind::InvalidInput,
+                "unsupported image version 0".to_string(),
+            )),
+            _ => {
+                let layout = Layout::decode(data)?;
+                let limit = match (self.block_limit(), layout.limit) {
+                    (Some(limit), _) => Some(limit),
+                    (None, 0) => None,
+                    (None, limit) => Some(limit),
+                };
+                let max_inodes = match (self.max_inodes, layout.inode_limit) {
+                    (Some(max_inodes), _) => Some(max_inodes),
+                    (None, 0) => None,
+                    (None, max_inodes) => Some(max_inodes),
+                };
+                let verity = layout.verity;
+                let mut vfs = self.empty(limit, max_inodes);
+                vfs.install(layout)?;
+                Ok((vfs, verity))
+            }
+        }
+    }
+
+    fn migrate_v1(&self, reader: &mut Reader) -> Result<Vfs, VfsError> {
+        let image = Image::decode_v1(reader)?;
+        let mut vfs = self.empty(self.block_limit(), self.max_inodes);
+        if vfs
+            .blocks
+            .available()
+            .is_some_and(|available| available < image.blocks_needed())
+        {
+            return Err(VfsError::new(
+                ErrorKind::NoSpace,
+                "No space left on device".to_string(),
+            ));
+        }
+        vfs.restore(&image, 0).ok_or_else(|| {
+            VfsError::new(ErrorKind::NoSpace, "No space left on device".to_string())
+        })?;
+        Ok(vfs)
+    }
+}
@@ -0,0 +1,321 @@
+use std::{error, fmt, io};
+
+use crate::{
+    namei::{join, LOOP, NOT_A_DIRECTORY},
+    Vfs, PATHNAME_SEPARATOR,
+};
+
+/// Format an I/O error like `strerror`, without the `(os error N)` suffix.
+pub(crate) fn strerror(err: &io::Error) -> String {
+    let message = err.to_string();
+    match message.find(" (os error") {
+        Some(idx) => message[..idx].to_string(),
+        None => message,
+    }
+}
+
+/// A failed host call after `context`, of the kind closest to `err`.
+pub(crate) fn host_error(context: String, err: &io::Error) -> VfsError {
+    VfsError::io(format!("{}: {}", context, strerror(err)), err)
+}
+
+/// What went wrong, set where the error is raised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    DirectoryNotEmpty,
+    PermissionDenied,
+    NotPermitted,
+    InvalidInput,
+    IllegalSeek,
+    NoSpace,
+    QuotaExceeded,
+    Loop,
+    ReadOnly,
+    Busy,
+    TooManyOpenFiles,
+    WouldBlock,
+    Deadlock,
+    BadDescriptor,
+    Stale,
+    Canceled,
+    DataCorruption,
+    Other,
+}
+
+// Linux errno values.
+const EPERM: i32 = 1;
+const ENOENT: i32 = 2;
+const EIO: i32 = 5;
+const EBADF: i32 = 9;
+const EAGAIN: i32 = 11;
+const EACCES: i32 = 13;
+const EBUSY: i32 = 16;
+const EEXIST: i32 = 17;
+const ENOTDIR: i32 = 20;
+const EISDIR: i32 = 21;
+const EINVAL: i32 = 22;
+const EMFILE: i32 = 24;
+const ENOSPC: i32 = 28;
+const ESPIPE: i32 = 29;
+const EROFS: i32 = 30;
+const EDEADLK: i32 = 35;
+const ENOTEMPTY: i32 = 39;
+pub(crate) const ELOOP: i32 = 40;
+const ESTALE: i32 = 116;
+const EUCLEAN: i32 = 117;
+const EDQUOT: i32 = 122;
+const ECANCELED: i32 = 125;
+
+impl ErrorKind {
+    /// The Linux `errno` for the kind; `EIO` for `Other`.
+    pub fn errno(self) -> i32 {
+        match self {
+            ErrorKind::NotFound => ENOENT,
+            ErrorKind::NotADirectory => ENOTDIR,
+            ErrorKind::IsADirectory => EISDIR,
+            ErrorKind::AlreadyExists => EEXIST,
+            ErrorKind::DirectoryNotEmpty => ENOTEMPTY,
+            ErrorKind::PermissionDenied => EACCES,
+            ErrorKind::NotPermitted => EPERM,
+            ErrorKind::InvalidInput => EINVAL,
+            ErrorKind::IllegalSeek => ESPIPE,
+            ErrorKind::NoSpace => ENOSPC,
+            ErrorKind::QuotaExceeded => EDQUOT,
+            ErrorKind::Loop => ELOOP,
+            ErrorKind::ReadOnly => EROFS,
+            ErrorKind::Busy => EBUSY,
+            ErrorKind::TooManyOpenFiles => EMFILE,
+            ErrorKind::WouldBlock => EAGAIN,
+            ErrorKind::Deadlock => EDEADLK,
+            ErrorKind::BadDescriptor => EBADF,
+            ErrorKind::Stale => ESTALE,
+            ErrorKind::Canceled => ECANCELED,
+            ErrorKind::DataCorruption => EUCLEAN,
+            ErrorKind::Other => EIO,
+        }
+    }
+}
+
+/// Why resolving a path stopped at a component.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cause {
+    /// The directory has no entry of that name.
+    Missing,
+    /// The component is not a directory but more components follow it.
+    NotADirectory,
+    /// The component is a symlink whose target does not resolve.
+    DanglingSymlink { target: String },
+    /// Too many symlinks were followed from the component on, or it is a
+    /// symlink in a `nosymfollow` directory.
+    Loop,
+}
+
+/// The component of a path that resolution failed at, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Component {
+    /// The path as written up to and including the component.
+    pub path: String,
+    pub name: String,
+    pub cause: Cause,
+}
+
+impl fmt::Display for Component {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.cause {
+            Cause::Missing => write!(
+                f,
+                "no entry '{}' in '{}'",
+                self.name,
+                Vfs::dirname(&self.path)
+            ),
+            Cause::NotADirectory => write!(f, "'{}' is not a directory", self.path),
+            Cause::DanglingSymlink { target } => {
+                write!(f, "'{}' is a dangling symlink to '{}'", self.path, target)
+            }
+            Cause::Loop => write!(f, "'{}' is a symlink loop", self.path),
+        }
+    }
+}
+
+/// An error of a `Vfs` call: the message the shell prints, its kind and,
+/// when resolving a path failed, the component it failed at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VfsError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub component: Option<Component>,
+}
+
+impl VfsError {
+    pub fn new(kind: ErrorKind, message: String) -> Self {
+        Self {
+            kind,
+            message,
+            component: None,
+        }
+    }
+
+    /// An error of a host call, of the kind closest to that of `err`.
+    pub(crate) fn io(message: String, err: &io::Error) -> Self {
+        let kind = match err.kind() {
+            io::ErrorKind::NotFound => ErrorKind::NotFound,
+            io::ErrorKind::NotADirectory => ErrorKind::NotADirectory,
+            io::ErrorKind::IsADirectory => ErrorKind::IsADirectory,
+            io::ErrorKind::AlreadyExists => ErrorKind::AlreadyExists,
+            io::ErrorKind::DirectoryNotEmpty => ErrorKind::DirectoryNotEmpty,
+            io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+            io::ErrorKind::InvalidInput | io::ErrorKind::InvalidFilename => ErrorKind::InvalidInput,
+            io::ErrorKind::InvalidData => ErrorKind::DataCorruption,
+            io::ErrorKind::NotSeekable => ErrorKind::IllegalSeek,
+            io::ErrorKind::StorageFull => ErrorKind::NoSpace,
+            io::ErrorKind::QuotaExceeded => ErrorKind::QuotaExceeded,
+            io::ErrorKind::ReadOnlyFilesystem => ErrorKind::ReadOnly,
+            io::ErrorKind::ResourceBusy => ErrorKind::Busy,
+            io::ErrorKind::WouldBlock => ErrorKind::WouldBlock,
+            io::ErrorKind::Deadlock => ErrorKind::Deadlock,
+            io::ErrorKind::StaleNetworkFileHandle => ErrorKind::Stale,
+            _ => ErrorKind::Other,
+        };
+        Self::new(kind, message)
+    }
+
+    /// The `errno` a FUSE, 9P or NFS server or a C caller should return.
+    pub fn errno(&self) -> i32 {
+        self.kind.errno()
+    }
+}
+
+impl fmt::Display for VfsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl error::Error for VfsError {}
+
+impl From<VfsError> for String {
+    fn from(err: VfsError) -> Self {
+        err.message
+    }
+}
+
+/// Carries the `VfsError` as the inner error, for callers to downcast.
+impl From<VfsError> for io::Error {
+    fn from(err: VfsError) -> Self {
+        let kind = match err.kind {
+            ErrorKind::NotFound => io::ErrorKind::NotFound,
+            ErrorKind::NotADirectory => io::ErrorKind::NotADirectory,
+            ErrorKind::IsADirectory => io::ErrorKind::IsADirectory,
+            ErrorKind::AlreadyExists => io::ErrorKind::AlreadyExists,
+            ErrorKind::DirectoryNotEmpty => io::ErrorKind::DirectoryNotEmpty,
+            ErrorKind::PermissionDenied | ErrorKind::NotPermitted => {
+                io::ErrorKind::PermissionDenied
+            }
+            ErrorKind::InvalidInput | ErrorKind::BadDescriptor => io::ErrorKind::InvalidInput,
+            ErrorKind::IllegalSeek => io::ErrorKind::NotSeekable,
+            ErrorKind::NoSpace => io::ErrorKind::StorageFull,
+            ErrorKind::QuotaExceeded => io::ErrorKind::QuotaExceeded,
+            ErrorKind::ReadOnly => io::ErrorKind::ReadOnlyFilesystem,
+            ErrorKind::Busy => io::ErrorKind::ResourceBusy,
+            ErrorKind::WouldBlock => io::ErrorKind::WouldBlock,
+            ErrorKind::Deadlock => io::ErrorKind::Deadlock,
+            ErrorKind::Stale => io::ErrorKind::StaleNetworkFileHandle,
+            ErrorKind::DataCorruption => io::ErrorKind::InvalidData,
+            // The descriptor limit is a quota of open files.
+            ErrorKind::TooManyOpenFiles => io::ErrorKind::QuotaExceeded,
+            ErrorKind::Loop => filesystem_loop(),
+            ErrorKind::Canceled | ErrorKind::Other => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// `FilesystemLoop`, which std has yet to name on stable, but decodes
+/// from `ELOOP`.
+#[cfg(target_os = "linux")]
+fn filesystem_loop() -> io::ErrorKind {
+    io::Error::from_raw_os_error(ELOOP).kind()
+}
+
+/// Elsewhere `ELOOP` has another value; a loop is one link too many.
+#[cfg(not(target_os = "linux"))]
+fn filesystem_loop() -> io::ErrorKind {
+    io::ErrorKind::TooManyLinks
+}
+
+impl Vfs {
+    /// Add to the error of a call that failed on `pathname` the component
+    /// resolution failed at, when the kind says it did.
+    pub(crate) fn error_at(&self, mut err: VfsError, pathname: &str) -> VfsError {
+        if !matches!(
+            err.kind,
+            ErrorKind::NotFound | ErrorKind::NotADirectory | ErrorKind::Loop
+        ) {
+            return err;
+        }
+        if let Some(component) = self.failed_component(pathname) {
+            let detail = format!(" ({})", component);
+            if !err.message.ends_with(&detail) {
+                err.message.push_str(&detail);
+            }
+            err.kind = match component.cause {
+                Cause::NotADirectory => ErrorKind::NotADirectory,
+                Cause::Loop => ErrorKind::Loop,
+                _ => err.kind,
+            };
+            err.component = Some(component);
+        }
+        err
+    }
+
+    /// Like `error_at` for a call on two paths, `pathname1` naming an
+    /// existing file and `pathname2` a new name for it.
+    pub(crate) fn error_at2(&self, err: VfsError, pathname1: &str, pathname2: &str) -> VfsError {
+        match self.trace_resolve(pathname1).error {
+            Some(_) => self.error_at(err, pathname1),
+            None => self.error_at(err, &Vfs::dirname(pathname2)),
+        }
+    }
+
+    /// Where and why resolving `pathname`, following symlinks, fails.
+    fn failed_component(&self, pathname: &str) -> Option<Component> {
+        let trace = self.trace_resolve(pathname);
+        let (seg, depth, reason) = trace.error?;
+        let mut path = String::new();
+        let mut last = None;
+        for step in trace.steps.iter().filter(|step| step.depth == 0) {
+            path = match step.name.as_str() {
+                PATHNAME_SEPARATOR => PATHNAME_SEPARATOR.to_string(),
+                name if path.is_empty() => name.to_string(),
+                name => join(&path, name),
+            };
+            last = Some(step);
+        }
+        let looped = reason == LOOP;
+        let (name, cause) = match (depth, last) {
+            (0, _) if looped => (seg, Cause::Loop),
+            (0, _) if reason == NOT_A_DIRECTORY => (last?.name.clone(), Cause::NotADirectory),
+            (0, _) => {
+                path = if path.is_empty() {
+                    seg.clone()
+                } else {
+                    join(&path, &seg)
+                };
+                (seg, Cause::Missing)
+            }
+            (_, Some(symlink)) if looped => (symlink.name.clone(), Cause::Loop),
+            (_, Some(symlink)) => (
+                symlink.name.clone(),
+                Cause::DanglingSymlink {
+                    target: symlink.target.clone()?,
+                },
+            ),
+            (_, None) => return None,
+        };
+        Some(Component { path, name, cause })
+    }
+}
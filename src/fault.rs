@@ -0,0 +1,97 @@
+use std::collections::BTreeSet;
+
+use crate::Vfs;
+
+/// Faults to inject into a `Vfs`, so that code built on top of it can test
+/// its error handling deterministically. Counting starts when the plan is
+/// installed.
+#[derive(Debug, Clone, Default)]
+pub struct FaultPlan {
+    alloc: Option<usize>,
+    blocks: BTreeSet<usize>,
+    corrupt: BTreeSet<usize>,
+    write_limit: Option<usize>,
+}
+
+impl FaultPlan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fail the `n`th block allocation, counting from 1, as if the device
+    /// were full; later allocations succeed again.
+    pub fn fail_alloc(mut self, n: usize) -> Self {
+        self.alloc = Some(n);
+        self
+    }
+
+    /// Fail every read of block `block_id` with an I/O error.
+    pub fn fail_block_read(mut self, block_id: usize) -> Self {
+        self.blocks.insert(block_id);
+        self
+    }
+
+    /// Flip a bit of block `block_id` as stored when the plan is installed,
+    /// so that it fails verification like a decayed block would. Unlike
+    /// the other faults this one stays after the plan is replaced.
+    pub fn corrupt_block(mut self, block_id: usize) -> Self {
+        self.corrupt.insert(block_id);
+        self
+    }
+
+    /// Fail writes to regular files with an I/O error once `bytes` bytes
+    /// have been submitted; the write crossing the limit stores what fits
+    /// before failing.
+    pub fn fail_writes_after(mut self, bytes: usize) -> Self {
+        self.write_limit = Some(bytes);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Faults {
+    plan: FaultPlan,
+    written: usize,
+}
+
+impl Faults {
+    pub(crate) fn fails_read(&self, block_id: usize) -> bool {
+        self.plan.blocks.contains(&block_id)
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn limits_writes(&self) -> bool {
+        self.plan.write_limit.is_some()
+    }
+
+    /// Account for a write of `len` bytes, returning how many of them may
+    /// be written.
+    pub(crate) fn allow_write(&mut self, len: usize) -> usize {
+        let allowed = match self.plan.write_limit {
+            Some(limit) => len.min(limit.saturating_sub(self.written)),
+            None => len,
+        };
+        self.written += allowed;
+        allowed
+    }
+}
+
+impl Vfs {
+    /// Install `plan`, replacing any faults injected before.
+    pub fn with_faults(mut self, plan: FaultPlan) -> Self {
+        self.set_faults(plan);
+        self
+    }
+
+    pub fn set_faults(&mut self, plan: FaultPlan) {
+        self.blocks.fail_alloc(plan.alloc);
+        for &block_id in &plan.corrupt {
+            self.blocks.corrupt(block_id);
+        }
+        self.faults = Faults { plan, written: 0 };
+    }
+
+    pub fn clear_faults(&mut self) {
+        self.set_faults(FaultPlan::new());
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{ErrorKind, Op, Vfs, VfsError};
+
+/// Writes go to the end of the file, whatever the offset.
+pub const O_APPEND: u32 = 0o2000;
+/// Reads from an empty FIFO that is open for writing fail with `Resource
+/// temporarily unavailable` rather than `Resource deadlock avoided`.
+pub const O_NONBLOCK: u32 = 0o4000;
+/// The descriptor is closed when the filesystem switches to another
+/// session, like `FD_CLOEXEC` on `execve(2)`.
+pub const O_CLOEXEC: u32 = 0o2000000;
+
+/// Every descriptor flag there is.
+pub(crate) const FD_FLAGS: u32 = O_APPEND | O_NONBLOCK | O_CLOEXEC;
+
+const NAMES: [(u32, &str); 3] = [
+    (O_APPEND, "append"),
+    (O_NONBLOCK, "nonblock"),
+    (O_CLOEXEC, "cloexec"),
+];
+
+/// The `lsof` form of descriptor flags, e.g. `append,cloexec`, or `-` if
+/// there are none.
+pub fn fd_flags_string(flags: u32) -> String {
+    let names: Vec<_> = NAMES
+        .iter()
+        .filter(|&&(flag, _)| flags & flag != 0)
+        .map(|&(_, name)| name)
+        .collect();
+    match names.is_empty() {
+        true => "-".to_string(),
+        false => names.join(","),
+    }
+}
+
+/// Parse comma-separated flag names, so that `fd_flags_string`
+/// round-trips.
+pub fn parse_fd_flags(s: &str) -> Option<u32> {
+    if s == "-" {
+        return Some(0);
+    }
+    s.split(',').try_fold(0, |flags, word| {
+        NAMES
+            .iter()
+            .find(|&&(_, name)| name == word)
+            .map(|&(flag, _)| flags | flag)
+    })
+}
+
+impl Vfs {
+    /// The flags of descriptor `oid` (`O_APPEND`, `O_NONBLOCK`,
+    /// `O_CLOEXEC`), like `fcntl(F_GETFL)` and `fcntl(F_GETFD)` together.
+    pub fn fd_flags(&self, oid: usize) -> Result<u32, VfsError> {
+        match self.open_fds.contains_key(&oid) {
+            true => Ok(self.open_fd_flags.get(&oid).copied().unwrap_or(0)),
+            false => Err(VfsError::new(
+                ErrorKind::BadDescriptor,
+                format!("fcntl: invalid file descriptor: {}", oid),
+            )),
+        }
+    }
+
+    /// Replace the flags of descriptor `oid`, like `fcntl(F_SETFL)`.
+    ///
+    /// The filesystem cannot wait for a writer, as nothing else can write
+    /// while a call runs, so a read of an empty FIFO that is open for
+    /// writing fails either way: with `WouldBlock` under `O_NONBLOCK`, for
+    /// the caller to retry once something was written, and otherwise with
+    /// `Deadlock`, as the read would never return.
+    pub fn set_fd_flags(&mut self, oid: usize, flags: u32) -> Result<(), VfsError> {
+        let result = self.set_fd_flags_unaudited(oid, flags);
+        self.audit(|| Op::Fcntl { fd: oid, flags }, &result);
+        result
+    }
+
+    fn set_fd_flags_unaudited(&mut self, oid: usize, flags: u32) -> Result<(), VfsError> {
+        self.fd_flags(oid)?;
+        if flags & !FD_FLAGS != 0 {
+            return Err(VfsError::new(
+                ErrorKind::InvalidInput,
+                format!("fcntl: cannot set flags of {}: Invalid argument", oid),
+            ));
+        }
+        match flags {
+            0 => self.open_fd_flags.remove(&oid),
+            flags => self.open_fd_flags.insert(oid, flags),
+        };
+        Ok(())
+    }
+
+    /// The open descriptors as descriptor, inode and offset, in
+    /// descriptor order.
+    pub fn open_descriptors(&self) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+        self.open_fds
+            .iter()
+            .map(|(&oid, &(id, cursor))| (oid, id, cursor))
+    }
+
+    /// Whether writes through descriptor `oid` go to the end of the file.
+    pub(crate) fn is_append_fd(&self, oid: usize) -> bool {
+        self.open_fd_flags
+            .get(&oid)
+            .is_some_and(|flags| flags & O_APPEND != 0)
+    }
+
+    /// Close the descriptors flagged `O_CLOEXEC`, as switching sessions
+    /// does.
+    pub(crate) fn close_on_exec(&mut self) {
+        let oids: Vec<_> = self
+            .open_fd_flags
+            .iter()
+            .filter(|(_, &flags)| flags & O_CLOEXEC != 0)
+            .map(|(&oid, _)| oid)
+            .collect();
+        for oid in oids {
+            let _ = self.close(oid);
+        }
+    }
+}
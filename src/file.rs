@@ -0,0 +1,78 @@
+use std::io::{self, BufRead, Read};
+
+use crate::{error::host_error, Vfs, VfsError, BLOCK_SIZE};
+
+/// An open file that implements `Read` and `BufRead`, reading a block at
+/// a time. The descriptor is closed when the handle is dropped.
+#[derive(Debug)]
+pub struct VfsFile<'a> {
+    vfs: &'a mut Vfs,
+    fd: usize,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl VfsFile<'_> {
+    /// The descriptor the handle reads from.
+    pub fn fd(&self) -> usize {
+        self.fd
+    }
+}
+
+impl Read for VfsFile<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for VfsFile<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.buf.len() {
+            self.buf = self.vfs.read(self.fd, BLOCK_SIZE)?;
+            self.pos = 0;
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buf.len());
+    }
+}
+
+impl Drop for VfsFile<'_> {
+    fn drop(&mut self) {
+        let _ = self.vfs.close(self.fd);
+    }
+}
+
+impl Vfs {
+    /// Open `pathname` as a handle for `std::io`.
+    pub fn open_file(&mut self, pathname: &str) -> Result<VfsFile<'_>, VfsError> {
+        let fd = self.open(pathname)?;
+        Ok(VfsFile {
+            vfs: self,
+            fd,
+            buf: Vec::new(),
+            pos: 0,
+        })
+    }
+
+    /// Stream the lines of `pathname` without their line endings, reading
+    /// a block at a time rather than the whole file.
+    pub fn read_lines<'a>(
+        &'a mut self,
+        pathname: &'a str,
+    ) -> Result<impl Iterator<Item = Result<String, VfsError>> + 'a, VfsError> {
+        let lines = self.open_file(pathname)?.lines();
+        Ok(lines.map(move |line| {
+            line.map_err(|err| match err.downcast::<VfsError>() {
+                Ok(err) => err,
+                Err(err) => host_error(format!("read: cannot read '{}'", pathname), &err),
+            })
+        }))
+    }
+}
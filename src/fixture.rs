@@ -0,0 +1,315 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{error::host_error, namei::join, ErrorKind, Vfs, VfsError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Dir(VfsFixture),
+    File(Vec<u8>),
+    Symlink(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FixtureEntry {
+    name: String,
+    node: Node,
+    mode: Option<u32>,
+    owner: Option<u32>,
+    group: Option<u32>,
+}
+
+/// A tree to create in tests, built in code:
+///
+/// ```
+/// # use vfs::VfsFixture;
+/// let vfs = VfsFixture::new()
+///     .dir("a", |d| d.file("b.txt", "hello").symlink("c", "/a/b.txt"))
+///     .build()
+///     .unwrap();
+/// assert_eq!(vfs.read_file("/a/b.txt").unwrap(), b"hello");
+/// ```
+///
+/// with `vfs_tree!`, or loaded from a YAML or JSON manifest, a list of
+/// entries such as
+///
+/// ```yaml
+/// - dir: a
+///   mode: 0o750
+///   owner: 1000
+///   group: 100
+///   entries:
+///     - file: b.txt
+///       content: hello
+///     - file: logo.png
+///       source: assets/logo.png
+///     - symlink: c
+///       target: /a/b.txt
+/// ```
+///
+/// where `source` names a host file to copy, relative to the manifest.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VfsFixture {
+    entries: Vec<FixtureEntry>,
+}
+
+impl VfsFixture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(mut self, name: &str, node: Node) -> Self {
+        self.entries.push(FixtureEntry {
+            name: name.to_string(),
+            node,
+            mode: None,
+            owner: None,
+            group: None,
+        });
+        self
+    }
+
+    /// Add directory `name` with the entries `f` adds to it.
+    pub fn dir<F>(self, name: &str, f: F) -> Self
+    where
+        F: FnOnce(VfsFixture) -> VfsFixture,
+    {
+        self.push(name, Node::Dir(f(VfsFixture::new())))
+    }
+
+    pub fn file<T: AsRef<[u8]>>(self, name: &str, content: T) -> Self {
+        self.push(name, Node::File(content.as_ref().to_vec()))
+    }
+
+    pub fn symlink(self, name: &str, target: &str) -> Self {
+        self.push(name, Node::Symlink(target.to_string()))
+    }
+
+    /// Set the permission bits of the entry added last.
+    pub fn mode(mut self, mode: u32) -> Self {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.mode = Some(mode);
+        }
+        self
+    }
+
+    /// Set the owner and group of the entry added last.
+    pub fn owner(mut self, uid: u32, gid: u32) -> Self {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.owner = Some(uid);
+            entry.group = Some(gid);
+        }
+        self
+    }
+
+    /// Parse a YAML or JSON manifest; `source` paths are relative to the
+    /// working directory of the process.
+    pub fn from_manifest(text: &str) -> Result<Self, VfsError> {
+        Self::parse(text, Path::new(""))
+    }
+
+    fn parse(text: &str, base: &Path) -> Result<Self, VfsError> {
+        let entries: Vec<ManifestEntry> = serde_yaml::from_str(text).map_err(|err| {
+            let message = format!("manifest: cannot parse manifest: {}", err);
+            VfsError::new(ErrorKind::InvalidInput, message)
+        })?;
+        Self::from_entries(entries, base)
+    }
+
+    /// Read and parse the manifest at host path `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, VfsError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|err| {
+            host_error(format!("manifest: cannot read '{}'", path.display()), &err)
+        })?;
+        Self::parse(&text, path.parent().unwrap_or(Path::new("")))
+    }
+
+    fn from_entries(entries: Vec<ManifestEntry>, base: &Path) -> Result<Self, VfsError> {
+        let mut fixture = Self::new();
+        for entry in entries {
+            let entry_name = entry.name().unwrap_or_default().to_string();
+            let invalid = |what: &str| {
+                let message = format!("manifest: invalid entry '{}': {}", entry_name, what);
+                Err(VfsError::new(ErrorKind::InvalidInput, message))
+            };
+            let (name, node) = match (&entry.dir, &entry.file, &entry.symlink) {
+                (Some(name), None, None) => {
+                    if entry.content.is_some() || entry.source.is_some() || entry.target.is_some() {
+                        return invalid("directories take only entries");
+                    }
+                    (name, Node::Dir(Self::from_entries(entry.entries, base)?))
+                }
+                (None, Some(name), None) => {
+                    if entry.target.is_some() || !entry.entries.is_empty() {
+                        return invalid("files take only content or a source");
+                    }
+                    let content = match (entry.content, &entry.source) {
+                        (Some(_), Some(_)) => return invalid("files take content or a source"),
+                        (Some(content), None) => content.into_bytes(),
+                        (None, Some(source)) => {
+                            let source = base.join(source);
+                            fs::read(&source).map_err(|err| {
+                                host_error(
+                                    format!("manifest: cannot read '{}'", source.display()),
+                                    &err,
+                                )
+                            })?
+                        }
+                        (None, None) => Vec::new(),
+                    };
+                    (name, Node::File(content))
+                }
+                (None, None, Some(name)) => {
+                    if entry.content.is_some()
+                        || entry.source.is_some()
+                        || !entry.entries.is_empty()
+                    {
+                        return invalid("symlinks take only a target");
+                    }
+                    let Some(target) = entry.target else {
+                        return invalid("symlinks need a target");
+                    };
+                    (name, Node::Symlink(target))
+                }
+                _ => return invalid("need exactly one of dir, file and symlink"),
+            };
+            let mode = match entry.mode {
+                Some(mode) => Some(mode.bits()?),
+                None => None,
+            };
+            fixture.entries.push(FixtureEntry {
+                name: name.clone(),
+                node,
+                mode,
+                owner: entry.owner,
+                group: entry.group,
+            });
+        }
+        Ok(fixture)
+    }
+
+    /// Create the entries below directory `pathname` of `vfs`, as the
+    /// current session. Modes and owners are set once a directory's
+    /// entries are in place, so a read-only directory can still be filled.
+    pub fn apply(&self, vfs: &mut Vfs, pathname: &str) -> Result<(), VfsError> {
+        for entry in &self.entries {
+            let path = join(pathname, &entry.name);
+            match &entry.node {
+                Node::Dir(fixture) => {
+                    vfs.mkdir(&path)?;
+                    fixture.apply(vfs, &path)?;
+                }
+                Node::File(content) => vfs.write_file(&path, content)?,
+                Node::Symlink(target) => vfs.symlink(target, &path)?,
+            }
+            if let Some(mode) = entry.mode {
+                vfs.chmod(&path, mode)?;
+            }
+            if entry.owner.is_some() || entry.group.is_some() {
+                vfs.chown(&path, entry.owner, entry.group)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// A new filesystem holding the fixture below its root.
+    pub fn build(&self) -> Result<Vfs, VfsError> {
+        let mut vfs = Vfs::new();
+        self.apply(&mut vfs, "/")?;
+        Ok(vfs)
+    }
+}
+
+impl Vfs {
+    /// Create what `manifest` describes below the root, as the current
+    /// session.
+    pub fn seed(&mut self, manifest: &VfsFixture) -> Result<(), VfsError> {
+        manifest.apply(self, "/")
+    }
+}
+
+/// Permission bits as a number, which YAML may write in octal as `0o644`,
+/// or as a string of octal digits, `"644"`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Mode {
+    Number(u32),
+    Text(String),
+}
+
+impl Mode {
+    fn bits(&self) -> Result<u32, VfsError> {
+        match self {
+            Mode::Number(mode) => Ok(*mode),
+            Mode::Text(text) => u32::from_str_radix(text, 8).map_err(|_| {
+                let message = format!("manifest: invalid mode '{}'", text);
+                VfsError::new(ErrorKind::InvalidInput, message)
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestEntry {
+    dir: Option<String>,
+    file: Option<String>,
+    symlink: Option<String>,
+    content: Option<String>,
+    source: Option<PathBuf>,
+    target: Option<String>,
+    mode: Option<Mode>,
+    owner: Option<u32>,
+    group: Option<u32>,
+    #[serde(default)]
+    entries: Vec<ManifestEntry>,
+}
+
+impl ManifestEntry {
+    fn name(&self) -> Option<&str> {
+        self.dir
+            .as_deref()
+            .or(self.file.as_deref())
+            .or(self.symlink.as_deref())
+    }
+}
+
+/// Build a `VfsFixture` from a nested listing: `"name" => { ... }` for a
+/// directory, `"name" => content` for a file and `"name" -> "target"` for
+/// a symlink.
+///
+/// ```
+/// let vfs = vfs::vfs_tree! {
+///     "a" => {
+///         "b.txt" => "hello",
+///         "c" -> "/a/b.txt",
+///     },
+/// }
+/// .build()
+/// .unwrap();
+/// assert_eq!(vfs.read_file("/a/b.txt").unwrap(), b"hello");
+/// ```
+#[macro_export]
+macro_rules! vfs_tree {
+    (@entries $fixture:expr;) => { $fixture };
+    (@entries $fixture:expr; $name:literal => { $($inner:tt)* } $(, $($rest:tt)*)?) => {
+        $crate::vfs_tree!(
+            @entries $fixture.dir($name, |dir| $crate::vfs_tree!(@entries dir; $($inner)*));
+            $($($rest)*)?
+        )
+    };
+    (@entries $fixture:expr; $name:literal -> $target:literal $(, $($rest:tt)*)?) => {
+        $crate::vfs_tree!(@entries $fixture.symlink($name, $target); $($($rest)*)?)
+    };
+    (@entries $fixture:expr; $name:literal => $content:expr $(, $($rest:tt)*)?) => {
+        $crate::vfs_tree!(@entries $fixture.file($name, $content); $($($rest)*)?)
+    };
+    ($($entries:tt)*) => {
+        $crate::vfs_tree!(@entries $crate::VfsFixture::new(); $($entries)*)
+    };
+}
@@ -0,0 +1,63 @@
+use std::ops::{Deref, DerefMut};
+
+use crate::{Vfs, VfsError};
+
+/// An open descriptor that is closed when the guard is dropped, so an
+/// early return cannot leak it and keep an unlinked file's blocks in use.
+/// The guard derefs to the `Vfs`, which stays usable through it.
+#[derive(Debug)]
+pub struct OpenGuard<'a> {
+    vfs: &'a mut Vfs,
+    fd: usize,
+}
+
+impl OpenGuard<'_> {
+    pub fn fd(&self) -> usize {
+        self.fd
+    }
+
+    /// Keep the descriptor open past the guard and return it.
+    pub fn into_fd(self) -> usize {
+        let fd = self.fd;
+        std::mem::forget(self);
+        fd
+    }
+}
+
+impl Deref for OpenGuard<'_> {
+    type Target = Vfs;
+
+    fn deref(&self) -> &Vfs {
+        self.vfs
+    }
+}
+
+impl DerefMut for OpenGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Vfs {
+        self.vfs
+    }
+}
+
+impl Drop for OpenGuard<'_> {
+    fn drop(&mut self) {
+        let _ = self.vfs.close(self.fd);
+    }
+}
+
+impl Vfs {
+    /// Open `pathname` like `open`, closing the descriptor when the
+    /// returned guard goes out of scope.
+    pub fn open_scoped(&mut self, pathname: &str) -> Result<OpenGuard<'_>, VfsError> {
+        let fd = self.open(pathname)?;
+        Ok(OpenGuard { vfs: self, fd })
+    }
+
+    /// Close every open descriptor and return how many there were.
+    pub fn close_all(&mut self) -> usize {
+        let fds: Vec<_> = self.open_fds.keys().copied().collect();
+        for &fd in &fds {
+            let _ = self.close(fd);
+        }
+        fds.len()
+    }
+}
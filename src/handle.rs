@@ -0,0 +1,120 @@
+use std::{fmt, str::FromStr};
+
+use crate::{fcntl::FD_FLAGS, ErrorKind, Statx, Vfs, VfsError, STATX_ALL};
+
+/// A persistent reference to a file independent of its path, as used by
+/// NFS, 9P and FUSE: the inode number and the generation it had when the
+/// handle was made. Once the inode is freed and its number reused, the
+/// generation differs and the handle is stale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileHandle {
+    pub inode: usize,
+    pub generation: u64,
+}
+
+impl fmt::Display for FileHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.inode, self.generation)
+    }
+}
+
+impl FromStr for FileHandle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid file handle: {}", s);
+        let (inode, generation) = s.split_once(':').ok_or_else(invalid)?;
+        Ok(FileHandle {
+            inode: inode.parse().map_err(|_| invalid())?,
+            generation: generation.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl Vfs {
+    /// Make a handle for the file at `pathname`, like `name_to_handle_at(2)`.
+    pub fn handle(&self, pathname: &str) -> Result<FileHandle, VfsError> {
+        match self.resolve(pathname) {
+            Some((fd, id, _)) => Ok(FileHandle {
+                inode: id,
+                generation: fd.generation,
+            }),
+            None => {
+                let message = VfsError::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "handle: cannot access '{}': No such file or directory",
+                        pathname
+                    ),
+                );
+                Err(self.error_at(message, pathname))
+            }
+        }
+    }
+
+    /// Open the file a handle refers to, like `open_by_handle_at(2)`;
+    /// fails with `Stale file handle` once the file is gone.
+    pub fn open_by_handle(&mut self, handle: FileHandle) -> Result<usize, VfsError> {
+        let id = self.live_handle("open: cannot open", handle)?;
+        let file_type = &self.fds[id].file_type;
+        if file_type.is_dir() || file_type.is_symlink() {
+            return Err(VfsError::new(
+                ErrorKind::NotPermitted,
+                format!(
+                    "open: cannot open handle {}: Operation not permitted",
+                    handle
+                ),
+            ));
+        }
+        self.check_open(id, || format!("open: cannot open handle {}", handle))?;
+        Ok(self.open_id(id))
+    }
+
+    /// Open inode `inode` if it still has generation `generation`, with
+    /// descriptor flags `flags`, so that a stateless server can reopen a
+    /// file from the handle it gave out without resolving a path.
+    pub fn open_by_inode(
+        &mut self,
+        inode: usize,
+        generation: u64,
+        flags: u32,
+    ) -> Result<usize, VfsError> {
+        let handle = FileHandle { inode, generation };
+        if flags & !FD_FLAGS != 0 {
+            return Err(VfsError::new(
+                ErrorKind::InvalidInput,
+                format!("open: cannot open handle {}: Invalid argument", handle),
+            ));
+        }
+        let oid = self.open_by_handle(handle)?;
+        if flags != 0 {
+            self.open_fd_flags.insert(oid, flags);
+        }
+        Ok(oid)
+    }
+
+    /// Metadata of inode `inode` if it still has generation `generation`,
+    /// named by one of its paths, or by the handle if it has none left.
+    pub fn stat_by_inode(&self, inode: usize, generation: u64) -> Result<Statx, VfsError> {
+        let handle = FileHandle { inode, generation };
+        let id = self.live_handle("stat: cannot statx", handle)?;
+        let name = self.path_of(id).unwrap_or_else(|| handle.to_string());
+        Ok(self.fds[id].statx(id, &name, STATX_ALL))
+    }
+
+    /// The inode `handle` refers to, or a `Stale file handle` error after
+    /// `context` once that file is gone.
+    fn live_handle(&self, context: &str, handle: FileHandle) -> Result<usize, VfsError> {
+        let id = handle.inode;
+        let live = id < self.fds.len()
+            && !self.fds_id.free.contains(&id)
+            && self.fds[id].generation == handle.generation;
+        match live {
+            true => Ok(id),
+            false => Err(VfsError::new(
+                ErrorKind::Stale,
+                format!("{} handle {}: Stale file handle", context, handle),
+            )),
+        }
+    }
+}
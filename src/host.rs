@@ -0,0 +1,507 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use crate::{
+    archive_error, error::host_error, namei::join, ErrorKind, FileDescriptor, FileType, Monitor,
+    SetTime, Timespec, Vfs, VfsBuilder, VfsError, BLOCK_SIZE, DOT, DOTDOT,
+};
+
+#[derive(Debug, Default)]
+pub struct SyncStats {
+    pub copied: usize,
+    pub unchanged: usize,
+}
+
+impl fmt::Display for SyncStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Copied: {} \tUnchanged: {}", self.copied, self.unchanged)
+    }
+}
+
+/// Copy parameters shared by the whole walk of one sync.
+struct CopyJob<'a, 'm> {
+    cmd: &'a str,
+    incremental: bool,
+    stats: SyncStats,
+    monitor: &'m mut Monitor<'a>,
+}
+
+#[cfg(unix)]
+fn host_symlink(target: &str, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn host_symlink(_target: &str, _link: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(unix)]
+fn host_mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn host_mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn set_host_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_host_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+fn host_mtime(metadata: &fs::Metadata) -> Option<Timespec> {
+    metadata.modified().ok().map(Timespec::from)
+}
+
+fn set_host_mtime(path: &Path, mtime: Timespec) -> io::Result<()> {
+    let nsec = Duration::from_nanos(u64::from(mtime.nsec));
+    let time = match u64::try_from(mtime.sec) {
+        Ok(sec) => UNIX_EPOCH + Duration::from_secs(sec) + nsec,
+        Err(_) => UNIX_EPOCH - Duration::from_secs(mtime.sec.unsigned_abs()) + nsec,
+    };
+    fs::File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(time)
+}
+
+/// Read the regular files among `entries` in parallel. Files that fail
+/// to read are left out, for the copy to report the error when it reads
+/// them again.
+#[cfg(feature = "rayon")]
+fn prefetch_host(entries: &[fs::DirEntry]) -> HashMap<PathBuf, Vec<u8>> {
+    use rayon::prelude::*;
+    entries
+        .par_iter()
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+        .filter_map(|entry| Some((entry.path(), fs::read(entry.path()).ok()?)))
+        .collect()
+}
+
+/// Without the `rayon` feature each file is read as it is copied.
+#[cfg(not(feature = "rayon"))]
+fn prefetch_host(_entries: &[fs::DirEntry]) -> HashMap<PathBuf, Vec<u8>> {
+    HashMap::new()
+}
+
+fn remove_host(path: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    if metadata.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+impl Vfs {
+    fn lookup(&self, pathname: &str) -> Option<&FileDescriptor> {
+        self.resolve(pathname).map(|(fd, _, _)| fd)
+    }
+
+    /// Copy `host` to `pathname`, taking the contents of a regular file
+    /// from `data` if it was read ahead.
+    fn copy_in(
+        &mut self,
+        copy: &mut CopyJob,
+        host: &Path,
+        pathname: &str,
+        data: Option<Vec<u8>>,
+    ) -> Result<(), VfsError> {
+        let host_err = |err: io::Error| {
+            host_error(
+                format!("{}: cannot read '{}'", copy.cmd, host.display()),
+                &err,
+            )
+        };
+        let cmd = copy.cmd;
+        let context = || format!("{}: cannot copy '{}'", cmd, host.display());
+        copy.monitor.check(context)?;
+        let metadata = fs::symlink_metadata(host).map_err(host_err)?;
+        if metadata.is_dir() {
+            match self.lookup(pathname) {
+                Some(fd) if fd.file_type.is_dir() => {}
+                Some(_) => {
+                    self.remove_all(pathname)?;
+                    self.mkdir(pathname)?;
+                }
+                None => self.mkdir(pathname)?,
+            }
+            let mut entries = fs::read_dir(host)
+                .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+                .map_err(host_err)?;
+            entries.sort_by_key(|entry| entry.file_name());
+            // A sync reads only the files that changed, which it cannot
+            // tell before comparing them.
+            let mut prefetched = match copy.incremental {
+                true => HashMap::new(),
+                false => prefetch_host(&entries),
+            };
+            for entry in entries {
+                let name = entry.file_name().into_string().map_err(|name| {
+                    VfsError::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "{}: cannot copy '{}': Invalid file name",
+                            copy.cmd,
+                            host.join(name).display()
+                        ),
+                    )
+                })?;
+                let data = prefetched.remove(&entry.path());
+                self.copy_in(copy, &entry.path(), &join(pathname, &name), data)?;
+            }
+            self.copy_mode_in(pathname, &metadata)?;
+        } else if metadata.is_symlink() {
+            let target = fs::read_link(host).map_err(host_err)?;
+            let target = target.to_str().ok_or_else(|| {
+                VfsError::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "{}: cannot copy '{}': Invalid symlink target",
+                        copy.cmd,
+                        host.display()
+                    ),
+                )
+            })?;
+            match self.lookup(pathname) {
+                Some(fd)
+                    if copy.incremental
+                        && fd.file_type.is_symlink()
+                        && fd.file_type.as_symlink() == target =>
+                {
+                    copy.stats.unchanged += 1;
+                    return copy.monitor.advance(0, 1, context);
+                }
+                Some(_) => self.remove_all(pathname)?,
+                None => {}
+            }
+            self.symlink(target, pathname)?;
+            copy.stats.copied += 1;
+        } else if metadata.is_file() {
+            let mtime = host_mtime(&metadata);
+            match self.lookup(pathname) {
+                Some(fd)
+                    if copy.incremental
+                        && fd.file_type.is_file()
+                        && fd.size as u64 == metadata.len()
+                        && Some(fd.times.mtime) == mtime =>
+                {
+                    self.copy_mode_in(pathname, &metadata)?;
+                    copy.stats.unchanged += 1;
+                    return copy.monitor.advance(0, 1, context);
+                }
+                Some(fd) if fd.file_type.is_file() => {}
+                Some(_) => self.remove_all(pathname)?,
+                None => {}
+            }
+            let data = match data {
+                Some(data) => data,
+                None => fs::read(host).map_err(host_err)?,
+            };
+            self.write_file_with(pathname, &data, copy.monitor, &context)?;
+            self.copy_mode_in(pathname, &metadata)?;
+            if let Some(mtime) = mtime {
+                self.utimens(pathname, SetTime::Omit, SetTime::At(mtime), false)?;
+            }
+            copy.stats.copied += 1;
+        }
+        copy.monitor.advance(0, 1, context)
+    }
+
+    /// Give `pathname` the permission bits of the host file it was copied
+    /// from, unless it already has them.
+    fn copy_mode_in(&mut self, pathname: &str, metadata: &fs::Metadata) -> Result<(), VfsError> {
+        match host_mode(metadata) {
+            Some(mode) if self.lookup(pathname).is_some_and(|fd| fd.mode != mode) => {
+                self.chmod(pathname, mode)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn copy_out(
+        &self,
+        copy: &mut CopyJob,
+        id: usize,
+        pathname: &str,
+        host: &Path,
+    ) -> Result<(), VfsError> {
+        let cmd = copy.cmd;
+        let host_err = |err: io::Error| {
+            host_error(format!("{}: cannot write '{}'", cmd, host.display()), &err)
+        };
+        let existing = match fs::symlink_metadata(host) {
+            Ok(metadata) => Some(metadata),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(host_err(err)),
+        };
+        match &self.fds[id].file_type {
+            FileType::Directory(entries) => {
+                if let Some(metadata) = existing.filter(|metadata| !metadata.is_dir()) {
+                    remove_host(host, &metadata).map_err(host_err)?;
+                }
+                fs::create_dir_all(host).map_err(host_err)?;
+                let entries = entries
+                    .iter()
+                    .filter(|(name, _)| *name != DOT && *name != DOTDOT);
+                for (name, &child_id) in entries {
+                    self.copy_out(copy, child_id, &join(pathname, name), &host.join(name))?;
+                }
+                set_host_mode(host, self.fds[id].mode).map_err(host_err)?;
+            }
+            FileType::Regular(_) | FileType::Archive(_) => {
+                let data = match &self.fds[id].file_type {
+                    FileType::Archive(member) => member.contents().map_err(|err| {
+                        archive_error(format!("{}: cannot read '{}'", copy.cmd, pathname), &err)
+                    })?,
+                    _ => self
+                        .file_contents(id)
+                        .map_err(|block_ref| self.corrupted(copy.cmd, pathname, block_ref))?,
+                };
+                let fd = &self.fds[id];
+                match existing {
+                    Some(metadata)
+                        if copy.incremental
+                            && metadata.is_file()
+                            && metadata.len() == data.len() as u64
+                            && host_mtime(&metadata) == Some(fd.times.mtime) =>
+                    {
+                        if host_mode(&metadata).is_some_and(|mode| mode != fd.mode) {
+                            set_host_mode(host, fd.mode).map_err(host_err)?;
+                        }
+                        copy.stats.unchanged += 1;
+                        return Ok(());
+                    }
+                    Some(metadata) if metadata.is_file() => {}
+                    Some(metadata) => remove_host(host, &metadata).map_err(host_err)?,
+                    None => {}
+                }
+                fs::write(host, data).map_err(host_err)?;
+                set_host_mtime(host, fd.times.mtime).map_err(host_err)?;
+                set_host_mode(host, fd.mode).map_err(host_err)?;
+                copy.stats.copied += 1;
+            }
+            FileType::Symlink(target) => {
+                if let Some(metadata) = existing {
+                    let same = metadata.is_symlink()
+                        && fs::read_link(host).is_ok_and(|old| old == Path::new(target));
+                    if copy.incremental && same {
+                        copy.stats.unchanged += 1;
+                        return Ok(());
+                    }
+                    remove_host(host, &metadata).map_err(host_err)?;
+                }
+                host_symlink(target, host).map_err(host_err)?;
+                copy.stats.copied += 1;
+            }
+            // Creating host FIFOs and device nodes needs mknod(2), which std
+            // does not offer.
+            FileType::Fifo(_) | FileType::Device(_) => {}
+            // Generated files describe this filesystem, not data to copy,
+            // and ring buffers have no host counterpart.
+            FileType::Proc(_) | FileType::Ring(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Bring `pathname` up to date with the host directory `host_dir`,
+    /// copying only files, directories and symlinks that differ.
+    ///
+    /// Files are compared by size and modification time, like rsync's quick
+    /// check. Copied files and directories take their permission bits, and
+    /// files their modification time. Entries missing on the host are kept.
+    pub fn sync_from_host<P: AsRef<Path>>(
+        &mut self,
+        host_dir: P,
+        pathname: &str,
+    ) -> Result<SyncStats, VfsError> {
+        self.check_writable_at(pathname, || format!("sync-in: cannot sync '{}'", pathname))?;
+        let mut copy = CopyJob {
+            cmd: "sync-in",
+            incremental: true,
+            stats: SyncStats::default(),
+            monitor: &mut Monitor::new(),
+        };
+        self.copy_in(&mut copy, host_dir.as_ref(), pathname, None)?;
+        Ok(copy.stats)
+    }
+
+    /// Bring the host directory `host_dir` up to date with `pathname`,
+    /// copying only what differs, compared by size and modification time.
+    /// Copied files and directories take their permission bits, and files
+    /// their modification time.
+    pub fn sync_to_host<P: AsRef<Path>>(
+        &self,
+        pathname: &str,
+        host_dir: P,
+    ) -> Result<SyncStats, VfsError> {
+        let id = match self.resolve(pathname) {
+            Some((_, id, _)) => id,
+            None => {
+                return Err(VfsError::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "sync-out: cannot access '{}': No such file or directory",
+                        pathname
+                    ),
+                ))
+            }
+        };
+        let mut copy = CopyJob {
+            cmd: "sync-out",
+            incremental: true,
+            stats: SyncStats::default(),
+            monitor: &mut Monitor::new(),
+        };
+        self.copy_out(&mut copy, id, pathname, host_dir.as_ref())?;
+        Ok(copy.stats)
+    }
+
+    /// Recursively copy the host directory `host_dir` to `pathname` with
+    /// permission bits and modification times, overwriting whatever is
+    /// already there. With the `rayon` feature the files of each directory
+    /// are read from the host in parallel before they are written.
+    pub fn import_dir<P: AsRef<Path>>(
+        &mut self,
+        host_dir: P,
+        pathname: &str,
+    ) -> Result<SyncStats, VfsError> {
+        self.import_dir_with(host_dir, pathname, &mut Monitor::new())
+    }
+
+    /// Like `import_dir`, reporting each block and entry copied to
+    /// `monitor` and stopping if it is cancelled, which leaves what was
+    /// imported so far in place.
+    pub fn import_dir_with<P: AsRef<Path>>(
+        &mut self,
+        host_dir: P,
+        pathname: &str,
+        monitor: &mut Monitor,
+    ) -> Result<SyncStats, VfsError> {
+        self.check_writable_at(pathname, || format!("import: cannot import '{}'", pathname))?;
+        let mut copy = CopyJob {
+            cmd: "import",
+            incremental: false,
+            stats: SyncStats::default(),
+            monitor,
+        };
+        self.copy_in(&mut copy, host_dir.as_ref(), pathname, None)?;
+        Ok(copy.stats)
+    }
+
+    /// Recursively copy `pathname` to the host directory `host_dir` with
+    /// permission bits and modification times, overwriting whatever is
+    /// already there.
+    pub fn export_dir<P: AsRef<Path>>(
+        &self,
+        pathname: &str,
+        host_dir: P,
+    ) -> Result<SyncStats, VfsError> {
+        let id = match self.resolve(pathname) {
+            Some((_, id, _)) => id,
+            None => {
+                return Err(VfsError::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "export: cannot access '{}': No such file or directory",
+                        pathname
+                    ),
+                ))
+            }
+        };
+        let mut copy = CopyJob {
+            cmd: "export",
+            incremental: false,
+            stats: SyncStats::default(),
+            monitor: &mut Monitor::new(),
+        };
+        self.copy_out(&mut copy, id, pathname, host_dir.as_ref())?;
+        Ok(copy.stats)
+    }
+
+    /// Copy one host file into `pathname` a block at a time, returning the
+    /// number of bytes copied.
+    pub fn upload<P: AsRef<Path>>(
+        &mut self,
+        host_file: P,
+        pathname: &str,
+    ) -> Result<usize, VfsError> {
+        let host = host_file.as_ref();
+        let host_err =
+            |err: io::Error| host_error(format!("upload: cannot read '{}'", host.display()), &err);
+        let mut file = fs::File::open(host).map_err(host_err)?;
+        self.write_file(pathname, &[])?;
+        let oid = self.open(pathname)?;
+        let mut block = [0; BLOCK_SIZE];
+        let mut total = 0;
+        let result = loop {
+            match file.read(&mut block) {
+                Ok(0) => break Ok(total),
+                Ok(n) => match self.write_all(oid, &block[..n]) {
+                    Ok(()) => total += n,
+                    Err(err) => break Err(err),
+                },
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => break Err(host_err(err)),
+            }
+        };
+        self.close(oid)?;
+        result
+    }
+
+    /// Copy the regular file `pathname` to a host file a block at a time,
+    /// returning the number of bytes copied.
+    pub fn download<P: AsRef<Path>>(
+        &self,
+        pathname: &str,
+        host_file: P,
+    ) -> Result<usize, VfsError> {
+        let host = host_file.as_ref();
+        let host_err = |err: io::Error| {
+            host_error(format!("download: cannot write '{}'", host.display()), &err)
+        };
+        let id = self.regular_file("download", pathname)?;
+        let mut file = fs::File::create(host).map_err(host_err)?;
+        let mut total = 0;
+        for chunk in self.file_chunks(id) {
+            let chunk =
+                chunk.map_err(|block_ref| self.corrupted("download", pathname, block_ref))?;
+            file.write_all(&chunk).map_err(host_err)?;
+            total += chunk.len();
+        }
+        Ok(total)
+    }
+}
+
+impl Vfs {
+    /// Save the whole filesystem as an image file on the host.
+    pub fn save_image<P: AsRef<Path>>(&self, host_file: P) -> Result<(), VfsError> {
+        let host = host_file.as_ref();
+        let image = self.to_image()?;
+        fs::write(host, image)
+            .map_err(|err| host_error(format!("save: cannot write '{}'", host.display()), &err))
+    }
+}
+
+impl VfsBuilder {
+    /// Build a filesystem from an image file on the host.
+    pub fn open_image<P: AsRef<Path>>(self, host_file: P) -> Result<Vfs, VfsError> {
+        let host = host_file.as_ref();
+        let data = fs::read(host)
+            .map_err(|err| host_error(format!("load: cannot read '{}'", host.display()), &err))?;
+        self.build_from_image(&data)
+    }
+}
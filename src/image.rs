@@ -0,0 +1,705 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    str::FromStr,
+};
+
+use crate::{
+    block, ErrorKind, FileDescriptor, FileType, Op, Times, Vfs, VfsBuilder, VfsError, BLOCK_SIZE,
+    DOT, DOTDOT,
+};
+
+const TAG_FILE: u8 = 0;
+const TAG_DIR: u8 = 1;
+const TAG_SYMLINK: u8 = 2;
+
+/// One inode of a serialized filesystem tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Node {
+    File(Vec<u8>),
+    Dir(Vec<(String, usize)>),
+    Symlink(String),
+}
+
+/// A filesystem tree detached from any block store; node `0` is the root
+/// directory and directory entries refer to nodes by index. Version 1
+/// images were a direct serialization of it.
+///
+/// Only regular files, directories and symlinks are kept: FIFOs, devices
+/// and generated files are live objects and are left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Image {
+    pub(crate) nodes: Vec<Node>,
+}
+
+/// A mounted image, written back to `image` on unmount, or a mounted
+/// archive on the host, which is always read-only.
+#[derive(Debug, Clone)]
+pub(crate) struct Mount {
+    pub(crate) image: String,
+    pub(crate) mountpoint: String,
+    pub(crate) root_id: usize,
+    pub(crate) options: MountOptions,
+    pub(crate) archive: bool,
+}
+
+/// Options of a mounted image, as `mount -o` takes them, e.g.
+/// `ro,nosymfollow`. They apply below the mount point but not inside
+/// other images mounted there.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MountOptions {
+    /// Refuse changes with `Read-only file system`; the image is not
+    /// written back on unmount.
+    pub read_only: bool,
+    /// Never update access times.
+    pub noatime: bool,
+    /// Refuse to follow symlinks when resolving paths.
+    pub nosymfollow: bool,
+}
+
+impl fmt::Display for MountOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", if self.read_only { "ro" } else { "rw" })?;
+        if self.noatime {
+            write!(f, ",noatime")?;
+        }
+        if self.nosymfollow {
+            write!(f, ",nosymfollow")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for MountOptions {
+    type Err = String;
+
+    fn from_str(options: &str) -> Result<Self, Self::Err> {
+        let mut parsed = MountOptions::default();
+        for option in options.split(',') {
+            match option {
+                "ro" => parsed.read_only = true,
+                "rw" => parsed.read_only = false,
+                "noatime" => parsed.noatime = true,
+                "atime" => parsed.noatime = false,
+                "nosymfollow" => parsed.nosymfollow = true,
+                "symfollow" => parsed.nosymfollow = false,
+                "defaults" => parsed = MountOptions::default(),
+                _ => return Err(format!("invalid mount option: '{}'", option)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+pub(crate) fn put_u64(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&(value as u64).to_le_bytes());
+}
+
+pub(crate) fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_u64(out, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+/// Cursor over an encoded image; every read fails once the data runs out.
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub(crate) fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if n > self.data.len() {
+            return None;
+        }
+        let (head, rest) = self.data.split_at(n);
+        self.data = rest;
+        Some(head)
+    }
+
+    pub(crate) fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    pub(crate) fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Option<usize> {
+        usize::try_from(u64::from_le_bytes(self.take(8)?.try_into().ok()?)).ok()
+    }
+
+    pub(crate) fn bytes(&mut self) -> Option<&'a [u8]> {
+        let n = self.u64()?;
+        self.take(n)
+    }
+
+    pub(crate) fn string(&mut self) -> Option<String> {
+        String::from_utf8(self.bytes()?.to_vec()).ok()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl Image {
+    /// Number of blocks needed to store the file contents, not counting
+    /// holes or sharing.
+    pub(crate) fn blocks_needed(&self) -> usize {
+        self.nodes
+            .iter()
+            .map(|node| match node {
+                Node::File(data) => data
+                    .chunks(BLOCK_SIZE)
+                    .filter(|chunk| chunk.iter().any(|&byte| byte != 0))
+                    .count(),
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Encode the tree as a standalone filesystem image.
+    pub(crate) fn encode(&self) -> Result<Vec<u8>, VfsError> {
+        let mut vfs = Vfs::new();
+        vfs.restore(self, 0).ok_or_else(|| {
+            VfsError::new(ErrorKind::NoSpace, "No space left on device".to_string())
+        })?;
+        vfs.to_image()
+    }
+
+    /// Decode the tree of an image of any supported version.
+    pub(crate) fn decode(data: &[u8]) -> Result<Self, VfsError> {
+        let (vfs, _) = VfsBuilder::new().load(data)?;
+        vfs.capture(0).map_err(|block_ref| {
+            VfsError::new(
+                ErrorKind::DataCorruption,
+                format!("cannot read block {}: Data corruption detected", block_ref),
+            )
+        })
+    }
+
+    /// Decode the rest of a version 1 image, after its magic and version.
+    pub(crate) fn decode_v1(reader: &mut Reader) -> Result<Self, VfsError> {
+        let image = Image::decode_nodes(reader).ok_or_else(|| {
+            VfsError::new(ErrorKind::DataCorruption, "truncated image".to_string())
+        })?;
+        if !reader.is_empty() {
+            return Err(VfsError::new(
+                ErrorKind::DataCorruption,
+                "trailing data after image".to_string(),
+            ));
+        }
+        image.validate()?;
+        Ok(image)
+    }
+
+    fn decode_nodes(reader: &mut Reader) -> Option<Self> {
+        let count = reader.u64()?;
+        let mut nodes = Vec::new();
+        for _ in 0..count {
+            let node = match reader.u8()? {
+                TAG_FILE => Node::File(reader.bytes()?.to_vec()),
+                TAG_DIR => {
+                    let n = reader.u64()?;
+                    let mut entries = Vec::new();
+                    for _ in 0..n {
+                        entries.push((reader.string()?, reader.u64()?));
+                    }
+                    Node::Dir(entries)
+                }
+                TAG_SYMLINK => Node::Symlink(reader.string()?),
+                _ => return None,
+            };
+            nodes.push(node);
+        }
+        Some(Self { nodes })
+    }
+
+    /// Check that the nodes form a tree of directories rooted at node `0`,
+    /// with regular files and symlinks possibly linked more than once.
+    pub(crate) fn validate(&self) -> Result<(), VfsError> {
+        let invalid = |reason: &str| {
+            Err(VfsError::new(
+                ErrorKind::DataCorruption,
+                format!("corrupted image: {}", reason),
+            ))
+        };
+        if !matches!(self.nodes.first(), Some(Node::Dir(_))) {
+            return invalid("root is not a directory");
+        }
+        let mut dir_refs = vec![0; self.nodes.len()];
+        for node in &self.nodes {
+            if let Node::Dir(entries) = node {
+                let mut names: Vec<_> = entries.iter().map(|(name, _)| name).collect();
+                names.sort_unstable();
+                names.dedup();
+                if names.len() != entries.len() {
+                    return invalid("duplicate file name");
+                }
+                for (name, id) in entries {
+                    if name.is_empty() || name == DOT || name == DOTDOT || name.contains('/') {
+                        return invalid("invalid file name");
+                    }
+                    match self.nodes.get(*id) {
+                        Some(Node::Dir(_)) => dir_refs[*id] += 1,
+                        Some(_) => {}
+                        None => return invalid("dangling directory entry"),
+                    }
+                }
+            }
+        }
+        let single_parent = dir_refs.iter().enumerate().all(|(id, &refs)| match id {
+            0 => refs == 0,
+            _ => refs == 1 || !matches!(self.nodes[id], Node::Dir(_)),
+        });
+        if !single_parent {
+            return invalid("directory linked more than once");
+        }
+        let mut seen = vec![false; self.nodes.len()];
+        let mut queue = VecDeque::from([0]);
+        seen[0] = true;
+        while let Some(id) = queue.pop_front() {
+            if let Node::Dir(entries) = &self.nodes[id] {
+                for &(_, child) in entries {
+                    if !seen[child] {
+                        seen[child] = true;
+                        queue.push_back(child);
+                    }
+                }
+            }
+        }
+        if seen.iter().any(|&seen| !seen) {
+            return invalid("unreachable inode");
+        }
+        Ok(())
+    }
+}
+
+impl Vfs {
+    /// Serialize the tree below directory `root_id`; fails with the id of
+    /// the first corrupted block.
+    pub(crate) fn capture(&self, root_id: usize) -> Result<Image, usize> {
+        let mut index = HashMap::from([(root_id, 0)]);
+        let mut nodes = vec![Node::Dir(Vec::new())];
+        let mut queue = VecDeque::from([root_id]);
+        while let Some(id) = queue.pop_front() {
+            let mut entries = Vec::new();
+            for (name, &child_id) in self.fds[id].file_type.as_dir() {
+                if name == DOT || name == DOTDOT {
+                    continue;
+                }
+                let node = match &self.fds[child_id].file_type {
+                    FileType::Regular(_) => Node::File(self.file_contents(child_id)?),
+                    FileType::Directory(_) => Node::Dir(Vec::new()),
+                    FileType::Symlink(target) => Node::Symlink(target.clone()),
+                    FileType::Fifo(_)
+                    | FileType::Device(_)
+                    | FileType::Proc(_)
+                    | FileType::Ring(_)
+                    | FileType::Archive(_) => continue,
+                };
+                let next = nodes.len();
+                let child = *index.entry(child_id).or_insert_with(|| {
+                    if matches!(node, Node::Dir(_)) {
+                        queue.push_back(child_id);
+                    }
+                    nodes.push(node);
+                    next
+                });
+                entries.push((name.clone(), child));
+            }
+            nodes[index[&id]] = Node::Dir(entries);
+        }
+        Ok(Image { nodes })
+    }
+
+    /// Store `data` in freshly allocated blocks, leaving zeroed blocks as
+    /// holes. Callers check there is room with `Image::blocks_needed`, but
+    /// an allocation may still fail; the blocks stored so far are then
+    /// released.
+    fn store_contents(&mut self, data: &[u8]) -> Option<Vec<usize>> {
+        let mut blocks_refs = Vec::new();
+        for chunk in data.chunks(BLOCK_SIZE) {
+            let block_ref = if chunk.iter().all(|&byte| byte == 0) {
+                0
+            } else {
+                let hint = block::hint(&blocks_refs, blocks_refs.len());
+                let stored = self
+                    .blocks
+                    .update(0, hint, |block| block[..chunk.len()].copy_from_slice(chunk));
+                match stored {
+                    Ok(block_ref) => block_ref,
+                    Err(_) => {
+                        for &block_ref in &blocks_refs {
+                            self.blocks.release(block_ref);
+                        }
+                        return None;
+                    }
+                }
+            };
+            blocks_refs.push(block_ref);
+        }
+        Some(blocks_refs)
+    }
+
+    /// Recreate the nodes of `image` below the empty directory `root_id`;
+    /// fails, leaving the directory empty again, when a block cannot be
+    /// allocated.
+    pub(crate) fn restore(&mut self, image: &Image, root_id: usize) -> Option<()> {
+        let restored = self.restore_nodes(image, root_id);
+        if restored.is_none() {
+            self.release_tree(root_id);
+        }
+        restored
+    }
+
+    fn restore_nodes(&mut self, image: &Image, root_id: usize) -> Option<()> {
+        let mut ids = HashMap::from([(0, root_id)]);
+        let mut queue = VecDeque::from([0]);
+        while let Some(node_id) = queue.pop_front() {
+            let Node::Dir(entries) = &image.nodes[node_id] else {
+                continue;
+            };
+            let parent_id = ids[&node_id];
+            for (name, child) in entries {
+                let id = match ids.get(child) {
+                    Some(&id) => {
+                        self.fds[id].links += 1;
+                        id
+                    }
+                    None => {
+                        let id = match &image.nodes[*child] {
+                            Node::File(data) => {
+                                let blocks_refs = self.store_contents(data)?;
+                                self.alloc_fd(|_| FileDescriptor {
+                                    file_type: FileType::Regular(blocks_refs),
+                                    size: data.len(),
+                                    links: 1,
+                                    refs: 0,
+                                    generation: 0,
+                                    uid: 0,
+                                    gid: 0,
+                                    mode: 0o644,
+                                    acl: Vec::new(),
+                                    attrs: 0,
+                                    project: 0,
+                                    times: Times::default(),
+                                })
+                            }
+                            Node::Dir(_) => {
+                                queue.push_back(*child);
+                                self.alloc_fd(|id| FileDescriptor::new_dir(id, parent_id))
+                            }
+                            Node::Symlink(target) => {
+                                self.alloc_fd(|_| FileDescriptor::new_symlink(target))
+                            }
+                        };
+                        ids.insert(*child, id);
+                        id
+                    }
+                };
+                let entries = self.fds[parent_id].file_type.as_dir_mut();
+                entries.insert(name.clone(), id);
+            }
+        }
+        Some(())
+    }
+
+    /// Release every inode below directory `id`, leaving it empty.
+    fn release_tree(&mut self, id: usize) {
+        let entries = std::mem::take(self.fds[id].file_type.as_dir_mut());
+        for (name, child_id) in entries {
+            if name == DOT || name == DOTDOT {
+                self.fds[id].file_type.as_dir_mut().insert(name, child_id);
+                continue;
+            }
+            if self.fds[child_id].file_type.is_dir() {
+                self.release_tree(child_id);
+            }
+            self.fds[child_id].links -= 1;
+            self.free_fd(child_id);
+        }
+    }
+
+    /// Save the tree below directory `pathname` as an image file
+    /// `image_pathname`.
+    pub fn create_image(&mut self, pathname: &str, image_pathname: &str) -> Result<(), VfsError> {
+        let id = match self.resolve(pathname) {
+            Some((fd, id, _)) if fd.file_type.is_dir() => id,
+            Some(_) => {
+                return Err(VfsError::new(
+                    ErrorKind::NotADirectory,
+                    format!("mkimage: cannot read '{}': Not a directory", pathname),
+                ))
+            }
+            None => {
+                return Err(VfsError::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "mkimage: cannot read '{}': No such file or directory",
+                        pathname
+                    ),
+                ))
+            }
+        };
+        let image = self
+            .capture(id)
+            .map_err(|block_ref| self.corrupted("mkimage", pathname, block_ref))?;
+        self.write_file(image_pathname, &image.encode()?)
+    }
+
+    /// Mount the image file `image_pathname` on the empty directory
+    /// `mountpoint`. The image is not read in place: its files are copied
+    /// into this filesystem's blocks until `unmount` writes them back, so
+    /// mounting fails with `No space left on device` up front unless there
+    /// are free blocks for all of them.
+    pub fn mount_image(&mut self, image_pathname: &str, mountpoint: &str) -> Result<(), VfsError> {
+        self.mount_image_with(image_pathname, mountpoint, MountOptions::default())
+    }
+
+    /// Like `mount_image`, with `options` applying below the mount point.
+    pub fn mount_image_with(
+        &mut self,
+        image_pathname: &str,
+        mountpoint: &str,
+        options: MountOptions,
+    ) -> Result<(), VfsError> {
+        let result = self.mount_image_unaudited(image_pathname, mountpoint, options);
+        self.audit(
+            || Op::Mount {
+                image: image_pathname.to_string(),
+                mountpoint: mountpoint.to_string(),
+                options,
+            },
+            &result,
+        );
+        result
+    }
+
+    fn mount_image_unaudited(
+        &mut self,
+        image_pathname: &str,
+        mountpoint: &str,
+        options: MountOptions,
+    ) -> Result<(), VfsError> {
+        let context = || {
+            format!(
+                "mount: cannot mount '{}' on '{}'",
+                image_pathname, mountpoint
+            )
+        };
+        self.check_writable_at(mountpoint, context)?;
+        let id = self.regular_file("mount", image_pathname)?;
+        let data = self
+            .file_contents(id)
+            .map_err(|block_ref| self.corrupted("mount", image_pathname, block_ref))?;
+        let image = Image::decode(&data)
+            .map_err(|err| VfsError::new(err.kind, format!("{}: {}", context(), err)))?;
+        let root_id = match self.resolve(&format!("{}/{}", mountpoint, DOT)) {
+            Some((fd, id, _)) if fd.file_type.is_dir() => {
+                if fd.file_type.as_dir().len() > 2 {
+                    return Err(VfsError::new(
+                        ErrorKind::DirectoryNotEmpty,
+                        format!("{}: Directory not empty", context()),
+                    ));
+                }
+                id
+            }
+            Some(_) => {
+                return Err(VfsError::new(
+                    ErrorKind::NotADirectory,
+                    format!("{}: Not a directory", context()),
+                ))
+            }
+            None => {
+                return Err(VfsError::new(
+                    ErrorKind::NotFound,
+                    format!("{}: No such file or directory", context()),
+                ))
+            }
+        };
+        if self.mounts.iter().any(|mount| mount.root_id == root_id) {
+            return Err(VfsError::new(
+                ErrorKind::Busy,
+                format!("{}: Device or resource busy", context()),
+            ));
+        }
+        if self
+            .blocks
+            .available()
+            .is_some_and(|available| available < image.blocks_needed())
+        {
+            return Err(VfsError::new(
+                ErrorKind::NoSpace,
+                format!("{}: No space left on device", context()),
+            ));
+        }
+        self.check_free_inodes(image.nodes.len() - 1, context)?;
+        self.restore(&image, root_id).ok_or_else(|| {
+            VfsError::new(
+                ErrorKind::NoSpace,
+                format!("{}: No space left on device", context()),
+            )
+        })?;
+        self.mounts.push(Mount {
+            image: self.realpath(image_pathname).unwrap(),
+            mountpoint: self.realpath(mountpoint).unwrap(),
+            root_id,
+            options,
+            archive: false,
+        });
+        Ok(())
+    }
+
+    /// Write the mounted tree back to its image file and detach it, leaving
+    /// the mountpoint empty. For a bind mount target, remove the bind.
+    pub fn unmount(&mut self, mountpoint: &str) -> Result<(), VfsError> {
+        let result = self.unmount_unaudited(mountpoint);
+        self.audit(
+            || Op::Unmount {
+                mountpoint: mountpoint.to_string(),
+            },
+            &result,
+        );
+        result
+    }
+
+    fn unmount_unaudited(&mut self, mountpoint: &str) -> Result<(), VfsError> {
+        let context = || format!("umount: cannot unmount '{}'", mountpoint);
+        self.check_writable(context)?;
+        if let Some(result) = self.unbind(mountpoint) {
+            return result;
+        }
+        let realpath = self.realpath(mountpoint);
+        let idx = self
+            .mounts
+            .iter()
+            .position(|mount| Some(&mount.mountpoint) == realpath.as_ref())
+            .ok_or_else(|| {
+                VfsError::new(
+                    ErrorKind::InvalidInput,
+                    format!("{}: Not mounted", context()),
+                )
+            })?;
+        let mount = self.mounts[idx].clone();
+        let prefix = format!("{}/", mount.mountpoint.trim_end_matches('/'));
+        let busy = self.session.cwd.starts_with(&prefix)
+            || self
+                .mounts
+                .iter()
+                .any(|other| other.mountpoint.starts_with(&prefix))
+            || self.is_bound(mount.root_id)
+            || self.has_binds_below(&prefix);
+        if busy {
+            return Err(VfsError::new(
+                ErrorKind::Busy,
+                format!("{}: Device or resource busy", context()),
+            ));
+        }
+        if !mount.options.read_only {
+            let image = self
+                .capture(mount.root_id)
+                .map_err(|block_ref| self.corrupted("umount", mountpoint, block_ref))?
+                .encode()
+                .map_err(|err| VfsError::new(err.kind, format!("{}: {}", context(), err)))?;
+            // The write-back is part of the unmount, not separate operations.
+            let audit = self.audit.take();
+            let result = self.write_file(&mount.image, &image);
+            self.audit = audit;
+            result?;
+        }
+        self.release_tree(mount.root_id);
+        self.mounts.remove(idx);
+        Ok(())
+    }
+
+    pub fn mounts(&self) -> impl Iterator<Item = (&str, &str, MountOptions)> {
+        self.mounts.iter().map(|mount| {
+            (
+                mount.image.as_str(),
+                mount.mountpoint.as_str(),
+                mount.options,
+            )
+        })
+    }
+
+    /// The options of the innermost mount that inode `id` belongs to, or
+    /// `None` if it is outside every mount.
+    fn mount_options_of(&self, id: usize) -> Option<MountOptions> {
+        if self.fds[id].file_type.is_dir() {
+            return self.dir_mount_options(id);
+        }
+        self.mounts.iter().find_map(|mount| {
+            let mut stack = vec![mount.root_id];
+            while let Some(dir_id) = stack.pop() {
+                for (name, &child_id) in self.fds[dir_id].file_type.as_dir() {
+                    if child_id == id {
+                        return Some(mount.options);
+                    }
+                    let inner = self.mounts.iter().any(|other| other.root_id == child_id);
+                    if name != DOT
+                        && name != DOTDOT
+                        && !inner
+                        && self.fds[child_id].file_type.is_dir()
+                    {
+                        stack.push(child_id);
+                    }
+                }
+            }
+            None
+        })
+    }
+
+    /// The options of the innermost mount that directory `dir_id` is in.
+    fn dir_mount_options(&self, mut dir_id: usize) -> Option<MountOptions> {
+        loop {
+            if let Some(mount) = self.mounts.iter().find(|mount| mount.root_id == dir_id) {
+                return Some(mount.options);
+            }
+            if dir_id == 0 {
+                return None;
+            }
+            dir_id = self.fds[dir_id].file_type.as_dir()[DOTDOT];
+        }
+    }
+
+    /// Fail with `Read-only file system` if inode `id` is below a mount
+    /// point mounted `ro`.
+    pub(crate) fn check_mount_writable<F>(&self, id: usize, context: F) -> Result<(), VfsError>
+    where
+        F: FnOnce() -> String,
+    {
+        if self.mounts.iter().any(|mount| mount.options.read_only)
+            && self
+                .mount_options_of(id)
+                .is_some_and(|options| options.read_only)
+        {
+            return Err(VfsError::new(
+                ErrorKind::ReadOnly,
+                format!("{}: Read-only file system", context()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether access times of inode `id` stay as they are because it is
+    /// below a mount point mounted `noatime`.
+    pub(crate) fn is_noatime_mount(&self, id: usize) -> bool {
+        self.mounts.iter().any(|mount| mount.options.noatime)
+            && self
+                .mount_options_of(id)
+                .is_some_and(|options| options.noatime)
+    }
+
+    /// Whether symlinks in directory `dir_id` must not be followed because
+    /// it is below a mount point mounted `nosymfollow`.
+    pub(crate) fn is_nosymfollow_dir(&self, dir_id: usize) -> bool {
+        self.mounts.iter().any(|mount| mount.options.nosymfollow)
+            && self
+                .dir_mount_options(dir_id)
+                .is_some_and(|options| options.nosymfollow)
+    }
+}
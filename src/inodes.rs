@@ -0,0 +1,63 @@
+use std::{
+    ops::{Index, IndexMut},
+    sync::Arc,
+};
+
+use crate::FileDescriptor;
+
+/// Inodes per page; pages are shared between clones of the table and
+/// copied on the first change, see `Vfs::fork`.
+const PAGE_INODES: usize = 64;
+
+/// The inode table, indexed by inode number like a `Vec`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Inodes {
+    pages: Vec<Arc<Vec<FileDescriptor>>>,
+    len: usize,
+}
+
+impl Inodes {
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn get(&self, id: usize) -> Option<&FileDescriptor> {
+        (id < self.len).then(|| &self[id])
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &FileDescriptor> {
+        self.pages.iter().flat_map(|page| page.iter())
+    }
+
+    pub(crate) fn push(&mut self, fd: FileDescriptor) {
+        match self.pages.last_mut() {
+            Some(page) if page.len() < PAGE_INODES => Arc::make_mut(page).push(fd),
+            _ => self.pages.push(Arc::new(vec![fd])),
+        }
+        self.len += 1;
+    }
+}
+
+impl From<Vec<FileDescriptor>> for Inodes {
+    fn from(fds: Vec<FileDescriptor>) -> Self {
+        let mut inodes = Self::default();
+        for fd in fds {
+            inodes.push(fd);
+        }
+        inodes
+    }
+}
+
+impl Index<usize> for Inodes {
+    type Output = FileDescriptor;
+
+    fn index(&self, id: usize) -> &FileDescriptor {
+        &self.pages[id / PAGE_INODES][id % PAGE_INODES]
+    }
+}
+
+impl IndexMut<usize> for Inodes {
+    fn index_mut(&mut self, id: usize) -> &mut FileDescriptor {
+        &mut Arc::make_mut(&mut self.pages[id / PAGE_INODES])[id % PAGE_INODES]
+    }
+}
@@ -1,8 +1,131 @@
+mod acl;
+#[cfg(feature = "std")]
+mod actor;
+mod archive;
+mod attr;
+mod audit;
+mod backup;
+mod bench;
+mod bind;
+mod block;
+mod checksum;
+mod compact;
+mod content;
+mod device;
+mod diff;
+mod disk;
+mod error;
+mod fault;
+mod fcntl;
+mod file;
+mod fixture;
+mod guard;
+mod handle;
+#[cfg(feature = "std")]
+mod host;
+mod image;
+mod inodes;
+mod locate;
+mod logfile;
+mod merkle;
+#[cfg(feature = "testing")]
+pub mod model;
+mod namei;
+mod notify;
+mod op;
+mod proc;
+mod progress;
+mod project;
+mod queue;
+mod readdir;
+mod reclaim;
+mod recursive;
+mod reserve;
+mod ring;
+mod root;
+mod seal;
+mod search;
+mod session;
+#[cfg(feature = "std")]
+mod shared;
+mod size;
+mod stats;
+mod statx;
+mod throttle;
+mod times;
+mod walk;
+
 use std::{
     cmp,
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, VecDeque},
     fmt,
+    time::Duration,
+};
+
+pub use acl::{mode_string, AclEntry, AclTag, R_OK, S_ISVTX, W_OK, X_OK};
+#[cfg(feature = "std")]
+pub use actor::{VfsActor, VfsClient};
+use archive::{archive_error, Member};
+pub use attr::{attr_string, parse_attrs, ATTR_APPEND, ATTR_IMMUTABLE};
+pub use audit::AuditRecord;
+pub use backup::BackupStats;
+pub use bench::{BenchResult, Workload};
+use bind::Bind;
+pub use bind::BindOptions;
+use block::{BlockStore, Plain};
+pub use block::{DedupStats, ScrubReport};
+pub use checksum::{Algo, Checksum};
+pub use compact::CompactReport;
+pub use content::{ContentType, FileStats};
+pub use device::{Device, Null, Urandom, Zero};
+pub use diff::{
+    assert_same_tree, assert_same_tree_with, unified_diff, Change, ChangeKind, Changeset,
+    TreeDigest, TreeOptions,
+};
+pub use error::{Cause, Component, ErrorKind, VfsError};
+pub use fault::FaultPlan;
+use fault::Faults;
+pub use fcntl::{fd_flags_string, parse_fd_flags, O_APPEND, O_CLOEXEC, O_NONBLOCK};
+pub use file::VfsFile;
+pub use fixture::VfsFixture;
+pub use guard::OpenGuard;
+pub use handle::FileHandle;
+#[cfg(feature = "std")]
+pub use host::SyncStats;
+use image::Mount;
+pub use image::MountOptions;
+use inodes::Inodes;
+use locate::NameIndex;
+pub use logfile::LogRotation;
+pub use merkle::MerkleRoot;
+use merkle::MerkleTree;
+use namei::LOOP;
+pub use namei::{ResolveStep, ResolveTrace};
+pub use notify::{Event, EventKind, Watches};
+pub use op::Op;
+use proc::ProcEntry;
+pub use progress::{CancelToken, Monitor, Progress};
+pub use project::{ProjectQuota, ProjectUsage};
+pub use queue::{Completion, Reply, Request, SubmissionQueue};
+pub use readdir::{DirCursor, DirEntry, DirPage};
+pub use reclaim::UnlinkedFile;
+use ring::Ring;
+pub use seal::{parse_seals, seals_string, F_SEAL_GROW, F_SEAL_SEAL, F_SEAL_SHRINK, F_SEAL_WRITE};
+use search::ContentIndex;
+pub use search::ContentIndexStats;
+pub use session::Session;
+#[cfg(feature = "std")]
+pub use shared::SharedVfs;
+pub use size::{format_size, parse_size};
+pub use stats::{Histogram, IoStats};
+pub use statx::{
+    Statx, STATX_ALL, STATX_ATIME, STATX_BASIC_STATS, STATX_BLOCKS, STATX_BTIME, STATX_CTIME,
+    STATX_GID, STATX_INO, STATX_MODE, STATX_MTIME, STATX_NLINK, STATX_SIZE, STATX_TYPE, STATX_UID,
 };
+pub use throttle::Throttle;
+use times::Times;
+pub use times::{AtimePolicy, SetTime, Timespec};
+pub use walk::{Walk, WalkEntry};
 
 const BLOCK_SIZE: usize = 512;
 const INITIAL_BLOCKS_COUNT: usize = 1024;
@@ -12,7 +135,7 @@ const PATHNAME_SEPARATOR: &str = "/";
 const TRAILING_SEPARATOR: char = '/';
 const SYMLINK_RESOLVE_LIMIT: usize = 8;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Identity {
     free: BTreeSet<usize>,
     next: usize,
@@ -32,7 +155,7 @@ impl Identity {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```ignore
     /// use vfs::Identity;
     ///
     /// let preallocate = 10;
@@ -63,22 +186,38 @@ impl Identity {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum FileType {
     Regular(Vec<usize>),
-    Directory(HashMap<String, usize>),
+    Directory(BTreeMap<String, usize>),
     Symlink(String),
+    Fifo(VecDeque<u8>),
+    Device(Box<dyn Device>),
+    Proc(ProcEntry),
+    Ring(Ring),
+    Archive(Member),
 }
 
 impl FileType {
-    fn as_dir(&self) -> &HashMap<String, usize> {
+    /// The file type as the first column of `ls -l` shows it, e.g. `d`.
+    fn type_char(&self) -> char {
+        match self {
+            Self::Directory(_) => 'd',
+            Self::Symlink(_) => 'l',
+            Self::Fifo(_) => 'p',
+            Self::Device(_) => 'c',
+            Self::Regular(_) | Self::Proc(_) | Self::Ring(_) | Self::Archive(_) => '-',
+        }
+    }
+
+    fn as_dir(&self) -> &BTreeMap<String, usize> {
         match self {
             Self::Directory(entries) => entries,
             _ => panic!("error: fd not a directory"),
         }
     }
 
-    fn as_dir_mut(&mut self) -> &mut HashMap<String, usize> {
+    fn as_dir_mut(&mut self) -> &mut BTreeMap<String, usize> {
         match self {
             Self::Directory(entries) => entries,
             _ => panic!("error: fd not a directory"),
@@ -107,24 +246,27 @@ impl FileType {
     }
 
     fn is_dir(&self) -> bool {
-        match self {
-            FileType::Directory(_) => true,
-            _ => false,
-        }
+        matches!(self, FileType::Directory(_))
     }
 
     fn is_file(&self) -> bool {
-        match self {
-            FileType::Regular(_) => true,
-            _ => false,
-        }
+        matches!(self, FileType::Regular(_))
+    }
+
+    fn is_device(&self) -> bool {
+        matches!(self, FileType::Device(_))
+    }
+
+    fn is_fifo(&self) -> bool {
+        matches!(self, FileType::Fifo(_))
     }
 
     fn is_symlink(&self) -> bool {
-        match self {
-            FileType::Symlink(_) => true,
-            _ => false,
-        }
+        matches!(self, FileType::Symlink(_))
+    }
+
+    fn is_ring(&self) -> bool {
+        matches!(self, FileType::Ring(_))
     }
 }
 
@@ -134,36 +276,59 @@ impl fmt::Display for FileType {
             Self::Regular(_) => write!(f, "regular file"),
             Self::Directory(_) => write!(f, "directory"),
             Self::Symlink(_) => write!(f, "symbolic link"),
+            Self::Fifo(_) => write!(f, "fifo"),
+            Self::Device(_) => write!(f, "character special file"),
+            Self::Proc(_) => write!(f, "proc file"),
+            Self::Ring(_) => write!(f, "ring buffer"),
+            Self::Archive(_) => write!(f, "archive member"),
         }
     }
 }
 
-#[derive(Debug)]
-pub struct Statx {
-    name: String,
-    size: usize,
-    blocks: usize,
-    links: usize,
-    refs: usize,
-    file_type: String,
+/// Filesystem geometry and usage, as reported by `statfs(2)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatFs {
+    pub block_size: usize,
+    pub blocks: usize,
+    pub blocks_free: usize,
+    /// Total inodes, including the root.
+    pub files: usize,
+    pub files_free: usize,
+    pub atime: AtimePolicy,
+    pub open_files: usize,
+    pub max_open_files: Option<usize>,
+    /// Files unlinked while open, and the bytes they hold until closed.
+    pub unlinked_files: usize,
+    pub unlinked_bytes: usize,
 }
 
-impl fmt::Display for Statx {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "File: {}\nSize: {} \tBlocks: {} \tLinks: {} \tRefs: {} \t {}",
-            self.name, self.size, self.blocks, self.links, self.refs, self.file_type
-        )
-    }
+/// What `rename` did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Renamed {
+    /// The link moved to a name that was free.
+    Moved,
+    /// The link replaced a file or an empty directory, which lost that
+    /// link.
+    Replaced,
+    /// Both names were already links to the same file, so nothing
+    /// changed, as POSIX requires.
+    SameFile,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct FileDescriptor {
     file_type: FileType,
     size: usize,
     links: usize,
     refs: usize,
+    generation: u64,
+    uid: u32,
+    gid: u32,
+    mode: u32,
+    acl: Vec<AclEntry>,
+    attrs: u32,
+    project: u32,
+    times: Times,
 }
 
 impl FileDescriptor {
@@ -173,11 +338,19 @@ impl FileDescriptor {
             size: 0,
             links: 1,
             refs: 0,
+            generation: 0,
+            uid: 0,
+            gid: 0,
+            mode: 0o644,
+            acl: Vec::new(),
+            attrs: 0,
+            project: 0,
+            times: Times::default(),
         }
     }
 
     fn new_dir(id: usize, parent_id: usize) -> Self {
-        let mut entries = HashMap::new();
+        let mut entries = BTreeMap::new();
         entries.insert(DOT.to_string(), id);
         entries.insert(DOTDOT.to_string(), parent_id);
         Self {
@@ -185,6 +358,14 @@ impl FileDescriptor {
             size: 0,
             links: 1,
             refs: 0,
+            generation: 0,
+            uid: 0,
+            gid: 0,
+            mode: 0o755,
+            acl: Vec::new(),
+            attrs: 0,
+            project: 0,
+            times: Times::default(),
         }
     }
 
@@ -194,56 +375,276 @@ impl FileDescriptor {
             size: 0,
             links: 1,
             refs: 0,
+            generation: 0,
+            uid: 0,
+            gid: 0,
+            mode: 0o777,
+            acl: Vec::new(),
+            attrs: 0,
+            project: 0,
+            times: Times::default(),
         }
     }
 
-    fn stat(&self, name: &str) -> Statx {
-        let blocks = match &self.file_type {
-            FileType::Regular(blocks_refs) => blocks_refs.iter().filter(|&&id| id != 0).count(),
-            FileType::Directory(_) => 0,
-            FileType::Symlink(_) => 0,
-        };
-        Statx {
-            name: if self.file_type.is_symlink() {
-                format!("{} -> {}", name, self.file_type.as_symlink())
-            } else {
-                name.to_string()
-            },
-            size: self.size,
-            blocks,
-            links: self.links,
-            refs: self.refs,
-            file_type: format!("{}", self.file_type),
+    fn new_fifo() -> Self {
+        Self {
+            file_type: FileType::Fifo(VecDeque::new()),
+            size: 0,
+            links: 1,
+            refs: 0,
+            generation: 0,
+            uid: 0,
+            gid: 0,
+            mode: 0o644,
+            acl: Vec::new(),
+            attrs: 0,
+            project: 0,
+            times: Times::default(),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Vfs {
-    blocks: Vec<u8>,
-    fds: Vec<FileDescriptor>,
-    open_fds: HashMap<usize, (usize, usize)>,
-    blocks_id: Identity,
+    blocks: BlockStore,
+    fds: Inodes,
+    open_fds: BTreeMap<usize, (usize, usize)>,
     fds_id: Identity,
     open_fds_id: Identity,
-    cwd_id: usize,
-    cwd: String,
+    session: Session,
+    verity: Option<MerkleTree>,
+    read_only: bool,
+    transaction: Option<Box<Vfs>>,
+    audit: Option<Vec<AuditRecord>>,
+    watches: Watches,
+    name_index: Option<NameIndex>,
+    content_index: Option<ContentIndex>,
+    log_rotations: BTreeMap<String, LogRotation>,
+    max_inodes: Option<usize>,
+    max_open_files: Option<usize>,
+    project_quotas: BTreeMap<u32, ProjectQuota>,
+    proc_fds: Option<usize>,
+    mounts: Vec<Mount>,
+    binds: Vec<Bind>,
+    /// Directories made with `create_root`, outside the tree.
+    roots: BTreeSet<usize>,
+    /// Open files without links, with the path of their last link.
+    unlinked: BTreeMap<usize, String>,
+    /// Descriptors opened through a read-only bind mount.
+    read_only_fds: BTreeSet<usize>,
+    /// Flags of descriptors that have any, set with `set_fd_flags`.
+    open_fd_flags: BTreeMap<usize, u32>,
+    /// Seals of files that have any, added with `add_seals`.
+    seals: BTreeMap<usize, u32>,
+    faults: Faults,
+    throttle: Option<Throttle>,
+    seed: Option<u64>,
+    ticks: u64,
+    atime: AtimePolicy,
+    io: IoStats,
 }
 
-impl Vfs {
+impl Default for Vfs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VfsBuilder {
+    encryption_key: Option<[u8; 32]>,
+    devices: Option<String>,
+    proc: Option<String>,
+    size: Option<usize>,
+    max_inodes: Option<usize>,
+    max_open_files: Option<usize>,
+    throttle: Option<Throttle>,
+    seed: Option<u64>,
+    atime: AtimePolicy,
+    root_mode: Option<u32>,
+    root_owner: Option<(u32, u32)>,
+    root_time: Option<Timespec>,
+}
+
+impl VfsBuilder {
     pub fn new() -> Self {
-        Self {
-            blocks: vec![0; BLOCK_SIZE * INITIAL_BLOCKS_COUNT],
-            fds: vec![FileDescriptor::new_dir(0, 0)],
-            open_fds: HashMap::new(),
-            blocks_id: Identity::new(INITIAL_BLOCKS_COUNT - 1, 1),
+        Self::default()
+    }
+
+    /// Encrypt block data at rest with XChaCha20-Poly1305 under `key`.
+    ///
+    /// Blocks are decrypted transparently on read; a block that fails
+    /// authentication is reported as data corruption.
+    pub fn encryption_key(mut self, key: [u8; 32]) -> Self {
+        self.encryption_key = Some(key);
+        self
+    }
+
+    /// Limit block storage to `size` bytes; writes that need more blocks
+    /// fail with `No space left on device`. Unlimited by default.
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    /// Limit the filesystem to `count` inodes, including the root and any
+    /// device nodes and generated files; creating files beyond it fails
+    /// with `No free inodes`. Unlimited by default.
+    pub fn max_inodes(mut self, count: usize) -> Self {
+        self.max_inodes = Some(count);
+        self
+    }
+
+    /// Allow at most `count` descriptors open at once; opening more fails
+    /// with `Too many open files`. Unlimited by default.
+    pub fn max_open_files(mut self, count: usize) -> Self {
+        self.max_open_files = Some(count);
+        self
+    }
+
+    /// Create a directory with `null`, `zero` and `urandom` device nodes,
+    /// typically `/dev`.
+    pub fn devices(mut self, dirname: &str) -> Self {
+        self.devices = Some(dirname.to_string());
+        self
+    }
+
+    /// Create a directory of files generated from the live filesystem
+    /// state on every read, typically `/proc`.
+    pub fn proc(mut self, dirname: &str) -> Self {
+        self.proc = Some(dirname.to_string());
+        self
+    }
+
+    /// Emulate a slow device: reads and writes of regular files take
+    /// `per_op_latency` plus their size at `bytes_per_sec`, where 0 means
+    /// no bandwidth limit.
+    pub fn throttle(mut self, bytes_per_sec: usize, per_op_latency: Duration) -> Self {
+        self.throttle = Some(Throttle::new(bytes_per_sec, per_op_latency));
+        self
+    }
+
+    /// Derive everything that is otherwise random from `seed`: the
+    /// `urandom` device, encryption nonces and audit timestamps, which then
+    /// count operations instead of reading the clock, as they always do
+    /// without the `std` feature. The same seed and operations always give
+    /// the same state and output; nonces repeat across runs, so this is
+    /// meant for tests and bug reports.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// When reads update access times, like the `noatime`, `relatime` and
+    /// `strictatime` mount options. `relatime` by default.
+    pub fn atime(mut self, policy: AtimePolicy) -> Self {
+        self.atime = policy;
+        self
+    }
+
+    /// Permission bits of the root directory, 0o755 by default. This and
+    /// the other root settings are ignored when opening an image, whose
+    /// root keeps its own.
+    pub fn root_mode(mut self, mode: u32) -> Self {
+        self.root_mode = Some(mode & 0o7777);
+        self
+    }
+
+    /// Owner and group of the root directory, root's by default.
+    pub fn root_owner(mut self, uid: u32, gid: u32) -> Self {
+        self.root_owner = Some((uid, gid));
+        self
+    }
+
+    /// Timestamps of the root directory, the time of `build` by default.
+    pub fn root_time(mut self, time: Timespec) -> Self {
+        self.root_time = Some(time);
+        self
+    }
+
+    fn block_limit(&self) -> Option<usize> {
+        self.size.map(|size| size / BLOCK_SIZE + 1)
+    }
+
+    /// An empty filesystem whose block store holds at most `limit` blocks
+    /// and that has at most `max_inodes` inodes.
+    fn empty(&self, limit: Option<usize>, max_inodes: Option<usize>) -> Vfs {
+        let mut blocks = BlockStore::new(INITIAL_BLOCKS_COUNT, self.encryption_key.as_ref(), limit);
+        if let Some(seed) = self.seed {
+            blocks.seed_nonces(seed);
+        }
+        let mut vfs = Vfs {
+            blocks,
+            fds: vec![FileDescriptor::new_dir(0, 0)].into(),
+            open_fds: BTreeMap::new(),
             fds_id: Identity::new(0, 1),
             open_fds_id: Identity::new(0, 0),
-            cwd_id: 0,
-            cwd: PATHNAME_SEPARATOR.to_string(),
+            session: Session::new(),
+            verity: None,
+            read_only: false,
+            transaction: None,
+            audit: None,
+            watches: Watches::default(),
+            name_index: None,
+            content_index: None,
+            log_rotations: BTreeMap::new(),
+            max_inodes,
+            max_open_files: self.max_open_files,
+            project_quotas: BTreeMap::new(),
+            proc_fds: None,
+            mounts: Vec::new(),
+            binds: Vec::new(),
+            roots: BTreeSet::new(),
+            unlinked: BTreeMap::new(),
+            read_only_fds: BTreeSet::new(),
+            open_fd_flags: BTreeMap::new(),
+            seals: BTreeMap::new(),
+            faults: Faults::default(),
+            throttle: self.throttle,
+            seed: self.seed,
+            ticks: 0,
+            atime: self.atime,
+            io: IoStats::default(),
+        };
+        vfs.fds[0].times = Times::at(vfs.now());
+        vfs
+    }
+
+    fn populate(&self, vfs: &mut Vfs) {
+        if let Some(dirname) = &self.devices {
+            vfs.populate_devices(dirname)
+                .expect("error: cannot create device nodes");
+        }
+        if let Some(dirname) = &self.proc {
+            vfs.populate_proc(dirname)
+                .expect("error: cannot create proc tree");
         }
     }
 
+    pub fn build(self) -> Vfs {
+        let mut vfs = self.empty(self.block_limit(), None);
+        let root = &mut vfs.fds[0];
+        if let Some(mode) = self.root_mode {
+            root.mode = mode;
+        }
+        if let Some((uid, gid)) = self.root_owner {
+            root.uid = uid;
+            root.gid = gid;
+        }
+        if let Some(time) = self.root_time {
+            root.times = Times::at(time);
+        }
+        self.populate(&mut vfs);
+        vfs.max_inodes = self.max_inodes;
+        vfs
+    }
+}
+
+impl Vfs {
+    pub fn new() -> Self {
+        VfsBuilder::new().build()
+    }
+
     pub fn is_absolute(pathname: &str) -> bool {
         pathname.starts_with(PATHNAME_SEPARATOR)
     }
@@ -287,42 +688,70 @@ impl Vfs {
     }
 
     fn resolve(&self, pathname: &str) -> Option<(&FileDescriptor, usize, usize)> {
+        self.resolve_bound(pathname)
+            .map(|(fd, id, parent_id, _)| (fd, id, parent_id))
+    }
+
+    /// Like `resolve`, also telling whether the path leads through a
+    /// read-only bind mount.
+    fn resolve_bound(&self, pathname: &str) -> Option<(&FileDescriptor, usize, usize, bool)> {
+        if let Some(pathname) = self.bind_path(pathname) {
+            return self.resolve_bound(&pathname);
+        }
         let mut fd = if Vfs::is_absolute(pathname) {
             self.root()
         } else {
-            &self.fds[self.cwd_id]
+            &self.fds[self.session.cwd_id]
         };
         let mut segments = Vfs::segmentize(pathname, true);
         let mut symlink_resolve_count = 0;
+        let mut binds = Vec::new();
         loop {
             let entries = fd.file_type.as_dir();
             let seg = segments.pop().unwrap_or(DOT);
-            match entries.get(seg) {
-                Some(&next_id) => match self.fds.get(next_id) {
+            let next_id = match seg {
+                DOT => Some(entries[DOT]),
+                DOTDOT => Some(self.bind_parent(entries[DOT], &mut binds)),
+                _ => entries.get(seg).map(|&id| match self.bind_at(id, &binds) {
+                    Some(bind) => {
+                        binds.push(bind);
+                        bind.source_id
+                    }
+                    None => id,
+                }),
+            };
+            match next_id {
+                Some(next_id) => match self.fds.get(next_id) {
                     Some(next_fd) => {
                         if segments.is_empty() {
-                            return match seg {
-                                DOTDOT => {
-                                    let entries = next_fd.file_type.as_dir();
-                                    Some((next_fd, next_id, entries[DOTDOT]))
-                                }
-                                DOT => Some((next_fd, next_id, entries[DOTDOT])),
-                                _ => Some((next_fd, next_id, entries[DOT])),
+                            let read_only = binds.iter().any(|bind| bind.options.read_only);
+                            let parent_id = match seg {
+                                DOT | DOTDOT => self.bind_parent(next_id, &mut binds),
+                                _ => entries[DOT],
                             };
+                            return Some((next_fd, next_id, parent_id, read_only));
                         }
                         match &next_fd.file_type {
                             FileType::Directory(_) => {
                                 fd = next_fd;
                             }
-                            FileType::Regular(_) => return None,
+                            FileType::Regular(_)
+                            | FileType::Fifo(_)
+                            | FileType::Device(_)
+                            | FileType::Proc(_)
+                            | FileType::Ring(_)
+                            | FileType::Archive(_) => return None,
                             FileType::Symlink(path) => {
-                                if symlink_resolve_count >= SYMLINK_RESOLVE_LIMIT {
+                                if symlink_resolve_count >= SYMLINK_RESOLVE_LIMIT
+                                    || self.is_nosymfollow_dir(entries[DOT])
+                                {
                                     return None;
                                 }
                                 symlink_resolve_count += 1;
                                 segments.extend(Vfs::segmentize(path, true));
-                                if Vfs::is_absolute(&path) {
+                                if Vfs::is_absolute(path) {
                                     fd = self.root();
+                                    binds.clear();
                                 }
                             }
                         };
@@ -334,29 +763,68 @@ impl Vfs {
         }
     }
 
+    /// Like `resolve`, but following a symlink in the last component too.
+    fn resolve_follow(&self, pathname: &str) -> Option<(&FileDescriptor, usize, usize)> {
+        let mut pathname = pathname.to_string();
+        for _ in 0..=SYMLINK_RESOLVE_LIMIT {
+            let resolved = self.resolve(&pathname)?;
+            if resolved.0.file_type.is_symlink() && self.is_nosymfollow_dir(resolved.2) {
+                return None;
+            }
+            pathname = match &resolved.0.file_type {
+                FileType::Symlink(path) if Vfs::is_absolute(path) => path.clone(),
+                FileType::Symlink(path) => format!("{}/{}", Vfs::dirname(&pathname), path),
+                _ => return Some(resolved),
+            };
+        }
+        None
+    }
+
     pub fn realpath(&self, pathname: &str) -> Option<String> {
+        if let Some(pathname) = self.bind_path(pathname) {
+            return self.realpath(&pathname);
+        }
         let (mut realpath, mut fd) = if Vfs::is_absolute(pathname) {
             (Vec::new(), self.root())
         } else {
-            (Vfs::segmentize(&self.cwd, false), &self.fds[self.cwd_id])
+            (
+                Vfs::segmentize(&self.session.cwd, false),
+                &self.fds[self.session.cwd_id],
+            )
         };
         let mut segments = Vfs::segmentize(pathname, true);
         let mut symlink_resolve_count = 0;
+        let mut binds = Vec::new();
         while let Some(seg) = segments.pop() {
             let entries = fd.file_type.as_dir();
             match seg {
                 DOT => {}
                 DOTDOT => {
                     realpath.pop();
-                    fd = &self.fds[entries[DOTDOT]];
+                    fd = &self.fds[self.bind_parent(entries[DOT], &mut binds)];
                 }
-                _ => match entries.get(seg).and_then(|&next_id| self.fds.get(next_id)) {
+                _ => match entries
+                    .get(seg)
+                    .map(|&next_id| match self.bind_at(next_id, &binds) {
+                        Some(bind) => {
+                            binds.push(bind);
+                            bind.source_id
+                        }
+                        None => next_id,
+                    })
+                    .and_then(|next_id| self.fds.get(next_id))
+                {
                     Some(next_fd) => match &next_fd.file_type {
                         FileType::Directory(_) => {
                             realpath.push(seg);
                             fd = next_fd;
                         }
-                        FileType::Regular(_) => {
+                        FileType::Regular(_)
+                        | FileType::Fifo(_)
+                        | FileType::Device(_)
+                        | FileType::Proc(_)
+                        | FileType::Ring(_)
+                        | FileType::Archive(_) => {
                             if segments.is_empty() {
                                 realpath.push(seg);
                             } else {
@@ -364,14 +832,17 @@ impl Vfs {
                             }
                         }
                         FileType::Symlink(path) => {
-                            if symlink_resolve_count >= SYMLINK_RESOLVE_LIMIT {
+                            if symlink_resolve_count >= SYMLINK_RESOLVE_LIMIT
+                                || self.is_nosymfollow_dir(entries[DOT])
+                            {
                                 return None;
                             }
                             symlink_resolve_count += 1;
                             let symlink_segments = Vfs::segmentize(path, true);
-                            if Vfs::is_absolute(&path) {
+                            if Vfs::is_absolute(path) {
                                 realpath.clear();
                                 fd = self.root();
+                                binds.clear();
                             }
                             segments.extend(symlink_segments);
                         }
@@ -380,108 +851,437 @@ impl Vfs {
                 },
             }
         }
-        Some(format!("{}{}", PATHNAME_SEPARATOR, realpath.join(PATHNAME_SEPARATOR)))
+        Some(format!(
+            "{}{}",
+            PATHNAME_SEPARATOR,
+            realpath.join(PATHNAME_SEPARATOR)
+        ))
     }
 
     pub fn cwd(&self) -> &str {
-        &self.cwd
+        self.session.cwd()
+    }
+
+    /// Freeze or unfreeze the filesystem; while frozen every mutating
+    /// operation fails with a read-only error.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only || self.verity.is_some()
     }
 
-    pub fn symlink(&mut self, path: &str, pathname: &str) -> Result<(), String> {
-        let basename = Vfs::basename(&pathname);
-        let dirname = Vfs::dirname(&pathname);
+    /// An independent copy of the whole filesystem, sharing block data and
+    /// inodes with this one until either side changes them; only the
+    /// touched pages of 64 blocks or inodes are then copied. A test suite
+    /// can build one large fixture and fork a fresh copy for every test.
+    /// `clone` does the same.
+    pub fn fork(&self) -> Vfs {
+        self.clone()
+    }
+
+    /// Start a transaction by taking a shadow copy of the whole filesystem,
+    /// including open file descriptors and the working directory.
+    pub fn begin(&mut self) -> Result<(), VfsError> {
+        let result = if self.transaction.is_some() {
+            Err(VfsError::new(
+                ErrorKind::Busy,
+                "begin: cannot begin transaction: Transaction already in progress".to_string(),
+            ))
+        } else {
+            self.transaction = Some(Box::new(self.clone()));
+            Ok(())
+        };
+        self.audit(|| Op::Begin, &result);
+        result
+    }
+
+    /// Keep every change made since `begin`.
+    pub fn commit(&mut self) -> Result<(), VfsError> {
+        let result = match self.transaction.take() {
+            Some(_) => Ok(()),
+            None => Err(VfsError::new(
+                ErrorKind::InvalidInput,
+                "commit: cannot commit transaction: No transaction in progress".to_string(),
+            )),
+        };
+        self.audit(|| Op::Commit, &result);
+        result
+    }
+
+    /// Discard every change made since `begin`. The audit log, if enabled,
+    /// keeps the records of the discarded operations, and watches and
+    /// indexes stay.
+    pub fn rollback(&mut self) -> Result<(), VfsError> {
+        let result = match self.transaction.take() {
+            Some(shadow) => {
+                let audit = self.audit.take();
+                let watches = self.take_watches();
+                let indexed = (self.is_name_indexed(), self.is_content_indexed());
+                *self = *shadow;
+                self.audit = audit;
+                self.watches = watches;
+                self.set_name_index(indexed.0);
+                self.set_content_index(indexed.1);
+                Ok(())
+            }
+            None => Err(VfsError::new(
+                ErrorKind::InvalidInput,
+                "rollback: cannot roll back transaction: No transaction in progress".to_string(),
+            )),
+        };
+        self.audit(|| Op::Rollback, &result);
+        result
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    fn check_writable<F>(&self, context: F) -> Result<(), VfsError>
+    where
+        F: FnOnce() -> String,
+    {
+        if self.is_read_only() {
+            return Err(VfsError::new(
+                ErrorKind::ReadOnly,
+                format!("{}: Read-only file system", context()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Enable or disable sharing of identical blocks between files.
+    ///
+    /// Blocks already shared stay shared after disabling; they are copied on
+    /// the next write as usual.
+    pub fn set_dedup(&mut self, enabled: bool) {
+        self.blocks.set_dedup(enabled);
+    }
+
+    pub fn is_dedup(&self) -> bool {
+        self.blocks.is_dedup()
+    }
+
+    pub fn dedup_stats(&self) -> DedupStats {
+        self.blocks.dedup_stats()
+    }
+
+    /// Verify the checksum of every allocated block.
+    pub fn scrub(&self) -> ScrubReport {
+        self.blocks.scrub()
+    }
+
+    pub fn symlink(&mut self, path: &str, pathname: &str) -> Result<(), VfsError> {
+        let result = self.symlink_unaudited(path, pathname);
+        self.audit(
+            || Op::Symlink {
+                path: path.to_string(),
+                pathname: pathname.to_string(),
+            },
+            &result,
+        );
+        result.map_err(|err| self.error_at(err, &Vfs::dirname(pathname)))
+    }
+
+    fn symlink_unaudited(&mut self, path: &str, pathname: &str) -> Result<(), VfsError> {
+        self.check_writable_at(pathname, || {
+            format!("symlink: cannot create symlink '{}'", pathname)
+        })?;
+        let basename = Vfs::basename(pathname);
+        let dirname = Vfs::dirname(pathname);
         match self.resolve(&dirname) {
             Some((fd, id, _)) => {
                 if !fd.file_type.is_dir() {
-                    return Err(format!(
-                        "symlink: cannot create symlink '{}': Not a directory",
-                        pathname
+                    return Err(VfsError::new(
+                        ErrorKind::NotADirectory,
+                        format!(
+                            "symlink: cannot create symlink '{}': Not a directory",
+                            pathname
+                        ),
                     ));
                 }
                 let entries = fd.file_type.as_dir();
                 if entries.contains_key(&basename) || basename.is_empty() {
-                    return Err(format!(
-                        "symlink: cannot create symlink '{}': File exists",
-                        pathname
+                    return Err(VfsError::new(
+                        ErrorKind::AlreadyExists,
+                        format!("symlink: cannot create symlink '{}': File exists", pathname),
                     ));
                 }
+                self.check_access(id, W_OK | X_OK, || {
+                    format!("symlink: cannot create symlink '{}'", pathname)
+                })?;
+                self.check_attrs(id, ATTR_IMMUTABLE, || {
+                    format!("symlink: cannot create symlink '{}'", pathname)
+                })?;
+                self.check_new_inode(id, || {
+                    format!("symlink: cannot create symlink '{}'", pathname)
+                })?;
                 let new_id = self.alloc_fd(|_| FileDescriptor::new_symlink(path));
+                self.inherit_project(id, new_id);
                 let fd = &mut self.fds[id];
                 let entries = fd.file_type.as_dir_mut();
                 entries.insert(basename.to_string(), new_id);
+                self.touch_modified(id);
                 Ok(())
             }
-            None => Err(format!(
-                "symlink: cannot create symlink '{}': No such file or directory",
-                pathname
+            None => Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!(
+                    "symlink: cannot create symlink '{}': No such file or directory",
+                    pathname
+                ),
             )),
         }
     }
 
-    pub fn cd(&mut self, pathname: &str) -> Result<(), String> {
+    /// Create a named pipe. Data written through one descriptor is read,
+    /// in order, through any other. A `Vfs` has a single caller, so a read
+    /// could never be woken up and reads behave as non-blocking: an empty
+    /// pipe fails with `Resource temporarily unavailable` while another
+    /// descriptor is open on it, and reads end of file otherwise.
+    pub fn mkfifo(&mut self, pathname: &str) -> Result<(), VfsError> {
+        let result = self.mkfifo_unaudited(pathname);
+        self.audit(
+            || Op::Mkfifo {
+                pathname: pathname.to_string(),
+            },
+            &result,
+        );
+        result.map_err(|err| self.error_at(err, &Vfs::dirname(pathname)))
+    }
+
+    fn mkfifo_unaudited(&mut self, pathname: &str) -> Result<(), VfsError> {
+        self.check_writable_at(pathname, || {
+            format!("mkfifo: cannot create fifo '{}'", pathname)
+        })?;
+        let basename = Vfs::basename(pathname);
+        let dirname = Vfs::dirname(pathname);
+        match self.resolve(&dirname) {
+            Some((fd, id, _)) => {
+                if !fd.file_type.is_dir() {
+                    return Err(VfsError::new(
+                        ErrorKind::NotADirectory,
+                        format!("mkfifo: cannot create fifo '{}': Not a directory", pathname),
+                    ));
+                }
+                let entries = fd.file_type.as_dir();
+                if entries.contains_key(&basename) || basename.is_empty() {
+                    return Err(VfsError::new(
+                        ErrorKind::AlreadyExists,
+                        format!("mkfifo: cannot create fifo '{}': File exists", pathname),
+                    ));
+                }
+                self.check_access(id, W_OK | X_OK, || {
+                    format!("mkfifo: cannot create fifo '{}'", pathname)
+                })?;
+                self.check_attrs(id, ATTR_IMMUTABLE, || {
+                    format!("mkfifo: cannot create fifo '{}'", pathname)
+                })?;
+                self.check_new_inode(id, || format!("mkfifo: cannot create fifo '{}'", pathname))?;
+                let new_id = self.alloc_fd(|_| FileDescriptor::new_fifo());
+                self.inherit_project(id, new_id);
+                let fd = &mut self.fds[id];
+                let entries = fd.file_type.as_dir_mut();
+                entries.insert(basename.to_string(), new_id);
+                self.touch_modified(id);
+                Ok(())
+            }
+            None => Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!(
+                    "mkfifo: cannot create fifo '{}': No such file or directory",
+                    pathname
+                ),
+            )),
+        }
+    }
+
+    pub fn cd(&mut self, pathname: &str) -> Result<(), VfsError> {
+        let result = self.cd_unaudited(pathname);
+        self.audit(
+            || Op::Cd {
+                pathname: pathname.to_string(),
+            },
+            &result,
+        );
+        result.map_err(|err| self.error_at(err, pathname))
+    }
+
+    fn cd_unaudited(&mut self, pathname: &str) -> Result<(), VfsError> {
         let dirname = &format!("{}/{}", pathname, DOT);
         match self.resolve(dirname) {
             Some((fd, id, _)) => {
                 if !fd.file_type.is_dir() {
-                    return Err(format!("cd: not a directory: {}", pathname));
+                    return Err(VfsError::new(
+                        ErrorKind::NotADirectory,
+                        format!("cd: not a directory: {}", pathname),
+                    ));
+                }
+                if !fd.permits(self.session.uid(), self.session.gid(), X_OK) {
+                    return Err(VfsError::new(
+                        ErrorKind::PermissionDenied,
+                        format!("cd: permission denied: {}", pathname),
+                    ));
                 }
-                self.cwd = self.realpath(dirname).unwrap();
-                self.cwd_id = id;
+                let cwd = self.realpath(dirname).unwrap();
+                self.set_cwd(id, cwd);
                 Ok(())
             }
-            None => Err(format!("cd: no such file or directory: {}", pathname)),
+            None => Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!("cd: no such file or directory: {}", pathname),
+            )),
         }
     }
 
-    pub fn mkdir(&mut self, pathname: &str) -> Result<(), String> {
+    pub fn mkdir(&mut self, pathname: &str) -> Result<(), VfsError> {
+        let result = self.mkdir_unaudited(pathname);
+        self.audit(
+            || Op::Mkdir {
+                pathname: pathname.to_string(),
+            },
+            &result,
+        );
+        result.map_err(|err| {
+            self.error_at(
+                err,
+                &Vfs::dirname(pathname.trim_end_matches(TRAILING_SEPARATOR)),
+            )
+        })
+    }
+
+    fn mkdir_unaudited(&mut self, pathname: &str) -> Result<(), VfsError> {
+        self.check_writable_at(pathname, || {
+            format!("mkdir: cannot create directory '{}'", pathname)
+        })?;
         let pathname = pathname.trim_end_matches(TRAILING_SEPARATOR);
-        let basename = Vfs::basename(&pathname);
-        let dirname = format!("{}/{}", Vfs::dirname(&pathname), DOT);
+        let basename = Vfs::basename(pathname);
+        let dirname = format!("{}/{}", Vfs::dirname(pathname), DOT);
         match self.resolve(&dirname) {
             Some((fd, parent_id, _)) => {
                 if !fd.file_type.is_dir() {
-                    return Err(format!(
-                        "mkdir: cannot create directory '{}': Not a directory",
-                        dirname
+                    return Err(VfsError::new(
+                        ErrorKind::NotADirectory,
+                        format!(
+                            "mkdir: cannot create directory '{}': Not a directory",
+                            dirname
+                        ),
                     ));
                 }
                 let entries = fd.file_type.as_dir();
                 if entries.contains_key(&basename) || basename.is_empty() {
-                    return Err(format!("mkdir: cannot create '{}': File exists", pathname));
+                    return Err(VfsError::new(
+                        ErrorKind::AlreadyExists,
+                        format!("mkdir: cannot create '{}': File exists", pathname),
+                    ));
                 }
+                self.check_access(parent_id, W_OK | X_OK, || {
+                    format!("mkdir: cannot create directory '{}'", pathname)
+                })?;
+                self.check_attrs(parent_id, ATTR_IMMUTABLE, || {
+                    format!("mkdir: cannot create directory '{}'", pathname)
+                })?;
+                self.check_new_inode(parent_id, || {
+                    format!("mkdir: cannot create directory '{}'", pathname)
+                })?;
                 let new_id = self.alloc_fd(|id| FileDescriptor::new_dir(id, parent_id));
+                self.inherit_project(parent_id, new_id);
                 let fd = &mut self.fds[parent_id];
                 let entries = fd.file_type.as_dir_mut();
                 entries.insert(basename.to_string(), new_id);
+                self.touch_modified(parent_id);
                 Ok(())
             }
-            None => Err(format!(
-                "mkdir: cannot create directory '{}': No such file or directory",
-                pathname
+            None => Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!(
+                    "mkdir: cannot create directory '{}': No such file or directory",
+                    pathname
+                ),
             )),
         }
     }
 
-    pub fn rmdir(&mut self, pathname: &str) -> Result<(), String> {
+    pub fn rmdir(&mut self, pathname: &str) -> Result<(), VfsError> {
+        let result = self.rmdir_unaudited(pathname);
+        self.audit(
+            || Op::Rmdir {
+                pathname: pathname.to_string(),
+            },
+            &result,
+        );
+        result.map_err(|err| self.error_at(err, pathname))
+    }
+
+    fn rmdir_unaudited(&mut self, pathname: &str) -> Result<(), VfsError> {
+        self.check_writable_at(pathname, || {
+            format!("rmdir: failed to remove '{}'", pathname)
+        })?;
         match self.resolve(pathname) {
             Some((fd, id, parent_id)) => {
                 if id == 0 {
-                    return Err(format!(
-                        "rmdir: cannot remove '{}': Is a root directory",
-                        pathname
+                    return Err(VfsError::new(
+                        ErrorKind::Busy,
+                        format!("rmdir: cannot remove '{}': Is a root directory", pathname),
                     ));
                 }
+                // `.` and `..` name the directory through itself or a child,
+                // whose entries must stay.
+                match Vfs::basename(pathname.trim_end_matches(TRAILING_SEPARATOR)).as_str() {
+                    DOT => {
+                        return Err(VfsError::new(
+                            ErrorKind::InvalidInput,
+                            format!("rmdir: failed to remove '{}': Invalid argument", pathname),
+                        ))
+                    }
+                    DOTDOT => {
+                        return Err(VfsError::new(
+                            ErrorKind::DirectoryNotEmpty,
+                            format!(
+                                "rmdir: failed to remove '{}': Directory not empty",
+                                pathname
+                            ),
+                        ))
+                    }
+                    _ => {}
+                }
                 if !fd.file_type.is_dir() {
-                    return Err(format!(
-                        "rmdir: failed to remove '{}': Not a directory",
-                        pathname
+                    return Err(VfsError::new(
+                        ErrorKind::NotADirectory,
+                        format!("rmdir: failed to remove '{}': Not a directory", pathname),
+                    ));
+                }
+                if self.is_proc_fds(id) || self.is_bound(id) {
+                    return Err(VfsError::new(
+                        ErrorKind::Busy,
+                        format!(
+                            "rmdir: failed to remove '{}': Device or resource busy",
+                            pathname
+                        ),
                     ));
                 }
+                self.check_access(parent_id, W_OK | X_OK, || {
+                    format!("rmdir: failed to remove '{}'", pathname)
+                })?;
+                self.check_attrs(parent_id, ATTR_IMMUTABLE | ATTR_APPEND, || {
+                    format!("rmdir: failed to remove '{}'", pathname)
+                })?;
+                self.check_attrs(id, ATTR_IMMUTABLE | ATTR_APPEND, || {
+                    format!("rmdir: failed to remove '{}'", pathname)
+                })?;
+                self.check_sticky(parent_id, id, || {
+                    format!("rmdir: failed to remove '{}'", pathname)
+                })?;
                 let entries = fd.file_type.as_dir();
                 if entries.len() > 2 {
-                    return Err(format!(
-                        "rmdir: failed to remove '{}': Directory not empty",
-                        pathname
+                    return Err(VfsError::new(
+                        ErrorKind::DirectoryNotEmpty,
+                        format!(
+                            "rmdir: failed to remove '{}': Directory not empty",
+                            pathname
+                        ),
                     ));
                 }
                 let dir = &mut self.fds[parent_id];
@@ -493,46 +1293,127 @@ impl Vfs {
                 if let Some(name) = name {
                     entries.remove(&name);
                 }
+                self.touch_modified(parent_id);
                 self.free_fd(id);
-                if id == self.cwd_id {
-                    self.cwd_id = 0;
-                    self.cwd = PATHNAME_SEPARATOR.to_string();
+                if id == self.session.cwd_id {
+                    self.set_cwd(0, PATHNAME_SEPARATOR.to_string());
                 }
                 Ok(())
             }
-            _ => Err(format!(
-                "rmdir: cannot rmdir '{}': No such file or directory",
-                pathname
+            _ => Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!(
+                    "rmdir: cannot rmdir '{}': No such file or directory",
+                    pathname
+                ),
             )),
         }
     }
 
-    pub fn stat(&self, pathname: &str) -> Result<Statx, String> {
-        match self.resolve(pathname) {
-            Some((fd, _, _)) => Ok(fd.stat(pathname)),
-            None => Err(format!(
-                "stat: cannot statx '{}': No such file or directory",
-                pathname
-            )),
+    /// Report block and inode usage. Without a size limit the block count
+    /// is the current size of the store, which grows on demand, and
+    /// likewise for inodes without an inode limit.
+    pub fn statfs(&self) -> StatFs {
+        let blocks = self.blocks.capacity();
+        let files = self.max_inodes.unwrap_or(self.fds.len());
+        StatFs {
+            block_size: BLOCK_SIZE,
+            blocks,
+            blocks_free: blocks - self.blocks.dedup_stats().physical_blocks,
+            files,
+            files_free: files.saturating_sub(self.inodes_used()),
+            atime: self.atime,
+            open_files: self.open_fds.len(),
+            max_open_files: self.max_open_files,
+            unlinked_files: self.unlinked.len(),
+            unlinked_bytes: self.unlinked_files().map(|file| file.bytes).sum(),
         }
     }
 
-    pub fn ls(&self, pathname: &str) -> Result<Vec<String>, String> {
-        match self.resolve(pathname) {
-            Some((fd, _, _)) => match &fd.file_type {
+    /// The inode limit, if any.
+    pub fn max_inodes(&self) -> Option<usize> {
+        self.max_inodes
+    }
+
+    /// The limit on open descriptors, if any.
+    pub fn max_open_files(&self) -> Option<usize> {
+        self.max_open_files
+    }
+
+    /// Change the limit on open descriptors, like `ulimit -n`. Descriptors
+    /// already open stay open when it is lowered below their number.
+    pub fn set_max_open_files(&mut self, max_open_files: Option<usize>) {
+        self.max_open_files = max_open_files;
+    }
+
+    pub(crate) fn inodes_used(&self) -> usize {
+        self.fds.len() - self.fds_id.free.len()
+    }
+
+    /// Fail with `No free inodes` if `count` more inodes would exceed the
+    /// inode limit.
+    pub(crate) fn check_free_inodes<F>(&self, count: usize, context: F) -> Result<(), VfsError>
+    where
+        F: FnOnce() -> String,
+    {
+        if self
+            .max_inodes
+            .is_some_and(|max_inodes| self.inodes_used() + count > max_inodes)
+        {
+            return Err(VfsError::new(
+                ErrorKind::NoSpace,
+                format!("{}: No free inodes", context()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Fail if a new file in directory `dir_id` would exceed the inode
+    /// limit or the quota of the directory's project.
+    pub(crate) fn check_new_inode<F>(&self, dir_id: usize, context: F) -> Result<(), VfsError>
+    where
+        F: FnOnce() -> String + Copy,
+    {
+        self.check_free_inodes(1, context)?;
+        self.check_project_inodes(dir_id, context)
+    }
+
+    /// What `ls` shows for `pathname`: the entries of a directory in name
+    /// order, `.` and `..` included, or the file itself, named
+    /// `pathname`. Entries are yielded as the caller asks for them.
+    pub fn ls(&self, pathname: &str) -> Result<impl Iterator<Item = DirEntry> + '_, VfsError> {
+        let (entries, file) = match self.resolve(pathname) {
+            Some((fd, id, _)) => match &fd.file_type {
                 FileType::Directory(entries) => {
-                    let mut names: Vec<_> = entries.keys().cloned().collect();
-                    names.sort_unstable();
-                    Ok(names)
+                    self.check_access(id, R_OK, || {
+                        format!("ls: cannot open directory '{}'", pathname)
+                    })?;
+                    (Some(entries), None)
                 }
-                FileType::Regular(_) => Ok(vec![pathname.to_string()]),
-                FileType::Symlink(_) => Ok(vec![pathname.to_string()]),
+                _ => (
+                    None,
+                    Some(DirEntry {
+                        name: pathname.to_string(),
+                        id,
+                    }),
+                ),
             },
-            None => Err(format!(
-                "ls: cannot access '{}': No such file or directory",
-                pathname
-            )),
-        }
+            None => {
+                let message = VfsError::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "ls: cannot access '{}': No such file or directory",
+                        pathname
+                    ),
+                );
+                return Err(self.error_at(message, pathname));
+            }
+        };
+        let entries = entries.into_iter().flatten().map(|(name, &id)| DirEntry {
+            name: name.clone(),
+            id,
+        });
+        Ok(entries.chain(file))
     }
 
     fn alloc_fd<F>(&mut self, f: F) -> usize
@@ -540,78 +1421,143 @@ impl Vfs {
         F: FnOnce(usize) -> FileDescriptor,
     {
         let (id, incremented) = self.fds_id.next();
-        let fd = f(id);
+        let mut fd = f(id);
+        fd.uid = self.session.uid();
+        fd.gid = self.session.gid();
+        fd.times = Times::at(self.now());
+        if fd.file_type.is_file()
+            || fd.file_type.is_dir()
+            || fd.file_type.is_fifo()
+            || fd.file_type.is_ring()
+        {
+            fd.mode = fd.file_type.base_mode() & !self.session.umask();
+        }
         if incremented {
             self.fds.push(fd);
         } else {
+            fd.generation = self.fds[id].generation.wrapping_add(1);
             self.fds[id] = fd;
         }
         id
     }
 
-    pub fn create(&mut self, pathname: &str) -> Result<(), String> {
-        let basename = Vfs::basename(&pathname);
-        let dirname = format!("{}/{}", Vfs::dirname(&pathname), DOT);
+    pub fn create(&mut self, pathname: &str) -> Result<(), VfsError> {
+        let result = self.create_unaudited(pathname);
+        self.audit(
+            || Op::Create {
+                pathname: pathname.to_string(),
+            },
+            &result,
+        );
+        result.map_err(|err| self.error_at(err, &Vfs::dirname(pathname)))
+    }
+
+    fn create_unaudited(&mut self, pathname: &str) -> Result<(), VfsError> {
+        self.check_writable_at(pathname, || format!("create: cannot create '{}'", pathname))?;
+        let basename = Vfs::basename(pathname);
+        let dirname = format!("{}/{}", Vfs::dirname(pathname), DOT);
         match self.resolve(&dirname) {
             Some((fd, id, _)) => {
                 if !fd.file_type.is_dir() {
-                    return Err(format!(
-                        "create: cannot create '{}': Not a directory",
-                        dirname
+                    return Err(VfsError::new(
+                        ErrorKind::NotADirectory,
+                        format!("create: cannot create '{}': Not a directory", dirname),
                     ));
                 }
                 let entries = fd.file_type.as_dir();
                 if entries.contains_key(&basename) || basename.is_empty() {
                     return Ok(());
                 }
+                self.check_access(id, W_OK | X_OK, || {
+                    format!("create: cannot create '{}'", pathname)
+                })?;
+                self.check_attrs(id, ATTR_IMMUTABLE, || {
+                    format!("create: cannot create '{}'", pathname)
+                })?;
+                self.check_new_inode(id, || format!("create: cannot create '{}'", pathname))?;
                 let new_id = self.alloc_fd(|_| FileDescriptor::new_file());
+                self.inherit_project(id, new_id);
                 let fd = &mut self.fds[id];
                 let entries = fd.file_type.as_dir_mut();
                 entries.insert(basename.to_string(), new_id);
+                self.touch_modified(id);
                 Ok(())
             }
-            None => Err(format!(
-                "create: cannot create '{}': No such file or directory",
-                pathname
+            None => Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!(
+                    "create: cannot create '{}': No such file or directory",
+                    pathname
+                ),
             )),
         }
     }
 
-    pub fn link(&mut self, pn1: &str, pn2: &str) -> Result<(), String> {
-        let basename = Vfs::basename(&pn2);
-        let dirname = Vfs::dirname(&pn2);
+    pub fn link(&mut self, pn1: &str, pn2: &str) -> Result<(), VfsError> {
+        let result = self.link_unaudited(pn1, pn2);
+        self.audit(
+            || Op::Link {
+                pathname1: pn1.to_string(),
+                pathname2: pn2.to_string(),
+            },
+            &result,
+        );
+        result.map_err(|err| self.error_at2(err, pn1, pn2))
+    }
+
+    fn link_unaudited(&mut self, pn1: &str, pn2: &str) -> Result<(), VfsError> {
+        self.check_writable_at(pn2, || format!("link: cannot link '{}' to '{}'", pn2, pn1))?;
+        let basename = Vfs::basename(pn2);
+        let dirname = Vfs::dirname(pn2);
         let r1 = self.resolve(pn1);
         let r2 = self.resolve(&dirname);
         match (r1, r2) {
             (Some((fd1, id1, _)), Some((fd2, id2, _))) => {
                 if fd1.file_type.is_dir() {
-                    return Err(format!(
-                        "link: cannot create link '{}' to '{}': Operation not permitted",
-                        pn2, pn1
+                    return Err(VfsError::new(
+                        ErrorKind::NotPermitted,
+                        format!(
+                            "link: cannot create link '{}' to '{}': Operation not permitted",
+                            pn2, pn1
+                        ),
                     ));
                 }
                 if !fd2.file_type.is_dir() {
-                    return Err(format!(
-                        "link: cannot link '{}' to '{}': Not a directory",
-                        pn2, pn1
+                    return Err(VfsError::new(
+                        ErrorKind::NotADirectory,
+                        format!("link: cannot link '{}' to '{}': Not a directory", pn2, pn1),
                     ));
                 }
+                self.check_access(id2, W_OK | X_OK, || {
+                    format!("link: cannot link '{}' to '{}'", pn2, pn1)
+                })?;
+                self.check_attrs(id2, ATTR_IMMUTABLE, || {
+                    format!("link: cannot link '{}' to '{}'", pn2, pn1)
+                })?;
+                self.check_attrs(id1, ATTR_IMMUTABLE | ATTR_APPEND, || {
+                    format!("link: cannot link '{}' to '{}'", pn2, pn1)
+                })?;
                 let fd2 = &mut self.fds[id2];
                 let entries = fd2.file_type.as_dir_mut();
                 if entries.contains_key(&basename) || basename.is_empty() {
-                    return Err(format!(
-                        "link: cannot link '{}' to '{}': File exists",
-                        pn2, pn1
+                    return Err(VfsError::new(
+                        ErrorKind::AlreadyExists,
+                        format!("link: cannot link '{}' to '{}': File exists", pn2, pn1),
                     ));
                 }
                 entries.insert(basename.to_string(), id1);
                 let fd1 = &mut self.fds[id1];
                 fd1.links += 1;
+                self.touch_modified(id2);
+                self.touch_changed(id1);
                 Ok(())
             }
-            _ => Err(format!(
-                "link: cannot link '{}' to '{}': No such file or directory",
-                pn2, pn1,
+            _ => Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!(
+                    "link: cannot link '{}' to '{}': No such file or directory",
+                    pn2, pn1,
+                ),
             )),
         }
     }
@@ -623,173 +1569,952 @@ impl Vfs {
         }
         match &fd.file_type {
             FileType::Regular(blocks_refs) => {
-                for id in blocks_refs {
-                    self.blocks_id.free(*id);
+                for &block_id in blocks_refs {
+                    self.blocks.release(block_id);
                 }
             }
             FileType::Directory(_) => {}
             FileType::Symlink(_) => {}
+            FileType::Fifo(_) => {}
+            FileType::Device(_) => {}
+            FileType::Proc(_) => {}
+            FileType::Ring(_) => {}
+            FileType::Archive(_) => {}
         }
+        self.reclaim(id);
+        self.seals.remove(&id);
         self.fds_id.free(id);
     }
 
-    pub fn unlink(&mut self, pathname: &str) -> Result<(), String> {
+    pub fn unlink(&mut self, pathname: &str) -> Result<(), VfsError> {
+        let result = self.unlink_unaudited(pathname);
+        self.audit(
+            || Op::Unlink {
+                pathname: pathname.to_string(),
+            },
+            &result,
+        );
+        result.map_err(|err| self.error_at(err, pathname))
+    }
+
+    fn unlink_unaudited(&mut self, pathname: &str) -> Result<(), VfsError> {
+        self.check_writable_at(pathname, || format!("unlink: cannot unlink '{}'", pathname))?;
         match self.resolve(pathname) {
             Some((fd, id, parent_id)) => {
                 if fd.file_type.is_dir() {
-                    return Err(format!(
-                        "unlink: cannot unlink '{}': Is a directory",
-                        pathname
+                    return Err(VfsError::new(
+                        ErrorKind::IsADirectory,
+                        format!("unlink: cannot unlink '{}': Is a directory", pathname),
                     ));
                 }
+                let context = || format!("unlink: cannot unlink '{}'", pathname);
+                // A trailing separator asks for a directory.
+                let name = Vfs::basename(pathname);
+                if name.is_empty() {
+                    return Err(VfsError::new(
+                        ErrorKind::NotADirectory,
+                        format!("{}: Not a directory", context()),
+                    ));
+                }
+                self.check_access(parent_id, W_OK | X_OK, context)?;
+                self.check_attrs(parent_id, ATTR_IMMUTABLE | ATTR_APPEND, context)?;
+                self.check_attrs(id, ATTR_IMMUTABLE | ATTR_APPEND, context)?;
+                self.check_sticky(parent_id, id, context)?;
                 let dir = &mut self.fds[parent_id];
                 let entries = dir.file_type.as_dir_mut();
-                let name = Vfs::basename(pathname);
                 entries.remove(&name);
                 let fd = &mut self.fds[id];
                 fd.links -= 1;
+                self.touch_modified(parent_id);
+                self.touch_changed(id);
+                self.note_unlinked(id, pathname);
                 self.free_fd(id);
                 Ok(())
             }
-            _ => Err(format!(
-                "unlink: cannot unlink '{}': No such file or directory",
-                pathname
+            _ => Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!(
+                    "unlink: cannot unlink '{}': No such file or directory",
+                    pathname
+                ),
             )),
         }
     }
 
-    pub fn open(&mut self, pathname: &str) -> Result<usize, String> {
-        match self.resolve(pathname) {
-            Some((fd, id, _)) => {
-                if !fd.file_type.is_file() {
-                    return Err(format!(
-                        "open: cannot open '{}': Operation not permitted",
-                        pathname
+    /// Move the hard link `pn1` to `pn2`, replacing a file or an empty
+    /// directory already there, as `rename(2)` does. Either path may end
+    /// with a separator if `pn1` is a directory; neither may end in `.` or
+    /// `..`.
+    pub fn rename(&mut self, pn1: &str, pn2: &str) -> Result<Renamed, VfsError> {
+        let result = self.rename_unaudited(pn1, pn2);
+        self.audit(
+            || Op::Rename {
+                pathname1: pn1.to_string(),
+                pathname2: pn2.to_string(),
+            },
+            &result,
+        );
+        result.map_err(|err| self.error_at2(err, pn1, pn2))
+    }
+
+    fn rename_unaudited(&mut self, pn1: &str, pn2: &str) -> Result<Renamed, VfsError> {
+        let context = || format!("mv: cannot move '{}' to '{}'", pn1, pn2);
+        self.check_writable_at(pn1, context)?;
+        self.check_bind_writable(pn2, context)?;
+        // Trailing separators are allowed on directories only, and do not
+        // make the last component empty.
+        fn trim(pn: &str) -> &str {
+            match pn.trim_end_matches(TRAILING_SEPARATOR) {
+                "" => pn,
+                trimmed => trimmed,
+            }
+        }
+        let trailing = trim(pn1) != pn1 || trim(pn2) != pn2;
+        let (pn1, pn2) = (trim(pn1), trim(pn2));
+        let name1 = Vfs::basename(pn1);
+        let name2 = Vfs::basename(pn2);
+        let (id, parent1) = match self.resolve(pn1) {
+            Some((_, id, parent_id)) => (id, parent_id),
+            None => {
+                return Err(VfsError::new(
+                    ErrorKind::NotFound,
+                    format!("{}: No such file or directory", context()),
+                ))
+            }
+        };
+        let parent2 = match self.resolve(&format!("{}/{}", Vfs::dirname(pn2), DOT)) {
+            Some((fd, id, _)) if fd.file_type.is_dir() => id,
+            Some(_) => {
+                return Err(VfsError::new(
+                    ErrorKind::NotADirectory,
+                    format!("{}: Not a directory", context()),
+                ))
+            }
+            None => {
+                return Err(VfsError::new(
+                    ErrorKind::NotFound,
+                    format!("{}: No such file or directory", context()),
+                ))
+            }
+        };
+        let special = |name: &str| name.is_empty() || name == DOT || name == DOTDOT;
+        if id == 0 || special(&name1) || special(&name2) {
+            return Err(VfsError::new(
+                ErrorKind::InvalidInput,
+                format!("{}: Invalid argument", context()),
+            ));
+        }
+        self.check_access(parent1, W_OK | X_OK, context)?;
+        self.check_access(parent2, W_OK | X_OK, context)?;
+        self.check_attrs(parent1, ATTR_IMMUTABLE | ATTR_APPEND, context)?;
+        self.check_attrs(parent2, ATTR_IMMUTABLE, context)?;
+        self.check_attrs(id, ATTR_IMMUTABLE | ATTR_APPEND, context)?;
+        self.check_sticky(parent1, id, context)?;
+        let is_dir = self.fds[id].file_type.is_dir();
+        if trailing && !is_dir {
+            return Err(VfsError::new(
+                ErrorKind::NotADirectory,
+                format!("{}: Not a directory", context()),
+            ));
+        }
+        if is_dir {
+            // Moving a directory below itself would detach it from the tree
+            // with a `..` cycle.
+            if self.is_ancestor(id, parent2) {
+                return Err(VfsError::new(
+                    ErrorKind::InvalidInput,
+                    format!("{}: Invalid argument", context()),
+                ));
+            }
+            let prefix = self.realpath(pn1).map(|path| format!("{}/", path));
+            let busy = self.is_proc_fds(id)
+                || self.is_bound(id)
+                || prefix
+                    .as_ref()
+                    .is_some_and(|prefix| self.has_binds_below(prefix))
+                || self.mounts.iter().any(|mount| {
+                    mount.root_id == id
+                        || prefix
+                            .as_ref()
+                            .is_some_and(|prefix| mount.mountpoint.starts_with(prefix))
+                });
+            if busy {
+                return Err(VfsError::new(
+                    ErrorKind::Busy,
+                    format!("{}: Device or resource busy", context()),
+                ));
+            }
+        }
+        let renamed = match self.fds[parent2].file_type.as_dir().get(&name2) {
+            Some(&target) if target == id => return Ok(Renamed::SameFile),
+            Some(&target) => {
+                self.check_attrs(parent2, ATTR_APPEND, context)?;
+                self.check_attrs(target, ATTR_IMMUTABLE | ATTR_APPEND, context)?;
+                self.check_sticky(parent2, target, context)?;
+                let fd = &self.fds[target];
+                match (is_dir, fd.file_type.is_dir()) {
+                    (true, false) => {
+                        return Err(VfsError::new(
+                            ErrorKind::NotADirectory,
+                            format!("{}: Not a directory", context()),
+                        ))
+                    }
+                    (false, true) => {
+                        return Err(VfsError::new(
+                            ErrorKind::IsADirectory,
+                            format!("{}: Is a directory", context()),
+                        ))
+                    }
+                    (true, true) if fd.file_type.as_dir().len() > 2 => {
+                        return Err(VfsError::new(
+                            ErrorKind::DirectoryNotEmpty,
+                            format!("{}: Directory not empty", context()),
+                        ))
+                    }
+                    (true, true)
+                        if self.is_proc_fds(target)
+                            || self.is_bound(target)
+                            || self.mounts.iter().any(|mount| mount.root_id == target) =>
+                    {
+                        return Err(VfsError::new(
+                            ErrorKind::Busy,
+                            format!("{}: Device or resource busy", context()),
+                        ))
+                    }
+                    _ => {}
+                }
+                self.fds[parent2].file_type.as_dir_mut().remove(&name2);
+                self.fds[target].links -= 1;
+                self.touch_changed(target);
+                self.note_unlinked(target, pn2);
+                self.free_fd(target);
+                if target == self.session.cwd_id {
+                    self.set_cwd(0, PATHNAME_SEPARATOR.to_string());
+                }
+                Renamed::Replaced
+            }
+            None => Renamed::Moved,
+        };
+        self.fds[parent1].file_type.as_dir_mut().remove(&name1);
+        self.fds[parent2]
+            .file_type
+            .as_dir_mut()
+            .insert(name2.to_string(), id);
+        if is_dir {
+            self.fds[id]
+                .file_type
+                .as_dir_mut()
+                .insert(DOTDOT.to_string(), parent2);
+            self.session.cwd = self.dir_path(self.session.cwd_id);
+        }
+        self.touch_modified(parent1);
+        self.touch_modified(parent2);
+        self.touch_changed(id);
+        Ok(renamed)
+    }
+
+    /// Whether directory `a` is directory `b` or one of its ancestors, found
+    /// by walking up the `..` entries from `b`; the root is an ancestor of
+    /// every directory.
+    pub(crate) fn is_ancestor(&self, a: usize, mut b: usize) -> bool {
+        loop {
+            if b == a {
+                return true;
+            }
+            if b == 0 {
+                return false;
+            }
+            b = self.fds[b].file_type.as_dir()[DOTDOT];
+        }
+    }
+
+    /// Absolute path of directory `id`, found by walking up its parents.
+    fn dir_path(&self, mut id: usize) -> String {
+        let mut segments = Vec::new();
+        while id != 0 {
+            let parent_id = self.fds[id].file_type.as_dir()[DOTDOT];
+            let name = self.fds[parent_id]
+                .file_type
+                .as_dir()
+                .iter()
+                .find(|(name, &child_id)| child_id == id && *name != DOT && *name != DOTDOT)
+                .map(|(name, _)| name.as_str())
+                .unwrap_or_default();
+            segments.push(name);
+            id = parent_id;
+        }
+        segments.reverse();
+        format!(
+            "{}{}",
+            PATHNAME_SEPARATOR,
+            segments.join(PATHNAME_SEPARATOR)
+        )
+    }
+
+    /// One absolute path of inode `inode`, or `None` if it is free or no
+    /// longer linked anywhere. A directory has exactly one path, found
+    /// through its `..` entries; for other files, which may have several
+    /// hard links, the tree is searched breadth-first for the shortest.
+    pub fn path_of(&self, inode: usize) -> Option<String> {
+        if inode >= self.fds.len() || self.fds_id.free.contains(&inode) {
+            return None;
+        }
+        if self.fds[inode].file_type.is_dir() {
+            return Some(self.dir_path(inode));
+        }
+        if self.fds[inode].links == 0 {
+            return None;
+        }
+        let mut queue = VecDeque::from([0]);
+        while let Some(dir_id) = queue.pop_front() {
+            for (name, &id) in self.fds[dir_id].file_type.as_dir() {
+                if name == DOT || name == DOTDOT {
+                    continue;
+                }
+                if id == inode {
+                    let dirname = self.dir_path(dir_id);
+                    return Some(format!("{}/{}", dirname.trim_end_matches('/'), name));
+                }
+                if self.fds[id].file_type.is_dir() {
+                    queue.push_back(id);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn open(&mut self, pathname: &str) -> Result<usize, VfsError> {
+        let result = self.open_unaudited(pathname);
+        self.audit(
+            || Op::Open {
+                pathname: pathname.to_string(),
+            },
+            &result,
+        );
+        result.map_err(|err| self.error_at(err, pathname))
+    }
+
+    /// Descriptors carry no access mode, so opening needs read or write
+    /// permission and each read or write then checks the one it needs.
+    /// Opening fails first if the open descriptor limit is reached.
+    fn check_open<F>(&self, id: usize, context: F) -> Result<(), VfsError>
+    where
+        F: FnOnce() -> String,
+    {
+        if self
+            .max_open_files
+            .is_some_and(|max_open_files| self.open_fds.len() >= max_open_files)
+        {
+            return Err(VfsError::new(
+                ErrorKind::TooManyOpenFiles,
+                format!("{}: Too many open files", context()),
+            ));
+        }
+        self.check_access(id, R_OK, String::new)
+            .or_else(|_| self.check_access(id, W_OK, context))
+    }
+
+    /// Open inode `id`, which must be a file that can be opened.
+    fn open_id(&mut self, id: usize) -> usize {
+        self.fds[id].refs += 1;
+        let (oid, _) = self.open_fds_id.next();
+        self.open_fds.insert(oid, (id, 0));
+        self.proc_open(oid);
+        oid
+    }
+
+    fn open_unaudited(&mut self, pathname: &str) -> Result<usize, VfsError> {
+        match self.resolve_bound(pathname) {
+            Some((fd, id, _, read_only)) => {
+                if fd.file_type.is_dir() || fd.file_type.is_symlink() {
+                    return Err(VfsError::new(
+                        ErrorKind::NotPermitted,
+                        format!("open: cannot open '{}': Operation not permitted", pathname),
                     ));
                 }
-                let fd = &mut self.fds[id];
-                fd.refs += 1;
-                let (oid, _) = self.open_fds_id.next();
-                self.open_fds.insert(oid, (id, 0));
+                self.check_open(id, || format!("open: cannot open '{}'", pathname))?;
+                let oid = self.open_id(id);
+                if read_only {
+                    self.read_only_fds.insert(oid);
+                }
                 Ok(oid)
             }
-            None => Err(format!(
-                "open: cannot open '{}': No such file or directory",
-                pathname
+            None => Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!(
+                    "open: cannot open '{}': No such file or directory",
+                    pathname
+                ),
             )),
         }
     }
 
-    pub fn close(&mut self, oid: usize) -> Result<(), String> {
+    pub fn close(&mut self, oid: usize) -> Result<(), VfsError> {
+        let result = self.close_unaudited(oid);
+        self.audit(|| Op::Close { fd: oid }, &result);
+        result
+    }
+
+    fn close_unaudited(&mut self, oid: usize) -> Result<(), VfsError> {
         match self.open_fds.remove(&oid) {
             Some((id, _)) => {
                 self.open_fds_id.free(oid);
+                self.read_only_fds.remove(&oid);
+                self.open_fd_flags.remove(&oid);
+                self.proc_close(oid);
                 let fd = &mut self.fds[id];
                 fd.refs -= 1;
                 self.free_fd(id);
                 Ok(())
             }
-            None => Err(format!("close: invalid file descriptor: {}", oid)),
+            None => Err(VfsError::new(
+                ErrorKind::BadDescriptor,
+                format!("close: invalid file descriptor: {}", oid),
+            )),
         }
     }
 
-    pub fn seek(&mut self, oid: usize, offset: usize) -> Result<(), String> {
+    pub fn seek(&mut self, oid: usize, offset: usize) -> Result<(), VfsError> {
+        let result = self.seek_unaudited(oid, offset);
+        self.audit(|| Op::Seek { fd: oid, offset }, &result);
+        result
+    }
+
+    fn seek_unaudited(&mut self, oid: usize, offset: usize) -> Result<(), VfsError> {
         match self.open_fds.get_mut(&oid) {
             Some((id, cursor)) => {
                 let fd = &self.fds[*id];
+                if fd.file_type.is_fifo() {
+                    return Err(VfsError::new(
+                        ErrorKind::IllegalSeek,
+                        format!("seek: cannot seek {}: Illegal seek", oid),
+                    ));
+                }
+                if fd.file_type.is_device() {
+                    return Ok(());
+                }
+                if let FileType::Proc(_) | FileType::Ring(_) | FileType::Archive(_) = fd.file_type {
+                    *cursor = offset;
+                    return Ok(());
+                }
                 if *cursor > fd.size {
                     *cursor = fd.size;
                 }
                 if offset > fd.size {
-                    return Err(format!("seek: invalid offset: {}", offset));
+                    return Err(VfsError::new(
+                        ErrorKind::InvalidInput,
+                        format!("seek: invalid offset: {}", offset),
+                    ));
                 }
                 *cursor = offset;
                 Ok(())
             }
-            None => Err(format!("seek: invalid file descriptor: {}", oid)),
+            None => Err(VfsError::new(
+                ErrorKind::BadDescriptor,
+                format!("seek: invalid file descriptor: {}", oid),
+            )),
         }
     }
 
-    pub fn write(&mut self, oid: usize, data: &[u8]) -> Result<usize, String> {
+    /// Write `data` at the offset of descriptor `oid`, returning how many
+    /// bytes were written. Like `write(2)`, this may be fewer than
+    /// `data.len()` when the device fills up or fails partway; the error
+    /// is only returned if nothing could be written, so retrying the rest
+    /// reports it. See `write_all`.
+    pub fn write(&mut self, oid: usize, data: &[u8]) -> Result<usize, VfsError> {
+        let result = self.write_unaudited(oid, data);
+        let written = *result.as_ref().unwrap_or(&0);
+        self.audit(
+            || Op::Write {
+                fd: oid,
+                data: data[..written].to_vec(),
+            },
+            &result,
+        );
+        result
+    }
+
+    fn write_unaudited(&mut self, oid: usize, data: &[u8]) -> Result<usize, VfsError> {
+        if let Some(&(id, _)) = self.open_fds.get(&oid) {
+            self.check_access(id, W_OK, || format!("write: cannot write {}", oid))?;
+            self.check_attrs(id, ATTR_IMMUTABLE, || {
+                format!("write: cannot write {}", oid)
+            })?;
+            match &self.fds[id].file_type {
+                FileType::Proc(_) => {
+                    return Err(VfsError::new(
+                        ErrorKind::PermissionDenied,
+                        format!("write: cannot write {}: Permission denied", oid),
+                    ))
+                }
+                FileType::Archive(_) => {
+                    return Err(VfsError::new(
+                        ErrorKind::ReadOnly,
+                        format!("write: cannot write {}: Read-only file system", oid),
+                    ))
+                }
+                _ => {}
+            }
+        }
+        self.check_writable(|| format!("write: cannot write {}", oid))?;
+        if self.read_only_fds.contains(&oid) {
+            return Err(VfsError::new(
+                ErrorKind::ReadOnly,
+                format!("write: cannot write {}: Read-only file system", oid),
+            ));
+        }
+        if let Some(&(id, cursor)) = self.open_fds.get(&oid) {
+            let context = || format!("write: cannot write {}", oid);
+            if !self.fds[id].file_type.is_file() {
+                self.check_seals(id, self.fds[id].size, true, context)?;
+                match &mut self.fds[id].file_type {
+                    FileType::Fifo(buffer) => {
+                        buffer.extend(data);
+                        return Ok(data.len());
+                    }
+                    FileType::Ring(ring) => {
+                        ring.push(data);
+                        self.fds[id].size = ring.len();
+                        self.touch_modified(id);
+                        return Ok(data.len());
+                    }
+                    FileType::Device(device) => return Ok(device.write(data)),
+                    _ => {}
+                }
+            }
+            let fd = &self.fds[id];
+            let start = if fd.attrs & ATTR_APPEND != 0 || self.is_append_fd(oid) {
+                fd.size
+            } else {
+                cursor
+            };
+            self.check_seals(id, fd.size.max(start + data.len()), true, context)?;
+            self.check_project_bytes(id, start + data.len(), context)?;
+        }
+        let append = self.is_append_fd(oid);
         match self.open_fds.get_mut(&oid) {
             Some((id, cursor)) => {
                 let fd = &mut self.fds[*id];
+                if fd.attrs & ATTR_APPEND != 0 || append {
+                    *cursor = fd.size;
+                }
                 let blocks_refs = fd.file_type.as_file_mut();
-                let mut rest = data;
+                let allowed = self.faults.allow_write(data.len());
+                let mut rest = &data[..allowed];
+                let mut error = (allowed < data.len()).then(|| {
+                    VfsError::new(
+                        ErrorKind::Other,
+                        format!("write: cannot write {}: Input/output error", oid),
+                    )
+                });
                 while !rest.is_empty() {
                     let i = *cursor / BLOCK_SIZE;
-                    let block_ref = match blocks_refs.get(i).copied().unwrap_or(0) {
-                        0 => {
-                            let (id, incremented) = self.blocks_id.next();
-                            if incremented {
-                                let new_len = self.blocks.len() + BLOCK_SIZE;
-                                self.blocks.resize(new_len + BLOCK_SIZE, 0);
-                            }
-                            if blocks_refs.len() == i {
-                                blocks_refs.push(id);
-                            } else {
-                                blocks_refs[i] = id;
-                            }
-                            id
-                        }
-                        id => id,
-                    };
+                    // The file may have been truncated below the offset
+                    // since; the gap reads as zeros.
+                    if blocks_refs.len() <= i {
+                        blocks_refs.resize(i + 1, 0);
+                    }
                     let offset = *cursor % BLOCK_SIZE;
                     let n = (BLOCK_SIZE - offset).min(rest.len());
-                    let from = block_ref * BLOCK_SIZE + offset;
-                    let to = from + n;
-                    self.blocks[from..to].copy_from_slice(&rest[..n]);
+                    let hint = block::hint(blocks_refs, i);
+                    match self.blocks.update(blocks_refs[i], hint, |block| {
+                        block[offset..offset + n].copy_from_slice(&rest[..n]);
+                    }) {
+                        Ok(block_ref) => blocks_refs[i] = block_ref,
+                        Err(err) => {
+                            error = Some(err.into_error(format!("write: cannot write {}", oid)));
+                            break;
+                        }
+                    }
                     rest = &rest[n..];
                     *cursor += n;
                 }
                 fd.size = fd.size.max(*cursor);
-                Ok(data.len())
+                blocks_refs.truncate(fd.size.div_ceil(BLOCK_SIZE));
+                let id = *id;
+                let written = allowed - rest.len();
+                self.record_write(written);
+                if written > 0 {
+                    self.touch_modified(id);
+                }
+                match error {
+                    Some(error) if written == 0 => Err(error),
+                    _ => Ok(written),
+                }
             }
-            None => Err(format!("write: invalid file descriptor: {}", oid)),
+            None => Err(VfsError::new(
+                ErrorKind::BadDescriptor,
+                format!("write: invalid file descriptor: {}", oid),
+            )),
         }
     }
 
-    pub fn read(&mut self, oid: usize, size: usize) -> Result<Vec<u8>, String> {
-        match self.open_fds.get_mut(&oid) {
-            Some((id, cursor)) => {
-                let fd = &self.fds[*id];
+    /// Return the plaintext of a block after checking its checksum and, in
+    /// verity mode, its Merkle path.
+    fn read_block(&self, block_ref: usize) -> Option<Plain> {
+        if self.faults.fails_read(block_ref) {
+            return None;
+        }
+        let verified = self
+            .verity
+            .as_ref()
+            .is_none_or(|tree| tree.verify(block_ref, &self.blocks.raw(block_ref)));
+        self.blocks.read(block_ref).filter(|_| verified)
+    }
+
+    /// Iterate over the plaintext of a file block by block, trimmed to the
+    /// file size; a corrupted block yields its id.
+    fn file_chunks(&self, id: usize) -> impl Iterator<Item = Result<Plain, usize>> + '_ {
+        let fd = &self.fds[id];
+        let blocks_refs = fd.file_type.as_file();
+        blocks_refs
+            .iter()
+            .take(fd.size.div_ceil(BLOCK_SIZE))
+            .enumerate()
+            .map(move |(i, &block_ref)| {
+                let n = (fd.size - i * BLOCK_SIZE).min(BLOCK_SIZE);
+                let mut block = self.read_block(block_ref).ok_or(block_ref)?;
+                block.truncate(n);
+                Ok(block)
+            })
+    }
+
+    fn file_contents(&self, id: usize) -> Result<Vec<u8>, usize> {
+        let fd = &self.fds[id];
+        let blocks_refs = fd.file_type.as_file();
+        let mut data = Vec::with_capacity(fd.size);
+        // Verity and injected read faults need each block checked on its
+        // own; otherwise a run of blocks is read under one page lock.
+        if self.verity.is_none() && !blocks_refs.iter().any(|&id| self.faults.fails_read(id)) {
+            self.blocks.read_into(blocks_refs, fd.size, &mut data)?;
+            return Ok(data);
+        }
+        for chunk in self.file_chunks(id) {
+            data.extend_from_slice(&chunk?);
+        }
+        Ok(data)
+    }
+
+    /// Resolve `pathname` to the id of a regular file, with errors prefixed
+    /// by `cmd`.
+    fn regular_file(&self, cmd: &str, pathname: &str) -> Result<usize, VfsError> {
+        match self.resolve(pathname) {
+            Some((fd, id, _)) => {
+                if !fd.file_type.is_file() {
+                    return Err(VfsError::new(
+                        ErrorKind::InvalidInput,
+                        format!("{}: cannot read '{}': Not a regular file", cmd, pathname),
+                    ));
+                }
+                self.check_access(id, R_OK, || format!("{}: cannot read '{}'", cmd, pathname))?;
+                Ok(id)
+            }
+            None => Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!(
+                    "{}: cannot read '{}': No such file or directory",
+                    cmd, pathname
+                ),
+            )),
+        }
+    }
+
+    /// Why block `block_ref` could not be read, after `context`.
+    fn block_error(&self, context: String, block_ref: usize) -> VfsError {
+        if self.faults.fails_read(block_ref) {
+            return VfsError::new(ErrorKind::Other, format!("{}: Input/output error", context));
+        }
+        VfsError::new(
+            ErrorKind::DataCorruption,
+            format!(
+                "{}: Data corruption detected in block {}",
+                context, block_ref
+            ),
+        )
+    }
+
+    fn corrupted(&self, cmd: &str, pathname: &str, block_ref: usize) -> VfsError {
+        self.block_error(format!("{}: cannot read '{}'", cmd, pathname), block_ref)
+    }
+
+    /// Read the whole contents of a regular file without opening it. Its
+    /// access time is left alone, see `AtimePolicy`.
+    pub fn read_file(&self, pathname: &str) -> Result<Vec<u8>, VfsError> {
+        self.read_file_as("read", pathname)
+    }
+
+    /// Like `read_file`, with errors reported as coming from the shell
+    /// command `cmd`.
+    pub fn read_file_as(&self, cmd: &str, pathname: &str) -> Result<Vec<u8>, VfsError> {
+        if let Some((fd, _, _)) = self.resolve(pathname) {
+            match &fd.file_type {
+                FileType::Proc(entry) => return Ok(self.proc_contents(entry)),
+                FileType::Ring(ring) => return Ok(ring.contents()),
+                FileType::Archive(member) => {
+                    return member.contents().map_err(|err| {
+                        archive_error(format!("{}: cannot read '{}'", cmd, pathname), &err)
+                    })
+                }
+                _ => {}
+            }
+        }
+        let id = self
+            .regular_file(cmd, pathname)
+            .map_err(|err| self.error_at(err, pathname))?;
+        self.file_contents(id)
+            .map_err(|block_ref| self.corrupted(cmd, pathname, block_ref))
+    }
+
+    /// Replace the contents of a regular file, creating it if needed.
+    pub fn write_file(&mut self, pathname: &str, data: &[u8]) -> Result<(), VfsError> {
+        self.create(pathname)?;
+        self.truncate(pathname, 0)?;
+        let oid = self.open(pathname)?;
+        let result = self.write_all(oid, data);
+        self.close(oid)?;
+        result
+    }
+
+    /// Replace the contents of a regular file in one step, creating it if
+    /// needed: the data is written to a new hidden file in the same
+    /// directory, which is then renamed over `pathname`. Readers see the
+    /// old contents or the new, never a partial file, and descriptors
+    /// open on the old file keep reading it. The new file takes the mode
+    /// of the one it replaces. A symlink is followed: the file it points
+    /// to is replaced, and the link kept.
+    pub fn write_atomic(&mut self, pathname: &str, data: &[u8]) -> Result<(), VfsError> {
+        if pathname.ends_with(TRAILING_SEPARATOR) {
+            return Err(VfsError::new(
+                ErrorKind::IsADirectory,
+                format!("write: cannot replace '{}': Is a directory", pathname),
+            ));
+        }
+        let link = pathname;
+        let pathname = &self.follow_links(pathname).ok_or_else(|| {
+            VfsError::new(
+                ErrorKind::Loop,
+                format!("write: cannot replace '{}': {}", link, LOOP),
+            )
+        })?;
+        let mode = match self.resolve(pathname) {
+            Some((fd, _, _)) if fd.file_type.is_dir() => {
+                let message = VfsError::new(
+                    ErrorKind::IsADirectory,
+                    format!("write: cannot replace '{}': Is a directory", pathname),
+                );
+                return Err(message);
+            }
+            Some((fd, _, _)) => Some(fd.mode),
+            None => None,
+        };
+        let temp = self.temp_name(pathname);
+        let result = self.write_temp(&temp, mode, data);
+        let result = result.and_then(|()| self.rename(&temp, pathname).map(|_| ()));
+        if result.is_err() && self.resolve(&temp).is_some() {
+            let _ = self.unlink(&temp);
+        }
+        result
+    }
+
+    fn write_temp(&mut self, temp: &str, mode: Option<u32>, data: &[u8]) -> Result<(), VfsError> {
+        self.create(temp)?;
+        if let Some(mode) = mode {
+            self.chmod(temp, mode)?;
+        }
+        let oid = self.open(temp)?;
+        let result = self.write_all(oid, data);
+        self.close(oid)?;
+        result
+    }
+
+    /// `pathname` with the symlinks in its last component followed, the
+    /// last target not necessarily existing, or `None` on a loop or a
+    /// symlink in a `nosymfollow` directory.
+    fn follow_links(&self, pathname: &str) -> Option<String> {
+        let mut pathname = pathname.to_string();
+        for _ in 0..=SYMLINK_RESOLVE_LIMIT {
+            let Some((fd, _, parent_id)) = self.resolve(&pathname) else {
+                return Some(pathname);
+            };
+            if fd.file_type.is_symlink() && self.is_nosymfollow_dir(parent_id) {
+                return None;
+            }
+            pathname = match &fd.file_type {
+                FileType::Symlink(path) if Vfs::is_absolute(path) => path.clone(),
+                FileType::Symlink(path) => format!("{}/{}", Vfs::dirname(&pathname), path),
+                _ => return Some(pathname),
+            };
+        }
+        None
+    }
+
+    /// A free hidden name next to `pathname`, e.g. `/a/.f.~1` for `/a/f`.
+    fn temp_name(&self, pathname: &str) -> String {
+        let basename = Vfs::basename(pathname);
+        let prefix = &pathname[..pathname.len() - basename.len()];
+        (1..)
+            .map(|n| format!("{}.{}.~{}", prefix, basename, n))
+            .find(|temp| self.resolve(temp).is_none())
+            .unwrap()
+    }
+
+    /// `write_file` a block at a time, reporting each block to `monitor`
+    /// and stopping after `context` if it is cancelled.
+    pub(crate) fn write_file_with<F>(
+        &mut self,
+        pathname: &str,
+        data: &[u8],
+        monitor: &mut Monitor,
+        context: &F,
+    ) -> Result<(), VfsError>
+    where
+        F: Fn() -> String,
+    {
+        self.create(pathname)?;
+        self.truncate(pathname, 0)?;
+        let oid = self.open(pathname)?;
+        let result = data.chunks(BLOCK_SIZE).try_for_each(|block| {
+            self.write_all(oid, block)?;
+            monitor.advance(block.len(), 0, context)
+        });
+        self.close(oid)?;
+        result
+    }
+
+    /// Write all of `data` through descriptor `oid`, retrying after short
+    /// writes until the rest fails.
+    pub fn write_all(&mut self, oid: usize, mut data: &[u8]) -> Result<(), VfsError> {
+        while !data.is_empty() {
+            match self.write(oid, data)? {
+                0 => {
+                    return Err(VfsError::new(
+                        ErrorKind::NoSpace,
+                        format!("write: cannot write {}: No space left on device", oid),
+                    ))
+                }
+                n => data = &data[n..],
+            }
+        }
+        Ok(())
+    }
+
+    /// Read up to `size` bytes from the offset of descriptor `oid`. Like
+    /// `read(2)`, fewer bytes are returned near the end of the file, and
+    /// none at or past it, e.g. after the file was truncated below the
+    /// offset.
+    pub fn read(&mut self, oid: usize, size: usize) -> Result<Vec<u8>, VfsError> {
+        match self.open_fds.get(&oid) {
+            Some(&(id, mut cursor)) => {
+                self.check_access(id, R_OK, || format!("read: cannot read {}", oid))?;
+                if let FileType::Archive(member) = &self.fds[id].file_type {
+                    let data = member
+                        .read_at(cursor, size)
+                        .map_err(|err| archive_error(format!("read: cannot read {}", oid), &err))?;
+                    self.open_fds.insert(oid, (id, cursor + data.len()));
+                    return Ok(data);
+                }
+                let generated = match &self.fds[id].file_type {
+                    FileType::Proc(entry) => Some(self.proc_contents(entry)),
+                    FileType::Ring(ring) => Some(ring.contents()),
+                    _ => None,
+                };
+                if let Some(data) = generated {
+                    let start = cursor.min(data.len());
+                    let end = (start + size).min(data.len());
+                    self.open_fds.insert(oid, (id, end));
+                    return Ok(data[start..end].to_vec());
+                }
+                let nonblock = self.open_fd_flags.get(&oid).copied().unwrap_or(0) & O_NONBLOCK != 0;
+                let fd = &mut self.fds[id];
+                if let FileType::Fifo(buffer) = &mut fd.file_type {
+                    if buffer.is_empty() && fd.refs > 1 {
+                        let (kind, reason) = match nonblock {
+                            true => (ErrorKind::WouldBlock, "Resource temporarily unavailable"),
+                            false => (ErrorKind::Deadlock, "Resource deadlock avoided"),
+                        };
+                        return Err(VfsError::new(
+                            kind,
+                            format!("read: cannot read {}: {}", oid, reason),
+                        ));
+                    }
+                    let n = size.min(buffer.len());
+                    return Ok(buffer.drain(..n).collect());
+                }
+                if let FileType::Device(device) = &mut fd.file_type {
+                    return Ok(device.read(size));
+                }
+                let fd = &self.fds[id];
                 let blocks_refs = fd.file_type.as_file();
-                let mut rest = size.min(fd.size - *cursor);
+                let mut rest = size.min(fd.size.saturating_sub(cursor));
                 let mut data = Vec::with_capacity(rest);
                 while rest > 0 {
-                    let i = *cursor / BLOCK_SIZE;
+                    let i = cursor / BLOCK_SIZE;
                     let block_ref = blocks_refs[i];
-                    let offset = *cursor % BLOCK_SIZE;
+                    let block = match self.read_block(block_ref) {
+                        Some(block) => block,
+                        None => {
+                            return Err(
+                                self.block_error(format!("read: cannot read {}", oid), block_ref)
+                            )
+                        }
+                    };
+                    let offset = cursor % BLOCK_SIZE;
                     let n = (BLOCK_SIZE - offset).min(rest);
-                    let from = block_ref * BLOCK_SIZE + offset;
-                    let to = from + n;
-                    let some = &self.blocks[from..to];
-                    data.extend_from_slice(some);
+                    data.extend_from_slice(&block[offset..offset + n]);
                     rest -= n;
-                    *cursor += n;
+                    cursor += n;
                 }
+                self.open_fds.insert(oid, (id, cursor));
+                self.record_read(data.len());
+                self.touch_accessed(id);
                 Ok(data)
             }
-            None => Err(format!("write: invalid file descriptor: {}", oid)),
+            None => Err(VfsError::new(
+                ErrorKind::BadDescriptor,
+                format!("read: invalid file descriptor: {}", oid),
+            )),
         }
     }
 
-    pub fn truncate(&mut self, pathname: &str, size: usize) -> Result<(), String> {
+    pub fn truncate(&mut self, pathname: &str, size: usize) -> Result<(), VfsError> {
+        let result = self.truncate_unaudited(pathname, size);
+        self.audit(
+            || Op::Truncate {
+                pathname: pathname.to_string(),
+                size,
+            },
+            &result,
+        );
+        result.map_err(|err| self.error_at(err, pathname))
+    }
+
+    fn truncate_unaudited(&mut self, pathname: &str, size: usize) -> Result<(), VfsError> {
+        self.check_writable_at(pathname, || {
+            format!("truncate: cannot truncate '{}'", pathname)
+        })?;
         match self.resolve(pathname) {
             Some((fd, id, _)) => {
                 if !fd.file_type.is_file() {
-                    return Err(format!(
-                        "truncate: cannot truncate '{}': Operation not permitted",
-                        pathname
+                    return Err(VfsError::new(
+                        ErrorKind::NotPermitted,
+                        format!(
+                            "truncate: cannot truncate '{}': Operation not permitted",
+                            pathname
+                        ),
                     ));
                 }
+                let context = || format!("truncate: cannot truncate '{}'", pathname);
+                self.check_access(id, W_OK, context)?;
+                self.check_attrs(id, ATTR_IMMUTABLE | ATTR_APPEND, context)?;
+                self.check_seals(id, size, false, context)?;
+                self.check_project_bytes(id, size, context)?;
                 let fd = &mut self.fds[id];
                 let blocks_refs = fd.file_type.as_file_mut();
                 match size.cmp(&fd.size) {
                     cmp::Ordering::Less => {
-                        let i = (size + BLOCK_SIZE - 1) / BLOCK_SIZE;
+                        let i = size.div_ceil(BLOCK_SIZE);
                         for block_id in blocks_refs.drain(i..) {
-                            self.blocks_id.free(block_id);
+                            self.blocks.release(block_id);
                         }
                         if fd.refs != 0 {
                             for (fid, cursor) in self.open_fds.values_mut() {
@@ -800,26 +2525,37 @@ impl Vfs {
                         }
                     }
                     cmp::Ordering::Greater => {
-                        let new_len = (size + BLOCK_SIZE - 1) / BLOCK_SIZE;
-                        blocks_refs.resize(new_len, 0);
                         let j = fd.size / BLOCK_SIZE;
-                        let block_ref = blocks_refs[j];
-                        if block_ref != 0 {
+                        if blocks_refs.get(j).is_some_and(|&block_ref| block_ref != 0) {
                             let offset = fd.size % BLOCK_SIZE;
                             let n = (BLOCK_SIZE - offset).min(size - fd.size);
-                            let from = block_ref * BLOCK_SIZE + offset;
-                            let to = from + n;
-                            self.blocks[from..to].fill(0);
+                            let hint = block::hint(blocks_refs, j);
+                            blocks_refs[j] = self
+                                .blocks
+                                .update(blocks_refs[j], hint, |block| {
+                                    block[offset..offset + n].fill(0);
+                                })
+                                .map_err(|err| {
+                                    err.into_error(format!(
+                                        "truncate: cannot truncate '{}'",
+                                        pathname
+                                    ))
+                                })?;
                         }
+                        blocks_refs.resize(size.div_ceil(BLOCK_SIZE), 0);
                     }
                     cmp::Ordering::Equal => {}
                 }
                 fd.size = size;
+                self.touch_modified(id);
                 Ok(())
             }
-            None => Err(format!(
-                "truncate: cannot truncate '{}': No such file or directory",
-                pathname
+            None => Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!(
+                    "truncate: cannot truncate '{}': No such file or directory",
+                    pathname
+                ),
             )),
         }
     }
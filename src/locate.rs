@@ -0,0 +1,123 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{Op, Vfs, DOT, DOTDOT};
+
+/// Every name in the tree, with the directories that have an entry by
+/// that name.
+pub(crate) type NameIndex = BTreeMap<String, BTreeSet<usize>>;
+
+impl Vfs {
+    /// Start or stop keeping an index of every name in the tree, so that
+    /// `locate` does not have to walk it. Starting builds the index from
+    /// the whole tree; after that, each operation updates it.
+    pub fn set_name_index(&mut self, enabled: bool) {
+        self.name_index = enabled.then(|| self.build_name_index());
+    }
+
+    pub fn is_name_indexed(&self) -> bool {
+        self.name_index.is_some()
+    }
+
+    fn build_name_index(&self) -> NameIndex {
+        let mut index = NameIndex::new();
+        let mut stack = vec![0];
+        while let Some(dir_id) = stack.pop() {
+            for (name, &id) in self.fds[dir_id].file_type.as_dir() {
+                if name == DOT || name == DOTDOT {
+                    continue;
+                }
+                index.entry(name.clone()).or_default().insert(dir_id);
+                if self.fds[id].file_type.is_dir() {
+                    stack.push(id);
+                }
+            }
+        }
+        index
+    }
+
+    /// The absolute paths of every file whose name contains `substring`,
+    /// like `locate(1)`. Uses the name index if enabled, yielding paths in
+    /// name order, and otherwise walks the tree as the caller asks for
+    /// more, yielding them in tree order; either way, permissions are not
+    /// checked. Sort them for a stable order.
+    pub fn locate<'a>(&'a self, substring: &'a str) -> Box<dyn Iterator<Item = String> + 'a> {
+        let path = move |dir_id: usize, name: &str| {
+            format!("{}/{}", self.dir_path(dir_id).trim_end_matches('/'), name)
+        };
+        if let Some(index) = &self.name_index {
+            return Box::new(
+                index
+                    .iter()
+                    .filter(move |(name, _)| name.contains(substring))
+                    .flat_map(move |(name, dirs)| {
+                        dirs.iter().map(move |&dir_id| path(dir_id, name))
+                    }),
+            );
+        }
+        let mut stack = vec![(0, self.fds[0].file_type.as_dir().iter())];
+        Box::new(std::iter::from_fn(move || loop {
+            let (dir_id, entries) = stack.last_mut()?;
+            let dir_id = *dir_id;
+            let Some((name, &id)) = entries.next() else {
+                stack.pop();
+                continue;
+            };
+            if name == DOT || name == DOTDOT {
+                continue;
+            }
+            if self.fds[id].file_type.is_dir() {
+                stack.push((id, self.fds[id].file_type.as_dir().iter()));
+            }
+            if name.contains(substring) {
+                return Some(path(dir_id, name));
+            }
+        }))
+    }
+
+    /// The directory and name of the entry `pathname` names.
+    fn entry_of(&self, pathname: &str) -> Option<(usize, String)> {
+        let pathname = pathname.trim_end_matches('/');
+        let (_, dir_id, _) = self.resolve_follow(&Vfs::dirname(pathname))?;
+        Some((dir_id, Vfs::basename(pathname)))
+    }
+
+    /// Update the name index after a successful `op`.
+    pub(crate) fn index_op(&mut self, op: &Op) {
+        let (removed, added) = match op {
+            Op::Create { pathname }
+            | Op::Mkdir { pathname }
+            | Op::Mkfifo { pathname }
+            | Op::Symlink { pathname, .. }
+            | Op::Link {
+                pathname2: pathname,
+                ..
+            } => (None, Some(pathname)),
+            Op::Rmdir { pathname } | Op::Unlink { pathname } => (Some(pathname), None),
+            Op::Rename {
+                pathname1,
+                pathname2,
+            } => (Some(pathname1), Some(pathname2)),
+            Op::Mount { .. } | Op::Unmount { .. } => {
+                self.set_name_index(true);
+                return;
+            }
+            _ => return,
+        };
+        let removed = removed.and_then(|pathname| self.entry_of(pathname));
+        let added = added.and_then(|pathname| self.entry_of(pathname));
+        let Some(index) = &mut self.name_index else {
+            return;
+        };
+        if let Some((dir_id, name)) = removed {
+            if let Some(dirs) = index.get_mut(&name) {
+                dirs.remove(&dir_id);
+                if dirs.is_empty() {
+                    index.remove(&name);
+                }
+            }
+        }
+        if let Some((dir_id, name)) = added {
+            index.entry(name).or_default().insert(dir_id);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::{notify::absolute, ErrorKind, Vfs, VfsError};
+
+/// When `append_log` rotates a log: once a record would take it past
+/// `max_size` bytes, `path` becomes `path.1`, `path.1` becomes `path.2`
+/// and so on, keeping at most `keep` old logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogRotation {
+    pub max_size: usize,
+    pub keep: usize,
+}
+
+impl Vfs {
+    /// Rotate the log at `pathname` as `rotation` says, or never if it is
+    /// `None`.
+    pub fn set_log_rotation(&mut self, pathname: &str, rotation: Option<LogRotation>) {
+        let path = absolute(self.session.cwd(), pathname);
+        match rotation {
+            Some(rotation) => self.log_rotations.insert(path, rotation),
+            None => self.log_rotations.remove(&path),
+        };
+    }
+
+    pub fn log_rotation(&self, pathname: &str) -> Option<LogRotation> {
+        let path = absolute(self.session.cwd(), pathname);
+        self.log_rotations.get(&path).copied()
+    }
+
+    /// Append `record` as one line to the log at `pathname`, creating it
+    /// if needed. The record is written at the end of the file without
+    /// reading or rewriting it, and the log is rotated first if it would
+    /// grow past its `LogRotation`.
+    pub fn append_log(&mut self, pathname: &str, record: &[u8]) -> Result<(), VfsError> {
+        let mut line = record.to_vec();
+        if line.last() != Some(&b'\n') {
+            line.push(b'\n');
+        }
+        let mut size = self.log_size(pathname)?;
+        if let Some(rotation) = self.log_rotation(pathname) {
+            if size > 0 && size + line.len() > rotation.max_size {
+                self.rotate_log(pathname, rotation.keep)?;
+                size = 0;
+            }
+        }
+        if size == 0 && self.resolve_follow(pathname).is_none() {
+            self.create(pathname)?;
+        }
+        let oid = self.open(pathname)?;
+        let result = self
+            .seek(oid, size)
+            .and_then(|_| self.write_all(oid, &line));
+        self.close(oid)?;
+        result
+    }
+
+    /// The size of the log at `pathname`, 0 if it does not exist yet.
+    fn log_size(&self, pathname: &str) -> Result<usize, VfsError> {
+        match self.resolve_follow(pathname) {
+            Some((fd, _, _)) if fd.file_type.is_file() => Ok(fd.size),
+            Some(_) => Err(VfsError::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "logger: cannot append to '{}': Not a regular file",
+                    pathname
+                ),
+            )),
+            None => Ok(0),
+        }
+    }
+
+    /// Shift `pathname` to `pathname.1`, dropping the oldest log beyond
+    /// `keep`.
+    fn rotate_log(&mut self, pathname: &str, keep: usize) -> Result<(), VfsError> {
+        let old = |n: usize| format!("{}.{}", pathname, n);
+        if keep == 0 {
+            return self.unlink(pathname);
+        }
+        if self.resolve(&old(keep)).is_some() {
+            self.unlink(&old(keep))?;
+        }
+        for n in (1..keep).rev() {
+            if self.resolve(&old(n)).is_some() {
+                self.rename(&old(n), &old(n + 1))?;
+            }
+        }
+        self.rename(pathname, &old(1))?;
+        Ok(())
+    }
+}
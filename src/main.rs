@@ -1,7 +1,72 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
 use clap::{Parser, Subcommand};
+use pager::Output;
+use record::Recorder;
 use rustyline::{error::ReadlineError, DefaultEditor};
-use shellwords::split;
-use vfs::Vfs;
+use shellwords::{escape, split};
+use vfs::{
+    attr_string, fd_flags_string, format_size, mode_string, parse_attrs, parse_fd_flags,
+    parse_seals, parse_size, seals_string, AclEntry, AclTag, Algo, AtimePolicy, BenchResult,
+    BindOptions, DirCursor, FileStats, Histogram, LogRotation, Monitor, MountOptions, ProjectQuota,
+    Renamed, Session, SetTime, StatFs, Throttle, Vfs, VfsBuilder, VfsFixture, Workload,
+    STATX_BASIC_STATS, STATX_BLOCKS, STATX_TYPE,
+};
+
+mod pager;
+mod record;
+mod tui;
+
+const HISTORY_LIMIT: usize = 32;
+const RC_FILE: &str = ".vfsrc";
+const HEREDOC_PROMPT: &str = "> ";
+const DEFAULT_VOLUME: &str = "default";
+const ROOT: &str = "root";
+const FIRST_ID: u32 = 1000;
+
+/// Interactive shell over an in-memory virtual filesystem.
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Cli {
+    /// load the default volume from an image file on the host (created by "save" if missing)
+    #[clap(long)]
+    image: Option<String>,
+    /// make the default volume read-only
+    #[clap(long)]
+    read_only: bool,
+    /// derive all randomness from this seed, so that a session can be replayed exactly
+    #[clap(long)]
+    seed: Option<u64>,
+    /// when reads update access times: noatime, relatime or strictatime
+    #[clap(long, default_value_t)]
+    atime: AtimePolicy,
+    /// run the commands in this file at startup instead of ~/.vfsrc
+    #[clap(long)]
+    rc: Option<String>,
+    #[command(subcommand)]
+    mode: Option<Mode>,
+}
+
+/// What to start instead of the shell.
+#[derive(Subcommand, Debug, Clone)]
+enum Mode {
+    /// Browse the default volume in a dual-pane file manager, then exit
+    Tui,
+    /// Run the shell, recording every command and its effects to a host file for replay (skips the rc file)
+    Record {
+        /// host file to write the session to
+        file: String,
+    },
+    /// Replay a recorded session against a fresh filesystem, stopping where it diverges, then exit
+    Replay {
+        /// host file written by record
+        file: String,
+    },
+}
 
 #[derive(Parser, Debug)]
 #[command(no_binary_name = true)]
@@ -15,7 +80,14 @@ struct Args {
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Output information about a file (file descriptor data).
+    #[command(disable_help_flag = true)]
     Stat {
+        /// print sizes in human readable format (e.g., 1K 234M 2G)
+        #[clap(short = 'h', long)]
+        human_readable: bool,
+        /// print only the fields in FORMAT, e.g. "%n %s %i" for name, size and inode
+        #[clap(short = 'c', long)]
+        format: Option<String>,
         /// hard link pathname
         pathname: String,
     },
@@ -25,6 +97,27 @@ enum Commands {
         /// hard link pathname
         #[clap(default_value = ".")]
         pathname: String,
+        /// list at most this many entries, then the cursor of the next page
+        #[clap(short = 'n', long)]
+        limit: Option<usize>,
+        /// continue after a cursor printed by a previous listing
+        #[clap(long, default_value = "")]
+        after: DirCursor,
+        /// use a long listing format with mode, links, owner, group and size
+        #[clap(short, long)]
+        long: bool,
+        /// list subdirectories recursively
+        #[clap(short = 'R', long, conflicts_with = "limit")]
+        recursive: bool,
+        /// color names by file type: always, auto (when output is a terminal) or never
+        #[clap(long, default_value = "auto")]
+        color: ColorWhen,
+        /// list one name per line instead of in columns
+        #[clap(short = '1')]
+        one_per_line: bool,
+        /// print everything even if it does not fit on the screen
+        #[clap(long)]
+        no_pager: bool,
     },
     /// Create a regular file and create a hard link with pathname to it in the directory
     Create {
@@ -36,23 +129,63 @@ enum Commands {
         /// hard link pathname
         pathname: String,
     },
-    /// Close previously opened file with numeric file descriptor
+    /// Close previously opened file with numeric file descriptor, or all of them
     Close {
         /// file descriptor number
-        fd: usize,
+        #[clap(required_unless_present = "all")]
+        fd: Option<usize>,
+        /// close every open file descriptor
+        #[clap(short, long, conflicts_with = "fd")]
+        all: bool,
     },
     /// Specify the offset for the open file where the next read or write will begin
     Seek {
         /// file descriptor number
         fd: usize,
         /// offset
+        #[clap(value_parser = parse_size)]
         offset: usize,
     },
+    /// Add (+), remove (-) or set (=) flags of an open file descriptor: append,
+    /// nonblock, cloexec; or output them
+    Fcntl {
+        /// file descriptor number
+        fd: usize,
+        /// flags change (e.g., +append, -nonblock, =append,cloexec)
+        #[clap(allow_hyphen_values = true)]
+        mode: Option<String>,
+    },
+    /// Add seals to the file open as a file descriptor: seal, shrink, grow,
+    /// write; or output them
+    Seal {
+        /// file descriptor number
+        fd: usize,
+        /// seals to add (e.g., shrink,grow)
+        seals: Option<String>,
+    },
+    /// List open file descriptors with their flags, offsets and paths
+    Lsof,
+    /// Output or set the limit on open file descriptors
+    Ulimit {
+        /// maximum number of open file descriptors, or "unlimited"
+        #[clap(short = 'n')]
+        open_files: Option<String>,
+    },
+    /// Output the contents of files
+    Cat {
+        /// hard link pathnames
+        #[clap(required = true)]
+        pathnames: Vec<String>,
+        /// print everything even if it does not fit on the screen
+        #[clap(long)]
+        no_pager: bool,
+    },
     /// Read size bytes of data from an open file, size is added to the offset value
     Read {
         /// file descriptor number
         fd: usize,
         /// number of bytes to read
+        #[clap(value_parser = parse_size)]
         size: usize,
     },
     /// Write size bytes of data to an open file, size is added to the offset value
@@ -62,6 +195,25 @@ enum Commands {
         /// data to write
         data: String,
     },
+    /// Allocate size bytes from the offset of an open file, extending it if needed, so that
+    /// writes there cannot run out of space
+    Reserve {
+        /// file descriptor number
+        fd: usize,
+        /// number of bytes to reserve
+        #[clap(value_parser = parse_size)]
+        size: usize,
+    },
+    /// Replace the contents of a regular file with data, creating it if needed
+    WriteFile {
+        /// hard link pathname
+        pathname: String,
+        /// data to write, e.g. a here-document: write-file /a.txt <<EOF
+        data: String,
+        /// write a new file and rename it over the old one
+        #[clap(short, long)]
+        atomic: bool,
+    },
     /// Create a hard link with pathname2 to the file pointed to by the hard link with pathname1
     Link {
         /// hard link pathname1
@@ -69,6 +221,22 @@ enum Commands {
         /// hard link pathname2
         pathname2: String,
     },
+    /// Move or rename a hard link, into the directory if the target is one
+    Mv {
+        /// prompt before replacing an existing file
+        #[clap(short, long, conflicts_with = "no_clobber")]
+        interactive: bool,
+        /// do not replace an existing file
+        #[clap(short, long)]
+        no_clobber: bool,
+        /// print what was moved
+        #[clap(short, long)]
+        verbose: bool,
+        /// source pathname
+        source: String,
+        /// target pathname or directory
+        target: String,
+    },
     /// Remove the hard link with pathname
     Unlink {
         /// hard link pathname
@@ -79,6 +247,7 @@ enum Commands {
         /// hard link pathname
         pathname: String,
         /// size
+        #[clap(value_parser = parse_size)]
         size: usize,
     },
     /// Change the current working directory to pathname
@@ -104,120 +273,2453 @@ enum Commands {
         /// hard link pathname
         pathname: String,
     },
+    /// Create a named pipe with pathname
+    Mkfifo {
+        /// hard link pathname
+        pathname: String,
+    },
+    /// Create a ring buffer file that keeps only the last capacity bytes written
+    Mkring {
+        /// hard link pathname
+        pathname: String,
+        /// capacity in bytes (e.g., 4096, 64K)
+        #[clap(value_parser = parse_size)]
+        capacity: usize,
+    },
+    /// Change the permission bits of the file pointed to by the hard link with pathname
+    Chmod {
+        /// octal mode (e.g., 644, or 1777 for a sticky directory)
+        #[clap(value_parser = parse_mode)]
+        mode: u32,
+        /// hard link pathname
+        pathname: String,
+    },
+    /// Output the access ACL of the file pointed to by the hard link with pathname
+    Getfacl {
+        /// hard link pathname
+        pathname: String,
+    },
+    /// Modify the access ACL of the file pointed to by the hard link with pathname
+    Setfacl {
+        /// add or replace entries (e.g., u:alice:rw,g::r)
+        #[clap(short, long)]
+        modify: Option<String>,
+        /// remove entries (e.g., u:alice,m)
+        #[clap(short = 'x', long)]
+        remove: Option<String>,
+        /// remove all extended entries
+        #[clap(short = 'b', long)]
+        remove_all: bool,
+        /// hard link pathname
+        pathname: String,
+    },
+    /// Add (+), remove (-) or set (=) attribute flags: i (immutable), a (append only)
+    Chattr {
+        /// flags change (e.g., +i, -a, =ia)
+        #[clap(allow_hyphen_values = true)]
+        mode: String,
+        /// hard link pathname
+        pathname: String,
+    },
+    /// Set the access and modification times of a file, creating it if missing
+    #[command(disable_help_flag = true)]
+    Touch {
+        /// change only the access time
+        #[clap(short = 'a')]
+        access: bool,
+        /// change only the modification time
+        #[clap(short = 'm')]
+        modification: bool,
+        /// do not create the file
+        #[clap(short = 'c', long = "no-create")]
+        no_create: bool,
+        /// change a symbolic link instead of the file it points to
+        #[clap(short = 'h', long = "no-dereference")]
+        no_dereference: bool,
+        /// seconds since the epoch (e.g., 1700000000.5) instead of the current time
+        #[clap(short, long)]
+        date: Option<SetTime>,
+        /// hard link pathname
+        pathname: String,
+    },
+    /// Output the attribute flags of files
+    Lsattr {
+        /// hard link pathnames
+        #[clap(required = true)]
+        pathnames: Vec<String>,
+    },
+    /// Assign a file and everything below it to a project, whose quota it is charged to
+    Chproj {
+        /// project ID, or 0 for none
+        project: u32,
+        /// hard link pathname
+        pathname: String,
+    },
+    /// Output the project ID of files
+    Lsproj {
+        /// hard link pathnames
+        #[clap(required = true)]
+        pathnames: Vec<String>,
+    },
+    /// Output the usage and limits of a project, or set its limits
+    Quota {
+        /// project ID
+        project: u32,
+        /// largest total size of the project's files
+        #[clap(long, value_parser = parse_size)]
+        bytes: Option<usize>,
+        /// largest number of the project's inodes
+        #[clap(long)]
+        inodes: Option<usize>,
+        /// remove the limits
+        #[clap(long, conflicts_with_all = ["bytes", "inodes"])]
+        off: bool,
+    },
+    /// Output the tree below a directory, followed by a count of its entries
+    Tree {
+        /// directory pathname
+        #[clap(default_value = ".")]
+        pathname: String,
+        /// descend at most this many levels
+        #[clap(short = 'L')]
+        level: Option<usize>,
+        /// list directories only
+        #[clap(short = 'd')]
+        dirs_only: bool,
+        /// print everything even if it does not fit on the screen
+        #[clap(long)]
+        no_pager: bool,
+    },
+    /// Follow a pathname component by component, showing where symlinks lead
+    Namei {
+        /// pathname to resolve
+        pathname: String,
+    },
+    /// Save the tree below a directory as an image file
+    Mkimage {
+        /// directory pathname
+        pathname: String,
+        /// image file pathname
+        image: String,
+    },
+    /// Mount an image file or a host archive on an empty directory, bind a
+    /// directory to another one, or output the mounted images and bind mounts
+    Mount {
+        /// comma-separated options: ro, rw, noatime, nosymfollow
+        #[clap(short, value_parser = str::parse::<MountOptions>, requires = "mountpoint")]
+        options: Option<MountOptions>,
+        /// make the directory IMAGE appear at MOUNTPOINT too
+        #[clap(long, requires = "mountpoint")]
+        bind: bool,
+        /// like --bind, also carrying over bind mounts below IMAGE
+        #[clap(long, requires = "mountpoint", conflicts_with = "bind")]
+        rbind: bool,
+        /// mount the tar or zip file IMAGE on the host read-only
+        #[clap(long, requires = "mountpoint", conflicts_with_all = ["bind", "rbind"])]
+        archive: bool,
+        /// image file pathname, directory pathname with --bind, or host path
+        /// with --archive
+        image: Option<String>,
+        /// mountpoint pathname
+        #[clap(requires = "image")]
+        mountpoint: Option<String>,
+    },
+    /// Write a mounted image back to its file and detach it, or remove a bind
+    /// mount
+    Umount {
+        /// mountpoint pathname
+        mountpoint: String,
+    },
+    /// Enable or disable block deduplication, or output deduplication statistics
+    Dedup {
+        /// on or off
+        #[clap(value_parser = ["on", "off"])]
+        mode: Option<String>,
+    },
+    /// Verify checksums of all allocated blocks
+    Scrub,
+    /// Make the blocks of every file contiguous and release unused space
+    Defrag,
+    /// Make the filesystem read-only or writable again, or output the current mode
+    Readonly {
+        /// on or off
+        #[clap(value_parser = ["on", "off"])]
+        mode: Option<String>,
+    },
+    /// Emulate a slow device for reads and writes of files, or output the current throttle
+    Throttle {
+        /// bytes per second (e.g., 64K 1M), 0 for unlimited, or off
+        rate: Option<String>,
+        /// milliseconds added to every read and write
+        #[clap(short, long, default_value_t = 0)]
+        latency: u64,
+    },
+    /// Output the Merkle root of the filesystem, or freeze it against a trusted root
+    Verity {
+        /// trusted merkle root (hex)
+        root: Option<String>,
+    },
+    /// Enable or disable the name index that makes locate fast, or output whether it is on
+    Index {
+        /// on or off
+        #[clap(value_parser = ["on", "off"])]
+        mode: Option<String>,
+    },
+    /// Output the paths of files whose name contains substring
+    Locate {
+        /// part of a name
+        substring: String,
+    },
+    /// Enable or disable the full-text index that makes search fast, or output its size
+    Textindex {
+        /// on or off
+        #[clap(value_parser = ["on", "off"])]
+        mode: Option<String>,
+    },
+    /// Output the paths of regular files that contain all the words, ignoring case
+    Search {
+        /// words to look for
+        #[clap(required = true)]
+        words: Vec<String>,
+    },
+    /// Append a line to a log file, rotating it if it grew too large
+    Logger {
+        /// hard link pathname
+        pathname: String,
+        /// words of the line
+        #[clap(required = true)]
+        message: Vec<String>,
+    },
+    /// Rotate a log file to pathname.1, pathname.2, ... once it would exceed a size, or stop rotating it
+    Logrotate {
+        /// hard link pathname
+        pathname: String,
+        /// largest size of the log before rotation; leave out to never rotate
+        #[clap(long, value_parser = parse_size)]
+        size: Option<usize>,
+        /// number of rotated logs to keep
+        #[clap(long, default_value_t = 5)]
+        keep: usize,
+    },
+    /// Enable or disable the audit log of mutating operations, or output it
+    Audit {
+        /// on or off
+        #[clap(value_parser = ["on", "off"])]
+        mode: Option<String>,
+    },
+    /// Save the current filesystem state under name for later comparison
+    Snapshot {
+        /// snapshot name
+        name: String,
+    },
+    /// Output paths added (A), removed (D) or modified (M) since the snapshot, or a unified diff of two files
+    Diff {
+        /// snapshot name or hard link pathname1
+        snapshot: String,
+        /// hard link pathname2
+        pathname: Option<String>,
+    },
+    /// Compare two files byte by byte
+    Cmp {
+        /// hard link pathname1
+        pathname1: String,
+        /// hard link pathname2
+        pathname2: String,
+    },
+    /// Copy changed files, directories and symlinks from a host directory into pathname
+    #[clap(name = "sync-in")]
+    SyncIn {
+        /// host directory
+        host_dir: String,
+        /// hard link pathname
+        pathname: String,
+    },
+    /// Copy changed files, directories and symlinks from pathname into a host directory
+    #[clap(name = "sync-out")]
+    SyncOut {
+        /// hard link pathname
+        pathname: String,
+        /// host directory
+        host_dir: String,
+    },
+    /// Recursively copy a host directory into pathname
+    Import {
+        /// host directory
+        host_dir: String,
+        /// hard link pathname
+        pathname: String,
+    },
+    /// Create the directories, files and symlinks a YAML or JSON manifest describes
+    Seed {
+        /// host manifest file
+        manifest: String,
+    },
+    /// Recursively copy pathname into a host directory, or a tar archive
+    Export {
+        /// write a ustar archive to host_dir instead
+        #[clap(long)]
+        tar: bool,
+        /// hard link pathname
+        pathname: String,
+        /// host directory, or archive with --tar
+        host_dir: String,
+    },
+    /// Copy a host file into the regular file with pathname
+    Upload {
+        /// host file
+        host_file: String,
+        /// hard link pathname
+        pathname: String,
+    },
+    /// Copy the regular file with pathname into a host file
+    Download {
+        /// hard link pathname
+        pathname: String,
+        /// host file
+        host_file: String,
+    },
+    /// Browse the filesystem in a dual-pane file manager; undo reverts the whole session
+    Tui,
+    /// Edit a regular file in $VISUAL or $EDITOR, creating it if needed
+    Edit {
+        /// hard link pathname
+        pathname: String,
+    },
+    /// Output SHA-256 checksums of files, or verify them against a manifest
+    Sha256sum {
+        /// read checksums from the manifest files and check them
+        #[clap(short, long)]
+        check: bool,
+        /// hash every regular file below directories
+        #[clap(short, long)]
+        recursive: bool,
+        /// hard link pathnames
+        #[clap(required = true)]
+        pathnames: Vec<String>,
+    },
+    /// Output MD5 checksums of files, or verify them against a manifest
+    Md5sum {
+        /// read checksums from the manifest files and check them
+        #[clap(short, long)]
+        check: bool,
+        /// hash every regular file below directories
+        #[clap(short, long)]
+        recursive: bool,
+        /// hard link pathnames
+        #[clap(required = true)]
+        pathnames: Vec<String>,
+    },
+    /// Output newline, word and byte counts of files
+    Wc {
+        /// print the newline counts
+        #[clap(short)]
+        lines: bool,
+        /// print the word counts
+        #[clap(short)]
+        words: bool,
+        /// print the byte counts
+        #[clap(short = 'c')]
+        bytes: bool,
+        /// hard link pathnames
+        #[clap(required = true)]
+        pathnames: Vec<String>,
+    },
+    /// Determine the type of files from their contents
+    File {
+        /// hard link pathnames
+        #[clap(required = true)]
+        pathnames: Vec<String>,
+    },
+    /// Create (or reformat) an empty volume of the given size
+    #[clap(alias = "format")]
+    Mkfs {
+        /// volume name
+        name: String,
+        /// volume size
+        #[clap(value_parser = parse_size)]
+        size: usize,
+        /// when reads update access times: noatime, relatime or strictatime
+        #[clap(long)]
+        atime: Option<AtimePolicy>,
+        /// most inodes the volume may have, including the root
+        #[clap(short = 'N', long)]
+        inodes: Option<usize>,
+    },
+    /// Switch to another volume
+    Use {
+        /// volume name
+        name: String,
+    },
+    /// Output size and usage of all volumes, marking the current one with '*'
+    #[command(disable_help_flag = true)]
+    Df {
+        /// print sizes in human readable format (e.g., 1K 234M 2G)
+        #[clap(short = 'h', long)]
+        human_readable: bool,
+        /// list inode usage instead of block usage
+        #[clap(short, long)]
+        inodes: bool,
+    },
+    /// Add a user, with a group of the same name unless a group is given
+    Useradd {
+        /// user name
+        name: String,
+        /// numeric user id
+        #[clap(short, long)]
+        uid: Option<u32>,
+        /// primary group name
+        #[clap(short, long)]
+        group: Option<String>,
+    },
+    /// Add a group
+    Groupadd {
+        /// group name
+        name: String,
+        /// numeric group id
+        #[clap(short, long)]
+        gid: Option<u32>,
+    },
+    /// Act as another user from now on
+    Su {
+        /// user name
+        #[clap(default_value = "root")]
+        user: String,
+    },
+    /// Output the name of the current user
+    Whoami,
+    /// Run a command, then output how long it took and the file data it moved
+    Time {
+        /// command and its arguments
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Re-run a command after every command that changes the tree below a path, or list the watches
+    Watch {
+        /// instead of re-running a command, output the changes below this pathname as they happen
+        #[clap(long, value_name = "PATHNAME", conflicts_with_all = ["path", "command"])]
+        events: Option<String>,
+        /// pathname whose changes re-run the command; defaults to the command's last
+        /// argument if that exists and the working directory otherwise
+        #[clap(long)]
+        path: Option<String>,
+        /// command and its arguments
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Stop watches by number, or all watches of the current volume
+    Unwatch {
+        /// watch numbers
+        wds: Vec<usize>,
+    },
+    /// Start or stop recording the latency of every command, or output histograms per command
+    Profile {
+        /// on, off or report
+        #[clap(value_parser = ["on", "off", "report"])]
+        mode: String,
+    },
+    /// Set the prompt from a template with {cwd}, {user} and {volume}, or output it
+    Prompt {
+        /// prompt template
+        template: Option<String>,
+    },
+    /// Make a name stand for a command and its first arguments (alias ll='ls -l'), or output aliases
+    Alias {
+        /// alias name, or name=command
+        name: Option<String>,
+        /// command and arguments the name expands to
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Remove an alias
+    Unalias {
+        /// alias name
+        name: String,
+    },
+    /// Set a variable that $NAME or ${NAME} expands to, or output variables
+    Set {
+        /// variable name
+        #[clap(requires = "value")]
+        name: Option<String>,
+        /// variable value
+        value: Option<String>,
+    },
+    /// Remove a variable
+    Unset {
+        /// variable name
+        name: String,
+    },
+    /// Set flags added to every use of a command (none to stop adding them), or output them
+    Defaults {
+        /// command name
+        command: Option<String>,
+        /// flags added after the command name
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+        flags: Vec<String>,
+    },
+    /// Time standard workloads on a copy of the volume and compare with the previous run
+    Bench,
+    /// Save the current volume as an image file on the host
+    Save {
+        /// host file pathname (defaults to the image the volume was loaded from)
+        host_file: Option<String>,
+    },
+    /// Back up the volume to a host file, only with changes since a snapshot if given
+    Backup {
+        /// host file pathname
+        host_file: String,
+        /// snapshot name
+        snapshot: Option<String>,
+    },
+    /// Restore a backup from a host file onto the volume
+    Restore {
+        /// host file pathname
+        host_file: String,
+    },
+    /// Revert the last filesystem modification (including open file descriptors)
+    Undo,
+    /// Reapply the last reverted filesystem modification
+    Redo,
     /// Exit the program
     Exit,
 }
 
-fn main() {
-    let mut editor = DefaultEditor::new().unwrap();
-    let mut vfs = Vfs::new();
-    let mut interupted = false;
-    println!(
-        "Welcome to VFS {}.\nType \"help\" for more information",
-        env!("CARGO_PKG_VERSION")
-    );
-    loop {
-        match editor.readline(&format!("$ {}> ", vfs.cwd())) {
-            Ok(line) => {
-                let input = match split(&line) {
-                    Ok(input) => input,
-                    Err(_) => {
-                        eprintln!("error: unterminated quote found");
-                        continue;
-                    }
+impl Commands {
+    /// The variant name in lower case, used to group profiling data.
+    fn name(&self) -> String {
+        format!("{:?}", self)
+            .split(|c: char| !c.is_alphanumeric())
+            .next()
+            .unwrap_or_default()
+            .to_lowercase()
+    }
+
+    fn is_mutating(&self) -> bool {
+        matches!(
+            self,
+            Commands::Create { .. }
+                | Commands::Write { .. }
+                | Commands::WriteFile { .. }
+                | Commands::Link { .. }
+                | Commands::Unlink { .. }
+                | Commands::Mv { .. }
+                | Commands::Truncate { .. }
+                | Commands::Reserve { .. }
+                | Commands::Mkdir { .. }
+                | Commands::Rmdir { .. }
+                | Commands::Symlink { .. }
+                | Commands::Mkfifo { .. }
+                | Commands::Mkring { .. }
+                | Commands::Chmod { .. }
+                | Commands::Setfacl { .. }
+                | Commands::Chattr { .. }
+                | Commands::Chproj { .. }
+                | Commands::Touch { .. }
+                | Commands::Mkimage { .. }
+                | Commands::Mount {
+                    mountpoint: Some(_),
+                    ..
+                }
+                | Commands::Umount { .. }
+                | Commands::SyncIn { .. }
+                | Commands::Import { .. }
+                | Commands::Seed { .. }
+                | Commands::Upload { .. }
+                | Commands::Edit { .. }
+                | Commands::Logger { .. }
+                | Commands::Tui
+                | Commands::Restore { .. }
+        )
+    }
+}
+
+/// Bounded undo/redo stacks of whole filesystem states.
+#[derive(Default)]
+struct History {
+    undo: VecDeque<Vfs>,
+    redo: Vec<Vfs>,
+}
+
+impl History {
+    fn record(&mut self, snapshot: Vfs) {
+        if self.undo.len() == HISTORY_LIMIT {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(snapshot);
+        self.redo.clear();
+    }
+
+    fn undo(&mut self, vfs: &mut Vfs) -> Result<(), String> {
+        match self.undo.pop_back() {
+            Some(mut snapshot) => {
+                snapshot.set_watches(vfs.take_watches());
+                self.redo.push(std::mem::replace(vfs, snapshot));
+                Ok(())
+            }
+            None => Err("undo: nothing to undo".to_string()),
+        }
+    }
+
+    fn redo(&mut self, vfs: &mut Vfs) -> Result<(), String> {
+        match self.redo.pop() {
+            Some(mut snapshot) => {
+                snapshot.set_watches(vfs.take_watches());
+                self.undo.push_back(std::mem::replace(vfs, snapshot));
+                Ok(())
+            }
+            None => Err("redo: nothing to redo".to_string()),
+        }
+    }
+}
+
+fn checksum(vfs: &Vfs, algo: Algo, modes: [bool; 2], pathnames: &[String]) -> Result<(), String> {
+    let [check, recursive] = modes;
+    let mut failed = 0;
+    for pathname in pathnames {
+        if recursive && !check {
+            for (path, sum) in vfs.hash_tree(pathname, algo)? {
+                println!("{}  {}", sum, path);
+            }
+            continue;
+        }
+        if !check {
+            println!("{}  {}", vfs.hash_file(pathname, algo)?, pathname);
+            continue;
+        }
+        for (path, matched) in vfs.check_manifest(pathname, algo)? {
+            println!("{}: {}", path, if matched { "OK" } else { "FAILED" });
+            failed += usize::from(!matched);
+        }
+    }
+    if failed > 0 {
+        return Err(format!(
+            "{}: WARNING: {} computed checksum(s) did NOT match",
+            algo.command(),
+            failed
+        ));
+    }
+    Ok(())
+}
+
+fn wc(vfs: &Vfs, columns: [bool; 3], pathnames: &[String]) -> Result<(), String> {
+    let columns = if columns.contains(&true) {
+        columns
+    } else {
+        [true; 3]
+    };
+    let print = |stats: &FileStats, name: &str| {
+        let counts = [stats.lines, stats.words, stats.bytes];
+        for (count, _) in counts.iter().zip(columns).filter(|(_, shown)| *shown) {
+            print!("{:>7} ", count);
+        }
+        println!("{}", name);
+    };
+    let mut total = FileStats::default();
+    for pathname in pathnames {
+        let stats = vfs.file_stats(pathname)?;
+        print(&stats, pathname);
+        total.lines += stats.lines;
+        total.words += stats.words;
+        total.bytes += stats.bytes;
+    }
+    if pathnames.len() > 1 {
+        print(&total, "total");
+    }
+    Ok(())
+}
+
+/// Ask `question` on standard error and read the answer from standard
+/// input; anything but `y` or `yes` is a no.
+fn confirm(question: &str) -> bool {
+    eprint!("{} ", question);
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Copy `pathname` to a host temporary file, open it in the user's
+/// editor, and write the result back if it changed. A missing file starts
+/// out empty and is only created if something is written to it.
+fn edit(vfs: &mut Vfs, pathname: &str) -> Result<(), String> {
+    let data = match vfs.statx(pathname, STATX_TYPE) {
+        Ok(_) => vfs.read_file_as("edit", pathname)?,
+        Err(_) => Vec::new(),
+    };
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+        .unwrap_or_else(|| "vi".to_string());
+    let words =
+        split(&editor).map_err(|err| format!("edit: invalid editor '{}': {}", editor, err))?;
+    let Some((program, args)) = words.split_first() else {
+        return Err(format!("edit: invalid editor '{}'", editor));
+    };
+    // Keep the file name so that the editor can tell the file type.
+    let name = pathname
+        .rsplit('/')
+        .find(|name| !name.is_empty())
+        .unwrap_or("file");
+    let host_file = std::env::temp_dir().join(format!("vfs-edit-{}-{}", std::process::id(), name));
+    std::fs::write(&host_file, &data)
+        .map_err(|err| format!("edit: cannot write '{}': {}", host_file.display(), err))?;
+    let status = std::process::Command::new(program)
+        .args(args)
+        .arg(&host_file)
+        .status();
+    let edited = std::fs::read(&host_file);
+    let _ = std::fs::remove_file(&host_file);
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => return Err(format!("edit: {} exited with {}", program, status)),
+        Err(err) => return Err(format!("edit: cannot run '{}': {}", program, err)),
+    }
+    let edited =
+        edited.map_err(|err| format!("edit: cannot read '{}': {}", host_file.display(), err))?;
+    if edited != data {
+        vfs.write_file(pathname, &edited)?;
+    }
+    Ok(())
+}
+
+/// Draw the tree below `pathname` with box-drawing characters, like
+/// `tree(1)`.
+fn tree(
+    vfs: &Vfs,
+    out: &mut Output,
+    pathname: &str,
+    level: Option<usize>,
+    dirs_only: bool,
+) -> Result<(), String> {
+    let mut walk = vfs.walk(pathname)?;
+    if let Some(level) = level {
+        walk = walk.max_depth(level);
+    }
+    if dirs_only {
+        walk = walk.dirs_only();
+    }
+    out.line(pathname);
+    let (mut dirs, mut files) = (0, 0);
+    // Whether the ancestors of the current entry were the last of theirs.
+    let mut lasts: Vec<bool> = Vec::new();
+    for entry in walk {
+        lasts.truncate(entry.depth - 1);
+        let indent: String = lasts
+            .iter()
+            .map(|&last| if last { "    " } else { "\u{2502}   " })
+            .collect();
+        let branch = if entry.last {
+            "\u{2514}\u{2500}\u{2500} "
+        } else {
+            "\u{251c}\u{2500}\u{2500} "
+        };
+        match &entry.target {
+            Some(target) => out.line(format!("{}{}{} -> {}", indent, branch, entry.name, target)),
+            None => out.line(format!("{}{}{}", indent, branch, entry.name)),
+        }
+        lasts.push(entry.last);
+        if entry.type_char == 'd' {
+            dirs += 1;
+        } else {
+            files += 1;
+        }
+    }
+    let plural = |count: usize, one: &str, many: &str| {
+        format!("{} {}", count, if count == 1 { one } else { many })
+    };
+    let dirs = plural(dirs, "directory", "directories");
+    out.line("");
+    if dirs_only {
+        out.line(dirs);
+    } else {
+        out.line(format!("{}, {}", dirs, plural(files, "file", "files")));
+    }
+    Ok(())
+}
+
+/// The directories `ls -R` lists: `pathname` and every one below it.
+fn recursive_dirs<'a>(
+    vfs: &'a Vfs,
+    pathname: &str,
+) -> Result<impl Iterator<Item = String> + 'a, String> {
+    let walk = vfs.walk_as("ls", pathname)?;
+    let dirs = walk.dirs_only().map(|entry| entry.path);
+    Ok(std::iter::once(pathname.to_string()).chain(dirs))
+}
+
+/// When `ls` colors names by file type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorWhen {
+    Always,
+    Auto,
+    Never,
+}
+
+impl FromStr for ColorWhen {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(ColorWhen::Always),
+            "auto" => Ok(ColorWhen::Auto),
+            "never" => Ok(ColorWhen::Never),
+            _ => Err(format!("invalid color mode: {}", s)),
+        }
+    }
+}
+
+/// Control characters in a name as escapes, e.g. `a\nb`, so that every
+/// name takes one line.
+fn escape_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_control() {
+                c.escape_default().collect()
+            } else {
+                c.to_string()
+            }
+        })
+        .collect()
+}
+
+/// How `ls` prints the entries of one directory.
+struct Listing<'a> {
+    vfs: &'a Vfs,
+    users: &'a Users,
+    long: bool,
+    color: bool,
+    /// Terminal width to lay names out in columns, or `None` for one name
+    /// per line.
+    columns: Option<usize>,
+}
+
+impl Listing<'_> {
+    /// Print `names`, the entries of `pathname` as `Vfs::ls` returns them:
+    /// names in a directory, or the pathname itself for any other file.
+    fn print(&self, out: &mut Output, pathname: &str, names: Vec<String>) -> Result<(), String> {
+        let is_dir = self
+            .vfs
+            .statx(pathname, STATX_TYPE)
+            .is_ok_and(|stat| stat.type_char() == 'd');
+        let path = |name: &str| {
+            if is_dir {
+                format!("{}/{}", pathname.trim_end_matches('/'), name)
+            } else {
+                pathname.to_string()
+            }
+        };
+        if self.long {
+            for name in &names {
+                // Everything but the block count, which ls -l does not show.
+                let stat = self
+                    .vfs
+                    .statx(&path(name), STATX_BASIC_STATS & !STATX_BLOCKS)?;
+                out.line(format!(
+                    "{}{}{} {:>3} {:<8} {:<8} {:>8} {}",
+                    stat.type_char(),
+                    mode_string(stat.mode()),
+                    if stat.has_extended_acl() { '+' } else { ' ' },
+                    stat.links(),
+                    self.users.user_name(stat.uid()),
+                    self.users.group_name(stat.gid()),
+                    stat.size(),
+                    self.paint(&path(name), &escape_name(name))
+                ));
+            }
+            return Ok(());
+        }
+        let cells: Vec<_> = names
+            .iter()
+            .map(|name| {
+                let shown = escape_name(name);
+                (shown.chars().count(), self.paint(&path(name), &shown))
+            })
+            .collect();
+        let Some(width) = self.columns.filter(|_| !cells.is_empty()) else {
+            for (_, cell) in &cells {
+                out.line(cell);
+            }
+            return Ok(());
+        };
+        // Fill columns top to bottom like ls, with as few rows as fit.
+        let column_widths = |rows: usize| -> Vec<usize> {
+            cells
+                .chunks(rows)
+                .map(|column| column.iter().map(|&(len, _)| len + 2).max().unwrap_or(0))
+                .collect()
+        };
+        let rows = (1..=cells.len())
+            .find(|&rows| column_widths(rows).iter().sum::<usize>() <= width + 2)
+            .unwrap_or(cells.len());
+        let widths = column_widths(rows);
+        for row in 0..rows {
+            let mut line = String::new();
+            for (column, &column_width) in widths.iter().enumerate() {
+                if let Some((len, cell)) = cells.get(column * rows + row) {
+                    line.push_str(cell);
+                    line.push_str(&" ".repeat(column_width - len));
+                }
+            }
+            out.line(line.trim_end());
+        }
+        Ok(())
+    }
+
+    /// Wrap `shown`, the name of `path`, in the color of its file type:
+    /// blue for directories, cyan for symlinks or red if they lead
+    /// nowhere, yellow for fifos and devices.
+    fn paint(&self, path: &str, shown: &str) -> String {
+        if !self.color {
+            return shown.to_string();
+        }
+        let code = match self
+            .vfs
+            .statx(path, STATX_TYPE)
+            .map(|stat| stat.type_char())
+        {
+            Ok('d') => "01;34",
+            Ok('l') if self.vfs.trace_resolve(path).error.is_some() => "01;31",
+            Ok('l') => "01;36",
+            Ok('p') => "33",
+            Ok('c') => "01;33",
+            _ => return shown.to_string(),
+        };
+        format!("\x1b[{}m{}\x1b[0m", code, shown)
+    }
+}
+
+/// `s` in single quotes, as `alias` and `set` print values.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Split off a here-document redirection, `<<WORD`, that ends `line`
+/// outside quotes. Returns the rest of the line and the delimiter with any
+/// quotes removed.
+fn heredoc(line: &str) -> Option<(&str, String)> {
+    let (mut single, mut double, mut escaped) = (false, false, false);
+    let mut start = None;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if !single => escaped = true,
+            '\'' if !double => single = !single,
+            '"' if !single => double = !double,
+            '<' if !single && !double && line[i..].starts_with("<<") => {
+                start.get_or_insert(i);
+            }
+            _ => {}
+        }
+    }
+    let start = start?;
+    match split(&line[start + 2..]).ok()?.as_slice() {
+        [delimiter] => Some((&line[..start], delimiter.clone())),
+        _ => None,
+    }
+}
+
+/// Words quoted where needed, so that `split` gives them back.
+fn join(words: &[String]) -> String {
+    let words: Vec<_> = words.iter().map(|word| escape(word)).collect();
+    words.join(" ")
+}
+
+fn parse_mode(s: &str) -> Result<u32, String> {
+    match u32::from_str_radix(s, 8) {
+        Ok(mode) if mode <= 0o1777 => Ok(mode),
+        _ => Err(format!("invalid mode: '{}'", s)),
+    }
+}
+
+/// Apply `setfacl` options to the current ACL of `pathname`. Like
+/// `setfacl(1)`, the mask is recomputed from the group class entries
+/// unless `modify` sets it.
+fn setfacl(
+    vfs: &mut Vfs,
+    modify: Option<String>,
+    remove: Option<String>,
+    remove_all: bool,
+    pathname: &str,
+) -> Result<(), String> {
+    let mut acl = vfs.get_acl(pathname)?;
+    if remove_all {
+        acl.retain(|entry| {
+            matches!(
+                entry.tag,
+                AclTag::UserObj | AclTag::GroupObj | AclTag::Other
+            )
+        });
+    }
+    for entry in remove.iter().flat_map(|entries| entries.split(',')) {
+        let padded = format!(
+            "{}{}",
+            entry,
+            ":".repeat(2 - entry.matches(':').count().min(2))
+        );
+        let tag = padded.parse::<AclEntry>()?.tag;
+        acl.retain(|entry| entry.tag != tag);
+    }
+    let mut mask_given = false;
+    for entry in modify.iter().flat_map(|entries| entries.split(',')) {
+        let entry = entry.parse::<AclEntry>()?;
+        mask_given |= entry.tag == AclTag::Mask;
+        acl.retain(|old| old.tag != entry.tag);
+        acl.push(entry);
+    }
+    if !mask_given {
+        acl.retain(|entry| entry.tag != AclTag::Mask);
+    }
+    Ok(vfs.set_acl(pathname, &acl)?)
+}
+
+fn execute(vfs: &mut Vfs, command: Commands) -> Result<(), String> {
+    match command {
+        Commands::Create { pathname } => vfs.create(&pathname)?,
+        Commands::Link {
+            pathname1,
+            pathname2,
+        } => vfs.link(&pathname1, &pathname2)?,
+        Commands::Unlink { pathname } => vfs.unlink(&pathname)?,
+        Commands::Mv {
+            interactive,
+            no_clobber,
+            verbose,
+            source,
+            target,
+        } => {
+            let target = match vfs.stat(&format!("{}/.", target)) {
+                Ok(_) => format!(
+                    "{}/{}",
+                    target.trim_end_matches('/'),
+                    Vfs::basename(source.trim_end_matches('/'))
+                ),
+                Err(_) => target,
+            };
+            if vfs.stat(&target).is_ok() {
+                if no_clobber {
+                    return Ok(());
+                }
+                if interactive && !confirm(&format!("mv: overwrite '{}'?", target)) {
+                    return Ok(());
+                }
+            }
+            match vfs.rename(&source, &target)? {
+                Renamed::SameFile if verbose => {
+                    println!("'{}' and '{}' are the same file", source, target)
+                }
+                _ if verbose => println!("renamed '{}' -> '{}'", source, target),
+                _ => {}
+            }
+        }
+        Commands::Open { pathname } => println!("{}", vfs.open(&pathname)?),
+        Commands::Close { fd: Some(fd), .. } => vfs.close(fd)?,
+        Commands::Close { fd: None, .. } => println!("{}", vfs.close_all()),
+        Commands::Seek { fd, offset } => vfs.seek(fd, offset)?,
+        Commands::Fcntl { fd, mode } => {
+            let flags = vfs.fd_flags(fd)?;
+            let Some(mode) = mode else {
+                println!("{}", fd_flags_string(flags));
+                return Ok(());
+            };
+            let invalid = || format!("fcntl: invalid flags: '{}'", mode);
+            let (op, names) = mode.split_at_checked(1).ok_or_else(invalid)?;
+            let changed = parse_fd_flags(names).ok_or_else(invalid)?;
+            let flags = match op {
+                "+" => flags | changed,
+                "-" => flags & !changed,
+                "=" => changed,
+                _ => return Err(invalid()),
+            };
+            vfs.set_fd_flags(fd, flags)?
+        }
+        Commands::Seal { fd, seals: None } => println!("{}", seals_string(vfs.seals(fd)?)),
+        Commands::Seal {
+            fd,
+            seals: Some(seals),
+        } => {
+            let seals =
+                parse_seals(&seals).ok_or_else(|| format!("seal: invalid seals: '{}'", seals))?;
+            vfs.add_seals(fd, seals)?
+        }
+        Commands::Lsof => {
+            println!(
+                "{:>4} {:<22} {:>8} {:>10} PATH",
+                "FD", "FLAGS", "INODE", "OFFSET"
+            );
+            let unlinked: HashMap<usize, String> = vfs
+                .unlinked_files()
+                .map(|file| (file.inode, file.path))
+                .collect();
+            for (fd, inode, offset) in vfs.open_descriptors() {
+                let path = match (vfs.path_of(inode), unlinked.get(&inode)) {
+                    (Some(path), _) => path,
+                    (None, Some(path)) => format!("{} (deleted)", path),
+                    (None, None) => "(deleted)".to_string(),
                 };
-                if input.is_empty() {
-                    continue;
+                println!(
+                    "{:>4} {:<22} {:>8} {:>10} {}",
+                    fd,
+                    fd_flags_string(vfs.fd_flags(fd)?),
+                    inode,
+                    offset,
+                    path
+                );
+            }
+            let statfs = vfs.statfs();
+            match statfs.max_open_files {
+                Some(max_open_files) => {
+                    println!(
+                        "{} of {} descriptors open",
+                        statfs.open_files, max_open_files
+                    )
                 }
-                match Args::try_parse_from(input) {
-                    Ok(args) => match args.commands {
-                        Commands::Exit => {
-                            break;
-                        }
-                        Commands::Stat { pathname } => {
-                            match vfs.stat(&pathname) {
-                                Ok(statx) => println!("{}", statx),
-                                Err(err) => eprintln!("{}", err),
-                            };
-                        }
-                        Commands::List { pathname } => match vfs.ls(&pathname) {
-                            Ok(entries) => {
-                                for entry in entries {
-                                    println!("{}", entry);
-                                }
-                            }
-                            Err(err) => eprintln!("{}", err),
-                        },
-                        Commands::Create { pathname } => {
-                            if let Err(err) = vfs.create(&pathname) {
-                                eprintln!("{}", err);
-                            }
-                        }
-                        Commands::Link {
-                            pathname1,
-                            pathname2,
-                        } => {
-                            if let Err(err) = vfs.link(&pathname1, &pathname2) {
-                                eprintln!("{}", err);
-                            }
-                        }
-                        Commands::Unlink { pathname } => {
-                            if let Err(err) = vfs.unlink(&pathname) {
-                                eprintln!("{}", err);
-                            }
+                None => println!("{} descriptors open", statfs.open_files),
+            }
+            if statfs.unlinked_files > 0 {
+                println!(
+                    "{} bytes held by {} deleted files",
+                    statfs.unlinked_bytes, statfs.unlinked_files
+                );
+            }
+        }
+        Commands::Ulimit { open_files } => match open_files.as_deref() {
+            Some("unlimited") => vfs.set_max_open_files(None),
+            Some(count) => {
+                let count = count
+                    .parse()
+                    .map_err(|_| format!("ulimit: invalid limit: '{}'", count))?;
+                vfs.set_max_open_files(Some(count))
+            }
+            None => match vfs.max_open_files() {
+                Some(max_open_files) => println!("{}", max_open_files),
+                None => println!("unlimited"),
+            },
+        },
+        Commands::Write { fd, data } => println!("{}", vfs.write(fd, data.as_bytes())?),
+        Commands::Reserve { fd, size } => vfs.reserve(fd, size)?,
+        Commands::Read { fd, size } => {
+            println!("{}", String::from_utf8_lossy(&vfs.read(fd, size)?))
+        }
+        Commands::Truncate { pathname, size } => vfs.truncate(&pathname, size)?,
+        Commands::Cd { pathname } => vfs.cd(&pathname)?,
+        Commands::Mkdir { pathname } => vfs.mkdir(&pathname)?,
+        Commands::Rmdir { pathname } => vfs.rmdir(&pathname)?,
+        Commands::Symlink { path, pathname } => vfs.symlink(&path, &pathname)?,
+        Commands::Mkfifo { pathname } => vfs.mkfifo(&pathname)?,
+        Commands::Mkring { pathname, capacity } => vfs.mkring(&pathname, capacity)?,
+        Commands::Chmod { mode, pathname } => vfs.chmod(&pathname, mode)?,
+        Commands::Setfacl {
+            modify,
+            remove,
+            remove_all,
+            pathname,
+        } => setfacl(vfs, modify, remove, remove_all, &pathname)?,
+        Commands::Chattr { mode, pathname } => {
+            let invalid = || format!("chattr: invalid flags: '{}'", mode);
+            let (op, letters) = mode.split_at_checked(1).ok_or_else(invalid)?;
+            let flags = parse_attrs(letters).ok_or_else(invalid)?;
+            let attrs = vfs.attrs(&pathname)?;
+            let attrs = match op {
+                "+" => attrs | flags,
+                "-" => attrs & !flags,
+                "=" => flags,
+                _ => return Err(invalid()),
+            };
+            vfs.set_attrs(&pathname, attrs)?
+        }
+        Commands::Touch {
+            access,
+            modification,
+            no_create,
+            no_dereference,
+            date,
+            pathname,
+        } => {
+            let time = date.unwrap_or(SetTime::Now);
+            let (atime, mtime) = match (access, modification) {
+                (true, false) => (time, SetTime::Omit),
+                (false, true) => (SetTime::Omit, time),
+                _ => (time, time),
+            };
+            if vfs.stat(&pathname).is_err() {
+                if no_create {
+                    return Ok(());
+                }
+                vfs.create(&pathname)?;
+            }
+            vfs.utimens(&pathname, atime, mtime, !no_dereference)?
+        }
+        Commands::Lsattr { pathnames } => {
+            for pathname in pathnames {
+                println!("{} {}", attr_string(vfs.attrs(&pathname)?), pathname);
+            }
+        }
+        Commands::Chproj { project, pathname } => vfs.set_project(&pathname, project)?,
+        Commands::Lsproj { pathnames } => {
+            for pathname in pathnames {
+                println!("{} {}", vfs.project(&pathname)?, pathname);
+            }
+        }
+        Commands::Quota {
+            project,
+            bytes,
+            inodes,
+            off,
+        } => {
+            if off {
+                vfs.set_project_quota(project, None);
+            } else if bytes.is_some() || inodes.is_some() {
+                let quota = ProjectQuota {
+                    max_bytes: bytes,
+                    max_inodes: inodes,
+                };
+                vfs.set_project_quota(project, Some(quota));
+            } else {
+                println!("used: {}", vfs.project_usage(project));
+                match vfs.project_quota(project) {
+                    Some(quota) => println!("limit: {}", quota),
+                    None => println!("limit: none"),
+                }
+            }
+        }
+        Commands::Namei { pathname } => println!("{}", vfs.trace_resolve(&pathname)),
+        Commands::Mkimage { pathname, image } => vfs.create_image(&pathname, &image)?,
+        Commands::Mount {
+            options,
+            bind,
+            rbind,
+            archive,
+            image,
+            mountpoint,
+        } => match (image, mountpoint) {
+            (Some(archive_path), Some(mountpoint)) if archive => {
+                vfs.mount_archive(archive_path, &mountpoint, options.unwrap_or_default())?
+            }
+            (Some(source), Some(target)) if bind || rbind => {
+                let options = options.unwrap_or_default();
+                if options.noatime || options.nosymfollow {
+                    return Err("mount: bind mounts only take ro and rw".to_string());
+                }
+                let options = BindOptions {
+                    recursive: rbind,
+                    read_only: options.read_only,
+                };
+                vfs.bind_mount_with(&source, &target, options)?
+            }
+            (Some(image), Some(mountpoint)) => {
+                vfs.mount_image_with(&image, &mountpoint, options.unwrap_or_default())?
+            }
+            _ => {
+                for (image, mountpoint, options) in vfs.mounts() {
+                    println!("{} on {} ({})", image, mountpoint, options);
+                }
+                for (source, target, options) in vfs.binds() {
+                    println!("{} on {} ({})", source, target, options);
+                }
+            }
+        },
+        Commands::Umount { mountpoint } => vfs.unmount(&mountpoint)?,
+        Commands::Dedup { mode } => match mode.as_deref() {
+            Some(mode) => vfs.set_dedup(mode == "on"),
+            None => println!("{}", vfs.dedup_stats()),
+        },
+        Commands::Scrub => println!("{}", vfs.scrub()),
+        Commands::Defrag => println!("{}", vfs.compact()?),
+        Commands::Readonly { mode } => match mode.as_deref() {
+            Some(mode) => vfs.set_read_only(mode == "on"),
+            None => println!("{}", if vfs.is_read_only() { "on" } else { "off" }),
+        },
+        Commands::Throttle { rate, latency } => match rate.as_deref() {
+            Some("off") => vfs.set_throttle(None),
+            Some(rate) => vfs.set_throttle(Some(Throttle::new(
+                parse_size(rate)?,
+                Duration::from_millis(latency),
+            ))),
+            None => match vfs.throttle() {
+                Some(throttle) => println!("{}", throttle),
+                None => println!("off"),
+            },
+        },
+        Commands::Verity { root } => match root {
+            Some(root) => vfs.enable_verity(root.parse()?)?,
+            None => println!("{}", vfs.merkle_root()),
+        },
+        Commands::Index { mode } => match mode.as_deref() {
+            Some(mode) => vfs.set_name_index(mode == "on"),
+            None => println!("{}", if vfs.is_name_indexed() { "on" } else { "off" }),
+        },
+        Commands::Locate { substring } => {
+            let mut paths: Vec<_> = vfs.locate(&substring).collect();
+            paths.sort();
+            for path in paths {
+                println!("{}", path);
+            }
+        }
+        Commands::Textindex { mode } => match mode.as_deref() {
+            Some(mode) => vfs.set_content_index(mode == "on"),
+            None => match vfs.content_index_stats() {
+                Some(stats) => println!("{}", stats),
+                None => println!("off"),
+            },
+        },
+        Commands::Search { words } => {
+            let mut paths: Vec<_> = vfs.search(&words.join(" ")).collect();
+            paths.sort();
+            for path in paths {
+                println!("{}", path);
+            }
+        }
+        Commands::Logger { pathname, message } => {
+            vfs.append_log(&pathname, message.join(" ").as_bytes())?
+        }
+        Commands::Logrotate {
+            pathname,
+            size,
+            keep,
+        } => {
+            let rotation = size.map(|max_size| LogRotation { max_size, keep });
+            vfs.set_log_rotation(&pathname, rotation)
+        }
+        Commands::Audit { mode } => match mode.as_deref() {
+            Some(mode) => vfs.set_audit(mode == "on"),
+            None => {
+                for record in vfs.audit_log() {
+                    println!("{}", record);
+                }
+            }
+        },
+        Commands::Cmp {
+            pathname1,
+            pathname2,
+        } => {
+            if !vfs.compare_files(&pathname1, &pathname2)? {
+                println!("{} {} differ", pathname1, pathname2);
+            }
+        }
+        Commands::SyncIn { host_dir, pathname } => {
+            println!("{}", vfs.sync_from_host(host_dir, &pathname)?)
+        }
+        Commands::SyncOut { pathname, host_dir } => {
+            println!("{}", vfs.sync_to_host(&pathname, host_dir)?)
+        }
+        Commands::Import { host_dir, pathname } => {
+            println!("{}", vfs.import_dir(host_dir, &pathname)?)
+        }
+        Commands::Seed { manifest } => vfs.seed(&VfsFixture::load(manifest)?)?,
+        Commands::Export {
+            tar: true,
+            pathname,
+            host_dir,
+        } => {
+            let mut monitor = Monitor::new();
+            vfs.export_tar_with(&pathname, host_dir, &mut monitor)?;
+            println!("{}", monitor.progress());
+        }
+        Commands::Export {
+            pathname, host_dir, ..
+        } => println!("{}", vfs.export_dir(&pathname, host_dir)?),
+        Commands::Upload {
+            host_file,
+            pathname,
+        } => println!("{}", vfs.upload(host_file, &pathname)?),
+        Commands::Download {
+            pathname,
+            host_file,
+        } => println!("{}", vfs.download(&pathname, host_file)?),
+        Commands::Edit { pathname } => edit(vfs, &pathname)?,
+        Commands::Tui => tui::run(vfs).map_err(|err| format!("tui: {}", err))?,
+        Commands::WriteFile {
+            pathname,
+            data,
+            atomic: false,
+        } => vfs.write_file(&pathname, data.as_bytes())?,
+        Commands::WriteFile {
+            pathname,
+            data,
+            atomic: true,
+        } => vfs.write_atomic(&pathname, data.as_bytes())?,
+        Commands::Restore { host_file } => {
+            let file = std::fs::File::open(&host_file)
+                .map_err(|err| format!("restore: cannot read '{}': {}", host_file, err))?;
+            println!("{}", vfs.apply_incremental(file)?);
+        }
+        Commands::Sha256sum {
+            check,
+            recursive,
+            pathnames,
+        } => checksum(vfs, Algo::Sha256, [check, recursive], &pathnames)?,
+        Commands::Md5sum {
+            check,
+            recursive,
+            pathnames,
+        } => checksum(vfs, Algo::Md5, [check, recursive], &pathnames)?,
+        Commands::Wc {
+            lines,
+            words,
+            bytes,
+            pathnames,
+        } => wc(vfs, [lines, words, bytes], &pathnames)?,
+        Commands::File { pathnames } => {
+            for pathname in pathnames {
+                println!("{}: {}", pathname, vfs.detect_type(&pathname)?);
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Shell {
+    vfs: Vfs,
+    history: History,
+    snapshots: HashMap<String, Vfs>,
+    image: Option<String>,
+    bench: Vec<BenchResult>,
+}
+
+impl Shell {
+    fn run(&mut self, command: Commands) -> Result<(), String> {
+        match command {
+            Commands::Undo => self.history.undo(&mut self.vfs),
+            Commands::Backup {
+                host_file,
+                snapshot,
+            } => {
+                let mut backup = Vec::new();
+                let stats = match &snapshot {
+                    Some(name) => match self.snapshots.get(name) {
+                        Some(base) => self.vfs.backup_incremental(base, &mut backup)?,
+                        None => return Err(format!("backup: no such snapshot: {}", name)),
+                    },
+                    None => self.vfs.backup(&mut backup)?,
+                };
+                std::fs::write(&host_file, backup)
+                    .map_err(|err| format!("backup: cannot write '{}': {}", host_file, err))?;
+                println!("{}", stats);
+                Ok(())
+            }
+            Commands::Save { host_file } => match host_file.or_else(|| self.image.clone()) {
+                Some(host_file) => Ok(self.vfs.save_image(host_file)?),
+                None => Err("save: no image file".to_string()),
+            },
+            Commands::Bench => {
+                let results = self.vfs.bench(&Workload::standard())?;
+                println!(
+                    "{:<16} {:>8} {:>12} {:>14} {:>8}",
+                    "Workload", "Ops", "Time", "Rate", "Change"
+                );
+                for result in &results {
+                    let change = self
+                        .bench
+                        .iter()
+                        .find(|last| last.workload == result.workload)
+                        .map_or("-".to_string(), |last| {
+                            format!("{:+.1}%", (result.rate() / last.rate() - 1.0) * 100.0)
+                        });
+                    println!("{} {:>8}", result, change);
+                }
+                self.bench = results;
+                Ok(())
+            }
+            Commands::Redo => self.history.redo(&mut self.vfs),
+            Commands::Snapshot { name } => {
+                self.snapshots.insert(name, self.vfs.clone());
+                Ok(())
+            }
+            Commands::Diff {
+                snapshot: pathname1,
+                pathname: Some(pathname2),
+            } => {
+                print!("{}", self.vfs.diff_files(&pathname1, &pathname2)?);
+                Ok(())
+            }
+            Commands::Diff { snapshot, .. } => match self.snapshots.get(&snapshot) {
+                Some(old) => {
+                    let changes = self.vfs.diff(old);
+                    if !changes.is_empty() {
+                        println!("{}", changes);
+                    }
+                    Ok(())
+                }
+                None => Err(format!("diff: no such snapshot: {}", snapshot)),
+            },
+            command => {
+                let snapshot = command.is_mutating().then(|| self.vfs.clone());
+                let result = execute(&mut self.vfs, command);
+                if let (Ok(()), Some(snapshot)) = (&result, snapshot) {
+                    self.history.record(snapshot);
+                }
+                result
+            }
+        }
+    }
+}
+
+impl Shell {
+    fn new(builder: VfsBuilder) -> Self {
+        Self {
+            vfs: builder.devices("/dev").proc("/proc").build(),
+            ..Default::default()
+        }
+    }
+
+    /// Load the shell's filesystem from a host image file, or start empty
+    /// if it does not exist yet; `save` writes it back.
+    fn open(builder: VfsBuilder, image: &str) -> Result<Self, String> {
+        let shell = if std::path::Path::new(image).exists() {
+            Self {
+                vfs: builder.devices("/dev").proc("/proc").open_image(image)?,
+                ..Default::default()
+            }
+        } else {
+            Self::new(builder)
+        };
+        Ok(Self {
+            image: Some(image.to_string()),
+            ..shell
+        })
+    }
+}
+
+/// Users and groups known to the shell, like `/etc/passwd` and
+/// `/etc/group`; files record the numeric ids only.
+struct Users {
+    users: BTreeMap<String, (u32, u32)>,
+    groups: BTreeMap<String, u32>,
+}
+
+impl Default for Users {
+    fn default() -> Self {
+        Self {
+            users: BTreeMap::from([(ROOT.to_string(), (0, 0))]),
+            groups: BTreeMap::from([(ROOT.to_string(), 0)]),
+        }
+    }
+}
+
+impl Users {
+    fn user_name(&self, uid: u32) -> String {
+        self.users
+            .iter()
+            .find(|(_, &(id, _))| id == uid)
+            .map_or_else(|| uid.to_string(), |(name, _)| name.clone())
+    }
+
+    fn group_name(&self, gid: u32) -> String {
+        self.groups
+            .iter()
+            .find(|(_, &id)| id == gid)
+            .map_or_else(|| gid.to_string(), |(name, _)| name.clone())
+    }
+
+    /// The `getfacl` form of an entry, with names for known ids.
+    fn acl_entry(&self, entry: &AclEntry) -> String {
+        let text = entry.to_string();
+        let (id, name) = match entry.tag {
+            AclTag::User(uid) => (uid, self.user_name(uid)),
+            AclTag::Group(gid) => (gid, self.group_name(gid)),
+            _ => return text,
+        };
+        text.replacen(&format!(":{}:", id), &format!(":{}:", name), 1)
+    }
+
+    /// Replace user and group names in `setfacl` entries with their ids.
+    fn numeric_acl(&self, entries: &str) -> Result<String, String> {
+        let entries: Result<Vec<_>, String> = entries
+            .split(',')
+            .map(|entry| {
+                let mut fields: Vec<_> = entry.split(':').map(str::to_string).collect();
+                let id = match (fields.first().map(String::as_str), fields.get(1)) {
+                    (_, None) => None,
+                    (_, Some(name)) if name.is_empty() || name.parse::<u32>().is_ok() => None,
+                    (Some("u" | "user"), Some(name)) => Some(
+                        self.users
+                            .get(name)
+                            .map(|&(uid, _)| uid)
+                            .ok_or_else(|| format!("setfacl: user {} does not exist", name))?,
+                    ),
+                    (Some("g" | "group"), Some(name)) => Some(
+                        *self
+                            .groups
+                            .get(name)
+                            .ok_or_else(|| format!("setfacl: group {} does not exist", name))?,
+                    ),
+                    _ => None,
+                };
+                if let Some(id) = id {
+                    fields[1] = id.to_string();
+                }
+                Ok(fields.join(":"))
+            })
+            .collect();
+        Ok(entries?.join(","))
+    }
+
+    /// Add group `name` on behalf of the shell command `cmd`.
+    fn add_group(&mut self, cmd: &str, name: &str, gid: Option<u32>) -> Result<u32, String> {
+        if self.groups.contains_key(name) {
+            return Err(format!("{}: group '{}' already exists", cmd, name));
+        }
+        let gid = match gid {
+            Some(gid) if self.groups.values().any(|&id| id == gid) => {
+                return Err(format!("{}: GID '{}' already exists", cmd, gid))
+            }
+            Some(gid) => gid,
+            None => self
+                .groups
+                .values()
+                .max()
+                .map_or(0, |&id| id.max(FIRST_ID - 1) + 1),
+        };
+        self.groups.insert(name.to_string(), gid);
+        Ok(gid)
+    }
+
+    fn add_user(
+        &mut self,
+        name: &str,
+        uid: Option<u32>,
+        group: Option<String>,
+    ) -> Result<(), String> {
+        if self.users.contains_key(name) {
+            return Err(format!("useradd: user '{}' already exists", name));
+        }
+        let uid = match uid {
+            Some(uid) if self.users.values().any(|&(id, _)| id == uid) => {
+                return Err(format!("useradd: UID {} is not unique", uid))
+            }
+            Some(uid) => uid,
+            None => self
+                .users
+                .values()
+                .map(|&(id, _)| id)
+                .max()
+                .map_or(0, |id| id.max(FIRST_ID - 1) + 1),
+        };
+        let gid = match group {
+            Some(group) => *self
+                .groups
+                .get(&group)
+                .ok_or_else(|| format!("useradd: group '{}' does not exist", group))?,
+            None => self.add_group("useradd", name, None)?,
+        };
+        self.users.insert(name.to_string(), (uid, gid));
+        Ok(())
+    }
+}
+
+/// A watch set up with `watch` on one volume: a command to re-run when
+/// the tree changes, or `None` to print the changes themselves.
+struct Watcher {
+    volume: String,
+    wd: usize,
+    command: Option<Vec<String>>,
+}
+
+/// Independent volumes of one session, each with its own filesystem and
+/// undo history.
+struct VolumeManager {
+    volumes: BTreeMap<String, Shell>,
+    current: String,
+    seed: Option<u64>,
+    atime: AtimePolicy,
+    profiling: bool,
+    profile: BTreeMap<String, Histogram>,
+    users: Users,
+    user: String,
+    /// Width and height of the terminal the shell prints to, or `None` if
+    /// output is not a terminal.
+    terminal: Option<(usize, usize)>,
+    /// Template set with `prompt`, or `None` for the default prompt.
+    prompt: Option<String>,
+    aliases: BTreeMap<String, String>,
+    defaults: BTreeMap<String, Vec<String>>,
+    variables: BTreeMap<String, String>,
+    watchers: Vec<Watcher>,
+}
+
+impl VolumeManager {
+    fn new(cli: &Cli) -> Result<Self, String> {
+        let builder = Self::builder(cli.seed, cli.atime);
+        let mut shell = match &cli.image {
+            Some(image) => Shell::open(builder, image)?,
+            None => Shell::new(builder),
+        };
+        shell.vfs.set_read_only(cli.read_only);
+        Ok(Self {
+            volumes: BTreeMap::from([(DEFAULT_VOLUME.to_string(), shell)]),
+            current: DEFAULT_VOLUME.to_string(),
+            seed: cli.seed,
+            atime: cli.atime,
+            profiling: false,
+            profile: BTreeMap::new(),
+            users: Users::default(),
+            user: ROOT.to_string(),
+            terminal: None,
+            prompt: None,
+            aliases: BTreeMap::new(),
+            defaults: BTreeMap::new(),
+            variables: BTreeMap::new(),
+            watchers: Vec::new(),
+        })
+    }
+
+    fn builder(seed: Option<u64>, atime: AtimePolicy) -> VfsBuilder {
+        let builder = VfsBuilder::new().atime(atime);
+        match seed {
+            Some(seed) => builder.seed(seed),
+            None => builder,
+        }
+    }
+
+    fn shell(&mut self) -> &mut Shell {
+        self.volumes.get_mut(&self.current).unwrap()
+    }
+
+    fn prompt(&self) -> String {
+        let cwd = self.volumes[&self.current].vfs.cwd();
+        if let Some(template) = &self.prompt {
+            return template
+                .replace("{cwd}", cwd)
+                .replace("{user}", &self.user)
+                .replace("{volume}", &self.current);
+        }
+        if self.current == DEFAULT_VOLUME {
+            format!("$ {}> ", cwd)
+        } else {
+            format!("$ {}:{}> ", self.current, cwd)
+        }
+    }
+
+    /// Split `line` into words before it is parsed: an alias in the first
+    /// word is replaced, then variables, and the default flags of the
+    /// command are added.
+    fn expand(&self, line: &str) -> Result<Vec<String>, String> {
+        let line = line.trim_start();
+        let (first, rest) = line.split_at(line.find(char::is_whitespace).unwrap_or(line.len()));
+        let line = match self.aliases.get(first) {
+            Some(command) => format!("{}{}", command, rest),
+            None => line.to_string(),
+        };
+        let mut input = split(&self.expand_variables(&line))
+            .map_err(|_| "error: unterminated quote found".to_string())?;
+        if let Some(flags) = input.first().and_then(|name| self.defaults.get(name)) {
+            input.splice(1..1, flags.clone());
+        }
+        Ok(input)
+    }
+
+    /// Replace `$NAME` and `${NAME}` with the values given to `set`, except
+    /// in single quotes. A value always stays within one word; unset
+    /// variables expand to nothing.
+    fn expand_variables(&self, line: &str) -> String {
+        let mut out = String::new();
+        let mut chars = line.chars().peekable();
+        let (mut single, mut double) = (false, false);
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if !single => {
+                    out.push(c);
+                    out.extend(chars.next());
+                }
+                '\'' if !double => {
+                    single = !single;
+                    out.push(c);
+                }
+                '"' if !single => {
+                    double = !double;
+                    out.push(c);
+                }
+                '$' if !single => {
+                    let braced = chars.next_if_eq(&'{').is_some();
+                    let mut name = String::new();
+                    while let Some(c) = chars.next_if(|&c| c == '_' || c.is_ascii_alphanumeric()) {
+                        name.push(c);
+                    }
+                    if name.is_empty() || braced && chars.next_if_eq(&'}').is_none() {
+                        // Not a variable after all; keep what was typed.
+                        out.push('$');
+                        if braced {
+                            out.push('{');
                         }
-                        Commands::Open { pathname } => match vfs.open(&pathname) {
-                            Ok(fd) => println!("{}", fd),
-                            Err(err) => eprintln!("{}", err),
-                        },
-                        Commands::Close { fd } => {
-                            if let Err(err) = vfs.close(fd) {
-                                eprintln!("{}", err);
+                        out.push_str(&name);
+                        continue;
+                    }
+                    let value = self.variables.get(&name).map_or("", String::as_str);
+                    if double {
+                        for c in value.chars() {
+                            if matches!(c, '$' | '`' | '"' | '\\') {
+                                out.push('\\');
                             }
+                            out.push(c);
                         }
-                        Commands::Seek { fd, offset } => {
-                            if let Err(err) = vfs.seek(fd, offset) {
-                                eprintln!("{}", err);
-                            }
+                    } else if !value.is_empty() {
+                        out.push_str(&escape(value));
+                    }
+                }
+                _ => out.push(c),
+            }
+        }
+        out
+    }
+
+    /// Parse and run one line of input, reporting errors. A line that
+    /// ends in `<<WORD` takes the lines that `more` reads up to `WORD` as
+    /// its last argument, verbatim. Returns `false` once the line asks to
+    /// exit.
+    fn run_line<F>(&mut self, line: &str, mut more: F) -> bool
+    where
+        F: FnMut() -> Option<String>,
+    {
+        let (line, body) = match heredoc(line) {
+            Some((line, delimiter)) => {
+                let mut body = String::new();
+                loop {
+                    match more() {
+                        Some(next) if next == delimiter => break,
+                        Some(next) => {
+                            body.push_str(&next);
+                            body.push('\n');
                         }
-                        Commands::Write { fd, data } => match vfs.write(fd, data.as_bytes()) {
-                            Ok(size) => println!("{}", size),
-                            Err(err) => eprintln!("{}", err),
-                        },
-                        Commands::Read { fd, size } => match vfs.read(fd, size) {
-                            Ok(data) => println!("{}", String::from_utf8_lossy(&data)),
-                            Err(err) => eprintln!("{}", err),
-                        },
-                        Commands::Truncate { pathname, size } => {
-                            if let Err(err) = vfs.truncate(&pathname, size) {
-                                eprintln!("{}", err);
-                            }
+                        None => {
+                            eprintln!("error: here-document not ended with '{}'", delimiter);
+                            return true;
                         }
-                        Commands::Cd { pathname } => {
-                            if let Err(err) = vfs.cd(&pathname) {
-                                eprintln!("{}", err);
-                            }
+                    }
+                }
+                (line, Some(body))
+            }
+            None => (line, None),
+        };
+        let input = match self.expand(line) {
+            Ok(mut input) => {
+                input.extend(body);
+                input
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                return true;
+            }
+        };
+        if input.is_empty() {
+            return true;
+        }
+        match Args::try_parse_from(input) {
+            Ok(Args {
+                commands: Commands::Exit,
+            }) => return false,
+            Ok(args) => {
+                if let Err(err) = self.run(args.commands) {
+                    eprintln!("{}", err);
+                }
+            }
+            Err(err) => eprint!("{}", err),
+        }
+        self.report_watches();
+        true
+    }
+
+    /// Print the changes queued for `watch --events`, and re-run each
+    /// watched command whose tree changed, on the volume it watches.
+    fn report_watches(&mut self) {
+        let mut changed = Vec::new();
+        for (name, shell) in &mut self.volumes {
+            if !self.watchers.iter().any(|watcher| watcher.volume == *name) {
+                continue;
+            }
+            for event in shell.vfs.read_events() {
+                let watcher = self
+                    .watchers
+                    .iter()
+                    .position(|watcher| watcher.volume == *name && watcher.wd == event.wd);
+                match watcher {
+                    Some(i) if self.watchers[i].command.is_none() => println!("{}", event),
+                    Some(i) if !changed.contains(&i) => changed.push(i),
+                    _ => {}
+                }
+            }
+        }
+        for i in changed {
+            let volume = self.watchers[i].volume.clone();
+            let current = std::mem::replace(&mut self.current, volume);
+            if let Err(err) = self.rerun(i) {
+                eprintln!("{}", err);
+            }
+            self.current = current;
+        }
+    }
+
+    /// Run the command of watcher `i` under a header like `watch(1)`'s.
+    fn rerun(&mut self, i: usize) -> Result<(), String> {
+        let watcher = &self.watchers[i];
+        let command = watcher.command.clone().unwrap_or_default();
+        let path = self.volumes[&watcher.volume].vfs.watched().get(&watcher.wd);
+        println!(
+            "Every change to {}: {}",
+            path.map_or("", String::as_str),
+            join(&command)
+        );
+        let args = Args::try_parse_from(command).map_err(|err| err.to_string())?;
+        self.run(args.commands)
+    }
+
+    /// Run the commands in the startup file `rc`, one per line, skipping
+    /// `#` comments. A missing file is not an error. Returns `false` if
+    /// the file asks to exit.
+    fn run_rc(&mut self, rc: &str) -> bool {
+        let Ok(contents) = std::fs::read_to_string(rc) else {
+            return true;
+        };
+        let mut lines = contents.lines();
+        while let Some(line) = lines.next() {
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+            if !self.run_line(line, || lines.next().map(str::to_string)) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn df(&self, human_readable: bool) {
+        let size = |blocks: usize, statfs: &StatFs| {
+            let bytes = blocks * statfs.block_size;
+            if human_readable {
+                format_size(bytes)
+            } else {
+                bytes.to_string()
+            }
+        };
+        println!(
+            "{:<16} {:>12} {:>12} {:>12} {:>5} Atime",
+            "Volume", "Size", "Used", "Avail", "Use%"
+        );
+        for (name, shell) in &self.volumes {
+            let statfs = shell.vfs.statfs();
+            let used = statfs.blocks - statfs.blocks_free;
+            let marker = if *name == self.current { "*" } else { "" };
+            println!(
+                "{:<16} {:>12} {:>12} {:>12} {:>4}% {}",
+                format!("{}{}", name, marker),
+                size(statfs.blocks, &statfs),
+                size(used, &statfs),
+                size(statfs.blocks_free, &statfs),
+                (used * 100).div_ceil(statfs.blocks.max(1)),
+                statfs.atime
+            );
+        }
+    }
+
+    /// Like `df`, for inodes, warning about volumes with less than a tenth
+    /// of their inodes free.
+    fn df_inodes(&self) {
+        println!(
+            "{:<16} {:>12} {:>12} {:>12} {:>5}",
+            "Volume", "Inodes", "IUsed", "IFree", "IUse%"
+        );
+        let mut low = Vec::new();
+        for (name, shell) in &self.volumes {
+            let statfs = shell.vfs.statfs();
+            let used = statfs.files - statfs.files_free;
+            let marker = if *name == self.current { "*" } else { "" };
+            println!(
+                "{:<16} {:>12} {:>12} {:>12} {:>4}%",
+                format!("{}{}", name, marker),
+                statfs.files,
+                used,
+                statfs.files_free,
+                (used * 100).div_ceil(statfs.files.max(1))
+            );
+            if shell.vfs.max_inodes().is_some() && statfs.files_free * 10 < statfs.files {
+                low.push((name, statfs.files_free));
+            }
+        }
+        for (name, free) in low {
+            eprintln!(
+                "df: warning: volume '{}' has {} free inodes left",
+                name, free
+            );
+        }
+    }
+
+    fn run(&mut self, command: Commands) -> Result<(), String> {
+        if !self.profiling || matches!(command, Commands::Profile { .. }) {
+            return self.dispatch(command);
+        }
+        let name = command.name();
+        let start = Instant::now();
+        let result = self.dispatch(command);
+        self.profile
+            .entry(name)
+            .or_default()
+            .record(start.elapsed());
+        result
+    }
+
+    /// Run `print` with output that goes through the pager if it does not
+    /// fit on the terminal, unless `no_pager` is set. Whatever was printed
+    /// before an error is still shown.
+    fn paged<F>(&mut self, no_pager: bool, print: F) -> Result<(), String>
+    where
+        F: FnOnce(&Self, &mut Output) -> Result<(), String>,
+    {
+        let rows = self.terminal.filter(|_| !no_pager).map(|(_, rows)| rows);
+        let mut out = match rows {
+            Some(_) => Output::paged(),
+            None => Output::direct(),
+        };
+        let result = print(self, &mut out);
+        out.finish(rows.unwrap_or(0))
+            .map_err(|err| format!("pager: {}", err))?;
+        result
+    }
+
+    fn dispatch(&mut self, command: Commands) -> Result<(), String> {
+        match command {
+            Commands::Mkfs {
+                name,
+                size,
+                atime,
+                inodes,
+            } => {
+                let atime = atime.unwrap_or(self.atime);
+                let mut builder = Self::builder(self.seed, atime).size(size);
+                if let Some(inodes) = inodes {
+                    builder = builder.max_inodes(inodes);
+                }
+                let mut shell = Shell::new(builder);
+                let (uid, gid) = self.users.users[&self.user];
+                shell
+                    .vfs
+                    .switch_session(Session::new().with_identity(uid, gid));
+                self.volumes.insert(name, shell);
+                Ok(())
+            }
+            Commands::Use { name } => {
+                if !self.volumes.contains_key(&name) {
+                    return Err(format!("use: no such volume: {}", name));
+                }
+                self.current = name;
+                Ok(())
+            }
+            Commands::Df {
+                human_readable,
+                inodes,
+            } => {
+                match inodes {
+                    true => self.df_inodes(),
+                    false => self.df(human_readable),
+                }
+                Ok(())
+            }
+            Commands::Time { command } => {
+                let args = Args::try_parse_from(self.expand(&join(&command))?)
+                    .map_err(|err| err.to_string().trim_end().to_string())?;
+                if let Commands::Exit = args.commands {
+                    return Err("time: cannot time exit".to_string());
+                }
+                let before = self.shell().vfs.io_stats();
+                let start = Instant::now();
+                let result = self.run(args.commands);
+                let elapsed = start.elapsed();
+                let io = self.shell().vfs.io_stats().since(&before);
+                println!("Time: {:?} \t{}", elapsed, io);
+                result
+            }
+            Commands::Stat {
+                human_readable,
+                format,
+                pathname,
+            } => {
+                let stat = self.shell().vfs.stat(&pathname)?;
+                if let Some(format) = format {
+                    println!("{}", stat.format(&format));
+                    return Ok(());
+                }
+                if human_readable {
+                    println!("{:#}", stat);
+                } else {
+                    println!("{}", stat);
+                }
+                println!(
+                    "Uid: {} ({}) \tGid: {} ({})",
+                    stat.uid(),
+                    self.users.user_name(stat.uid()),
+                    stat.gid(),
+                    self.users.group_name(stat.gid())
+                );
+                Ok(())
+            }
+            Commands::List {
+                pathname,
+                limit,
+                after,
+                long,
+                recursive,
+                color,
+                one_per_line,
+                no_pager,
+            } => self.paged(no_pager, |volumes, out| {
+                let vfs = &volumes.volumes[&volumes.current].vfs;
+                let listing = Listing {
+                    vfs,
+                    users: &volumes.users,
+                    long,
+                    color: match color {
+                        ColorWhen::Always => true,
+                        ColorWhen::Auto => volumes.terminal.is_some(),
+                        ColorWhen::Never => false,
+                    },
+                    columns: volumes
+                        .terminal
+                        .map(|(width, _)| width)
+                        .filter(|_| !long && !one_per_line),
+                };
+                if recursive {
+                    for (i, dirname) in recursive_dirs(vfs, &pathname)?.enumerate() {
+                        if i > 0 {
+                            out.line("");
                         }
-                        Commands::Mkdir { pathname } => {
-                            if let Err(err) = vfs.mkdir(&pathname) {
-                                eprintln!("{}", err);
-                            }
+                        out.line(format!("{}:", dirname));
+                        let names = vfs.read_dir(&dirname)?.map(|entry| entry.name);
+                        listing.print(out, &dirname, names.collect())?;
+                    }
+                    return Ok(());
+                }
+                match limit {
+                    Some(limit) => {
+                        let page = vfs.readdir_page(&pathname, &after, limit)?;
+                        let names = page.entries.into_iter().map(|entry| entry.name);
+                        listing.print(out, &pathname, names.collect())?;
+                        if let Some(next) = page.next {
+                            out.line(format!("next: {}", next));
                         }
-                        Commands::Rmdir { pathname } => {
-                            if let Err(err) = vfs.rmdir(&pathname) {
-                                eprintln!("{}", err);
+                    }
+                    None => {
+                        let names = vfs.ls(&pathname)?.map(|entry| entry.name);
+                        listing.print(out, &pathname, names.collect())?
+                    }
+                }
+                Ok(())
+            }),
+            Commands::Tree {
+                pathname,
+                level,
+                dirs_only,
+                no_pager,
+            } => self.paged(no_pager, |volumes, out| {
+                let vfs = &volumes.volumes[&volumes.current].vfs;
+                tree(vfs, out, &pathname, level, dirs_only)
+            }),
+            Commands::Cat {
+                pathnames,
+                no_pager,
+            } => self.paged(no_pager, |volumes, out| {
+                let vfs = &volumes.volumes[&volumes.current].vfs;
+                for pathname in &pathnames {
+                    let data = vfs.read_file_as("cat", pathname)?;
+                    for line in String::from_utf8_lossy(&data).lines() {
+                        out.line(line);
+                    }
+                }
+                Ok(())
+            }),
+            Commands::Getfacl { pathname } => {
+                let vfs = &self.volumes[&self.current].vfs;
+                let stat = vfs.stat(&pathname)?;
+                let acl = vfs.get_acl(&pathname)?;
+                println!("# file: {}", pathname);
+                println!("# owner: {}", self.users.user_name(stat.uid()));
+                println!("# group: {}", self.users.group_name(stat.gid()));
+                for entry in &acl {
+                    println!("{}", self.users.acl_entry(entry));
+                }
+                Ok(())
+            }
+            Commands::Setfacl {
+                modify,
+                remove,
+                remove_all,
+                pathname,
+            } => {
+                let command = Commands::Setfacl {
+                    modify: modify
+                        .map(|entries| self.users.numeric_acl(&entries))
+                        .transpose()?,
+                    remove: remove
+                        .map(|entries| self.users.numeric_acl(&entries))
+                        .transpose()?,
+                    remove_all,
+                    pathname,
+                };
+                self.shell().run(command)
+            }
+            Commands::Useradd { name, uid, group } => self.users.add_user(&name, uid, group),
+            Commands::Groupadd { name, gid } => {
+                self.users.add_group("groupadd", &name, gid).map(|_| ())
+            }
+            Commands::Su { user } => {
+                let &(uid, gid) = self
+                    .users
+                    .users
+                    .get(&user)
+                    .ok_or_else(|| format!("su: user {} does not exist", user))?;
+                for shell in self.volumes.values_mut() {
+                    let session = shell.vfs.session().clone().with_identity(uid, gid);
+                    shell.vfs.switch_session(session);
+                }
+                self.user = user;
+                Ok(())
+            }
+            Commands::Whoami => {
+                println!("{}", self.user);
+                Ok(())
+            }
+            Commands::Prompt {
+                template: Some(template),
+            } => {
+                self.prompt = Some(template);
+                Ok(())
+            }
+            Commands::Prompt { template: None } => {
+                match &self.prompt {
+                    Some(template) => println!("{}", template),
+                    None => println!("$ {{cwd}}> "),
+                }
+                Ok(())
+            }
+            Commands::Alias { name: None, .. } => {
+                for (name, command) in &self.aliases {
+                    println!("alias {}={}", name, quote(command));
+                }
+                Ok(())
+            }
+            Commands::Alias {
+                name: Some(name),
+                command,
+            } => {
+                let (name, first) = match name.split_once('=') {
+                    Some((name, first)) => (name.to_string(), Some(first.to_string())),
+                    None if command.is_empty() => {
+                        return match self.aliases.get(&name) {
+                            Some(command) => {
+                                println!("alias {}={}", name, quote(command));
+                                Ok(())
                             }
+                            None => Err(format!("alias: {}: not found", name)),
                         }
-                        Commands::Symlink { path, pathname } => {
-                            if let Err(err) = vfs.symlink(&path, &pathname) {
-                                eprintln!("{}", err);
-                            }
+                    }
+                    None => (name, None),
+                };
+                // The part after `=` is text already; the words after it
+                // need quoting again.
+                let mut text = first.unwrap_or_default();
+                if !command.is_empty() {
+                    if !text.is_empty() {
+                        text.push(' ');
+                    }
+                    text.push_str(&join(&command));
+                }
+                self.aliases.insert(name, text);
+                Ok(())
+            }
+            Commands::Unalias { name } => match self.aliases.remove(&name) {
+                Some(_) => Ok(()),
+                None => Err(format!("unalias: {}: not found", name)),
+            },
+            Commands::Set {
+                name: Some(name),
+                value: Some(value),
+            } => {
+                self.variables.insert(name, value);
+                Ok(())
+            }
+            Commands::Set { .. } => {
+                for (name, value) in &self.variables {
+                    println!("{}={}", name, quote(value));
+                }
+                Ok(())
+            }
+            Commands::Unset { name } => {
+                self.variables.remove(&name);
+                Ok(())
+            }
+            Commands::Defaults { command: None, .. } => {
+                for (command, flags) in &self.defaults {
+                    println!("defaults {} {}", command, join(flags));
+                }
+                Ok(())
+            }
+            Commands::Defaults {
+                command: Some(command),
+                flags,
+            } => {
+                if flags.is_empty() {
+                    self.defaults.remove(&command);
+                } else {
+                    self.defaults.insert(command, flags);
+                }
+                Ok(())
+            }
+            Commands::Watch {
+                events: Some(pathname),
+                ..
+            } => {
+                let wd = self.shell().vfs.watch(&pathname)?;
+                self.watchers.push(Watcher {
+                    volume: self.current.clone(),
+                    wd,
+                    command: None,
+                });
+                println!("{}", wd);
+                Ok(())
+            }
+            Commands::Watch { command, .. } if command.is_empty() => {
+                let vfs = &self.volumes[&self.current].vfs;
+                for watcher in &self.watchers {
+                    if watcher.volume != self.current {
+                        continue;
+                    }
+                    let path = &vfs.watched()[&watcher.wd];
+                    match &watcher.command {
+                        Some(command) => println!("{} {} {}", watcher.wd, path, join(command)),
+                        None => println!("{} {} --events", watcher.wd, path),
+                    }
+                }
+                Ok(())
+            }
+            Commands::Watch { path, command, .. } => {
+                let command = self.expand(&join(&command))?;
+                let args = Args::try_parse_from(&command)
+                    .map_err(|err| err.to_string().trim_end().to_string())?;
+                if let Commands::Exit | Commands::Watch { .. } = args.commands {
+                    return Err(format!("watch: cannot watch {}", command[0]));
+                }
+                let vfs = &mut self.shell().vfs;
+                let path = path.unwrap_or_else(|| match command.last() {
+                    Some(last) if command.len() > 1 && vfs.stat(last).is_ok() => last.clone(),
+                    _ => ".".to_string(),
+                });
+                let wd = vfs.watch(&path)?;
+                self.watchers.push(Watcher {
+                    volume: self.current.clone(),
+                    wd,
+                    command: Some(command),
+                });
+                self.rerun(self.watchers.len() - 1)
+            }
+            Commands::Unwatch { wds } => {
+                let current = self.current.clone();
+                let wds = if wds.is_empty() {
+                    self.watchers
+                        .iter()
+                        .filter(|watcher| watcher.volume == current)
+                        .map(|watcher| watcher.wd)
+                        .collect()
+                } else {
+                    wds
+                };
+                for wd in wds {
+                    self.shell().vfs.unwatch(wd)?;
+                    self.watchers
+                        .retain(|watcher| watcher.volume != current || watcher.wd != wd);
+                }
+                Ok(())
+            }
+            Commands::Profile { mode } => {
+                match mode.as_str() {
+                    "on" => self.profiling = true,
+                    "off" => self.profiling = false,
+                    _ => {
+                        for (name, histogram) in &self.profile {
+                            println!("{}: {}", name, histogram);
                         }
-                    },
-                    Err(err) => {
-                        eprint!("{}", err);
                     }
                 }
+                Ok(())
+            }
+            command => self.shell().run(command),
+        }
+    }
+}
+
+fn main() {
+    let mut cli = Cli::parse();
+    if let Some(Mode::Replay { file }) = &cli.mode {
+        match record::replay(file) {
+            Ok(count) => println!("replay: {} commands replayed", count),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    let mut recorder = match &cli.mode {
+        Some(Mode::Record { file }) => match Recorder::create(&file.clone(), &mut cli) {
+            Ok(recorder) => Some(recorder),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        },
+        _ => None,
+    };
+    let mut volumes = match VolumeManager::new(&cli) {
+        Ok(volumes) => volumes,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let mut editor = DefaultEditor::new().unwrap();
+    let mut interupted = false;
+    println!(
+        "Welcome to VFS {}.\nType \"help\" for more information",
+        env!("CARGO_PKG_VERSION")
+    );
+    if recorder.is_some() {
+        volumes.start_audit();
+    }
+    let rc = cli.rc.clone().or_else(|| {
+        std::env::var("HOME")
+            .ok()
+            .map(|home| format!("{}/{}", home, RC_FILE))
+    });
+    if let Some(rc) = rc.filter(|_| recorder.is_none()) {
+        if !volumes.run_rc(&rc) {
+            return;
+        }
+    }
+    if let Some(Mode::Tui) = &cli.mode {
+        if let Err(err) = volumes.run(Commands::Tui) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    loop {
+        match editor.readline(&volumes.prompt()) {
+            Ok(line) => {
+                volumes.terminal = editor.dimensions();
+                let mut lines = vec![line.clone()];
+                let more = || {
+                    let next = editor.readline(HEREDOC_PROMPT).ok()?;
+                    lines.push(next.clone());
+                    Some(next)
+                };
+                let mark = volumes.mark();
+                let go_on = volumes.run_line(&line, more);
+                if let Some(recorder) = recorder.as_mut().filter(|_| !line.trim().is_empty()) {
+                    if let Err(err) = recorder.record(&lines, &volumes.effects(&mark)) {
+                        eprintln!("{}", err);
+                    }
+                }
+                if !go_on {
+                    break;
+                }
                 editor.add_history_entry(line).unwrap();
             }
             Err(ReadlineError::Interrupted) => {
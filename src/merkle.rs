@@ -0,0 +1,205 @@
+use std::{fmt, str::FromStr};
+
+use sha2::{Digest, Sha256};
+
+use crate::{ErrorKind, FileType, Vfs, VfsError};
+
+type Hash = [u8; 32];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MerkleRoot(pub [u8; 32]);
+
+impl fmt::Display for MerkleRoot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for MerkleRoot {
+    type Err = String;
+
+    fn from_str(hex: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid merkle root: {}", hex);
+        if hex.len() != 64 {
+            return Err(invalid());
+        }
+        let mut hash = [0; 32];
+        for (i, byte) in hash.iter_mut().enumerate() {
+            let digits = hex.get(2 * i..2 * i + 2).ok_or_else(invalid)?;
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+        }
+        Ok(Self(hash))
+    }
+}
+
+/// Binary hash tree over the raw contents of the block store.
+///
+/// Leaves are SHA-256 digests of individual blocks; an odd node at the end of
+/// a level is carried up unchanged. The root of the whole filesystem
+/// additionally commits to the inode table, see `Vfs::merkle_root`.
+#[derive(Debug, Clone)]
+pub(crate) struct MerkleTree {
+    levels: Vec<Vec<Hash>>,
+}
+
+impl MerkleTree {
+    fn leaf(block: &[u8]) -> Hash {
+        Sha256::digest(block).into()
+    }
+
+    pub(crate) fn node(left: &Hash, right: &Hash) -> Hash {
+        let mut hasher = Sha256::new();
+        hasher.update(left);
+        hasher.update(right);
+        hasher.finalize().into()
+    }
+
+    pub(crate) fn build<I>(blocks: I) -> Self
+    where
+        I: Iterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut levels = vec![blocks
+            .map(|block| Self::leaf(block.as_ref()))
+            .collect::<Vec<_>>()];
+        while levels.last().unwrap().len() > 1 {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => Self::node(left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+        Self { levels }
+    }
+
+    pub(crate) fn root(&self) -> Hash {
+        self.levels
+            .last()
+            .and_then(|level| level.first())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Check a block against the stored tree, walking from its leaf up to
+    /// the root.
+    pub(crate) fn verify(&self, index: usize, block: &[u8]) -> bool {
+        let mut hash = Self::leaf(block);
+        let mut index = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            if level.get(index) != Some(&hash) {
+                return false;
+            }
+            hash = match level.get(index ^ 1) {
+                Some(sibling) if index.is_multiple_of(2) => Self::node(&hash, sibling),
+                Some(sibling) => Self::node(sibling, &hash),
+                None => hash,
+            };
+            index /= 2;
+        }
+        hash == self.root()
+    }
+}
+
+impl Vfs {
+    /// Digest of every inode except its timestamps, which reads update.
+    fn metadata_digest(&self) -> Hash {
+        let mut hasher = Sha256::new();
+        for (id, fd) in self.fds.iter().enumerate() {
+            hasher.update(id.to_le_bytes());
+            hasher.update(fd.size.to_le_bytes());
+            hasher.update(fd.links.to_le_bytes());
+            hasher.update(fd.mode.to_le_bytes());
+            hasher.update(fd.uid.to_le_bytes());
+            hasher.update(fd.gid.to_le_bytes());
+            hasher.update(fd.attrs.to_le_bytes());
+            for entry in &fd.acl {
+                hasher.update(entry.to_string().as_bytes());
+            }
+            match &fd.file_type {
+                FileType::Regular(blocks_refs) => {
+                    hasher.update([0]);
+                    for block_ref in blocks_refs {
+                        hasher.update(block_ref.to_le_bytes());
+                    }
+                }
+                FileType::Directory(entries) => {
+                    hasher.update([1]);
+                    for (name, id) in entries {
+                        hasher.update(name.len().to_le_bytes());
+                        hasher.update(name.as_bytes());
+                        hasher.update(id.to_le_bytes());
+                    }
+                }
+                FileType::Symlink(target) => {
+                    hasher.update([2]);
+                    hasher.update(target.as_bytes());
+                }
+                FileType::Fifo(_) => hasher.update([3]),
+                FileType::Device(device) => {
+                    hasher.update([4]);
+                    hasher.update(device.name().as_bytes());
+                }
+                FileType::Proc(entry) => {
+                    hasher.update([5]);
+                    hasher.update(format!("{:?}", entry).as_bytes());
+                }
+                FileType::Ring(ring) => {
+                    hasher.update([6]);
+                    hasher.update(ring.capacity().to_le_bytes());
+                    hasher.update(ring.contents());
+                }
+                FileType::Archive(member) => {
+                    hasher.update([7]);
+                    hasher.update(format!("{:?}", member).as_bytes());
+                }
+            }
+        }
+        hasher.finalize().into()
+    }
+
+    fn merkle_tree(&self) -> MerkleTree {
+        MerkleTree::build(self.blocks.raw_blocks())
+    }
+
+    /// Compute the Merkle root committing to both the inode table and every
+    /// stored block.
+    pub fn merkle_root(&self) -> MerkleRoot {
+        MerkleRoot(MerkleTree::node(
+            &self.metadata_digest(),
+            &self.merkle_tree().root(),
+        ))
+    }
+
+    /// Freeze the filesystem against a trusted Merkle root.
+    ///
+    /// Fails if the current state does not match `root`. Afterwards every
+    /// block read is verified against the tree and every mutation fails with
+    /// a read-only error.
+    pub fn enable_verity(&mut self, root: MerkleRoot) -> Result<(), VfsError> {
+        let tree = self.merkle_tree();
+        if MerkleTree::node(&self.metadata_digest(), &tree.root()) != root.0 {
+            return Err(VfsError::new(
+                ErrorKind::DataCorruption,
+                format!(
+                    "verity: cannot enable verity with root '{}': Root hash mismatch",
+                    root
+                ),
+            ));
+        }
+        self.verity = Some(tree);
+        Ok(())
+    }
+
+    pub fn is_verity(&self) -> bool {
+        self.verity.is_some()
+    }
+}
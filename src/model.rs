@@ -0,0 +1,564 @@
+//! Reference model of the filesystem for differential testing.
+//!
+//! The model keeps a flat map from absolute paths to nodes and implements
+//! the core namespace and file operations as plainly as possible.
+//! `check_against_model` runs a sequence of operations against both the
+//! model and a `Vfs`, comparing every result and the whole visible state
+//! after each step, and checks the internal link and block accounting of
+//! the `Vfs` along the way. `torture_truncate` does the same for reads,
+//! writes and truncations interleaved through several descriptors of one
+//! file.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::{Device, FileType, Op, Urandom, Vfs, BLOCK_SIZE, DOT, DOTDOT};
+
+const NAMES: [&str; 4] = ["a", "b", "c", "d"];
+const MAX_DEPTH: usize = 3;
+const MAX_FDS: u8 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Kind {
+    Dir,
+    File(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    kind: Kind,
+    links: usize,
+}
+
+/// Reference model: absolute paths mapped to node ids, plus open files.
+#[derive(Debug, Clone)]
+pub struct Model {
+    paths: HashMap<String, usize>,
+    nodes: HashMap<usize, Node>,
+    next: usize,
+    open: BTreeMap<usize, (usize, usize)>,
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parent(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) => "/".to_string(),
+        Some(idx) => path[..idx].to_string(),
+        None => ".".to_string(),
+    }
+}
+
+fn is_below(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| rest.starts_with('/'))
+}
+
+impl Model {
+    pub fn new() -> Self {
+        let root = Node {
+            kind: Kind::Dir,
+            links: 1,
+        };
+        Self {
+            paths: HashMap::from([("/".to_string(), 0)]),
+            nodes: HashMap::from([(0, root)]),
+            next: 1,
+            open: BTreeMap::new(),
+        }
+    }
+
+    fn node(&self, path: &str) -> Option<&Node> {
+        self.paths.get(path).map(|id| &self.nodes[id])
+    }
+
+    fn is_dir(&self, path: &str) -> bool {
+        self.node(path).is_some_and(|node| node.kind == Kind::Dir)
+    }
+
+    fn children(&self, dir: &str) -> BTreeSet<String> {
+        self.paths
+            .keys()
+            .filter(|path| *path != "/" && parent(path) == dir)
+            .map(|path| Vfs::basename(path))
+            .collect()
+    }
+
+    fn add(&mut self, path: &str, kind: Kind) {
+        let id = self.next;
+        self.next += 1;
+        self.nodes.insert(id, Node { kind, links: 1 });
+        self.paths.insert(path.to_string(), id);
+    }
+
+    fn drop_link(&mut self, path: &str) {
+        let id = self.paths.remove(path).unwrap();
+        let node = self.nodes.get_mut(&id).unwrap();
+        node.links -= 1;
+        if node.links == 0 && !self.open.values().any(|&(open_id, _)| open_id == id) {
+            self.nodes.remove(&id);
+        }
+    }
+
+    fn file_mut(&mut self, id: usize) -> &mut Vec<u8> {
+        match &mut self.nodes.get_mut(&id).unwrap().kind {
+            Kind::File(data) => data,
+            Kind::Dir => unreachable!(),
+        }
+    }
+
+    /// Read up to `size` bytes through descriptor `fd`, or `None` if it is
+    /// not open.
+    fn read(&mut self, fd: usize, size: usize) -> Option<Vec<u8>> {
+        let &(id, cursor) = self.open.get(&fd)?;
+        let file = self.file_mut(id);
+        let start = cursor.min(file.len());
+        let end = (start + size).min(file.len());
+        let data = file[start..end].to_vec();
+        self.open.insert(fd, (id, end));
+        Some(data)
+    }
+
+    /// Apply one operation and return whether it succeeded; error messages
+    /// are not modelled.
+    pub fn apply(&mut self, op: &Op) -> bool {
+        self.try_apply(op).is_some()
+    }
+
+    fn try_apply(&mut self, op: &Op) -> Option<()> {
+        match op {
+            Op::Create { pathname } => {
+                if !self.is_dir(&parent(pathname)) {
+                    return None;
+                }
+                if !self.paths.contains_key(pathname) {
+                    self.add(pathname, Kind::File(Vec::new()));
+                }
+            }
+            Op::Mkdir { pathname } => {
+                if !self.is_dir(&parent(pathname)) || self.paths.contains_key(pathname) {
+                    return None;
+                }
+                self.add(pathname, Kind::Dir);
+            }
+            Op::Rmdir { pathname } => {
+                if pathname == "/" || !self.is_dir(pathname) || !self.children(pathname).is_empty()
+                {
+                    return None;
+                }
+                self.drop_link(pathname);
+            }
+            Op::Link {
+                pathname1,
+                pathname2,
+            } => {
+                let ok = self
+                    .node(pathname1)
+                    .is_some_and(|node| node.kind != Kind::Dir)
+                    && self.is_dir(&parent(pathname2))
+                    && !self.paths.contains_key(pathname2);
+                if !ok {
+                    return None;
+                }
+                let id = self.paths[pathname1];
+                self.nodes.get_mut(&id).unwrap().links += 1;
+                self.paths.insert(pathname2.clone(), id);
+            }
+            Op::Unlink { pathname } => {
+                if self
+                    .node(pathname)
+                    .is_none_or(|node| node.kind == Kind::Dir)
+                {
+                    return None;
+                }
+                self.drop_link(pathname);
+            }
+            Op::Rename {
+                pathname1,
+                pathname2,
+            } => return self.rename(pathname1, pathname2),
+            Op::Open { pathname } => match self.node(pathname) {
+                Some(Node {
+                    kind: Kind::File(_),
+                    ..
+                }) => {
+                    let fd = (0..).find(|fd| !self.open.contains_key(fd)).unwrap();
+                    self.open.insert(fd, (self.paths[pathname], 0));
+                }
+                _ => return None,
+            },
+            Op::Close { fd } => {
+                let (id, _) = self.open.remove(fd)?;
+                if self.nodes[&id].links == 0
+                    && !self.open.values().any(|&(open_id, _)| open_id == id)
+                {
+                    self.nodes.remove(&id);
+                }
+            }
+            Op::Seek { fd, offset } => {
+                let &(id, _) = self.open.get(fd)?;
+                if *offset > self.file_mut(id).len() {
+                    return None;
+                }
+                self.open.insert(*fd, (id, *offset));
+            }
+            Op::Write { fd, data } => {
+                let &(id, cursor) = self.open.get(fd)?;
+                let file = self.file_mut(id);
+                let end = cursor + data.len();
+                if file.len() < end {
+                    file.resize(end, 0);
+                }
+                file[cursor..end].copy_from_slice(data);
+                self.open.insert(*fd, (id, end));
+            }
+            Op::Truncate { pathname, size } => {
+                let id = match self.node(pathname) {
+                    Some(Node {
+                        kind: Kind::File(_),
+                        ..
+                    }) => self.paths[pathname],
+                    _ => return None,
+                };
+                self.file_mut(id).resize(*size, 0);
+                for (open_id, cursor) in self.open.values_mut() {
+                    if *open_id == id {
+                        *cursor = (*cursor).min(*size);
+                    }
+                }
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> Option<()> {
+        let id = *self.paths.get(from)?;
+        if !self.is_dir(&parent(to)) || from == "/" || to == "/" {
+            return None;
+        }
+        let is_dir = self.nodes[&id].kind == Kind::Dir;
+        if is_dir && (parent(to) == from || is_below(&parent(to), from)) {
+            return None;
+        }
+        match self.paths.get(to) {
+            Some(&target) if target == id => return Some(()),
+            Some(_) => {
+                match (is_dir, self.is_dir(to)) {
+                    (true, true) if self.children(to).is_empty() => {}
+                    (false, false) => {}
+                    _ => return None,
+                }
+                self.drop_link(to);
+            }
+            None => {}
+        }
+        let moved: Vec<_> = self
+            .paths
+            .keys()
+            .filter(|path| *path == from || is_below(path, from))
+            .cloned()
+            .collect();
+        for path in moved {
+            let id = self.paths.remove(&path).unwrap();
+            self.paths
+                .insert(format!("{}{}", to, &path[from.len()..]), id);
+        }
+        Some(())
+    }
+}
+
+/// Turn arbitrary bytes into operations over a small namespace, so that
+/// they collide often; every byte string decodes to something.
+pub fn ops_from_bytes(data: &[u8]) -> Vec<Op> {
+    let mut bytes = data.iter().copied();
+    let mut next = || bytes.next();
+    let mut ops = Vec::new();
+    let path = |b: u8| {
+        let mut path = String::new();
+        let mut rest = b as usize;
+        for _ in 0..rest % MAX_DEPTH + 1 {
+            rest /= NAMES.len();
+            path.push('/');
+            path.push_str(NAMES[rest % NAMES.len()]);
+        }
+        path
+    };
+    while let Some(tag) = next() {
+        let Some(arg) = next() else { break };
+        let op = match tag % 11 {
+            0 => Op::Create {
+                pathname: path(arg),
+            },
+            1 => Op::Mkdir {
+                pathname: path(arg),
+            },
+            2 => Op::Rmdir {
+                pathname: path(arg),
+            },
+            3 => Op::Link {
+                pathname1: path(arg),
+                pathname2: path(next().unwrap_or_default()),
+            },
+            4 => Op::Unlink {
+                pathname: path(arg),
+            },
+            5 => Op::Rename {
+                pathname1: path(arg),
+                pathname2: path(next().unwrap_or_default()),
+            },
+            6 => Op::Open {
+                pathname: path(arg),
+            },
+            7 => Op::Close {
+                fd: (arg % MAX_FDS) as usize,
+            },
+            8 => Op::Seek {
+                fd: (arg % MAX_FDS) as usize,
+                offset: next().unwrap_or_default() as usize * 9,
+            },
+            9 => Op::Write {
+                fd: (arg % MAX_FDS) as usize,
+                data: vec![arg; next().unwrap_or_default() as usize * 9],
+            },
+            _ => Op::Truncate {
+                pathname: path(arg),
+                size: next().unwrap_or_default() as usize * 13,
+            },
+        };
+        ops.push(op);
+    }
+    ops
+}
+
+/// `ops_from_bytes` over `len` pseudo-random bytes from `seed`.
+pub fn random_ops(seed: u64, len: usize) -> Vec<Op> {
+    ops_from_bytes(&Urandom::with_seed(seed).read(len))
+}
+
+/// Decode `data` into opens, closes, seeks, reads, writes, truncations,
+/// unlinks and re-creations of a single file, so that descriptors keep
+/// pointing into it while it shrinks, grows and is replaced, and check
+/// every read and the state after every step against the model, without
+/// and with deduplication. Every byte string decodes to something.
+pub fn torture_truncate(data: &[u8]) -> Result<(), String> {
+    let pathname = || "/a".to_string();
+    for dedup in [false, true] {
+        let mut vfs = Vfs::new();
+        vfs.set_dedup(dedup);
+        let mut model = Model::new();
+        let mut bytes = data.iter().copied();
+        let mut next = || bytes.next();
+        let mut i = 0;
+        step(
+            &mut vfs,
+            &mut model,
+            i,
+            &Op::Create {
+                pathname: pathname(),
+            },
+        )?;
+        while let Some(tag) = next() {
+            let Some(arg) = next() else { break };
+            i += 1;
+            let fd = (arg % MAX_FDS) as usize;
+            let op = match tag % 8 {
+                0 => Op::Open {
+                    pathname: pathname(),
+                },
+                1 => Op::Close { fd },
+                2 => Op::Seek {
+                    fd,
+                    offset: next().unwrap_or_default() as usize * 9,
+                },
+                3 => Op::Write {
+                    fd,
+                    data: vec![arg; next().unwrap_or_default() as usize * 9],
+                },
+                4 => Op::Truncate {
+                    pathname: pathname(),
+                    size: next().unwrap_or_default() as usize * 13,
+                },
+                5 => Op::Unlink {
+                    pathname: pathname(),
+                },
+                6 => Op::Create {
+                    pathname: pathname(),
+                },
+                _ => {
+                    let size = next().unwrap_or_default() as usize * 9;
+                    let actual = vfs.read(fd, size).ok();
+                    let expected = model.read(fd, size);
+                    if actual != expected {
+                        return Err(format!(
+                            "read {} of {} bytes{}: vfs returned {:?}, model {:?}",
+                            fd,
+                            size,
+                            if dedup { " with dedup" } else { "" },
+                            actual,
+                            expected
+                        ));
+                    }
+                    if let Err(reason) = vfs.check_accounting() {
+                        return Err(format!("read {} of {} bytes: {}", fd, size, reason));
+                    }
+                    continue;
+                }
+            };
+            step(&mut vfs, &mut model, i, &op)?;
+        }
+    }
+    Ok(())
+}
+
+impl Vfs {
+    /// Check that link counts match directory entries, block reference
+    /// counts match the files using each block, files hold exactly the
+    /// blocks their size covers and no descriptor points past the end of
+    /// its file.
+    fn check_accounting(&self) -> Result<(), String> {
+        for (&oid, &(id, cursor)) in &self.open_fds {
+            let fd = &self.fds[id];
+            if fd.file_type.is_file() && cursor > fd.size {
+                return Err(format!(
+                    "descriptor {} is at {}, past the end of inode {} at {}",
+                    oid, cursor, id, fd.size
+                ));
+            }
+        }
+        let mut links = vec![0; self.fds.len()];
+        let mut blocks = HashMap::new();
+        for (id, fd) in self.fds.iter().enumerate() {
+            if self.fds_id.free.contains(&id) {
+                continue;
+            }
+            match &fd.file_type {
+                FileType::Directory(entries) => {
+                    for (name, &child_id) in entries {
+                        if name != DOT && name != DOTDOT {
+                            links[child_id] += 1;
+                        }
+                    }
+                }
+                FileType::Regular(blocks_refs) => {
+                    if blocks_refs.len() != fd.size.div_ceil(BLOCK_SIZE) {
+                        return Err(format!(
+                            "inode {} of {} bytes holds {} blocks",
+                            id,
+                            fd.size,
+                            blocks_refs.len()
+                        ));
+                    }
+                    for &block_id in blocks_refs.iter().filter(|&&block_id| block_id != 0) {
+                        *blocks.entry(block_id).or_insert(0) += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        for (id, fd) in self.fds.iter().enumerate() {
+            if self.fds_id.free.contains(&id) || fd.file_type.is_dir() {
+                continue;
+            }
+            if fd.links != links[id] {
+                return Err(format!(
+                    "inode {} has {} links but {} entries",
+                    id, fd.links, links[id]
+                ));
+            }
+            if fd.links == 0 && fd.refs == 0 {
+                return Err(format!("inode {} is unreachable but not freed", id));
+            }
+        }
+        let stats = self.blocks.dedup_stats();
+        let referenced: usize = blocks.values().sum();
+        if stats.logical_blocks != referenced || stats.physical_blocks != blocks.len() {
+            return Err(format!(
+                "block store counts {} references to {} blocks, files hold {} to {}",
+                stats.logical_blocks,
+                stats.physical_blocks,
+                referenced,
+                blocks.len()
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_model(&self, model: &Model) -> Result<(), String> {
+        let mut dirs = vec!["/".to_string()];
+        while let Some(dir) = dirs.pop() {
+            let expected = model.children(&dir);
+            let actual: BTreeSet<_> = self
+                .ls(&dir)?
+                .map(|entry| entry.name)
+                .filter(|name| name != DOT && name != DOTDOT)
+                .collect();
+            if actual != expected {
+                return Err(format!(
+                    "'{}' lists {:?}, model has {:?}",
+                    dir, actual, expected
+                ));
+            }
+            for name in expected {
+                let path = match dir.as_str() {
+                    "/" => format!("/{}", name),
+                    _ => format!("{}/{}", dir, name),
+                };
+                let node = model.node(&path).unwrap();
+                let (fd, _, _) = self.resolve(&path).unwrap();
+                match &node.kind {
+                    Kind::Dir if fd.file_type.is_dir() => dirs.push(path),
+                    Kind::File(data) if fd.file_type.is_file() => {
+                        if fd.links != node.links {
+                            return Err(format!(
+                                "'{}' has {} links, model has {}",
+                                path, fd.links, node.links
+                            ));
+                        }
+                        if self.read_file(&path)? != *data {
+                            return Err(format!("'{}' differs from the model", path));
+                        }
+                    }
+                    _ => return Err(format!("'{}' has the wrong file type", path)),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run `ops` against a fresh `Vfs` and the model, failing on the first
+/// operation whose outcome or resulting state differs.
+pub fn check_against_model(ops: &[Op]) -> Result<(), String> {
+    let mut vfs = Vfs::new();
+    let mut model = Model::new();
+    for (i, op) in ops.iter().enumerate() {
+        step(&mut vfs, &mut model, i, op)?;
+    }
+    Ok(())
+}
+
+/// Apply operation `i` to both `vfs` and `model`, failing if the outcomes
+/// or the resulting states differ.
+fn step(vfs: &mut Vfs, model: &mut Model, i: usize, op: &Op) -> Result<(), String> {
+    let actual = vfs.apply_op(op);
+    let expected = model.apply(op);
+    let fail = |reason: String| Err(format!("op {} '{}': {}", i, op, reason));
+    if actual.is_ok() != expected {
+        return fail(format!(
+            "vfs returned {:?}, model {}",
+            actual,
+            if expected { "succeeded" } else { "failed" }
+        ));
+    }
+    if let Err(reason) = vfs.check_accounting() {
+        return fail(reason);
+    }
+    if let Err(reason) = vfs.check_model(model) {
+        return fail(reason);
+    }
+    Ok(())
+}
@@ -0,0 +1,162 @@
+use std::fmt;
+
+use crate::{FileType, Vfs, DOTDOT, PATHNAME_SEPARATOR, SYMLINK_RESOLVE_LIMIT, TRAILING_SEPARATOR};
+
+/// Why resolution stopped, as `ResolveTrace::error` gives it.
+pub(crate) const NOT_FOUND: &str = "No such file or directory";
+pub(crate) const LOOP: &str = "Too many levels of symbolic links";
+pub(crate) const NOT_A_DIRECTORY: &str = "Not a directory";
+
+pub(crate) fn join(pathname: &str, name: &str) -> String {
+    if pathname.ends_with(TRAILING_SEPARATOR) {
+        format!("{}{}", pathname, name)
+    } else {
+        format!("{}{}{}", pathname, PATHNAME_SEPARATOR, name)
+    }
+}
+
+/// One component looked up while resolving a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolveStep {
+    pub name: String,
+    pub inode: usize,
+    /// The file type as `ls -l` shows it, e.g. `d`.
+    pub type_char: char,
+    /// What a symlink pointed to; the components that follow come from it.
+    pub target: Option<String>,
+    /// How many symlinks deep the component is.
+    pub depth: usize,
+}
+
+/// Every step of resolving a path, and where it failed if it did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolveTrace {
+    pub pathname: String,
+    pub steps: Vec<ResolveStep>,
+    /// The component that could not be looked up, its depth and why.
+    pub error: Option<(String, usize, String)>,
+}
+
+/// The `namei(1)` form: one line per component, indented under the
+/// symlink it came from.
+impl fmt::Display for ResolveTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "f: {}", self.pathname)?;
+        for step in &self.steps {
+            write!(
+                f,
+                "\n{:indent$} {} {}",
+                "",
+                step.type_char,
+                step.name,
+                indent = 2 * step.depth
+            )?;
+            if let Some(target) = &step.target {
+                write!(f, " -> {}", target)?;
+            }
+        }
+        if let Some((name, depth, error)) = &self.error {
+            write!(
+                f,
+                "\n{:indent$}   {} - {}",
+                "",
+                name,
+                error,
+                indent = 2 * depth
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Vfs {
+    /// Resolve `pathname` like `namei(1)`, recording each component
+    /// looked up with its inode and type and the symlinks expanded on the
+    /// way, including one in the last component. Resolution stops at the
+    /// first component that cannot be looked up.
+    pub fn trace_resolve(&self, pathname: &str) -> ResolveTrace {
+        let mut trace = ResolveTrace {
+            pathname: pathname.to_string(),
+            steps: Vec::new(),
+            error: None,
+        };
+        let absolute = self.bind_path(pathname);
+        let pathname = absolute.as_deref().unwrap_or(pathname);
+        let mut dir_id = if Vfs::is_absolute(pathname) {
+            trace.steps.push(Self::root_step(0));
+            0
+        } else {
+            self.session.cwd_id
+        };
+        let mut segments: Vec<(String, usize)> = Vfs::segmentize(pathname, true)
+            .into_iter()
+            .map(|seg| (seg.to_string(), 0))
+            .collect();
+        let mut symlink_resolve_count = 0;
+        let mut binds = Vec::new();
+        while let Some((seg, depth)) = segments.pop() {
+            let entry = match seg.as_str() {
+                DOTDOT => Some(self.bind_parent(dir_id, &mut binds)),
+                _ => self.fds[dir_id].file_type.as_dir().get(&seg).copied(),
+            };
+            let Some(mut id) = entry else {
+                let error = NOT_FOUND.to_string();
+                trace.error = Some((seg, depth, error));
+                break;
+            };
+            if let Some(bind) = self.bind_at(id, &binds) {
+                binds.push(bind);
+                id = bind.source_id;
+            }
+            let file_type = &self.fds[id].file_type;
+            let target = match file_type {
+                FileType::Symlink(path) => Some(path.clone()),
+                _ => None,
+            };
+            trace.steps.push(ResolveStep {
+                name: seg.clone(),
+                inode: id,
+                type_char: file_type.type_char(),
+                target: target.clone(),
+                depth,
+            });
+            match (file_type, target) {
+                (FileType::Directory(_), _) => dir_id = id,
+                (_, Some(path)) => {
+                    if symlink_resolve_count >= SYMLINK_RESOLVE_LIMIT
+                        || self.is_nosymfollow_dir(dir_id)
+                    {
+                        let error = LOOP.to_string();
+                        trace.error = Some((seg, depth, error));
+                        break;
+                    }
+                    symlink_resolve_count += 1;
+                    if Vfs::is_absolute(&path) {
+                        trace.steps.push(Self::root_step(depth + 1));
+                        dir_id = 0;
+                        binds.clear();
+                    }
+                    let expanded = Vfs::segmentize(&path, true);
+                    segments.extend(expanded.into_iter().map(|seg| (seg.to_string(), depth + 1)));
+                }
+                _ if !segments.is_empty() => {
+                    let (next, depth) = segments.pop().unwrap();
+                    trace.error = Some((next, depth, NOT_A_DIRECTORY.to_string()));
+                    break;
+                }
+                _ => {}
+            }
+        }
+        trace
+    }
+
+    fn root_step(depth: usize) -> ResolveStep {
+        ResolveStep {
+            name: PATHNAME_SEPARATOR.to_string(),
+            inode: 0,
+            type_char: 'd',
+            target: None,
+            depth,
+        }
+    }
+}
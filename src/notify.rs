@@ -0,0 +1,240 @@
+use std::{collections::BTreeMap, fmt};
+
+use crate::{ErrorKind, Op, Vfs, VfsError, PATHNAME_SEPARATOR};
+
+/// Events kept for `read_events` before further ones are dropped.
+const MAX_EVENTS: usize = 16384;
+
+/// What happened to a watched path, after the `inotify(7)` event names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Create,
+    Delete,
+    Modify,
+    Attrib,
+    MovedFrom,
+    MovedTo,
+    /// A file unlinked while open was closed for the last time and its
+    /// space freed; the path is that of its last link.
+    Reclaim,
+    /// The queue was full and later events were lost.
+    Overflow,
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            EventKind::Create => "CREATE",
+            EventKind::Delete => "DELETE",
+            EventKind::Modify => "MODIFY",
+            EventKind::Attrib => "ATTRIB",
+            EventKind::MovedFrom => "MOVED_FROM",
+            EventKind::MovedTo => "MOVED_TO",
+            EventKind::Reclaim => "RECLAIM",
+            EventKind::Overflow => "Q_OVERFLOW",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A change at or below a watched path, e.g. `1 CREATE /d/a.txt`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// The watch descriptor `Vfs::watch` returned.
+    pub wd: usize,
+    pub kind: EventKind,
+    /// The absolute path that changed, without symlinks resolved.
+    pub path: String,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            EventKind::Overflow => write!(f, "{} {}", self.wd, self.kind),
+            _ => write!(f, "{} {} {}", self.wd, self.kind, self.path),
+        }
+    }
+}
+
+/// The watches of a filesystem and the events waiting to be read.
+#[derive(Debug, Clone, Default)]
+pub struct Watches {
+    paths: BTreeMap<usize, String>,
+    next_wd: usize,
+    events: Vec<Event>,
+}
+
+impl Watches {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+}
+
+/// `pathname` as an absolute path relative to `cwd`, with `.` and `..`
+/// removed lexically.
+pub(crate) fn absolute(cwd: &str, pathname: &str) -> String {
+    let joined = if Vfs::is_absolute(pathname) {
+        pathname.to_string()
+    } else {
+        format!("{}/{}", cwd, pathname)
+    };
+    let mut segments = Vec::new();
+    for segment in joined.split(PATHNAME_SEPARATOR) {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    format!("/{}", segments.join("/"))
+}
+
+/// Whether `path` is `dir` or below it.
+fn is_below(path: &str, dir: &str) -> bool {
+    dir == "/"
+        || path
+            .strip_prefix(dir)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+impl Vfs {
+    /// Report changes to `pathname` and everything below it, like
+    /// `inotify_add_watch(2)` on a whole subtree. Returns a watch
+    /// descriptor for `unwatch`; changes are collected by `read_events`.
+    pub fn watch(&mut self, pathname: &str) -> Result<usize, VfsError> {
+        if self.resolve(pathname).is_none() {
+            return Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!(
+                    "watch: cannot watch '{}': No such file or directory",
+                    pathname
+                ),
+            ));
+        }
+        let watches = &mut self.watches;
+        watches.next_wd += 1;
+        let path = absolute(self.session.cwd(), pathname);
+        watches.paths.insert(watches.next_wd, path);
+        Ok(watches.next_wd)
+    }
+
+    /// Stop the watch `wd`; events already queued for it stay readable.
+    pub fn unwatch(&mut self, wd: usize) -> Result<(), VfsError> {
+        match self.watches.paths.remove(&wd) {
+            Some(_) => Ok(()),
+            None => Err(VfsError::new(
+                ErrorKind::InvalidInput,
+                format!("unwatch: no such watch: {}", wd),
+            )),
+        }
+    }
+
+    /// The watched paths by watch descriptor.
+    pub fn watched(&self) -> &BTreeMap<usize, String> {
+        &self.watches.paths
+    }
+
+    /// Take the events queued since the last call, oldest first.
+    pub fn read_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.watches.events)
+    }
+
+    /// Remove the watches and queued events, to carry them over to a
+    /// filesystem that replaces this one with `set_watches`.
+    pub fn take_watches(&mut self) -> Watches {
+        std::mem::take(&mut self.watches)
+    }
+
+    pub fn set_watches(&mut self, watches: Watches) {
+        self.watches = watches;
+    }
+
+    /// Queue events for the paths a successful `op` changed.
+    pub(crate) fn notify(&mut self, op: &Op) {
+        let changes: Vec<(EventKind, &str)> = match op {
+            Op::Create { pathname }
+            | Op::Mkdir { pathname }
+            | Op::Mkfifo { pathname }
+            | Op::Symlink { pathname, .. } => vec![(EventKind::Create, pathname)],
+            Op::Link { pathname2, .. } => vec![(EventKind::Create, pathname2)],
+            Op::Rmdir { pathname } | Op::Unlink { pathname } => {
+                vec![(EventKind::Delete, pathname)]
+            }
+            Op::Rename {
+                pathname1,
+                pathname2,
+            } => vec![
+                (EventKind::MovedFrom, pathname1),
+                (EventKind::MovedTo, pathname2),
+            ],
+            Op::Truncate { pathname, .. } => vec![(EventKind::Modify, pathname)],
+            Op::Mount { mountpoint, .. }
+            | Op::Bind {
+                target: mountpoint, ..
+            }
+            | Op::Graft {
+                target: mountpoint, ..
+            }
+            | Op::Unmount { mountpoint } => {
+                vec![(EventKind::Modify, mountpoint)]
+            }
+            Op::Chmod { pathname, .. }
+            | Op::Chown { pathname, .. }
+            | Op::SetAcl { pathname, .. }
+            | Op::Chattr { pathname, .. }
+            | Op::SetProject { pathname, .. }
+            | Op::Utimens { pathname, .. } => vec![(EventKind::Attrib, pathname)],
+            Op::Write { fd, .. } | Op::Reserve { fd, .. } => {
+                let path = self.open_fds.get(fd).and_then(|&(id, _)| self.path_of(id));
+                match path {
+                    Some(path) => return self.queue(EventKind::Modify, path),
+                    None => return,
+                }
+            }
+            Op::Open { .. }
+            | Op::Close { .. }
+            | Op::Read { .. }
+            | Op::Stat { .. }
+            | Op::Seek { .. }
+            | Op::Fcntl { .. }
+            | Op::Seal { .. }
+            | Op::Cd { .. }
+            | Op::CreateRoot
+            | Op::RemoveRoot { .. }
+            | Op::Begin
+            | Op::Commit
+            | Op::Rollback => return,
+        };
+        let paths: Vec<_> = changes
+            .into_iter()
+            .map(|(kind, pathname)| (kind, absolute(self.session.cwd(), pathname)))
+            .collect();
+        for (kind, path) in paths {
+            self.queue(kind, path);
+        }
+    }
+
+    pub(crate) fn queue(&mut self, kind: EventKind, path: String) {
+        let watches = &mut self.watches;
+        for (&wd, dir) in &watches.paths {
+            if !is_below(&path, dir) {
+                continue;
+            }
+            match watches.events.len() {
+                len if len < MAX_EVENTS => watches.events.push(Event {
+                    wd,
+                    kind,
+                    path: path.clone(),
+                }),
+                MAX_EVENTS => watches.events.push(Event {
+                    wd,
+                    kind: EventKind::Overflow,
+                    path: String::new(),
+                }),
+                _ => {}
+            }
+        }
+    }
+}
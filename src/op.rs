@@ -0,0 +1,559 @@
+use std::{fmt, str::FromStr};
+
+use crate::{
+    attr_string, fd_flags_string, parse_attrs, parse_fd_flags, parse_seals, seals_string, AclEntry,
+    BindOptions, FileHandle, MountOptions, Reply, SetTime, Vfs, VfsError,
+};
+
+/// A single filesystem operation, as recorded by the audit log and
+/// executed in batches by `Vfs::apply`. `Read` and `Stat` change nothing
+/// and are never recorded.
+///
+/// The textual form is one line per operation: the command name followed by
+/// its arguments, with strings quoted and escaped and data hex encoded, e.g.
+/// `write 0 68656c6c6f`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Create {
+        pathname: String,
+    },
+    Mkdir {
+        pathname: String,
+    },
+    Rmdir {
+        pathname: String,
+    },
+    Link {
+        pathname1: String,
+        pathname2: String,
+    },
+    Unlink {
+        pathname: String,
+    },
+    Rename {
+        pathname1: String,
+        pathname2: String,
+    },
+    Symlink {
+        path: String,
+        pathname: String,
+    },
+    Mkfifo {
+        pathname: String,
+    },
+    Open {
+        pathname: String,
+    },
+    Close {
+        fd: usize,
+    },
+    Seek {
+        fd: usize,
+        offset: usize,
+    },
+    Fcntl {
+        fd: usize,
+        flags: u32,
+    },
+    Seal {
+        fd: usize,
+        seals: u32,
+    },
+    Write {
+        fd: usize,
+        data: Vec<u8>,
+    },
+    Read {
+        fd: usize,
+        size: usize,
+    },
+    Stat {
+        pathname: String,
+    },
+    Reserve {
+        fd: usize,
+        len: usize,
+    },
+    Truncate {
+        pathname: String,
+        size: usize,
+    },
+    Cd {
+        pathname: String,
+    },
+    Chmod {
+        pathname: String,
+        mode: u32,
+    },
+    Chown {
+        pathname: String,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    },
+    SetAcl {
+        pathname: String,
+        acl: Vec<AclEntry>,
+    },
+    Chattr {
+        pathname: String,
+        attrs: u32,
+    },
+    SetProject {
+        pathname: String,
+        project: u32,
+    },
+    Utimens {
+        pathname: String,
+        atime: SetTime,
+        mtime: SetTime,
+        follow: bool,
+    },
+    Mount {
+        image: String,
+        mountpoint: String,
+        options: MountOptions,
+    },
+    Bind {
+        source: String,
+        target: String,
+        options: BindOptions,
+    },
+    Unmount {
+        mountpoint: String,
+    },
+    CreateRoot,
+    Graft {
+        root: FileHandle,
+        target: String,
+        options: BindOptions,
+    },
+    RemoveRoot {
+        root: FileHandle,
+    },
+    Begin,
+    Commit,
+    Rollback,
+}
+
+pub(crate) fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn unescape(chars: &mut std::str::Chars) -> Option<String> {
+    let mut value = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                '0' => value.push('\0'),
+                'u' => {
+                    if chars.next()? != '{' {
+                        return None;
+                    }
+                    let code: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    value.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+}
+
+/// Split a line into bare words and `Debug`-quoted strings.
+pub(crate) fn tokenize(line: &str) -> Option<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars();
+    let mut word = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if word.is_empty() => tokens.push(unescape(&mut chars)?),
+            ' ' => {
+                if !word.is_empty() {
+                    tokens.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    Some(tokens)
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Create { pathname } => write!(f, "create {:?}", pathname),
+            Op::Mkdir { pathname } => write!(f, "mkdir {:?}", pathname),
+            Op::Rmdir { pathname } => write!(f, "rmdir {:?}", pathname),
+            Op::Link {
+                pathname1,
+                pathname2,
+            } => write!(f, "link {:?} {:?}", pathname1, pathname2),
+            Op::Unlink { pathname } => write!(f, "unlink {:?}", pathname),
+            Op::Rename {
+                pathname1,
+                pathname2,
+            } => write!(f, "rename {:?} {:?}", pathname1, pathname2),
+            Op::Symlink { path, pathname } => write!(f, "symlink {:?} {:?}", path, pathname),
+            Op::Mkfifo { pathname } => write!(f, "mkfifo {:?}", pathname),
+            Op::Open { pathname } => write!(f, "open {:?}", pathname),
+            Op::Close { fd } => write!(f, "close {}", fd),
+            Op::Seek { fd, offset } => write!(f, "seek {} {}", fd, offset),
+            Op::Fcntl { fd, flags } => write!(f, "fcntl {} {}", fd, fd_flags_string(*flags)),
+            Op::Seal { fd, seals } => write!(f, "seal {} {}", fd, seals_string(*seals)),
+            Op::Write { fd, data } => write!(f, "write {} {}", fd, hex(data)),
+            Op::Read { fd, size } => write!(f, "read {} {}", fd, size),
+            Op::Stat { pathname } => write!(f, "stat {:?}", pathname),
+            Op::Reserve { fd, len } => write!(f, "reserve {} {}", fd, len),
+            Op::Truncate { pathname, size } => write!(f, "truncate {:?} {}", pathname, size),
+            Op::Cd { pathname } => write!(f, "cd {:?}", pathname),
+            Op::Chmod { pathname, mode } => write!(f, "chmod {:?} {:o}", pathname, mode),
+            Op::Chown { pathname, uid, gid } => {
+                let id = |id: &Option<u32>| id.map_or("-".to_string(), |id| id.to_string());
+                write!(f, "chown {:?} {} {}", pathname, id(uid), id(gid))
+            }
+            Op::SetAcl { pathname, acl } => {
+                let acl: Vec<_> = acl.iter().map(AclEntry::to_string).collect();
+                write!(f, "setfacl {:?} {}", pathname, acl.join(","))
+            }
+            Op::Chattr { pathname, attrs } => {
+                write!(f, "chattr {:?} {}", pathname, attr_string(*attrs))
+            }
+            Op::SetProject { pathname, project } => {
+                write!(f, "chproj {:?} {}", pathname, project)
+            }
+            Op::Utimens {
+                pathname,
+                atime,
+                mtime,
+                follow,
+            } => {
+                let follow = if *follow { "follow" } else { "nofollow" };
+                write!(f, "utimens {:?} {} {} {}", pathname, atime, mtime, follow)
+            }
+            Op::Mount {
+                image,
+                mountpoint,
+                options,
+            } => write!(f, "mount {:?} {:?} {}", image, mountpoint, options),
+            Op::Bind {
+                source,
+                target,
+                options,
+            } => write!(f, "bind {:?} {:?} {}", source, target, options),
+            Op::Unmount { mountpoint } => write!(f, "umount {:?}", mountpoint),
+            Op::CreateRoot => write!(f, "mkroot"),
+            Op::Graft {
+                root,
+                target,
+                options,
+            } => write!(f, "graft {} {:?} {}", root, target, options),
+            Op::RemoveRoot { root } => write!(f, "rmroot {}", root),
+            Op::Begin => write!(f, "begin"),
+            Op::Commit => write!(f, "commit"),
+            Op::Rollback => write!(f, "rollback"),
+        }
+    }
+}
+
+impl Op {
+    /// Build an operation from already tokenized arguments, returning it
+    /// together with the number of tokens consumed.
+    pub(crate) fn from_tokens(tokens: &[String]) -> Result<(Self, usize), String> {
+        let invalid = || format!("invalid operation: {}", tokens.join(" "));
+        let arg = |i: usize| tokens.get(i).cloned().ok_or_else(invalid);
+        let num = |i: usize| arg(i)?.parse::<usize>().map_err(|_| invalid());
+        let id = |i: usize| match arg(i)?.as_str() {
+            "-" => Ok(None),
+            id => id.parse::<u32>().map(Some).map_err(|_| invalid()),
+        };
+        let op = match tokens.first().map(String::as_str) {
+            Some("create") => (Op::Create { pathname: arg(1)? }, 2),
+            Some("mkdir") => (Op::Mkdir { pathname: arg(1)? }, 2),
+            Some("rmdir") => (Op::Rmdir { pathname: arg(1)? }, 2),
+            Some("link") => (
+                Op::Link {
+                    pathname1: arg(1)?,
+                    pathname2: arg(2)?,
+                },
+                3,
+            ),
+            Some("unlink") => (Op::Unlink { pathname: arg(1)? }, 2),
+            Some("rename") => (
+                Op::Rename {
+                    pathname1: arg(1)?,
+                    pathname2: arg(2)?,
+                },
+                3,
+            ),
+            Some("symlink") => (
+                Op::Symlink {
+                    path: arg(1)?,
+                    pathname: arg(2)?,
+                },
+                3,
+            ),
+            Some("mkfifo") => (Op::Mkfifo { pathname: arg(1)? }, 2),
+            Some("open") => (Op::Open { pathname: arg(1)? }, 2),
+            Some("close") => (Op::Close { fd: num(1)? }, 2),
+            Some("seek") => (
+                Op::Seek {
+                    fd: num(1)?,
+                    offset: num(2)?,
+                },
+                3,
+            ),
+            Some("fcntl") => (
+                Op::Fcntl {
+                    fd: num(1)?,
+                    flags: parse_fd_flags(&arg(2)?).ok_or_else(invalid)?,
+                },
+                3,
+            ),
+            Some("seal") => (
+                Op::Seal {
+                    fd: num(1)?,
+                    seals: parse_seals(&arg(2)?).ok_or_else(invalid)?,
+                },
+                3,
+            ),
+            Some("write") => (
+                Op::Write {
+                    fd: num(1)?,
+                    data: unhex(&arg(2)?).ok_or_else(invalid)?,
+                },
+                3,
+            ),
+            Some("read") => (
+                Op::Read {
+                    fd: num(1)?,
+                    size: num(2)?,
+                },
+                3,
+            ),
+            Some("stat") => (Op::Stat { pathname: arg(1)? }, 2),
+            Some("reserve") => (
+                Op::Reserve {
+                    fd: num(1)?,
+                    len: num(2)?,
+                },
+                3,
+            ),
+            Some("truncate") => (
+                Op::Truncate {
+                    pathname: arg(1)?,
+                    size: num(2)?,
+                },
+                3,
+            ),
+            Some("cd") => (Op::Cd { pathname: arg(1)? }, 2),
+            Some("chmod") => (
+                Op::Chmod {
+                    pathname: arg(1)?,
+                    mode: u32::from_str_radix(&arg(2)?, 8).map_err(|_| invalid())?,
+                },
+                3,
+            ),
+            Some("chown") => (
+                Op::Chown {
+                    pathname: arg(1)?,
+                    uid: id(2)?,
+                    gid: id(3)?,
+                },
+                4,
+            ),
+            Some("setfacl") => (
+                Op::SetAcl {
+                    pathname: arg(1)?,
+                    acl: arg(2)?
+                        .split(',')
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| invalid())?,
+                },
+                3,
+            ),
+            Some("chattr") => (
+                Op::Chattr {
+                    pathname: arg(1)?,
+                    attrs: parse_attrs(&arg(2)?).ok_or_else(invalid)?,
+                },
+                3,
+            ),
+            Some("chproj") => (
+                Op::SetProject {
+                    pathname: arg(1)?,
+                    project: arg(2)?.parse().map_err(|_| invalid())?,
+                },
+                3,
+            ),
+            Some("utimens") => (
+                Op::Utimens {
+                    pathname: arg(1)?,
+                    atime: arg(2)?.parse().map_err(|_| invalid())?,
+                    mtime: arg(3)?.parse().map_err(|_| invalid())?,
+                    follow: match arg(4)?.as_str() {
+                        "follow" => true,
+                        "nofollow" => false,
+                        _ => return Err(invalid()),
+                    },
+                },
+                5,
+            ),
+            // Older logs left default options out.
+            Some("mount") => {
+                let options = match tokens.get(3) {
+                    Some(options) => Some(
+                        options
+                            .parse()
+                            .map_err(|err| format!("{}: {}", invalid(), err))?,
+                    ),
+                    None => None,
+                };
+                (
+                    Op::Mount {
+                        image: arg(1)?,
+                        mountpoint: arg(2)?,
+                        options: options.unwrap_or_default(),
+                    },
+                    if options.is_some() { 4 } else { 3 },
+                )
+            }
+            Some("bind") => (
+                Op::Bind {
+                    source: arg(1)?,
+                    target: arg(2)?,
+                    options: arg(3)?.parse().map_err(|_| invalid())?,
+                },
+                4,
+            ),
+            Some("umount") => (
+                Op::Unmount {
+                    mountpoint: arg(1)?,
+                },
+                2,
+            ),
+            Some("mkroot") => (Op::CreateRoot, 1),
+            Some("graft") => (
+                Op::Graft {
+                    root: arg(1)?.parse().map_err(|_| invalid())?,
+                    target: arg(2)?,
+                    options: arg(3)?.parse().map_err(|_| invalid())?,
+                },
+                4,
+            ),
+            Some("rmroot") => (
+                Op::RemoveRoot {
+                    root: arg(1)?.parse().map_err(|_| invalid())?,
+                },
+                2,
+            ),
+            Some("begin") => (Op::Begin, 1),
+            Some("commit") => (Op::Commit, 1),
+            Some("rollback") => (Op::Rollback, 1),
+            _ => return Err(invalid()),
+        };
+        Ok(op)
+    }
+}
+
+impl FromStr for Op {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(line).ok_or_else(|| format!("invalid operation: {}", line))?;
+        match Op::from_tokens(&tokens)? {
+            (op, consumed) if consumed == tokens.len() => Ok(op),
+            _ => Err(format!("invalid operation: {}", line)),
+        }
+    }
+}
+
+impl Vfs {
+    /// Execute `ops` in order, carrying on past failures, and return the
+    /// result of each, e.g. the descriptor an `Open` returned.
+    pub fn apply(&mut self, ops: &[Op]) -> Vec<Result<Reply, VfsError>> {
+        ops.iter().map(|op| self.apply_op(op)).collect()
+    }
+
+    pub(crate) fn apply_op(&mut self, op: &Op) -> Result<Reply, VfsError> {
+        match op {
+            Op::Create { pathname } => self.create(pathname)?,
+            Op::Mkdir { pathname } => self.mkdir(pathname)?,
+            Op::Rmdir { pathname } => self.rmdir(pathname)?,
+            Op::Link {
+                pathname1,
+                pathname2,
+            } => self.link(pathname1, pathname2)?,
+            Op::Unlink { pathname } => self.unlink(pathname)?,
+            Op::Rename {
+                pathname1,
+                pathname2,
+            } => return self.rename(pathname1, pathname2).map(Reply::Renamed),
+            Op::Symlink { path, pathname } => self.symlink(path, pathname)?,
+            Op::Mkfifo { pathname } => self.mkfifo(pathname)?,
+            Op::Open { pathname } => return self.open(pathname).map(Reply::Fd),
+            Op::Close { fd } => self.close(*fd)?,
+            Op::Seek { fd, offset } => self.seek(*fd, *offset)?,
+            Op::Fcntl { fd, flags } => self.set_fd_flags(*fd, *flags)?,
+            Op::Seal { fd, seals } => self.add_seals(*fd, *seals)?,
+            Op::Write { fd, data } => return self.write(*fd, data).map(Reply::Written),
+            Op::Read { fd, size } => return self.read(*fd, *size).map(Reply::Data),
+            Op::Stat { pathname } => {
+                return self.stat(pathname).map(|stat| Reply::Stat(stat.into()))
+            }
+            Op::Reserve { fd, len } => self.reserve(*fd, *len)?,
+            Op::Truncate { pathname, size } => self.truncate(pathname, *size)?,
+            Op::Cd { pathname } => self.cd(pathname)?,
+            Op::Chmod { pathname, mode } => self.chmod(pathname, *mode)?,
+            Op::Chown { pathname, uid, gid } => self.chown(pathname, *uid, *gid)?,
+            Op::SetAcl { pathname, acl } => self.set_acl(pathname, acl)?,
+            Op::Chattr { pathname, attrs } => self.set_attrs(pathname, *attrs)?,
+            Op::SetProject { pathname, project } => self.set_project(pathname, *project)?,
+            Op::Utimens {
+                pathname,
+                atime,
+                mtime,
+                follow,
+            } => self.utimens(pathname, *atime, *mtime, *follow)?,
+            Op::Mount {
+                image,
+                mountpoint,
+                options,
+            } => self.mount_image_with(image, mountpoint, *options)?,
+            Op::Bind {
+                source,
+                target,
+                options,
+            } => self.bind_mount_with(source, target, *options)?,
+            Op::Unmount { mountpoint } => self.unmount(mountpoint)?,
+            Op::CreateRoot => return self.create_root().map(Reply::Root),
+            Op::Graft {
+                root,
+                target,
+                options,
+            } => self.graft_with(*root, target, *options)?,
+            Op::RemoveRoot { root } => self.remove_root(*root)?,
+            Op::Begin => self.begin()?,
+            Op::Commit => self.commit()?,
+            Op::Rollback => self.rollback()?,
+        }
+        Ok(Reply::Done)
+    }
+}
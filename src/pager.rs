@@ -0,0 +1,247 @@
+//! A `less`-like pager for commands whose output may not fit on the screen.
+
+use std::{
+    fmt,
+    io::{self, Write},
+};
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{Print, PrintStyledContent, Stylize},
+    terminal::{self, ClearType},
+};
+
+/// Where a pageable command sends its output, one line at a time.
+pub struct Output {
+    /// Lines kept for the pager, or `None` to print them as they come.
+    lines: Option<Vec<String>>,
+}
+
+impl Output {
+    pub fn direct() -> Self {
+        Self { lines: None }
+    }
+
+    pub fn paged() -> Self {
+        Self {
+            lines: Some(Vec::new()),
+        }
+    }
+
+    pub fn line<T: fmt::Display>(&mut self, line: T) {
+        match &mut self.lines {
+            Some(lines) => lines.push(line.to_string()),
+            None => println!("{}", line),
+        }
+    }
+
+    /// Show the kept lines: printed if they fit in `rows`, in the pager
+    /// otherwise.
+    pub fn finish(self, rows: usize) -> io::Result<()> {
+        let Some(lines) = self.lines else {
+            return Ok(());
+        };
+        if lines.len() < rows {
+            for line in &lines {
+                println!("{}", line);
+            }
+            return Ok(());
+        }
+        Pager {
+            lines,
+            top: 0,
+            pattern: None,
+            message: None,
+        }
+        .run()
+    }
+}
+
+/// Cut `line` to `width` columns for display, keeping color sequences,
+/// expanding tabs and showing other control characters as `?`.
+fn fit(line: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut column = 0;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' && chars.peek() == Some(&'[') {
+            out.push(c);
+            for c in chars.by_ref() {
+                out.push(c);
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+            continue;
+        }
+        let shown = match c {
+            '\t' => " ".repeat(8 - column % 8),
+            c if c.is_control() => "?".to_string(),
+            c => c.to_string(),
+        };
+        column += shown.chars().count();
+        if column > width {
+            break;
+        }
+        out.push_str(&shown);
+    }
+    if line.contains('\x1b') {
+        out.push_str("\x1b[0m");
+    }
+    out
+}
+
+/// `line` without color sequences, as searches see it.
+fn plain(line: &str) -> String {
+    let mut out = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' && chars.peek() == Some(&'[') {
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+struct Pager {
+    lines: Vec<String>,
+    /// Index of the first line on the screen.
+    top: usize,
+    /// The last pattern searched for.
+    pattern: Option<String>,
+    /// Shown in the status line until the next key.
+    message: Option<String>,
+}
+
+impl Pager {
+    fn run(mut self) -> io::Result<()> {
+        let mut out = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+        let result = self.interact(&mut out);
+        execute!(out, cursor::Show, terminal::LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()?;
+        result
+    }
+
+    /// Scroll with j/k, arrows, space, b, g and G, search with /, n and N,
+    /// and quit with q.
+    fn interact(&mut self, out: &mut impl Write) -> io::Result<()> {
+        loop {
+            let (width, height) = terminal::size()?;
+            let (width, rows) = (width as usize, (height as usize).saturating_sub(1).max(1));
+            self.top = self.top.min(self.lines.len().saturating_sub(rows));
+            self.draw(out, width, rows)?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            self.message = None;
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(())
+                }
+                KeyCode::Char('j') | KeyCode::Down | KeyCode::Enter => {
+                    self.top = self.top.saturating_add(1)
+                }
+                KeyCode::Char('k') | KeyCode::Up => self.top = self.top.saturating_sub(1),
+                KeyCode::Char(' ') | KeyCode::Char('f') | KeyCode::PageDown => {
+                    self.top = self.top.saturating_add(rows)
+                }
+                KeyCode::Char('b') | KeyCode::PageUp => self.top = self.top.saturating_sub(rows),
+                KeyCode::Char('g') | KeyCode::Home => self.top = 0,
+                KeyCode::Char('G') | KeyCode::End => self.top = usize::MAX,
+                KeyCode::Char('/') => {
+                    if let Some(pattern) = self.read_pattern(out, rows)? {
+                        if !pattern.is_empty() {
+                            self.pattern = Some(pattern);
+                        }
+                        self.search(true);
+                    }
+                }
+                KeyCode::Char('n') => self.search(true),
+                KeyCode::Char('N') => self.search(false),
+                _ => {}
+            }
+        }
+    }
+
+    fn draw(&self, out: &mut impl Write, width: usize, rows: usize) -> io::Result<()> {
+        queue!(out, terminal::Clear(ClearType::All))?;
+        for (row, line) in self.lines.iter().skip(self.top).take(rows).enumerate() {
+            queue!(out, cursor::MoveTo(0, row as u16), Print(fit(line, width)))?;
+        }
+        let status = match &self.message {
+            Some(message) => message.clone(),
+            None if self.top + rows >= self.lines.len() => "(END)".to_string(),
+            None => format!(
+                "lines {}-{} of {}",
+                self.top + 1,
+                self.top + rows,
+                self.lines.len()
+            ),
+        };
+        queue!(
+            out,
+            cursor::MoveTo(0, rows as u16),
+            PrintStyledContent(fit(&status, width).reverse())
+        )?;
+        out.flush()
+    }
+
+    /// Read a search pattern on the status line; empty to repeat the last
+    /// search, or `None` if the search is cancelled.
+    fn read_pattern(&self, out: &mut impl Write, rows: usize) -> io::Result<Option<String>> {
+        let mut pattern = String::new();
+        loop {
+            queue!(
+                out,
+                cursor::MoveTo(0, rows as u16),
+                terminal::Clear(ClearType::CurrentLine),
+                Print(format!("/{}", pattern))
+            )?;
+            out.flush()?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Enter => return Ok(Some(pattern)),
+                KeyCode::Esc => return Ok(None),
+                KeyCode::Backspace => {
+                    pattern.pop();
+                }
+                KeyCode::Char(c) => pattern.push(c),
+                _ => {}
+            }
+        }
+    }
+
+    /// Scroll to the next line after the top one, or the previous line
+    /// before it, that contains the pattern.
+    fn search(&mut self, forward: bool) {
+        let Some(pattern) = &self.pattern else {
+            self.message = Some("No previous search pattern".to_string());
+            return;
+        };
+        let matches = |&index: &usize| plain(&self.lines[index]).contains(pattern.as_str());
+        let found = if forward {
+            (self.top + 1..self.lines.len()).find(matches)
+        } else {
+            (0..self.top).rev().find(matches)
+        };
+        match found {
+            Some(index) => self.top = index,
+            None => self.message = Some("Pattern not found".to_string()),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::fmt::Write;
+
+use crate::{FileDescriptor, FileType, MountOptions, Times, Vfs, VfsError};
+
+/// A file under the `/proc`-style tree whose contents are generated from
+/// the live filesystem state each time it is read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ProcEntry {
+    Mounts,
+    Meminfo,
+    Fd(usize),
+}
+
+impl Vfs {
+    fn add_proc_entry(&mut self, dir_id: usize, name: &str, entry: ProcEntry) {
+        let id = self.alloc_fd(|_| FileDescriptor {
+            file_type: FileType::Proc(entry),
+            size: 0,
+            links: 1,
+            refs: 0,
+            generation: 0,
+            uid: 0,
+            gid: 0,
+            mode: 0o444,
+            acl: Vec::new(),
+            attrs: 0,
+            project: 0,
+            times: Times::default(),
+        });
+        let entries = self.fds[dir_id].file_type.as_dir_mut();
+        entries.insert(name.to_string(), id);
+    }
+
+    /// Create `dirname` holding `mounts`, `meminfo` and a `fds` directory
+    /// with one entry per open file descriptor. Directories left over in a
+    /// loaded image are reused.
+    pub(crate) fn populate_proc(&mut self, dirname: &str) -> Result<(), VfsError> {
+        let dirname = dirname.trim_end_matches('/');
+        let fds = format!("{}/fds", dirname);
+        for dirname in [dirname, &fds] {
+            if self.resolve(dirname).is_none() {
+                self.mkdir(dirname)?;
+            }
+        }
+        let (_, dir_id, _) = self.resolve(dirname).unwrap();
+        for (name, entry) in [
+            ("mounts", ProcEntry::Mounts),
+            ("meminfo", ProcEntry::Meminfo),
+        ] {
+            if !self.fds[dir_id].file_type.as_dir().contains_key(name) {
+                self.add_proc_entry(dir_id, name, entry);
+            }
+        }
+        let (_, fds_id, _) = self.resolve(&fds).unwrap();
+        self.proc_fds = Some(fds_id);
+        for oid in self.open_fds.keys().copied().collect::<Vec<_>>() {
+            self.add_proc_entry(fds_id, &oid.to_string(), ProcEntry::Fd(oid));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn proc_open(&mut self, oid: usize) {
+        if let Some(fds_id) = self.proc_fds {
+            self.add_proc_entry(fds_id, &oid.to_string(), ProcEntry::Fd(oid));
+        }
+    }
+
+    pub(crate) fn proc_close(&mut self, oid: usize) {
+        if let Some(fds_id) = self.proc_fds {
+            let entries = self.fds[fds_id].file_type.as_dir_mut();
+            if let Some(id) = entries.remove(&oid.to_string()) {
+                self.fds[id].links -= 1;
+                self.free_fd(id);
+            }
+        }
+    }
+
+    pub(crate) fn is_proc_fds(&self, id: usize) -> bool {
+        self.proc_fds == Some(id)
+    }
+
+    pub(crate) fn proc_contents(&self, entry: &ProcEntry) -> Vec<u8> {
+        let mut out = String::new();
+        match entry {
+            ProcEntry::Mounts => {
+                let mode = if self.is_read_only() { "ro" } else { "rw" };
+                writeln!(out, "vfs / vfs {} 0 0", mode).unwrap();
+                for mount in &self.mounts {
+                    let options = match self.is_read_only() {
+                        true => MountOptions {
+                            read_only: true,
+                            ..mount.options
+                        },
+                        false => mount.options,
+                    };
+                    let (fstype, options) = match mount.archive {
+                        true => ("archive", options.to_string()),
+                        false => ("vfs", format!("{},loop", options)),
+                    };
+                    writeln!(
+                        out,
+                        "{} {} {} {} 0 0",
+                        mount.image, mount.mountpoint, fstype, options
+                    )
+                    .unwrap();
+                }
+                for (source, target, options) in self.binds() {
+                    let mode = match self.is_read_only() || options.read_only {
+                        true => "ro",
+                        false => "rw",
+                    };
+                    let kind = if options.recursive { "rbind" } else { "bind" };
+                    writeln!(out, "{} {} vfs {},{} 0 0", source, target, mode, kind).unwrap();
+                }
+            }
+            ProcEntry::Meminfo => {
+                let stats = self.blocks.dedup_stats();
+                let statfs = self.statfs();
+                writeln!(out, "BlockSize:    {:>10}", statfs.block_size).unwrap();
+                writeln!(out, "BlocksTotal:  {:>10}", statfs.blocks).unwrap();
+                writeln!(out, "BlocksUsed:   {:>10}", stats.physical_blocks).unwrap();
+                writeln!(out, "BlocksFree:   {:>10}", statfs.blocks_free).unwrap();
+                writeln!(
+                    out,
+                    "BlocksShared: {:>10}",
+                    stats.logical_blocks - stats.physical_blocks
+                )
+                .unwrap();
+                writeln!(out, "Inodes:       {:>10}", self.inodes_used()).unwrap();
+                writeln!(out, "InodesFree:   {:>10}", statfs.files_free).unwrap();
+                writeln!(out, "OpenFiles:    {:>10}", self.open_fds.len()).unwrap();
+            }
+            ProcEntry::Fd(oid) => {
+                if let Some((id, cursor)) = self.open_fds.get(oid) {
+                    writeln!(out, "pos:\t{}", cursor).unwrap();
+                    let flags = self.open_fd_flags.get(oid).copied().unwrap_or(0);
+                    writeln!(out, "flags:\t0{:o}", flags).unwrap();
+                    writeln!(out, "inode:\t{}", id).unwrap();
+                    if let Some(path) = self.path_of(*id) {
+                        writeln!(out, "path:\t{}", path).unwrap();
+                    }
+                }
+            }
+        }
+        out.into_bytes()
+    }
+}
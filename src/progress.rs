@@ -0,0 +1,124 @@
+use crate::{ErrorKind, VfsError};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// How much of a long-running operation is done so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// File contents read, written or hashed.
+    pub bytes: usize,
+    /// Files, directories and symlinks finished.
+    pub entries: usize,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Bytes: {} \tEntries: {}", self.bytes, self.entries)
+    }
+}
+
+/// A flag that asks an operation to stop; clones share it, so one can be
+/// handed to the operation and the other kept, e.g. by another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Progress reporting and cancellation for the `_with` variants of
+/// recursive copies, removals, exports and hashing. The callback runs
+/// after every block and every entry, and cancellation is checked then,
+/// so an operation stops within a block of being cancelled and fails with
+/// `Operation canceled`. What was done until then stays done.
+#[derive(Default)]
+pub struct Monitor<'a> {
+    progress: Progress,
+    on_progress: Option<Box<dyn FnMut(Progress) + 'a>>,
+    cancel: Option<CancelToken>,
+}
+
+impl fmt::Debug for Monitor<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Monitor")
+            .field("progress", &self.progress)
+            .field("cancel", &self.cancel)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> Monitor<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call `f` with the running totals as the operation advances.
+    pub fn on_progress<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(Progress) + 'a,
+    {
+        self.on_progress = Some(Box::new(f));
+        self
+    }
+
+    /// Stop once `token` is cancelled.
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// The totals so far; a monitor used for several operations keeps
+    /// counting.
+    pub fn progress(&self) -> Progress {
+        self.progress
+    }
+
+    /// Fail with `Operation canceled` after `context` if cancellation was
+    /// asked for.
+    pub(crate) fn check<F>(&self, context: F) -> Result<(), VfsError>
+    where
+        F: FnOnce() -> String,
+    {
+        match self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+            true => Err(VfsError::new(
+                ErrorKind::Canceled,
+                format!("{}: Operation canceled", context()),
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// Count `bytes` and `entries` more as done, report the totals, and
+    /// then `check`.
+    pub(crate) fn advance<F>(
+        &mut self,
+        bytes: usize,
+        entries: usize,
+        context: F,
+    ) -> Result<(), VfsError>
+    where
+        F: FnOnce() -> String,
+    {
+        self.progress.bytes += bytes;
+        self.progress.entries += entries;
+        if let Some(on_progress) = &mut self.on_progress {
+            on_progress(self.progress);
+        }
+        self.check(context)
+    }
+}
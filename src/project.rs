@@ -0,0 +1,195 @@
+use std::fmt;
+
+use crate::{format_size, ErrorKind, FileType, Op, Vfs, VfsError, DOT, DOTDOT};
+
+/// Limits on what the files of a project may use; `None` is unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProjectQuota {
+    /// Total size of the project's regular files.
+    pub max_bytes: Option<usize>,
+    pub max_inodes: Option<usize>,
+}
+
+/// What the files of a project use, as charged against its quota.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProjectUsage {
+    /// Total size of the project's regular files.
+    pub bytes: usize,
+    pub inodes: usize,
+}
+
+impl fmt::Display for ProjectQuota {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unlimited = || "unlimited".to_string();
+        write!(
+            f,
+            "{} in {} inodes",
+            self.max_bytes.map_or_else(unlimited, format_size),
+            self.max_inodes.map_or_else(unlimited, |n| n.to_string())
+        )
+    }
+}
+
+impl fmt::Display for ProjectUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} in {} inodes", format_size(self.bytes), self.inodes)
+    }
+}
+
+impl Vfs {
+    /// The project ID of `pathname`, 0 if it belongs to none.
+    pub fn project(&self, pathname: &str) -> Result<u32, VfsError> {
+        match self.resolve(pathname) {
+            Some((fd, _, _)) => Ok(fd.project),
+            None => Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!(
+                    "lsproj: cannot access '{}': No such file or directory",
+                    pathname
+                ),
+            )),
+        }
+    }
+
+    /// Assign `pathname` and everything below it to `project`, or to none
+    /// if it is 0, like `xfs_quota -x -c 'project -s'`. Files created later
+    /// in a directory inherit its project, and a file stays charged to its
+    /// project when it is renamed elsewhere. Only the superuser may assign
+    /// projects.
+    pub fn set_project(&mut self, pathname: &str, project: u32) -> Result<(), VfsError> {
+        let result = self.set_project_unaudited(pathname, project);
+        self.audit(
+            || Op::SetProject {
+                pathname: pathname.to_string(),
+                project,
+            },
+            &result,
+        );
+        result
+    }
+
+    fn set_project_unaudited(&mut self, pathname: &str, project: u32) -> Result<(), VfsError> {
+        let context = || format!("chproj: cannot set project of '{}'", pathname);
+        self.check_writable_at(pathname, context)?;
+        let id = match self.resolve(pathname) {
+            Some((_, id, _)) => id,
+            None => {
+                return Err(VfsError::new(
+                    ErrorKind::NotFound,
+                    format!("{}: No such file or directory", context()),
+                ))
+            }
+        };
+        if self.session.uid() != 0 {
+            return Err(VfsError::new(
+                ErrorKind::NotPermitted,
+                format!("{}: Operation not permitted", context()),
+            ));
+        }
+        self.check_mount_writable(id, context)?;
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if let FileType::Directory(entries) = &self.fds[id].file_type {
+                stack.extend(
+                    entries
+                        .iter()
+                        .filter(|(name, _)| *name != DOT && *name != DOTDOT)
+                        .map(|(_, &child_id)| child_id),
+                );
+            }
+            self.fds[id].project = project;
+            self.touch_changed(id);
+        }
+        Ok(())
+    }
+
+    /// Limit what the files of `project` may use, or lift the limits if
+    /// `quota` is `None`. Writes, truncations and file creations that
+    /// would exceed a limit fail with `Disk quota exceeded`. The limits
+    /// are kept in memory, not in images.
+    pub fn set_project_quota(&mut self, project: u32, quota: Option<ProjectQuota>) {
+        match quota {
+            Some(quota) => self.project_quotas.insert(project, quota),
+            None => self.project_quotas.remove(&project),
+        };
+    }
+
+    pub fn project_quota(&self, project: u32) -> Option<ProjectQuota> {
+        self.project_quotas.get(&project).copied()
+    }
+
+    /// What the files of `project` use, counting unlinked files that are
+    /// still open.
+    pub fn project_usage(&self, project: u32) -> ProjectUsage {
+        let mut usage = ProjectUsage::default();
+        for (id, fd) in self.fds.iter().enumerate() {
+            if fd.project != project
+                || self.fds_id.free.contains(&id)
+                || (fd.links == 0 && fd.refs == 0)
+            {
+                continue;
+            }
+            usage.inodes += 1;
+            if fd.file_type.is_file() {
+                usage.bytes += fd.size;
+            }
+        }
+        usage
+    }
+
+    /// Fail with `Disk quota exceeded` if a new file in directory `dir_id`
+    /// would take its project past the inode limit.
+    pub(crate) fn check_project_inodes<F>(&self, dir_id: usize, context: F) -> Result<(), VfsError>
+    where
+        F: FnOnce() -> String,
+    {
+        let project = self.fds[dir_id].project;
+        let Some(max_inodes) = self
+            .project_quota(project)
+            .and_then(|quota| quota.max_inodes)
+        else {
+            return Ok(());
+        };
+        if self.project_usage(project).inodes >= max_inodes {
+            return Err(VfsError::new(
+                ErrorKind::QuotaExceeded,
+                format!("{}: Disk quota exceeded", context()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Fail with `Disk quota exceeded` if growing file `id` to `size` bytes
+    /// would take its project past the byte limit.
+    pub(crate) fn check_project_bytes<F>(
+        &self,
+        id: usize,
+        size: usize,
+        context: F,
+    ) -> Result<(), VfsError>
+    where
+        F: FnOnce() -> String,
+    {
+        let fd = &self.fds[id];
+        let Some(max_bytes) = self
+            .project_quota(fd.project)
+            .and_then(|quota| quota.max_bytes)
+        else {
+            return Ok(());
+        };
+        let grown = size.saturating_sub(fd.size);
+        if grown > 0 && self.project_usage(fd.project).bytes + grown > max_bytes {
+            return Err(VfsError::new(
+                ErrorKind::QuotaExceeded,
+                format!("{}: Disk quota exceeded", context()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Charge the new inode `id` in directory `dir_id` to the directory's
+    /// project.
+    pub(crate) fn inherit_project(&mut self, dir_id: usize, id: usize) {
+        self.fds[id].project = self.fds[dir_id].project;
+    }
+}
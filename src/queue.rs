@@ -0,0 +1,96 @@
+use crate::{FileHandle, Renamed, Statx, Vfs, VfsError};
+
+/// One operation of a batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Open { pathname: String },
+    Close { fd: usize },
+    Seek { fd: usize, offset: usize },
+    Read { fd: usize, size: usize },
+    Write { fd: usize, data: Vec<u8> },
+    Stat { pathname: String },
+}
+
+/// What a successful request, or an `Op` passed to `Vfs::apply`,
+/// produced.
+#[derive(Debug)]
+pub enum Reply {
+    /// The descriptor `Open` returned.
+    Fd(usize),
+    /// The bytes `Read` returned.
+    Data(Vec<u8>),
+    /// How many bytes `Write` wrote, which may be short.
+    Written(usize),
+    Stat(Box<Statx>),
+    /// What `Rename` did.
+    Renamed(Renamed),
+    /// The handle `CreateRoot` returned.
+    Root(FileHandle),
+    /// An operation that returns nothing, e.g. `Close` or `Seek`,
+    /// succeeded.
+    Done,
+}
+
+/// The outcome of one request, tagged with the `user_data` it was
+/// submitted with.
+#[derive(Debug)]
+pub struct Completion {
+    pub user_data: u64,
+    pub result: Result<Reply, VfsError>,
+}
+
+/// A batch of requests, in the style of an `io_uring` submission queue:
+/// frontends queue everything a client asked for, submit it at once, and
+/// match completions to clients by their `user_data` tags.
+#[derive(Debug, Clone, Default)]
+pub struct SubmissionQueue {
+    entries: Vec<(u64, Request)>,
+}
+
+impl SubmissionQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue `request`, tagging its completion with `user_data`.
+    pub fn push(&mut self, user_data: u64, request: Request) -> &mut Self {
+        self.entries.push((user_data, request));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Vfs {
+    /// Run every request of `queue` in order and return one completion
+    /// per request, in the same order. A failed request does not stop
+    /// the ones after it. Each request is audited like the call it stands
+    /// for.
+    pub fn submit(&mut self, queue: SubmissionQueue) -> Vec<Completion> {
+        queue
+            .entries
+            .into_iter()
+            .map(|(user_data, request)| Completion {
+                user_data,
+                result: self.execute(request),
+            })
+            .collect()
+    }
+
+    pub(crate) fn execute(&mut self, request: Request) -> Result<Reply, VfsError> {
+        match request {
+            Request::Open { pathname } => self.open(&pathname).map(Reply::Fd),
+            Request::Close { fd } => self.close(fd).map(|_| Reply::Done),
+            Request::Seek { fd, offset } => self.seek(fd, offset).map(|_| Reply::Done),
+            Request::Read { fd, size } => self.read(fd, size).map(Reply::Data),
+            Request::Write { fd, data } => self.write(fd, &data).map(Reply::Written),
+            Request::Stat { pathname } => self.stat(&pathname).map(|stat| Reply::Stat(stat.into())),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::{collections::BTreeMap, fmt, ops::Bound, str::FromStr};
+
+use crate::{
+    op::{hex, unhex},
+    ErrorKind, Vfs, VfsError, R_OK,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub id: usize,
+}
+
+/// Position in a directory listing, just past the last entry returned.
+///
+/// The cursor records a name rather than an index, so entries created or
+/// removed between pages never make a listing skip or repeat the others.
+/// It round-trips through its string form for use across requests.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirCursor(Option<String>);
+
+impl DirCursor {
+    /// Cursor at the start of a directory.
+    pub fn start() -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Display for DirCursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.0 {
+            Some(name) => write!(f, "{}", hex(name.as_bytes())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for DirCursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Ok(Self::start());
+        }
+        unhex(s)
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .map(|name| Self(Some(name)))
+            .ok_or_else(|| format!("invalid directory cursor: {}", s))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirPage {
+    pub entries: Vec<DirEntry>,
+    /// Where the next page starts, or `None` after the last entry.
+    pub next: Option<DirCursor>,
+}
+
+impl Vfs {
+    /// List at most `limit` entries of a directory in name order, starting
+    /// after `cursor`, without materializing the rest of the directory.
+    pub fn readdir_page(
+        &self,
+        pathname: &str,
+        cursor: &DirCursor,
+        limit: usize,
+    ) -> Result<DirPage, VfsError> {
+        let entries = self.dir_entries(pathname)?;
+        let start = match &cursor.0 {
+            Some(name) => Bound::Excluded(name.as_str()),
+            None => Bound::Unbounded,
+        };
+        let mut range = entries.range::<str, _>((start, Bound::Unbounded));
+        let entries: Vec<_> = range
+            .by_ref()
+            .take(limit)
+            .map(|(name, &id)| DirEntry {
+                name: name.clone(),
+                id,
+            })
+            .collect();
+        let next = match (range.next(), entries.last()) {
+            (Some(_), Some(last)) => Some(DirCursor(Some(last.name.clone()))),
+            (Some(_), None) => Some(cursor.clone()),
+            (None, _) => None,
+        };
+        Ok(DirPage { entries, next })
+    }
+
+    /// Stream the entries of a directory in name order, `.` and `..`
+    /// included.
+    pub fn read_dir(
+        &self,
+        pathname: &str,
+    ) -> Result<impl Iterator<Item = DirEntry> + '_, VfsError> {
+        let entries = self
+            .dir_entries(pathname)
+            .map_err(|err| self.error_at(err, pathname))?;
+        Ok(entries.iter().map(|(name, &id)| DirEntry {
+            name: name.clone(),
+            id,
+        }))
+    }
+
+    /// The entries of directory `pathname`, if the session may read them.
+    fn dir_entries(&self, pathname: &str) -> Result<&BTreeMap<String, usize>, VfsError> {
+        match self.resolve(pathname) {
+            Some((fd, id, _)) if fd.file_type.is_dir() => {
+                self.check_access(id, R_OK, || {
+                    format!("ls: cannot open directory '{}'", pathname)
+                })?;
+                Ok(fd.file_type.as_dir())
+            }
+            Some(_) => Err(VfsError::new(
+                ErrorKind::NotADirectory,
+                format!("ls: cannot open directory '{}': Not a directory", pathname),
+            )),
+            None => Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!(
+                    "ls: cannot access '{}': No such file or directory",
+                    pathname
+                ),
+            )),
+        }
+    }
+}
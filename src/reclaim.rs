@@ -0,0 +1,57 @@
+use crate::{
+    notify::{absolute, EventKind},
+    FileType, Vfs, BLOCK_SIZE,
+};
+
+/// A file that lost its last link while open: its space is only
+/// reclaimed once the last descriptor on it is closed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnlinkedFile {
+    pub inode: usize,
+    /// The absolute path of its last link.
+    pub path: String,
+    /// The bytes of the blocks it holds.
+    pub bytes: usize,
+}
+
+impl Vfs {
+    /// The unlinked files still held open, in inode order.
+    pub fn unlinked_files(&self) -> impl Iterator<Item = UnlinkedFile> + '_ {
+        self.unlinked.iter().map(|(&id, path)| UnlinkedFile {
+            inode: id,
+            path: path.clone(),
+            bytes: self.held_bytes(id),
+        })
+    }
+
+    fn held_bytes(&self, id: usize) -> usize {
+        match &self.fds[id].file_type {
+            FileType::Regular(blocks_refs) => {
+                blocks_refs
+                    .iter()
+                    .filter(|&&block_id| block_id != 0)
+                    .count()
+                    * BLOCK_SIZE
+            }
+            _ => 0,
+        }
+    }
+
+    /// Remember `pathname` if file `id` just lost its last link there but
+    /// is still open.
+    pub(crate) fn note_unlinked(&mut self, id: usize, pathname: &str) {
+        let fd = &self.fds[id];
+        if fd.links == 0 && fd.refs > 0 {
+            let path = absolute(self.session.cwd(), pathname);
+            self.unlinked.insert(id, path);
+        }
+    }
+
+    /// Report that file `id` is being freed, with a `RECLAIM` event at
+    /// its last path if it was held open after being unlinked.
+    pub(crate) fn reclaim(&mut self, id: usize) {
+        if let Some(path) = self.unlinked.remove(&id) {
+            self.queue(EventKind::Reclaim, path);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Recording shell sessions to a file and replaying them.
+//!
+//! A recording starts with a header that gives the seed and atime policy
+//! of the session. After that, each command is a `$` line, and each line
+//! of its here-document is a `>` line. Then come `=` lines for the audit
+//! records the command produced on the current volume, without their
+//! timestamps:
+//!
+//! ```text
+//! # vfs session seed=42 atime=relatime
+//! $ mkdir /a
+//! = ok mkdir "/a"
+//! ```
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use vfs::AtimePolicy;
+
+use crate::{Cli, VolumeManager};
+
+const HEADER: &str = "# vfs session";
+
+/// Where a command's audit records start: the volume that was current
+/// and the length of its audit log.
+pub struct Mark {
+    volume: String,
+    len: usize,
+}
+
+impl VolumeManager {
+    /// Audit the default volume, so that commands' effects are recorded.
+    pub fn start_audit(&mut self) {
+        self.shell().vfs.set_audit(true);
+    }
+
+    pub fn mark(&self) -> Mark {
+        Mark {
+            volume: self.current.clone(),
+            len: self.volumes[&self.current].vfs.audit_log().len(),
+        }
+    }
+
+    /// The audit records since `mark`, without timestamps; none if the
+    /// current volume changed.
+    pub fn effects(&self, mark: &Mark) -> Vec<String> {
+        if mark.volume != self.current {
+            return Vec::new();
+        }
+        let log = self.volumes[&self.current].vfs.audit_log();
+        log.get(mark.len..)
+            .unwrap_or_default()
+            .iter()
+            .map(|record| {
+                let record = record.to_string();
+                let (_, rest) = record.split_once(' ').unwrap_or_default();
+                rest.to_string()
+            })
+            .collect()
+    }
+}
+
+pub struct Recorder {
+    path: String,
+    out: BufWriter<File>,
+}
+
+impl Recorder {
+    /// Start recording to host file `path` and set up `cli` for a
+    /// session that can be replayed, choosing a seed if it has none.
+    pub fn create(path: &str, cli: &mut Cli) -> Result<Self, String> {
+        if cli.image.is_some() {
+            return Err("record: cannot record a session on an image".to_string());
+        }
+        let seed = *cli.seed.get_or_insert_with(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+        });
+        let file = File::create(path)
+            .map_err(|err| format!("record: cannot create '{}': {}", path, err))?;
+        let mut recorder = Self {
+            path: path.to_string(),
+            out: BufWriter::new(file),
+        };
+        recorder.write(&format!("{} seed={} atime={}", HEADER, seed, cli.atime))?;
+        Ok(recorder)
+    }
+
+    /// Append a command, given as its line and here-document lines, and
+    /// its effects.
+    pub fn record(&mut self, lines: &[String], effects: &[String]) -> Result<(), String> {
+        for (i, line) in lines.iter().enumerate() {
+            self.write(&format!("{} {}", if i == 0 { '$' } else { '>' }, line))?;
+        }
+        for effect in effects {
+            self.write(&format!("= {}", effect))?;
+        }
+        self.out
+            .flush()
+            .map_err(|err| format!("record: cannot write '{}': {}", self.path, err))
+    }
+
+    fn write(&mut self, line: &str) -> Result<(), String> {
+        writeln!(self.out, "{}", line)
+            .map_err(|err| format!("record: cannot write '{}': {}", self.path, err))
+    }
+}
+
+struct Command {
+    lines: Vec<String>,
+    effects: Vec<String>,
+}
+
+/// Replay the session recorded in host file `path` against a fresh
+/// filesystem, echoing each command, and fail at the first command whose
+/// effects differ from the recorded ones.
+pub fn replay(path: &str) -> Result<usize, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| format!("replay: cannot read '{}': {}", path, err))?;
+    let invalid = |line: &str| format!("replay: invalid session line: {}", line);
+    let mut lines = contents.lines();
+    let header = lines.next().unwrap_or_default();
+    let mut cli = Cli {
+        image: None,
+        read_only: false,
+        seed: None,
+        atime: AtimePolicy::default(),
+        rc: None,
+        mode: None,
+    };
+    let fields = header.strip_prefix(HEADER).ok_or_else(|| invalid(header))?;
+    for field in fields.split_whitespace() {
+        match field.split_once('=') {
+            Some(("seed", seed)) => cli.seed = Some(seed.parse().map_err(|_| invalid(header))?),
+            Some(("atime", atime)) => cli.atime = atime.parse().map_err(|_| invalid(header))?,
+            _ => return Err(invalid(header)),
+        }
+    }
+    let mut commands: Vec<Command> = Vec::new();
+    for line in lines {
+        match line.split_at_checked(2) {
+            _ if line.is_empty() || line.starts_with('#') => {}
+            Some(("$ ", command)) => commands.push(Command {
+                lines: vec![command.to_string()],
+                effects: Vec::new(),
+            }),
+            Some(("> ", more)) => match commands.last_mut() {
+                Some(command) if command.effects.is_empty() => command.lines.push(more.to_string()),
+                _ => return Err(invalid(line)),
+            },
+            Some(("= ", effect)) => match commands.last_mut() {
+                Some(command) => command.effects.push(effect.to_string()),
+                None => return Err(invalid(line)),
+            },
+            _ => return Err(invalid(line)),
+        }
+    }
+    let mut volumes = VolumeManager::new(&cli)?;
+    volumes.start_audit();
+    for (i, command) in commands.iter().enumerate() {
+        println!("{}{}", volumes.prompt(), command.lines[0]);
+        let mut more = command.lines[1..].iter().cloned();
+        let mark = volumes.mark();
+        let go_on = volumes.run_line(&command.lines[0], || more.next());
+        let effects = volumes.effects(&mark);
+        if effects != command.effects {
+            let expected = command.effects.join("; ");
+            let got = effects.join("; ");
+            return Err(format!(
+                "replay: command {} '{}' diverged: expected '{}', got '{}'",
+                i + 1,
+                command.lines[0],
+                expected,
+                got
+            ));
+        }
+        if !go_on {
+            return Ok(i + 1);
+        }
+    }
+    Ok(commands.len())
+}
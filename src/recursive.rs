@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use crate::{namei::join, ErrorKind, FileType, Monitor, Vfs, VfsError, DOT, DOTDOT};
+
+impl Vfs {
+    /// Copy `source` to `target`, which must not exist, like `cp -r`:
+    /// directories with everything below them, symlinks as symlinks, and
+    /// regular files by contents, as new files owned by the current user.
+    /// With the `rayon` feature the files of each directory are read in
+    /// parallel before they are written.
+    pub fn copy_recursive(&mut self, source: &str, target: &str) -> Result<(), VfsError> {
+        self.copy_recursive_with(source, target, &mut Monitor::new())
+    }
+
+    /// Like `copy_recursive`, reporting each block and entry copied to
+    /// `monitor` and stopping if it is cancelled, which leaves what was
+    /// copied so far in place.
+    pub fn copy_recursive_with(
+        &mut self,
+        source: &str,
+        target: &str,
+        monitor: &mut Monitor,
+    ) -> Result<(), VfsError> {
+        let source_id = match self.resolve(source) {
+            Some((fd, id, _)) if fd.file_type.is_dir() => Some(id),
+            Some(_) => None,
+            None => {
+                return Err(VfsError::new(
+                    ErrorKind::NotFound,
+                    format!("cp: cannot stat '{}': No such file or directory", source),
+                ))
+            }
+        };
+        let parent_id = self
+            .resolve(&format!("{}/{}", Vfs::dirname(target), DOT))
+            .map(|(_, id, _)| id);
+        if let (Some(source_id), Some(parent_id)) = (source_id, parent_id) {
+            if self.is_ancestor(source_id, parent_id) {
+                return Err(VfsError::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "cp: cannot copy a directory, '{}', into itself, '{}'",
+                        source, target
+                    ),
+                ));
+            }
+        }
+        self.copy_entry(source, target, None, monitor)
+    }
+
+    /// Copy `source` to `target`, taking the contents of a regular file
+    /// from `data` if it was read ahead.
+    fn copy_entry(
+        &mut self,
+        source: &str,
+        target: &str,
+        data: Option<Vec<u8>>,
+        monitor: &mut Monitor,
+    ) -> Result<(), VfsError> {
+        let context = || format!("cp: cannot copy '{}' to '{}'", source, target);
+        let Some((fd, _, _)) = self.resolve(source) else {
+            return Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!("{}: No such file or directory", context()),
+            ));
+        };
+        match &fd.file_type {
+            FileType::Directory(entries) => {
+                let names: Vec<_> = entries
+                    .keys()
+                    .filter(|&name| name != DOT && name != DOTDOT)
+                    .cloned()
+                    .collect();
+                self.mkdir(target)?;
+                monitor.advance(0, 1, context)?;
+                let mut prefetched = self.prefetch(source, &names);
+                for name in names {
+                    let data = prefetched.remove(&name);
+                    self.copy_entry(&join(source, &name), &join(target, &name), data, monitor)?;
+                }
+                Ok(())
+            }
+            FileType::Symlink(path) => {
+                let path = path.clone();
+                self.symlink(&path, target)?;
+                monitor.advance(0, 1, context)
+            }
+            FileType::Regular(_) | FileType::Archive(_) => {
+                let data = match data {
+                    Some(data) => data,
+                    None => self.read_file(source)?,
+                };
+                self.write_file_with(target, &data, monitor, &context)?;
+                monitor.advance(0, 1, context)
+            }
+            _ => Err(VfsError::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "cp: cannot copy '{}': Not a regular file or directory",
+                    source
+                ),
+            )),
+        }
+    }
+
+    /// Read the regular files among the `names` in directory `dirname` in
+    /// parallel. Files that fail to read are left out, for the copy to
+    /// report the error when it reads them again.
+    #[cfg(feature = "rayon")]
+    fn prefetch(&self, dirname: &str, names: &[String]) -> HashMap<String, Vec<u8>> {
+        use rayon::prelude::*;
+        names
+            .par_iter()
+            .filter_map(|name| {
+                let pathname = join(dirname, name);
+                match &self.resolve(&pathname)?.0.file_type {
+                    FileType::Regular(_) => Some((name.clone(), self.read_file(&pathname).ok()?)),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    /// Without the `rayon` feature each file is read as it is copied.
+    #[cfg(not(feature = "rayon"))]
+    fn prefetch(&self, _dirname: &str, _names: &[String]) -> HashMap<String, Vec<u8>> {
+        HashMap::new()
+    }
+
+    /// Remove a file, symlink or whole directory tree, like `rm -r`.
+    pub fn remove_all(&mut self, pathname: &str) -> Result<(), VfsError> {
+        self.remove_all_with(pathname, &mut Monitor::new())
+    }
+
+    /// Like `remove_all`, reporting each entry removed to `monitor` and
+    /// stopping if it is cancelled, which leaves what was not removed yet
+    /// in place.
+    pub fn remove_all_with(
+        &mut self,
+        pathname: &str,
+        monitor: &mut Monitor,
+    ) -> Result<(), VfsError> {
+        let context = || format!("rm: cannot remove '{}'", pathname);
+        match self.resolve(pathname) {
+            Some((fd, _, _)) if fd.file_type.is_dir() => {
+                let names: Vec<_> = fd
+                    .file_type
+                    .as_dir()
+                    .keys()
+                    .filter(|&name| name != DOT && name != DOTDOT)
+                    .cloned()
+                    .collect();
+                for name in names {
+                    self.remove_all_with(&join(pathname, &name), monitor)?;
+                }
+                self.rmdir(pathname)?;
+            }
+            _ => self.unlink(pathname)?,
+        }
+        monitor.advance(0, 1, context)
+    }
+}
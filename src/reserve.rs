@@ -0,0 +1,116 @@
+use crate::{
+    block::{self, UpdateError},
+    ErrorKind, FileType, Op, Vfs, VfsError, ATTR_IMMUTABLE, BLOCK_SIZE, W_OK,
+};
+
+impl Vfs {
+    /// Allocate blocks for `len` bytes from the offset of descriptor
+    /// `oid`, like `posix_fallocate(3)`, extending the file if they reach
+    /// past its end. Holes and blocks shared with other files are given
+    /// private blocks, so later writes within the range never fail with
+    /// `No space left on device`. Enough free blocks are checked for up
+    /// front; if allocation still fails, or a shared block fails
+    /// verification, the size is left unchanged.
+    ///
+    /// In dedup mode, a block written to match another is shared again
+    /// and gives up its reservation.
+    pub fn reserve(&mut self, oid: usize, len: usize) -> Result<(), VfsError> {
+        let result = self.reserve_unaudited(oid, len);
+        self.audit(|| Op::Reserve { fd: oid, len }, &result);
+        result
+    }
+
+    fn reserve_unaudited(&mut self, oid: usize, len: usize) -> Result<(), VfsError> {
+        let Some(&(id, cursor)) = self.open_fds.get(&oid) else {
+            return Err(VfsError::new(
+                ErrorKind::BadDescriptor,
+                format!("reserve: invalid file descriptor: {}", oid),
+            ));
+        };
+        let context = || format!("reserve: cannot reserve {}", oid);
+        match self.fds[id].file_type {
+            FileType::Regular(_) => {}
+            FileType::Fifo(_) | FileType::Ring(_) => {
+                return Err(VfsError::new(
+                    ErrorKind::IllegalSeek,
+                    format!("{}: Illegal seek", context()),
+                ))
+            }
+            _ => {
+                return Err(VfsError::new(
+                    ErrorKind::NotPermitted,
+                    format!("{}: Operation not permitted", context()),
+                ))
+            }
+        }
+        let end = match cursor.checked_add(len) {
+            Some(end) if len > 0 => end,
+            _ => {
+                return Err(VfsError::new(
+                    ErrorKind::InvalidInput,
+                    format!("{}: Invalid argument", context()),
+                ))
+            }
+        };
+        self.check_writable(context)?;
+        if self.read_only_fds.contains(&oid) {
+            return Err(VfsError::new(
+                ErrorKind::ReadOnly,
+                format!("{}: Read-only file system", context()),
+            ));
+        }
+        self.check_access(id, W_OK, context)?;
+        self.check_attrs(id, ATTR_IMMUTABLE, context)?;
+        let size = self.fds[id].size.max(end);
+        self.check_seals(id, size, false, context)?;
+        self.check_project_bytes(id, size, context)?;
+        let fd = &mut self.fds[id];
+        let blocks_refs = fd.file_type.as_file_mut();
+        let (first, last) = (cursor / BLOCK_SIZE, end.div_ceil(BLOCK_SIZE));
+        let needed = (first..last)
+            .filter(|&i| {
+                blocks_refs
+                    .get(i)
+                    .is_none_or(|&block_id| block_id == 0 || self.blocks.refs(block_id) > 1)
+            })
+            .count();
+        if self.blocks.available().is_some_and(|free| free < needed) {
+            return Err(UpdateError::NoSpace.into_error(context()));
+        }
+        // Bytes past the end of the file may be stale; they read as zeros
+        // once the file is extended over them.
+        let j = fd.size / BLOCK_SIZE;
+        let offset = fd.size % BLOCK_SIZE;
+        if size > fd.size && offset > 0 && blocks_refs.get(j).is_some_and(|&block_id| block_id != 0)
+        {
+            let hint = block::hint(blocks_refs, j);
+            blocks_refs[j] = self
+                .blocks
+                .update(blocks_refs[j], hint, |block| block[offset..].fill(0))
+                .map_err(|err| err.into_error(context()))?;
+        }
+        let len = blocks_refs.len();
+        if len < last {
+            blocks_refs.resize(last, 0);
+        }
+        for i in first..last {
+            let hint = block::hint(blocks_refs, i);
+            match self.blocks.reserve(blocks_refs[i], hint) {
+                Ok(block_id) => blocks_refs[i] = block_id,
+                Err(err) => {
+                    // Blocks past the old end would not be covered by the
+                    // size; those within it keep their contents.
+                    for block_id in blocks_refs.drain(len..) {
+                        self.blocks.release(block_id);
+                    }
+                    return Err(err.into_error(context()));
+                }
+            }
+        }
+        if size > fd.size {
+            fd.size = size;
+            self.touch_modified(id);
+        }
+        Ok(())
+    }
+}
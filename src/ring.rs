@@ -0,0 +1,123 @@
+use std::collections::VecDeque;
+
+use crate::{
+    ErrorKind, FileDescriptor, FileType, Times, Vfs, VfsError, ATTR_IMMUTABLE, W_OK, X_OK,
+};
+
+/// The contents of a ring buffer file: the last `capacity` bytes written,
+/// like the kernel log buffer that `dmesg(1)` reads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Ring {
+    data: VecDeque<u8>,
+    capacity: usize,
+}
+
+impl Ring {
+    /// A ring buffer holding the last `capacity` bytes of `data`.
+    pub(crate) fn new(capacity: usize, data: &[u8]) -> Self {
+        let mut ring = Self {
+            data: VecDeque::with_capacity(capacity),
+            capacity,
+        };
+        ring.push(data);
+        ring
+    }
+
+    /// Append `data`, dropping the oldest bytes beyond the capacity.
+    pub(crate) fn push(&mut self, data: &[u8]) {
+        let data = &data[data.len().saturating_sub(self.capacity)..];
+        let overflow = (self.data.len() + data.len()).saturating_sub(self.capacity);
+        self.data.drain(..overflow);
+        self.data.extend(data);
+    }
+
+    /// The bytes currently held, oldest first.
+    pub(crate) fn contents(&self) -> Vec<u8> {
+        self.data.iter().copied().collect()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl Vfs {
+    /// Create a ring buffer file that holds at most `capacity` bytes:
+    /// writes always succeed and overwrite the oldest data once it is
+    /// full, and reads return the current window from the oldest byte.
+    ///
+    /// Images store a ring buffer's capacity and current window, but
+    /// creating one is not recorded by the audit log.
+    pub fn mkring(&mut self, pathname: &str, capacity: usize) -> Result<(), VfsError> {
+        self.check_writable_at(pathname, || format!("mkring: cannot create '{}'", pathname))?;
+        if capacity == 0 {
+            return Err(VfsError::new(
+                ErrorKind::InvalidInput,
+                format!("mkring: cannot create '{}': Invalid argument", pathname),
+            ));
+        }
+        let basename = Vfs::basename(pathname);
+        let dirname = Vfs::dirname(pathname);
+        match self.resolve(&dirname) {
+            Some((fd, id, _)) => {
+                if !fd.file_type.is_dir() {
+                    return Err(VfsError::new(
+                        ErrorKind::NotADirectory,
+                        format!("mkring: cannot create '{}': Not a directory", pathname),
+                    ));
+                }
+                let entries = fd.file_type.as_dir();
+                if entries.contains_key(&basename) || basename.is_empty() {
+                    return Err(VfsError::new(
+                        ErrorKind::AlreadyExists,
+                        format!("mkring: cannot create '{}': File exists", pathname),
+                    ));
+                }
+                let context = || format!("mkring: cannot create '{}'", pathname);
+                self.check_access(id, W_OK | X_OK, context)?;
+                self.check_attrs(id, ATTR_IMMUTABLE, context)?;
+                self.check_new_inode(id, context)?;
+                let new_id = self.alloc_fd(|_| FileDescriptor {
+                    file_type: FileType::Ring(Ring::new(capacity, &[])),
+                    size: 0,
+                    links: 1,
+                    refs: 0,
+                    generation: 0,
+                    uid: 0,
+                    gid: 0,
+                    mode: 0o644,
+                    acl: Vec::new(),
+                    attrs: 0,
+                    project: 0,
+                    times: Times::default(),
+                });
+                self.inherit_project(id, new_id);
+                let fd = &mut self.fds[id];
+                let entries = fd.file_type.as_dir_mut();
+                entries.insert(basename.to_string(), new_id);
+                self.touch_modified(id);
+                Ok(())
+            }
+            None => Err(VfsError::new(
+                ErrorKind::NotFound,
+                format!(
+                    "mkring: cannot create '{}': No such file or directory",
+                    pathname
+                ),
+            )),
+        }
+    }
+
+    /// The capacity of the ring buffer at `pathname`, or `None` if it is
+    /// not one.
+    pub fn ring_capacity(&self, pathname: &str) -> Option<usize> {
+        match &self.resolve_follow(pathname)?.0.file_type {
+            FileType::Ring(ring) => Some(ring.capacity()),
+            _ => None,
+        }
+    }
+}
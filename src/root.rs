@@ -0,0 +1,116 @@
+use crate::{BindOptions, ErrorKind, FileDescriptor, FileHandle, Op, Vfs, VfsError};
+
+impl Vfs {
+    /// Create an empty directory outside the tree, owned by the session,
+    /// and return a handle to it. It is filled and used through `graft`,
+    /// and outlives unmounting, so the same anonymous tree can be grafted
+    /// again elsewhere. Detached roots, like bind mounts, are kept in
+    /// memory, not in images.
+    pub fn create_root(&mut self) -> Result<FileHandle, VfsError> {
+        let result = self.create_root_unaudited();
+        self.audit(|| Op::CreateRoot, &result);
+        result
+    }
+
+    fn create_root_unaudited(&mut self) -> Result<FileHandle, VfsError> {
+        let context = || "mkroot: cannot create root".to_string();
+        self.check_writable(context)?;
+        self.check_free_inodes(1, context)?;
+        // `..` leads to the root of the tree, so that walks up the parents
+        // end; through a graft it leads to the parent of the target.
+        let id = self.alloc_fd(|id| FileDescriptor::new_dir(id, 0));
+        self.roots.insert(id);
+        Ok(FileHandle {
+            inode: id,
+            generation: self.fds[id].generation,
+        })
+    }
+
+    /// The detached roots, in inode order.
+    pub fn roots(&self) -> impl Iterator<Item = FileHandle> + '_ {
+        self.roots.iter().map(|&id| FileHandle {
+            inode: id,
+            generation: self.fds[id].generation,
+        })
+    }
+
+    pub fn graft(&mut self, root: FileHandle, target: &str) -> Result<(), VfsError> {
+        self.graft_with(root, target, BindOptions::default())
+    }
+
+    /// Make detached root `root` appear at directory `target`, as a bind
+    /// mount of it would; `unmount(target)` detaches it again.
+    pub fn graft_with(
+        &mut self,
+        root: FileHandle,
+        target: &str,
+        options: BindOptions,
+    ) -> Result<(), VfsError> {
+        let result = self.graft_unaudited(root, target, options);
+        self.audit(
+            || Op::Graft {
+                root,
+                target: target.to_string(),
+                options,
+            },
+            &result,
+        );
+        result.map_err(|err| self.error_at(err, target))
+    }
+
+    fn graft_unaudited(
+        &mut self,
+        root: FileHandle,
+        target: &str,
+        options: BindOptions,
+    ) -> Result<(), VfsError> {
+        let context = || format!("mount: cannot graft root {} on '{}'", root, target);
+        self.check_writable(context)?;
+        let root_id = self.root_id(root, context)?;
+        self.add_bind(format!("root:{}", root), root_id, target, options, context)
+    }
+
+    /// Free detached root `root`, which must be empty and not grafted.
+    pub fn remove_root(&mut self, root: FileHandle) -> Result<(), VfsError> {
+        let result = self.remove_root_unaudited(root);
+        self.audit(|| Op::RemoveRoot { root }, &result);
+        result
+    }
+
+    fn remove_root_unaudited(&mut self, root: FileHandle) -> Result<(), VfsError> {
+        let context = || format!("rmroot: cannot remove root {}", root);
+        self.check_writable(context)?;
+        let root_id = self.root_id(root, context)?;
+        if self.is_bound(root_id) {
+            return Err(VfsError::new(
+                ErrorKind::Busy,
+                format!("{}: Device or resource busy", context()),
+            ));
+        }
+        if self.fds[root_id].file_type.as_dir().len() > 2 {
+            return Err(VfsError::new(
+                ErrorKind::DirectoryNotEmpty,
+                format!("{}: Directory not empty", context()),
+            ));
+        }
+        self.roots.remove(&root_id);
+        self.fds[root_id].links = 0;
+        self.free_fd(root_id);
+        Ok(())
+    }
+
+    fn root_id<F>(&self, root: FileHandle, context: F) -> Result<usize, VfsError>
+    where
+        F: FnOnce() -> String,
+    {
+        let live =
+            self.roots.contains(&root.inode) && self.fds[root.inode].generation == root.generation;
+        match live {
+            true => Ok(root.inode),
+            false => Err(VfsError::new(
+                ErrorKind::Stale,
+                format!("{}: Stale file handle", context()),
+            )),
+        }
+    }
+}
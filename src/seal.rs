@@ -0,0 +1,121 @@
+use crate::{ErrorKind, Op, Vfs, VfsError};
+
+/// No more seals can be added.
+pub const F_SEAL_SEAL: u32 = 0x1;
+/// The file cannot be truncated to a smaller size.
+pub const F_SEAL_SHRINK: u32 = 0x2;
+/// The file cannot be extended, by writes past its end or truncation.
+pub const F_SEAL_GROW: u32 = 0x4;
+/// The contents of the file cannot be written.
+pub const F_SEAL_WRITE: u32 = 0x8;
+
+/// Every seal there is.
+const SEALS: u32 = F_SEAL_SEAL | F_SEAL_SHRINK | F_SEAL_GROW | F_SEAL_WRITE;
+
+const NAMES: [(u32, &str); 4] = [
+    (F_SEAL_SEAL, "seal"),
+    (F_SEAL_SHRINK, "shrink"),
+    (F_SEAL_GROW, "grow"),
+    (F_SEAL_WRITE, "write"),
+];
+
+/// Comma-separated seal names, e.g. `shrink,grow`, or `-` if there are
+/// none.
+pub fn seals_string(seals: u32) -> String {
+    let names: Vec<_> = NAMES
+        .iter()
+        .filter(|&&(seal, _)| seals & seal != 0)
+        .map(|&(_, name)| name)
+        .collect();
+    match names.is_empty() {
+        true => "-".to_string(),
+        false => names.join(","),
+    }
+}
+
+/// Parse comma-separated seal names, so that `seals_string` round-trips.
+pub fn parse_seals(s: &str) -> Option<u32> {
+    if s == "-" {
+        return Some(0);
+    }
+    s.split(',').try_fold(0, |seals, word| {
+        NAMES
+            .iter()
+            .find(|&&(_, name)| name == word)
+            .map(|&(seal, _)| seals | seal)
+    })
+}
+
+impl Vfs {
+    /// The seals of the file open as descriptor `oid`, like
+    /// `fcntl(F_GET_SEALS)`.
+    pub fn seals(&self, oid: usize) -> Result<u32, VfsError> {
+        match self.open_fds.get(&oid) {
+            Some((id, _)) => Ok(self.seals.get(id).copied().unwrap_or(0)),
+            None => Err(VfsError::new(
+                ErrorKind::BadDescriptor,
+                format!("seal: invalid file descriptor: {}", oid),
+            )),
+        }
+    }
+
+    /// Add `seals` to the regular file open as descriptor `oid`, like
+    /// `fcntl(F_ADD_SEALS)` on a memfd. Seals belong to the file, not the
+    /// descriptor: they bind every descriptor and path to it, cannot be
+    /// removed, and last until the file is freed. Like bind mounts, they
+    /// are kept in memory, not in images.
+    pub fn add_seals(&mut self, oid: usize, seals: u32) -> Result<(), VfsError> {
+        let result = self.add_seals_unaudited(oid, seals);
+        self.audit(|| Op::Seal { fd: oid, seals }, &result);
+        result
+    }
+
+    fn add_seals_unaudited(&mut self, oid: usize, seals: u32) -> Result<(), VfsError> {
+        let current = self.seals(oid)?;
+        let context = || format!("seal: cannot seal {}", oid);
+        let id = self.open_fds[&oid].0;
+        if seals & !SEALS != 0 || !self.fds[id].file_type.is_file() {
+            return Err(VfsError::new(
+                ErrorKind::InvalidInput,
+                format!("{}: Invalid argument", context()),
+            ));
+        }
+        if current & F_SEAL_SEAL != 0 {
+            return Err(VfsError::new(
+                ErrorKind::NotPermitted,
+                format!("{}: Operation not permitted", context()),
+            ));
+        }
+        if current | seals != 0 {
+            self.seals.insert(id, current | seals);
+        }
+        Ok(())
+    }
+
+    /// Fail with `Operation not permitted` if the seals of inode `id`
+    /// forbid changing its size to `new_size`, or, if
+    /// `writing`, writing its contents.
+    pub(crate) fn check_seals<F>(
+        &self,
+        id: usize,
+        new_size: usize,
+        writing: bool,
+        context: F,
+    ) -> Result<(), VfsError>
+    where
+        F: FnOnce() -> String,
+    {
+        let seals = self.seals.get(&id).copied().unwrap_or(0);
+        let size = self.fds[id].size;
+        let sealed = (writing && seals & F_SEAL_WRITE != 0)
+            || (new_size < size && seals & F_SEAL_SHRINK != 0)
+            || (new_size > size && seals & F_SEAL_GROW != 0);
+        match sealed {
+            true => Err(VfsError::new(
+                ErrorKind::NotPermitted,
+                format!("{}: Operation not permitted", context()),
+            )),
+            false => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    mem::size_of,
+};
+
+use crate::{format_size, Op, Vfs};
+
+/// The words in the contents of regular files, and the files that contain
+/// each word.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ContentIndex {
+    words: BTreeMap<String, BTreeSet<usize>>,
+    /// The words indexed for each file, to remove them when it changes.
+    files: BTreeMap<usize, BTreeSet<String>>,
+    /// Files written through a descriptor since they were last indexed.
+    dirty: BTreeSet<usize>,
+}
+
+/// The size of the full-text index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentIndexStats {
+    pub files: usize,
+    pub words: usize,
+    /// File and word pairs.
+    pub postings: usize,
+    /// Approximate heap usage, not counting the nodes of the maps.
+    pub bytes: usize,
+}
+
+impl fmt::Display for ContentIndexStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "files: {}, words: {}, postings: {}, memory: {}",
+            self.files,
+            self.words,
+            self.postings,
+            format_size(self.bytes)
+        )
+    }
+}
+
+/// The distinct words of `text`: runs of letters and digits, lowercased.
+fn words(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+impl ContentIndex {
+    fn remove(&mut self, id: usize) {
+        for word in self.files.remove(&id).unwrap_or_default() {
+            if let Some(ids) = self.words.get_mut(&word) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.words.remove(&word);
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, id: usize, words: BTreeSet<String>) {
+        self.remove(id);
+        for word in &words {
+            self.words.entry(word.clone()).or_default().insert(id);
+        }
+        if !words.is_empty() {
+            self.files.insert(id, words);
+        }
+    }
+}
+
+impl Vfs {
+    /// Start or stop keeping a full-text index of regular files for
+    /// `search`. Starting indexes every file; after that, files are
+    /// re-indexed when they are created, truncated, or closed after a
+    /// write.
+    pub fn set_content_index(&mut self, enabled: bool) {
+        self.content_index = None;
+        if enabled {
+            self.content_index = Some(self.build_content_index());
+        }
+    }
+
+    pub fn is_content_indexed(&self) -> bool {
+        self.content_index.is_some()
+    }
+
+    /// The size of the full-text index, or `None` if it is off.
+    pub fn content_index_stats(&self) -> Option<ContentIndexStats> {
+        let index = self.content_index.as_ref()?;
+        let postings = index.files.values().map(BTreeSet::len).sum();
+        let words: usize = index
+            .files
+            .values()
+            .flatten()
+            .map(|word| size_of::<String>() + word.len())
+            .sum();
+        let bytes = words
+            + index
+                .words
+                .keys()
+                .map(|word| size_of::<String>() + word.len())
+                .sum::<usize>()
+            + postings * size_of::<usize>()
+            + index.files.len() * size_of::<usize>();
+        Some(ContentIndexStats {
+            files: index.files.len(),
+            words: index.words.len(),
+            postings,
+            bytes,
+        })
+    }
+
+    fn build_content_index(&self) -> ContentIndex {
+        let mut index = ContentIndex::default();
+        for id in 0..self.fds.len() {
+            if let Some(words) = self.file_words(id) {
+                index.insert(id, words);
+            }
+        }
+        index
+    }
+
+    /// The words of inode `id` if it is a linked regular file whose
+    /// blocks can be read.
+    fn file_words(&self, id: usize) -> Option<BTreeSet<String>> {
+        let fd = &self.fds[id];
+        if self.fds_id.free.contains(&id) || !fd.file_type.is_file() || fd.links == 0 {
+            return None;
+        }
+        let data = self.file_contents(id).ok()?;
+        Some(words(&String::from_utf8_lossy(&data)))
+    }
+
+    fn reindex(&mut self, id: usize) {
+        let words = self.file_words(id).unwrap_or_default();
+        if let Some(index) = &mut self.content_index {
+            index.dirty.remove(&id);
+            index.insert(id, words);
+        }
+    }
+
+    /// The paths of the regular files that contain every word of `query`,
+    /// ignoring case, in inode order. Uses the full-text index if enabled
+    /// and otherwise reads every file; paths are looked up only as the
+    /// caller asks for them.
+    pub fn search(&mut self, query: &str) -> impl Iterator<Item = String> + '_ {
+        let dirty: Vec<_> = match &self.content_index {
+            Some(index) => index.dirty.iter().copied().collect(),
+            None => Vec::new(),
+        };
+        for id in dirty {
+            self.reindex(id);
+        }
+        let built;
+        let index = match &self.content_index {
+            Some(index) => index,
+            None => {
+                built = self.build_content_index();
+                &built
+            }
+        };
+        let mut ids: Option<BTreeSet<usize>> = None;
+        for word in words(query) {
+            let found = index.words.get(&word).cloned().unwrap_or_default();
+            ids = Some(match ids {
+                Some(ids) => ids.intersection(&found).copied().collect(),
+                None => found,
+            });
+        }
+        ids.unwrap_or_default()
+            .into_iter()
+            .filter_map(|id| self.path_of(id))
+    }
+
+    /// Update the full-text index after a successful `op`.
+    pub(crate) fn index_content(&mut self, op: &Op) {
+        let Some(index) = &mut self.content_index else {
+            return;
+        };
+        match op {
+            Op::Write { fd, .. } => {
+                if let Some(&(id, _)) = self.open_fds.get(fd) {
+                    index.dirty.insert(id);
+                }
+            }
+            Op::Close { .. } => {
+                let closed: Vec<_> = index
+                    .dirty
+                    .iter()
+                    .copied()
+                    .filter(|&id| self.open_fds.values().all(|&(open, _)| open != id))
+                    .collect();
+                for id in closed {
+                    self.reindex(id);
+                }
+            }
+            // A new file may reuse the inode of a removed one.
+            Op::Create { pathname }
+            | Op::Mkdir { pathname }
+            | Op::Mkfifo { pathname }
+            | Op::Symlink { pathname, .. } => {
+                if let Some((_, id, _)) = self.resolve(pathname) {
+                    self.reindex(id);
+                }
+            }
+            Op::Truncate { pathname, .. } => {
+                if let Some((_, id, _)) = self.resolve_follow(pathname) {
+                    self.reindex(id);
+                }
+            }
+            Op::Unlink { .. } | Op::Rename { .. } => {
+                let gone: Vec<_> = index
+                    .files
+                    .keys()
+                    .copied()
+                    .filter(|&id| self.fds[id].links == 0 || self.fds_id.free.contains(&id))
+                    .collect();
+                for id in gone {
+                    index.remove(id);
+                }
+            }
+            Op::Mount { .. } | Op::Unmount { .. } => self.set_content_index(true),
+            _ => {}
+        }
+    }
+}
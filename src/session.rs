@@ -0,0 +1,98 @@
+use crate::{Vfs, PATHNAME_SEPARATOR};
+
+const DEFAULT_UMASK: u32 = 0o022;
+
+/// The per-process context of filesystem calls: working directory, file
+/// creation mask and credentials. A `Vfs` acts on behalf of one session at
+/// a time; switching sessions lets several logical processes share one
+/// filesystem, each with its own working directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub(crate) cwd_id: usize,
+    cwd_generation: u64,
+    pub(crate) cwd: String,
+    umask: u32,
+    uid: u32,
+    gid: u32,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    /// A session of the superuser in the root directory.
+    pub fn new() -> Self {
+        Self {
+            cwd_id: 0,
+            cwd_generation: 0,
+            cwd: PATHNAME_SEPARATOR.to_string(),
+            umask: DEFAULT_UMASK,
+            uid: 0,
+            gid: 0,
+        }
+    }
+
+    pub fn with_identity(mut self, uid: u32, gid: u32) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    pub fn cwd(&self) -> &str {
+        &self.cwd
+    }
+
+    pub fn umask(&self) -> u32 {
+        self.umask
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+}
+
+impl Vfs {
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Act on behalf of `session` from now on, returning the previous one.
+    /// A working directory renamed meanwhile is followed; one removed falls
+    /// back to the root. Descriptors flagged `O_CLOEXEC` are closed.
+    pub fn switch_session(&mut self, mut session: Session) -> Session {
+        self.close_on_exec();
+        let id = session.cwd_id;
+        let live = id < self.fds.len()
+            && !self.fds_id.free.contains(&id)
+            && self.fds[id].file_type.is_dir()
+            && self.fds[id].generation == session.cwd_generation;
+        session.cwd = if live {
+            self.dir_path(id)
+        } else {
+            session.cwd_id = 0;
+            session.cwd_generation = self.fds[0].generation;
+            PATHNAME_SEPARATOR.to_string()
+        };
+        std::mem::replace(&mut self.session, session)
+    }
+
+    /// Set the file creation mask of the current session, returning the
+    /// previous one like `umask(2)`.
+    pub fn set_umask(&mut self, umask: u32) -> u32 {
+        std::mem::replace(&mut self.session.umask, umask & 0o777)
+    }
+
+    /// Make directory `id`, reachable as `cwd`, the working directory.
+    pub(crate) fn set_cwd(&mut self, id: usize, cwd: String) {
+        self.session.cwd_id = id;
+        self.session.cwd_generation = self.fds[id].generation;
+        self.session.cwd = cwd;
+    }
+}
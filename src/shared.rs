@@ -0,0 +1,246 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard},
+    time::SystemTime,
+};
+
+use crate::{
+    Completion, Statx, SubmissionQueue, Timespec, Vfs, VfsError, ATTR_APPEND, ATTR_IMMUTABLE,
+    BLOCK_SIZE, W_OK,
+};
+
+/// Number of locks the inodes are spread over by id.
+const FILE_LOCKS: usize = 64;
+
+/// A `Vfs` shared between threads. Locking is split three ways:
+///
+/// - the tree lock, a reader-writer lock around the whole `Vfs`: anything
+///   that changes the tree, an inode or a descriptor takes it exclusively,
+///   everything else shares it;
+/// - a reader-writer lock per file, one of `FILE_LOCKS` picked by inode,
+///   taken by `read_file` to read and by `write_at` to write;
+/// - a lock per page of 64 blocks in the block store, held while a block
+///   is copied in or out.
+///
+/// `write_at` overwrites the existing bytes of a file under the shared tree
+/// lock and the lock of the file, so it runs alongside reads and
+/// overwrites of other files and only waits for those of the same file.
+/// Writes that allocate, grow the file or need anything else from the
+/// `Vfs` take the tree lock exclusively. Clones share the `Vfs`.
+#[derive(Debug, Clone)]
+pub struct SharedVfs(Arc<Shared>);
+
+#[derive(Debug)]
+struct Shared {
+    vfs: RwLock<Vfs>,
+    files: Vec<RwLock<()>>,
+    pending: Mutex<Pending>,
+}
+
+/// What overwrites under the shared tree lock changed in inodes, applied
+/// to the `Vfs` the next time the tree lock is taken exclusively.
+#[derive(Debug, Default)]
+struct Pending {
+    modified: BTreeMap<usize, Timespec>,
+    writes: usize,
+    bytes_written: usize,
+}
+
+impl Default for SharedVfs {
+    fn default() -> Self {
+        Self::new(Vfs::new())
+    }
+}
+
+impl SharedVfs {
+    pub fn new(vfs: Vfs) -> Self {
+        Self(Arc::new(Shared {
+            vfs: RwLock::new(vfs),
+            files: (0..FILE_LOCKS).map(|_| RwLock::new(())).collect(),
+            pending: Mutex::new(Pending::default()),
+        }))
+    }
+
+    fn tree(&self) -> RwLockReadGuard<'_, Vfs> {
+        self.0.vfs.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn file(&self, id: usize) -> &RwLock<()> {
+        &self.0.files[id % FILE_LOCKS]
+    }
+
+    fn pending(&self) -> MutexGuard<'_, Pending> {
+        self.0
+            .pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Run `f` under the shared tree lock, alongside other readers. It
+    /// takes no file lock, so it may see an overwrite by `write_at` half
+    /// done, and times and I/O counters without the overwrites since the
+    /// tree lock was last taken exclusively.
+    pub fn read<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&Vfs) -> T,
+    {
+        f(&self.tree())
+    }
+
+    /// Run `f` under the exclusive tree lock.
+    pub fn write<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut Vfs) -> T,
+    {
+        let mut vfs = self.0.vfs.write().unwrap_or_else(PoisonError::into_inner);
+        vfs.apply_pending(&mut self.pending());
+        f(&mut vfs)
+    }
+
+    pub fn read_file(&self, pathname: &str) -> Result<Vec<u8>, VfsError> {
+        let vfs = self.tree();
+        let _file = vfs
+            .resolve(pathname)
+            .map(|(_, id, _)| self.file(id).read().unwrap_or_else(PoisonError::into_inner));
+        vfs.read_file(pathname)
+    }
+
+    pub fn stat(&self, pathname: &str) -> Result<Statx, VfsError> {
+        let vfs = self.tree();
+        let mut stat = vfs.stat(pathname)?;
+        if let Some(&now) = self.pending().modified.get(&stat.inode()) {
+            stat.set_modified(now);
+        }
+        Ok(stat)
+    }
+
+    /// Write `data` at `offset` of the regular file `pathname`, like
+    /// `pwrite(2)`, returning how many bytes were written as `Vfs::write`
+    /// does. Bytes that already exist in blocks of this file alone are
+    /// overwritten in place under the shared tree lock and the lock of the
+    /// file; otherwise, or with an audit log, watches, indexes, a fault
+    /// plan or a seed, the file is opened and written under the exclusive
+    /// tree lock.
+    pub fn write_at(&self, pathname: &str, offset: usize, data: &[u8]) -> Result<usize, VfsError> {
+        {
+            let vfs = self.tree();
+            if let Some(id) = vfs.overwritable(pathname, offset, data.len()) {
+                let _file = self
+                    .file(id)
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner);
+                let result = vfs.overwrite(id, offset, data, || {
+                    format!("write: cannot write '{}'", pathname)
+                });
+                let written = *result.as_ref().unwrap_or(&0);
+                let mut pending = self.pending();
+                pending.writes += 1;
+                pending.bytes_written += written;
+                if written > 0 {
+                    pending.modified.insert(id, SystemTime::now().into());
+                }
+                return result;
+            }
+        }
+        self.write(|vfs| {
+            let oid = vfs.open(pathname)?;
+            let result = vfs.seek(oid, offset).and_then(|()| vfs.write(oid, data));
+            vfs.close(oid)?;
+            result
+        })
+    }
+
+    /// Run a batch under one exclusive lock rather than one per request.
+    pub fn submit(&self, queue: SubmissionQueue) -> Vec<Completion> {
+        self.write(|vfs| vfs.submit(queue))
+    }
+
+    /// Take the `Vfs` back if this is the last clone.
+    pub fn into_inner(self) -> Result<Vfs, Self> {
+        let shared = Arc::try_unwrap(self.0).map_err(Self)?;
+        let mut vfs = shared
+            .vfs
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        vfs.apply_pending(
+            &mut shared
+                .pending
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        Ok(vfs)
+    }
+}
+
+impl Vfs {
+    /// The inode of the regular file `pathname` if `len` bytes at `offset`
+    /// can be overwritten through `&self`: all of them lie within the file
+    /// and in blocks of no other file, the write is allowed, and nothing
+    /// that needs `&mut self` has to see it. Otherwise the write is left
+    /// to the exclusive path, which also reports why it fails.
+    fn overwritable(&self, pathname: &str, offset: usize, len: usize) -> Option<usize> {
+        if len == 0 || self.seed.is_some() || self.is_observed() || self.faults.limits_writes() {
+            return None;
+        }
+        self.check_writable_at(pathname, String::new).ok()?;
+        let (fd, id, _) = self.resolve(pathname)?;
+        if !fd.file_type.is_file() || fd.attrs & ATTR_APPEND != 0 || offset + len > fd.size {
+            return None;
+        }
+        self.check_access(id, W_OK, String::new).ok()?;
+        self.check_attrs(id, ATTR_IMMUTABLE, String::new).ok()?;
+        self.check_seals(id, fd.size, true, String::new).ok()?;
+        let blocks_refs = fd.file_type.as_file();
+        (offset / BLOCK_SIZE..=(offset + len - 1) / BLOCK_SIZE)
+            .all(|i| {
+                blocks_refs
+                    .get(i)
+                    .is_some_and(|&block_ref| self.blocks.is_overwritable(block_ref))
+            })
+            .then_some(id)
+    }
+
+    /// Overwrite `data` at `offset` of inode `id`, which `overwritable`
+    /// allowed, block by block. Stops at a block that fails verification,
+    /// failing only if nothing was written.
+    fn overwrite<F>(
+        &self,
+        id: usize,
+        offset: usize,
+        data: &[u8],
+        context: F,
+    ) -> Result<usize, VfsError>
+    where
+        F: FnOnce() -> String,
+    {
+        let blocks_refs = self.fds[id].file_type.as_file();
+        let mut written = 0;
+        while written < data.len() {
+            let at = offset + written;
+            let start = at % BLOCK_SIZE;
+            let n = (BLOCK_SIZE - start).min(data.len() - written);
+            match self.blocks.overwrite(
+                blocks_refs[at / BLOCK_SIZE],
+                start,
+                &data[written..written + n],
+            ) {
+                Some(Ok(())) => written += n,
+                Some(Err(err)) if written == 0 => return Err(err.into_error(context())),
+                _ => break,
+            }
+        }
+        self.stall(written);
+        Ok(written)
+    }
+
+    /// Apply what overwrites under the shared tree lock changed.
+    fn apply_pending(&mut self, pending: &mut Pending) {
+        for (id, now) in std::mem::take(&mut pending.modified) {
+            let times = &mut self.fds[id].times;
+            times.mtime = now;
+            times.ctime = now;
+        }
+        self.io.writes += std::mem::take(&mut pending.writes);
+        self.io.bytes_written += std::mem::take(&mut pending.bytes_written);
+    }
+}
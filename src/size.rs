@@ -0,0 +1,48 @@
+const UNITS: [&str; 7] = ["K", "M", "G", "T", "P", "E", "Z"];
+
+/// Parse a size with an optional `K`, `M`, `G`, ... suffix, in powers of
+/// 1024 (`K`, `KiB`) or 1000 (`KB`), as accepted by coreutils.
+pub fn parse_size(size: &str) -> Result<usize, String> {
+    let invalid = || format!("invalid size: '{}'", size);
+    let idx = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (digits, suffix) = size.split_at(idx);
+    let value: usize = digits.parse().map_err(|_| invalid())?;
+    if suffix.is_empty() {
+        return Ok(value);
+    }
+    let mut chars = suffix.chars();
+    let unit = chars.next().unwrap().to_ascii_uppercase().to_string();
+    let exp = UNITS.iter().position(|&u| u == unit).ok_or_else(invalid)? as u32 + 1;
+    let base: usize = match chars.as_str() {
+        "" | "iB" => 1024,
+        "B" => 1000,
+        _ => return Err(invalid()),
+    };
+    base.checked_pow(exp)
+        .and_then(|multiplier| value.checked_mul(multiplier))
+        .ok_or_else(invalid)
+}
+
+/// Format a size in powers of 1024 the way `ls -h` does: at most one
+/// decimal for values below ten, always rounded up.
+pub fn format_size(size: usize) -> String {
+    if size < 1024 {
+        return size.to_string();
+    }
+    let mut value = size as f64;
+    for unit in UNITS {
+        value /= 1024.0;
+        if value < 10.0 {
+            let tenths = (value * 10.0).ceil();
+            if tenths < 100.0 {
+                return format!("{:.1}{}", tenths / 10.0, unit);
+            }
+        }
+        if value.ceil() < 1024.0 {
+            return format!("{}{}", value.ceil(), unit);
+        }
+    }
+    format!("{}{}", value.ceil(), UNITS[UNITS.len() - 1])
+}
@@ -0,0 +1,112 @@
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+use crate::{format_size, Vfs};
+
+/// Counters of data moved through descriptors of regular files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoStats {
+    pub reads: usize,
+    pub writes: usize,
+    pub bytes_read: usize,
+    pub bytes_written: usize,
+}
+
+impl IoStats {
+    /// The activity since `earlier` was taken.
+    pub fn since(&self, earlier: &IoStats) -> IoStats {
+        IoStats {
+            reads: self.reads.saturating_sub(earlier.reads),
+            writes: self.writes.saturating_sub(earlier.writes),
+            bytes_read: self.bytes_read.saturating_sub(earlier.bytes_read),
+            bytes_written: self.bytes_written.saturating_sub(earlier.bytes_written),
+        }
+    }
+}
+
+impl fmt::Display for IoStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Reads: {} ({}) \tWrites: {} ({})",
+            self.reads,
+            format_size(self.bytes_read),
+            self.writes,
+            format_size(self.bytes_written)
+        )
+    }
+}
+
+/// Latencies bucketed by powers of two microseconds.
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    buckets: BTreeMap<u32, usize>,
+    count: usize,
+    total: Duration,
+    max: Duration,
+}
+
+impl Histogram {
+    pub fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros().max(1);
+        *self.buckets.entry(micros.ilog2()).or_insert(0) += 1;
+        self.count += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => self.total / count as u32,
+        }
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Non-empty buckets as the lower bound of their range and their count.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, usize)> + '_ {
+        self.buckets
+            .iter()
+            .map(|(&bucket, &count)| (Duration::from_micros(1 << bucket), count))
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Count: {} \tMean: {:?} \tMax: {:?}",
+            self.count,
+            self.mean(),
+            self.max
+        )?;
+        for (bound, count) in self.buckets() {
+            write!(f, "\n  >= {:?}: {}", bound, count)?;
+        }
+        Ok(())
+    }
+}
+
+impl Vfs {
+    pub fn io_stats(&self) -> IoStats {
+        self.io
+    }
+
+    pub(crate) fn record_read(&mut self, len: usize) {
+        self.io.reads += 1;
+        self.io.bytes_read += len;
+        self.stall(len);
+    }
+
+    pub(crate) fn record_write(&mut self, len: usize) {
+        self.io.writes += 1;
+        self.io.bytes_written += len;
+        self.stall(len);
+    }
+}
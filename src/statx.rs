@@ -0,0 +1,280 @@
+use std::fmt;
+
+use crate::{
+    format_size, mode_string, ErrorKind, FileDescriptor, FileType, Times, Timespec, Vfs, VfsError,
+    BLOCK_SIZE,
+};
+
+pub const STATX_TYPE: u32 = 0x1;
+pub const STATX_MODE: u32 = 0x2;
+pub const STATX_NLINK: u32 = 0x4;
+pub const STATX_UID: u32 = 0x8;
+pub const STATX_GID: u32 = 0x10;
+pub const STATX_ATIME: u32 = 0x20;
+pub const STATX_MTIME: u32 = 0x40;
+pub const STATX_CTIME: u32 = 0x80;
+pub const STATX_INO: u32 = 0x100;
+pub const STATX_SIZE: u32 = 0x200;
+/// The block count, which takes a pass over the block list of the file.
+pub const STATX_BLOCKS: u32 = 0x400;
+/// What `stat(2)` returns: every field above.
+pub const STATX_BASIC_STATS: u32 = 0x7ff;
+pub const STATX_BTIME: u32 = 0x800;
+pub const STATX_ALL: u32 = STATX_BASIC_STATS | STATX_BTIME;
+
+/// Metadata of one file, like `struct statx`. Only the fields in `mask()`
+/// are filled in; the others read as zero.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Statx {
+    mask: u32,
+    name: String,
+    inode: usize,
+    generation: u64,
+    uid: u32,
+    gid: u32,
+    mode: u32,
+    extended_acl: bool,
+    times: Times,
+    size: usize,
+    blocks: usize,
+    links: usize,
+    refs: usize,
+    file_type: String,
+    type_char: char,
+}
+
+impl Statx {
+    /// The `STATX_*` flags of the fields that were filled in: those
+    /// requested and possibly more that cost nothing to fill in.
+    pub fn mask(&self) -> u32 {
+        self.mask
+    }
+
+    /// The pathname the file was looked up by, followed by ` -> target`
+    /// for a symlink.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn inode(&self) -> usize {
+        self.inode
+    }
+
+    /// Bumped every time the inode number is reused, so that `inode` and
+    /// `generation` together identify one file for its whole lifetime.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    /// Permission bits; with an extended ACL the group bits are its mask.
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    /// Whether the file has an ACL beyond what its mode expresses.
+    pub fn has_extended_acl(&self) -> bool {
+        self.extended_acl
+    }
+
+    /// Time of the last read of the contents.
+    pub fn atime(&self) -> Timespec {
+        self.times.atime
+    }
+
+    /// Time of the last change of the contents.
+    pub fn mtime(&self) -> Timespec {
+        self.times.mtime
+    }
+
+    /// Time of the last change of the contents or the inode itself.
+    pub fn ctime(&self) -> Timespec {
+        self.times.ctime
+    }
+
+    /// Time the file was created.
+    pub fn btime(&self) -> Timespec {
+        self.times.btime
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Account for a change of the contents at `now` that the inode does
+    /// not show yet, see `SharedVfs::write_at`.
+    #[cfg(feature = "std")]
+    pub(crate) fn set_modified(&mut self, now: Timespec) {
+        self.times.mtime = now;
+        self.times.ctime = now;
+    }
+
+    /// Number of blocks allocated to the contents, which differs from the
+    /// size for sparse files. Only filled in with `STATX_BLOCKS`.
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+
+    pub fn links(&self) -> usize {
+        self.links
+    }
+
+    /// Number of open file descriptions of the file.
+    pub fn refs(&self) -> usize {
+        self.refs
+    }
+
+    /// The file type as `stat(1)` names it, e.g. `regular file`.
+    pub fn file_type(&self) -> &str {
+        &self.file_type
+    }
+
+    /// The file type as the first column of `ls -l` shows it, e.g. `d`.
+    pub fn type_char(&self) -> char {
+        self.type_char
+    }
+
+    /// Expand the directives of `fmt` like `stat -c` does, e.g. `%n %s %i`
+    /// gives the name, size and inode number. Supported directives are:
+    ///
+    /// * `%n` name, `%F` file type, `%s` size in bytes, `%b` blocks
+    ///   allocated, `%B` size of each block, `%h` links
+    /// * `%i` inode number, `%a` access rights in octal, `%A` in `ls -l`
+    ///   form, `%u` owner and `%g` group id
+    /// * `%x`, `%y`, `%z`, `%w` access, modify, change and birth time, and
+    ///   `%X`, `%Y`, `%Z`, `%W` the same in seconds since the epoch
+    /// * `%%` a literal `%`
+    ///
+    /// Unknown directives are copied as they are.
+    pub fn format(&self, fmt: &str) -> String {
+        let mut out = String::new();
+        let mut chars = fmt.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            let field = match chars.next() {
+                Some('%') => "%".to_string(),
+                Some('n') => self.name.clone(),
+                Some('F') => self.file_type.clone(),
+                Some('s') => self.size.to_string(),
+                Some('b') => self.blocks.to_string(),
+                Some('B') => BLOCK_SIZE.to_string(),
+                Some('h') => self.links.to_string(),
+                Some('i') => self.inode.to_string(),
+                Some('a') => format!("{:o}", self.mode),
+                Some('A') => format!("{}{}", self.type_char(), mode_string(self.mode)),
+                Some('u') => self.uid.to_string(),
+                Some('g') => self.gid.to_string(),
+                Some('x') => self.times.atime.to_string(),
+                Some('y') => self.times.mtime.to_string(),
+                Some('z') => self.times.ctime.to_string(),
+                Some('w') => self.times.btime.to_string(),
+                Some('X') => self.times.atime.sec.to_string(),
+                Some('Y') => self.times.mtime.sec.to_string(),
+                Some('Z') => self.times.ctime.sec.to_string(),
+                Some('W') => self.times.btime.sec.to_string(),
+                Some(other) => format!("%{}", other),
+                None => "%".to_string(),
+            };
+            out.push_str(&field);
+        }
+        out
+    }
+}
+
+/// The alternate form (`{:#}`) prints the size in human-readable units.
+impl fmt::Display for Statx {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let size = if f.alternate() {
+            format_size(self.size)
+        } else {
+            self.size.to_string()
+        };
+        write!(
+            f,
+            "File: {}\nSize: {} \tBlocks: {} \tLinks: {} \tRefs: {} \t {}\nInode: {} \tGeneration: {} \tAccess: {:04o}\nAccess: {}\nModify: {}\nChange: {}\n Birth: {}",
+            self.name,
+            size,
+            self.blocks,
+            self.links,
+            self.refs,
+            self.file_type,
+            self.inode,
+            self.generation,
+            self.mode,
+            self.times.atime,
+            self.times.mtime,
+            self.times.ctime,
+            self.times.btime
+        )
+    }
+}
+
+impl FileDescriptor {
+    /// Fill in a `Statx` for inode `id` looked up by `name`, skipping the
+    /// expensive fields not in `mask`.
+    pub(crate) fn statx(&self, id: usize, name: &str, mask: u32) -> Statx {
+        let blocks = match &self.file_type {
+            FileType::Regular(blocks_refs) if mask & STATX_BLOCKS != 0 => {
+                blocks_refs.iter().filter(|&&id| id != 0).count()
+            }
+            _ => 0,
+        };
+        Statx {
+            mask: STATX_ALL & !STATX_BLOCKS | mask & STATX_BLOCKS,
+            name: if self.file_type.is_symlink() {
+                format!("{} -> {}", name, self.file_type.as_symlink())
+            } else {
+                name.to_string()
+            },
+            inode: id,
+            generation: self.generation,
+            uid: self.uid,
+            gid: self.gid,
+            mode: self.mode,
+            extended_acl: !self.acl.is_empty(),
+            times: self.times,
+            size: self.size,
+            blocks,
+            links: self.links,
+            refs: self.refs,
+            file_type: format!("{}", self.file_type),
+            type_char: self.file_type.type_char(),
+        }
+    }
+}
+
+impl Vfs {
+    /// Metadata of `pathname` with every field filled in.
+    pub fn stat(&self, pathname: &str) -> Result<Statx, VfsError> {
+        self.statx(pathname, STATX_ALL)
+    }
+
+    /// Metadata of `pathname`, computing at least the fields in `mask`
+    /// (`STATX_*` flags), like `statx(2)`.
+    pub fn statx(&self, pathname: &str, mask: u32) -> Result<Statx, VfsError> {
+        match self.resolve(pathname) {
+            Some((fd, id, _)) => Ok(fd.statx(id, pathname, mask)),
+            None => {
+                let message = VfsError::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "stat: cannot statx '{}': No such file or directory",
+                        pathname
+                    ),
+                );
+                Err(self.error_at(message, pathname))
+            }
+        }
+    }
+}
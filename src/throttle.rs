@@ -0,0 +1,59 @@
+use std::{fmt, thread, time::Duration};
+
+use crate::{format_size, Vfs};
+
+/// Simulated device speed: every `read` and `write` of a regular file
+/// sleeps for a fixed latency plus the time its bytes take at the given
+/// rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Throttle {
+    /// Bytes per second, or 0 for no bandwidth limit.
+    pub bytes_per_sec: usize,
+    pub latency: Duration,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: usize, latency: Duration) -> Self {
+        Self {
+            bytes_per_sec,
+            latency,
+        }
+    }
+
+    /// How long a transfer of `len` bytes takes.
+    pub fn delay(&self, len: usize) -> Duration {
+        let transfer = match self.bytes_per_sec {
+            0 => Duration::ZERO,
+            rate => Duration::from_secs_f64(len as f64 / rate as f64),
+        };
+        self.latency + transfer
+    }
+}
+
+impl fmt::Display for Throttle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.bytes_per_sec {
+            0 => write!(f, "Rate: unlimited")?,
+            rate => write!(f, "Rate: {}/s", format_size(rate))?,
+        }
+        write!(f, " \tLatency: {:?}", self.latency)
+    }
+}
+
+impl Vfs {
+    /// Throttle reads and writes of regular files, or stop throttling.
+    pub fn set_throttle(&mut self, throttle: Option<Throttle>) {
+        self.throttle = throttle;
+    }
+
+    pub fn throttle(&self) -> Option<Throttle> {
+        self.throttle
+    }
+
+    /// Sleep as long as transferring `len` bytes takes under the throttle.
+    pub(crate) fn stall(&self, len: usize) {
+        if let Some(throttle) = &self.throttle {
+            thread::sleep(throttle.delay(len));
+        }
+    }
+}
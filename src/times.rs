@@ -0,0 +1,305 @@
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fmt, str::FromStr};
+
+use crate::{ErrorKind, Op, Vfs, VfsError, ATTR_APPEND, ATTR_IMMUTABLE, W_OK};
+
+/// A point in time as seconds and nanoseconds since the Unix epoch, like
+/// `struct timespec`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timespec {
+    pub sec: i64,
+    pub nsec: u32,
+}
+
+impl Timespec {
+    pub fn new(sec: i64, nsec: u32) -> Self {
+        Self { sec, nsec }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<SystemTime> for Timespec {
+    fn from(time: SystemTime) -> Self {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => Timespec::new(elapsed.as_secs() as i64, elapsed.subsec_nanos()),
+            Err(err) => {
+                let before = err.duration();
+                match before.subsec_nanos() {
+                    0 => Timespec::new(-(before.as_secs() as i64), 0),
+                    nsec => Timespec::new(-(before.as_secs() as i64) - 1, 1_000_000_000 - nsec),
+                }
+            }
+        }
+    }
+}
+
+/// The `stat(1)` form in UTC, e.g. `2024-05-01 12:00:00.000000000 +0000`.
+impl fmt::Display for Timespec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let days = self.sec.div_euclid(86400);
+        let secs = self.sec.rem_euclid(86400);
+        // Civil date from days since the epoch, after Howard Hinnant's
+        // `civil_from_days`.
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:09} +0000",
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            self.nsec
+        )
+    }
+}
+
+/// How `utimens` sets one timestamp, as `UTIME_NOW` and `UTIME_OMIT` do
+/// for `utimensat(2)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetTime {
+    Now,
+    Omit,
+    At(Timespec),
+}
+
+/// `now`, `omit`, or seconds since the epoch with optional nanoseconds,
+/// e.g. `1700000000.5`.
+impl fmt::Display for SetTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SetTime::Now => write!(f, "now"),
+            SetTime::Omit => write!(f, "omit"),
+            SetTime::At(time) => write!(f, "{}.{:09}", time.sec, time.nsec),
+        }
+    }
+}
+
+impl FromStr for SetTime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid time: {}", s);
+        match s {
+            "now" => return Ok(SetTime::Now),
+            "omit" => return Ok(SetTime::Omit),
+            _ => {}
+        }
+        let (sec, frac) = s.split_once('.').unwrap_or((s, ""));
+        if frac.len() > 9 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let nsec = format!("{:0<9}", frac).parse().map_err(|_| invalid())?;
+        let sec = sec.parse().map_err(|_| invalid())?;
+        Ok(SetTime::At(Timespec::new(sec, nsec)))
+    }
+}
+
+/// When reading a file updates its access time, after the mount options
+/// of the same names.
+///
+/// Only reads through a descriptor, i.e. `Vfs::read` and what builds on
+/// it such as `VfsFile` and `read_lines`, apply the policy. Path-based
+/// reads such as `read_file`, `hash_file` and `file_stats` borrow the
+/// filesystem shared, so that they can run in parallel under `SharedVfs`,
+/// and leave the access time alone under every policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AtimePolicy {
+    /// Never; reads leave the inode untouched.
+    NoAtime,
+    /// Only if the access time is not after the modification or change
+    /// time, or is more than a day old.
+    #[default]
+    Relatime,
+    /// On every read.
+    StrictAtime,
+}
+
+impl fmt::Display for AtimePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AtimePolicy::NoAtime => write!(f, "noatime"),
+            AtimePolicy::Relatime => write!(f, "relatime"),
+            AtimePolicy::StrictAtime => write!(f, "strictatime"),
+        }
+    }
+}
+
+impl FromStr for AtimePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "noatime" => Ok(AtimePolicy::NoAtime),
+            "relatime" => Ok(AtimePolicy::Relatime),
+            "strictatime" => Ok(AtimePolicy::StrictAtime),
+            _ => Err(format!("invalid atime policy: {}", s)),
+        }
+    }
+}
+
+/// The timestamps of an inode: last access, last modification of the
+/// contents, last change of the inode, and creation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Times {
+    pub(crate) atime: Timespec,
+    pub(crate) mtime: Timespec,
+    pub(crate) ctime: Timespec,
+    pub(crate) btime: Timespec,
+}
+
+impl Times {
+    pub(crate) fn at(now: Timespec) -> Self {
+        Self {
+            atime: now,
+            mtime: now,
+            ctime: now,
+            btime: now,
+        }
+    }
+}
+
+impl Vfs {
+    /// The current time: the system clock, or with a seed or without the
+    /// `std` feature a clock that advances one second per call so that
+    /// timestamps are reproducible.
+    pub(crate) fn now(&mut self) -> Timespec {
+        match self.seed {
+            #[cfg(feature = "std")]
+            None => SystemTime::now().into(),
+            _ => {
+                self.ticks += 1;
+                Timespec::new(self.ticks as i64, 0)
+            }
+        }
+    }
+
+    /// Record that the contents of inode `id` changed.
+    pub(crate) fn touch_modified(&mut self, id: usize) {
+        let now = self.now();
+        let times = &mut self.fds[id].times;
+        times.mtime = now;
+        times.ctime = now;
+    }
+
+    /// Record that inode `id` itself (links, mode, owner) changed.
+    pub(crate) fn touch_changed(&mut self, id: usize) {
+        self.fds[id].times.ctime = self.now();
+    }
+
+    /// Record that the contents of inode `id` were read, as far as the
+    /// atime policy asks for it and unless the filesystem is read-only.
+    pub(crate) fn touch_accessed(&mut self, id: usize) {
+        if self.is_read_only() || self.atime == AtimePolicy::NoAtime || self.is_noatime_mount(id) {
+            return;
+        }
+        let now = self.now();
+        let times = &mut self.fds[id].times;
+        let stale = times.atime <= times.mtime
+            || times.atime <= times.ctime
+            || now.sec - times.atime.sec >= 86400;
+        if self.atime == AtimePolicy::StrictAtime || stale {
+            times.atime = now;
+        }
+    }
+
+    /// The atime policy chosen with `VfsBuilder::atime`.
+    pub fn atime_policy(&self) -> AtimePolicy {
+        self.atime
+    }
+
+    /// Set the access and modification times of `pathname`, like
+    /// `utimensat(2)`: either may be the current time or left alone. The
+    /// change time is updated unless both are omitted. A symlink in the
+    /// last component is followed when `follow` is set and changed itself
+    /// otherwise.
+    pub fn utimens(
+        &mut self,
+        pathname: &str,
+        atime: SetTime,
+        mtime: SetTime,
+        follow: bool,
+    ) -> Result<(), VfsError> {
+        let result = self.utimens_unaudited(pathname, atime, mtime, follow);
+        self.audit(
+            || Op::Utimens {
+                pathname: pathname.to_string(),
+                atime,
+                mtime,
+                follow,
+            },
+            &result,
+        );
+        result.map_err(|err| self.error_at(err, pathname))
+    }
+
+    fn utimens_unaudited(
+        &mut self,
+        pathname: &str,
+        atime: SetTime,
+        mtime: SetTime,
+        follow: bool,
+    ) -> Result<(), VfsError> {
+        let context = || format!("touch: cannot touch '{}'", pathname);
+        self.check_writable_at(pathname, context)?;
+        let resolved = if follow {
+            self.resolve_follow(pathname)
+        } else {
+            self.resolve(pathname)
+        };
+        let id = match resolved {
+            Some((_, id, _)) => id,
+            None => {
+                return Err(VfsError::new(
+                    ErrorKind::NotFound,
+                    format!("{}: No such file or directory", context()),
+                ))
+            }
+        };
+        if atime == SetTime::Omit && mtime == SetTime::Omit {
+            return Ok(());
+        }
+        let explicit = matches!(atime, SetTime::At(_)) || matches!(mtime, SetTime::At(_));
+        let uid = self.session.uid();
+        let owner = uid == 0 || uid == self.fds[id].uid;
+        if explicit {
+            if !owner {
+                return Err(VfsError::new(
+                    ErrorKind::NotPermitted,
+                    format!("{}: Operation not permitted", context()),
+                ));
+            }
+            self.check_attrs(id, ATTR_IMMUTABLE | ATTR_APPEND, context)?;
+        } else {
+            if !owner {
+                self.check_access(id, W_OK, context)?;
+            }
+            self.check_attrs(id, ATTR_IMMUTABLE, context)?;
+        }
+        let now = self.now();
+        let resolve = |time| match time {
+            SetTime::Now => Some(now),
+            SetTime::Omit => None,
+            SetTime::At(time) => Some(time),
+        };
+        let times = &mut self.fds[id].times;
+        if let Some(atime) = resolve(atime) {
+            times.atime = atime;
+        }
+        if let Some(mtime) = resolve(mtime) {
+            times.mtime = mtime;
+        }
+        times.ctime = now;
+        Ok(())
+    }
+}
@@ -0,0 +1,374 @@
+//! A dual-pane file manager over the current volume, in the style of
+//! Midnight Commander.
+
+use std::io;
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Block, List, ListItem, ListState, Paragraph},
+    DefaultTerminal, Frame,
+};
+use vfs::{Vfs, WalkEntry};
+
+const KEYS: &str =
+    "Tab pane  Enter open  F2 rename  F3 view  F5 copy  F6 move  F7 mkdir  F8 delete  q quit";
+const PAGE: usize = 10;
+
+/// Browse the filesystem until the user quits. All changes go through the
+/// `Vfs` API, so they are checked and audited like shell commands.
+pub fn run(vfs: &mut Vfs) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = App::new(vfs).run(vfs, &mut terminal);
+    ratatui::restore();
+    result
+}
+
+/// One side of the screen: the entries of a directory and a cursor.
+struct Pane {
+    dir: String,
+    entries: Vec<WalkEntry>,
+    state: ListState,
+}
+
+impl Pane {
+    fn new(vfs: &Vfs, dir: &str) -> Self {
+        let mut pane = Self {
+            dir: dir.to_string(),
+            entries: Vec::new(),
+            state: ListState::default().with_selected(Some(0)),
+        };
+        pane.refresh(vfs);
+        pane
+    }
+
+    /// Re-read the directory, moving up while it no longer exists.
+    fn refresh(&mut self, vfs: &Vfs) {
+        loop {
+            match vfs.walk(&self.dir) {
+                Ok(walk) => {
+                    self.entries = walk.max_depth(1).collect();
+                    break;
+                }
+                Err(_) if self.dir != "/" => self.dir = Vfs::dirname(&self.dir),
+                Err(_) => {
+                    self.entries.clear();
+                    break;
+                }
+            }
+        }
+        let last = self.len().saturating_sub(1);
+        self.state
+            .select(Some(self.state.selected().unwrap_or(0).min(last)));
+    }
+
+    /// Rows before the entries: `..` everywhere but the root.
+    fn offset(&self) -> usize {
+        usize::from(self.dir != "/")
+    }
+
+    fn len(&self) -> usize {
+        self.offset() + self.entries.len()
+    }
+
+    /// The entry under the cursor, or `None` on `..`.
+    fn selected(&self) -> Option<&WalkEntry> {
+        let row = self.state.selected()?;
+        self.entries.get(row.checked_sub(self.offset())?)
+    }
+
+    fn path(&self, name: &str) -> String {
+        format!("{}/{}", self.dir.trim_end_matches('/'), name)
+    }
+
+    fn enter(&mut self, vfs: &Vfs, dir: String) {
+        self.dir = dir;
+        self.state.select(Some(0));
+        self.refresh(vfs);
+    }
+
+    /// Go to the parent directory with the cursor on the one left.
+    fn leave(&mut self, vfs: &Vfs) {
+        if self.dir == "/" {
+            return;
+        }
+        let left = Vfs::basename(&self.dir);
+        self.enter(vfs, Vfs::dirname(&self.dir));
+        if let Some(i) = self.entries.iter().position(|entry| entry.name == left) {
+            self.state.select(Some(self.offset() + i));
+        }
+    }
+
+    fn move_by(&mut self, delta: isize) {
+        let row = self
+            .state
+            .selected()
+            .unwrap_or(0)
+            .saturating_add_signed(delta);
+        self.state
+            .select(Some(row.min(self.len().saturating_sub(1))));
+    }
+}
+
+enum Mode {
+    Browse,
+    View {
+        title: String,
+        lines: Vec<String>,
+        scroll: usize,
+    },
+    /// Reading a name for `rename` (of the given path) or `mkdir`.
+    Input {
+        rename: Option<String>,
+        text: String,
+    },
+    ConfirmDelete(WalkEntry),
+}
+
+struct App {
+    panes: [Pane; 2],
+    active: usize,
+    mode: Mode,
+    /// The outcome of the last action, shown instead of the key help.
+    message: Option<String>,
+}
+
+impl App {
+    fn new(vfs: &Vfs) -> Self {
+        Self {
+            panes: [Pane::new(vfs, vfs.cwd()), Pane::new(vfs, vfs.cwd())],
+            active: 0,
+            mode: Mode::Browse,
+            message: None,
+        }
+    }
+
+    fn run(&mut self, vfs: &mut Vfs, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(vfs, frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            self.message = None;
+            let mode = std::mem::replace(&mut self.mode, Mode::Browse);
+            let result = match mode {
+                Mode::Browse => match key.code {
+                    KeyCode::Char('q') | KeyCode::F(10) | KeyCode::Esc => return Ok(()),
+                    code => self.browse(vfs, code),
+                },
+                Mode::View {
+                    title,
+                    lines,
+                    scroll,
+                } => {
+                    let scroll = match key.code {
+                        KeyCode::Char('q') | KeyCode::F(3) | KeyCode::Esc => continue,
+                        KeyCode::Down | KeyCode::Char('j') => scroll.saturating_add(1),
+                        KeyCode::Up | KeyCode::Char('k') => scroll.saturating_sub(1),
+                        KeyCode::PageDown | KeyCode::Char(' ') => scroll.saturating_add(PAGE),
+                        KeyCode::PageUp | KeyCode::Char('b') => scroll.saturating_sub(PAGE),
+                        KeyCode::Home | KeyCode::Char('g') => 0,
+                        KeyCode::End | KeyCode::Char('G') => usize::MAX,
+                        _ => scroll,
+                    };
+                    let scroll = scroll.min(lines.len().saturating_sub(1));
+                    self.mode = Mode::View {
+                        title,
+                        lines,
+                        scroll,
+                    };
+                    Ok(())
+                }
+                Mode::Input { rename, mut text } => match key.code {
+                    KeyCode::Enter => self.finish_input(vfs, rename, &text),
+                    KeyCode::Esc => Ok(()),
+                    code => {
+                        match code {
+                            KeyCode::Backspace => {
+                                text.pop();
+                            }
+                            KeyCode::Char(c) => text.push(c),
+                            _ => {}
+                        }
+                        self.mode = Mode::Input { rename, text };
+                        Ok(())
+                    }
+                },
+                Mode::ConfirmDelete(entry) => match key.code {
+                    KeyCode::Char('y') => vfs.remove_all(&entry.path).map_err(String::from),
+                    _ => Ok(()),
+                },
+            };
+            if let Err(err) = result {
+                self.message = Some(err);
+            }
+            for pane in &mut self.panes {
+                pane.refresh(vfs);
+            }
+        }
+    }
+
+    fn browse(&mut self, vfs: &mut Vfs, code: KeyCode) -> Result<(), String> {
+        let (active, other) = match &mut self.panes {
+            [left, right] if self.active == 0 => (left, right),
+            [left, right] => (right, left),
+        };
+        match code {
+            KeyCode::Tab => self.active ^= 1,
+            KeyCode::Down | KeyCode::Char('j') => active.move_by(1),
+            KeyCode::Up | KeyCode::Char('k') => active.move_by(-1),
+            KeyCode::PageDown => active.move_by(PAGE as isize),
+            KeyCode::PageUp => active.move_by(-(PAGE as isize)),
+            KeyCode::Home | KeyCode::Char('g') => active.state.select(Some(0)),
+            KeyCode::End | KeyCode::Char('G') => active.move_by(isize::MAX),
+            KeyCode::Backspace | KeyCode::Left | KeyCode::Char('h') => active.leave(vfs),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => match active.selected() {
+                None => active.leave(vfs),
+                Some(entry) if entry.type_char == 'd' => {
+                    let dir = entry.path.clone();
+                    active.enter(vfs, dir);
+                }
+                Some(entry) => self.mode = view(vfs, &entry.path)?,
+            },
+            KeyCode::F(3) | KeyCode::Char('v') => {
+                if let Some(entry) = active.selected() {
+                    self.mode = view(vfs, &entry.path)?;
+                }
+            }
+            KeyCode::F(5) | KeyCode::Char('c') => {
+                if let Some(entry) = active.selected() {
+                    vfs.copy_recursive(&entry.path, &other.path(&entry.name))?;
+                    self.message = Some(format!("Copied '{}' to '{}'", entry.path, other.dir));
+                }
+            }
+            KeyCode::F(6) | KeyCode::Char('m') => {
+                if let Some(entry) = active.selected() {
+                    vfs.rename(&entry.path, &other.path(&entry.name))?;
+                    self.message = Some(format!("Moved '{}' to '{}'", entry.path, other.dir));
+                }
+            }
+            KeyCode::F(2) | KeyCode::Char('r') => {
+                if let Some(entry) = active.selected() {
+                    self.mode = Mode::Input {
+                        rename: Some(entry.path.clone()),
+                        text: entry.name.clone(),
+                    };
+                }
+            }
+            KeyCode::F(7) | KeyCode::Char('n') => {
+                self.mode = Mode::Input {
+                    rename: None,
+                    text: String::new(),
+                }
+            }
+            KeyCode::F(8) | KeyCode::Delete | KeyCode::Char('d') => {
+                if let Some(entry) = active.selected() {
+                    self.mode = Mode::ConfirmDelete(entry.clone());
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish_input(
+        &mut self,
+        vfs: &mut Vfs,
+        rename: Option<String>,
+        name: &str,
+    ) -> Result<(), String> {
+        if name.is_empty() {
+            return Ok(());
+        }
+        let pathname = self.panes[self.active].path(name);
+        match rename {
+            Some(path) => {
+                vfs.rename(&path, &pathname)?;
+            }
+            None => vfs.mkdir(&pathname)?,
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, vfs: &Vfs, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let status_line = match &self.mode {
+            Mode::View {
+                title,
+                lines,
+                scroll,
+            } => {
+                let text = lines[*scroll..].join("\n");
+                let block = Block::bordered().title(title.as_str());
+                frame.render_widget(Paragraph::new(text).block(block), main);
+                "Up/Down scroll  q close".to_string()
+            }
+            mode => {
+                let [left, right] =
+                    Layout::horizontal([Constraint::Fill(1), Constraint::Fill(1)]).areas(main);
+                for (i, area) in [left, right].into_iter().enumerate() {
+                    draw_pane(vfs, frame, &mut self.panes[i], area, i == self.active);
+                }
+                match mode {
+                    Mode::Input {
+                        rename: Some(_),
+                        text,
+                    } => format!("Rename to: {}", text),
+                    Mode::Input { rename: None, text } => format!("New directory: {}", text),
+                    Mode::ConfirmDelete(entry) => format!("Delete '{}'? (y/n)", entry.path),
+                    _ => self.message.clone().unwrap_or_else(|| KEYS.to_string()),
+                }
+            }
+        };
+        let style = Style::new().add_modifier(Modifier::REVERSED);
+        frame.render_widget(Paragraph::new(status_line).style(style), status);
+    }
+}
+
+/// Draw `pane`, coloring names by file type as `ls --color` does.
+fn draw_pane(vfs: &Vfs, frame: &mut Frame, pane: &mut Pane, area: Rect, active: bool) {
+    let mut items = Vec::new();
+    if pane.offset() == 1 {
+        items.push(ListItem::new(".."));
+    }
+    for entry in &pane.entries {
+        let bold = Style::new().add_modifier(Modifier::BOLD);
+        let (text, style) = match (&entry.target, entry.type_char) {
+            (Some(target), _) => {
+                let broken = vfs.trace_resolve(&entry.path).error.is_some();
+                let color = if broken { Color::Red } else { Color::Cyan };
+                (format!("{} -> {}", entry.name, target), bold.fg(color))
+            }
+            (None, 'd') => (format!("{}/", entry.name), bold.fg(Color::Blue)),
+            (None, 'p') => (entry.name.clone(), Style::new().fg(Color::Yellow)),
+            (None, 'c' | 'b') => (entry.name.clone(), bold.fg(Color::Yellow)),
+            (None, _) => (entry.name.clone(), Style::new()),
+        };
+        items.push(ListItem::new(Line::styled(text, style)));
+    }
+    let mut block = Block::bordered().title(pane.dir.as_str());
+    let mut list = List::new(items);
+    if active {
+        block = block.title_style(Style::new().add_modifier(Modifier::BOLD));
+        list = list.highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    }
+    frame.render_stateful_widget(list.block(block), area, &mut pane.state);
+}
+
+fn view(vfs: &Vfs, path: &str) -> Result<Mode, String> {
+    let data = vfs.read_file_as("view", path)?;
+    Ok(Mode::View {
+        title: path.to_string(),
+        lines: String::from_utf8_lossy(&data)
+            .lines()
+            .map(str::to_string)
+            .collect(),
+        scroll: 0,
+    })
+}
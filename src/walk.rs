@@ -0,0 +1,165 @@
+use std::collections::btree_map;
+
+use crate::{ErrorKind, FileType, Vfs, VfsError, DOT, DOTDOT, R_OK};
+
+/// A file found below the directory a walk started at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalkEntry {
+    pub path: String,
+    pub name: String,
+    pub id: usize,
+    /// 1 for the entries of the starting directory, 2 for theirs, etc.
+    pub depth: usize,
+    /// The file type as `ls -l` shows it, e.g. `d`.
+    pub type_char: char,
+    /// What a symlink points to; symlinks are never followed.
+    pub target: Option<String>,
+    /// Whether this is the last entry of its directory that the walk
+    /// yields.
+    pub last: bool,
+}
+
+struct Frame<'a> {
+    path: String,
+    depth: usize,
+    entries: btree_map::Iter<'a, String, usize>,
+    next: Option<(&'a String, usize)>,
+}
+
+/// Depth-first walk of a directory tree in name order, yielding each
+/// directory before its entries. Only one directory is read at a time, so
+/// walking a huge tree does not collect it first.
+pub struct Walk<'a> {
+    vfs: &'a Vfs,
+    stack: Vec<Frame<'a>>,
+    max_depth: Option<usize>,
+    dirs_only: bool,
+}
+
+impl<'a> Walk<'a> {
+    /// Do not descend below `depth`; 1 yields only the entries of the
+    /// starting directory.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Yield only directories.
+    pub fn dirs_only(mut self) -> Self {
+        self.dirs_only = true;
+        for frame in &mut self.stack {
+            if frame
+                .next
+                .is_some_and(|(_, id)| !self.vfs.fds[id].file_type.is_dir())
+            {
+                frame.next = Self::next_entry(self.vfs, true, &mut frame.entries);
+            }
+        }
+        self
+    }
+
+    fn frame(vfs: &'a Vfs, dirs_only: bool, path: String, depth: usize, id: usize) -> Frame<'a> {
+        let mut entries = vfs.fds[id].file_type.as_dir().iter();
+        let next = Self::next_entry(vfs, dirs_only, &mut entries);
+        Frame {
+            path,
+            depth,
+            entries,
+            next,
+        }
+    }
+
+    fn next_entry(
+        vfs: &Vfs,
+        dirs_only: bool,
+        entries: &mut btree_map::Iter<'a, String, usize>,
+    ) -> Option<(&'a String, usize)> {
+        entries
+            .find(|&(name, &id)| {
+                name != DOT && name != DOTDOT && (!dirs_only || vfs.fds[id].file_type.is_dir())
+            })
+            .map(|(name, &id)| (name, id))
+    }
+}
+
+impl Iterator for Walk<'_> {
+    type Item = WalkEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let frame = self.stack.last_mut()?;
+            let Some((name, id)) = frame.next else {
+                self.stack.pop();
+                continue;
+            };
+            frame.next = Self::next_entry(self.vfs, self.dirs_only, &mut frame.entries);
+            let path = format!("{}/{}", frame.path.trim_end_matches('/'), name);
+            let depth = frame.depth + 1;
+            let last = frame.next.is_none();
+            let file_type = &self.vfs.fds[id].file_type;
+            let descend = file_type.is_dir()
+                && self.max_depth.is_none_or(|max_depth| depth < max_depth)
+                && self.vfs.check_access(id, R_OK, String::new).is_ok();
+            if descend {
+                let frame = Self::frame(self.vfs, self.dirs_only, path.clone(), depth, id);
+                self.stack.push(frame);
+            }
+            return Some(WalkEntry {
+                path,
+                name: name.clone(),
+                id,
+                depth,
+                type_char: file_type.type_char(),
+                target: match file_type {
+                    FileType::Symlink(target) => Some(target.clone()),
+                    _ => None,
+                },
+                last,
+            });
+        }
+    }
+}
+
+impl Vfs {
+    /// Walk the tree below directory `pathname` without following
+    /// symlinks. Directories the session cannot read are yielded but not
+    /// descended into.
+    pub fn walk(&self, pathname: &str) -> Result<Walk<'_>, VfsError> {
+        self.walk_as("tree", pathname)
+    }
+
+    /// Like `walk`, with errors reported as coming from the shell command
+    /// `cmd`.
+    pub fn walk_as(&self, cmd: &str, pathname: &str) -> Result<Walk<'_>, VfsError> {
+        let id = match self.resolve(pathname) {
+            Some((fd, id, _)) if fd.file_type.is_dir() => id,
+            Some(_) => {
+                return Err(VfsError::new(
+                    ErrorKind::NotADirectory,
+                    format!(
+                        "{}: cannot open directory '{}': Not a directory",
+                        cmd, pathname
+                    ),
+                ))
+            }
+            None => {
+                return Err(VfsError::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "{}: cannot access '{}': No such file or directory",
+                        cmd, pathname
+                    ),
+                ))
+            }
+        };
+        self.check_access(id, R_OK, || {
+            format!("{}: cannot open directory '{}'", cmd, pathname)
+        })?;
+        Ok(Walk {
+            vfs: self,
+            stack: vec![Walk::frame(self, false, pathname.to_string(), 0, id)],
+            max_depth: None,
+            dirs_only: false,
+        })
+    }
+}
@@ -0,0 +1,128 @@
+use vfs::{AclEntry, AclTag, ErrorKind, Session, Vfs, R_OK, W_OK, X_OK};
+
+const OWNER: u32 = 1000;
+const NAMED_USER: u32 = 2000;
+const GROUP: u32 = 100;
+const NAMED_GROUP: u32 = 300;
+
+fn acl(entries: &[&str]) -> Vec<AclEntry> {
+    entries.iter().map(|entry| entry.parse().unwrap()).collect()
+}
+
+/// `/file` owned by `OWNER:GROUP`, where the owner may do less than the
+/// named user, the groups and others.
+fn file() -> Vfs {
+    let mut vfs = Vfs::new();
+    vfs.create("/file").unwrap();
+    vfs.chown("/file", Some(OWNER), Some(GROUP)).unwrap();
+    vfs.set_acl(
+        "/file",
+        &acl(&[
+            "user::r--",
+            &format!("user:{}:rw-", NAMED_USER),
+            "group::r--",
+            &format!("group:{}:rw-", NAMED_GROUP),
+            "mask::rwx",
+            "other::rwx",
+        ]),
+    )
+    .unwrap();
+    vfs
+}
+
+/// The permissions out of `R_OK`, `W_OK` and `X_OK` that `uid:gid` has on
+/// `/file`.
+fn granted(vfs: &mut Vfs, uid: u32, gid: u32) -> u32 {
+    let previous = vfs.switch_session(Session::new().with_identity(uid, gid));
+    let granted = [R_OK, W_OK, X_OK]
+        .into_iter()
+        .filter(|&want| vfs.access("/file", want).is_ok())
+        .fold(0, |granted, want| granted | want);
+    vfs.switch_session(previous);
+    granted
+}
+
+#[test]
+fn the_owner_entry_decides_for_the_owner() {
+    let mut vfs = file();
+    assert_eq!(granted(&mut vfs, OWNER, GROUP), R_OK);
+    assert_eq!(granted(&mut vfs, OWNER, NAMED_GROUP), R_OK);
+}
+
+#[test]
+fn a_named_user_entry_comes_before_the_groups() {
+    let mut vfs = file();
+    assert_eq!(granted(&mut vfs, NAMED_USER, GROUP), R_OK | W_OK);
+    assert_eq!(granted(&mut vfs, NAMED_USER, 999), R_OK | W_OK);
+}
+
+#[test]
+fn group_entries_come_before_other() {
+    let mut vfs = file();
+    assert_eq!(granted(&mut vfs, 3000, GROUP), R_OK);
+    assert_eq!(granted(&mut vfs, 3000, NAMED_GROUP), R_OK | W_OK);
+    assert_eq!(granted(&mut vfs, 3000, 999), R_OK | W_OK | X_OK);
+}
+
+#[test]
+fn the_mask_limits_named_entries_and_groups_but_not_owner_or_other() {
+    let mut vfs = file();
+    vfs.chmod("/file", 0o447).unwrap();
+    let entries = vfs.get_acl("/file").unwrap();
+    assert!(entries.contains(&AclEntry::new(AclTag::Mask, R_OK)));
+    assert_eq!(granted(&mut vfs, OWNER, GROUP), R_OK);
+    assert_eq!(granted(&mut vfs, NAMED_USER, GROUP), R_OK);
+    assert_eq!(granted(&mut vfs, 3000, NAMED_GROUP), R_OK);
+    assert_eq!(granted(&mut vfs, 3000, 999), R_OK | W_OK | X_OK);
+    assert_eq!(granted(&mut vfs, 0, 0), R_OK | W_OK | X_OK);
+}
+
+#[test]
+fn set_acl_adds_a_mask_and_sorts_entries() {
+    let mut vfs = Vfs::new();
+    vfs.create("/file").unwrap();
+    vfs.set_acl(
+        "/file",
+        &acl(&["other::---", "group:7:r-x", "group::r--", "user::rw-"]),
+    )
+    .unwrap();
+    let text: Vec<_> = vfs
+        .get_acl("/file")
+        .unwrap()
+        .iter()
+        .map(|entry| entry.to_string())
+        .collect();
+    assert_eq!(
+        text,
+        [
+            "user::rw-",
+            "group::r--",
+            "group:7:r-x",
+            "mask::r-x",
+            "other::---"
+        ]
+    );
+    assert_eq!(vfs.stat("/file").unwrap().mode() & 0o777, 0o650);
+    assert!(vfs.stat("/file").unwrap().has_extended_acl());
+}
+
+#[test]
+fn invalid_acls_and_non_owners_are_refused() {
+    let mut vfs = file();
+    let err = vfs
+        .set_acl("/file", &acl(&["user::rw-", "group::r--"]))
+        .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+    let err = vfs
+        .set_acl(
+            "/file",
+            &acl(&["user::rw-", "user::r--", "group::r--", "other::---"]),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+    vfs.switch_session(Session::new().with_identity(NAMED_USER, GROUP));
+    let err = vfs
+        .set_acl("/file", &acl(&["user::rwx", "group::rwx", "other::rwx"]))
+        .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::NotPermitted);
+}
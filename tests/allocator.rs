@@ -0,0 +1,61 @@
+//! Block placement is not visible directly, but `compact` renumbers blocks
+//! so that each file is contiguous and files follow in inode order, so it
+//! moves nothing exactly when the allocator already laid them out that way.
+
+use vfs::{Vfs, VfsBuilder};
+
+fn data(vfs: &Vfs, blocks: usize, fill: u8) -> Vec<u8> {
+    vec![fill; blocks * vfs.statfs().block_size]
+}
+
+#[test]
+fn files_written_one_after_another_are_contiguous() {
+    let mut vfs = Vfs::new();
+    vfs.write_file("/a", &data(&vfs, 4, 1)).unwrap();
+    vfs.write_file("/b", &data(&vfs, 3, 2)).unwrap();
+    let report = vfs.compact().unwrap();
+    assert_eq!(report.moved, 0);
+}
+
+#[test]
+fn appends_continue_next_to_the_last_block_once_it_is_free() {
+    let mut vfs = Vfs::new();
+    vfs.write_file("/a", &data(&vfs, 2, 1)).unwrap();
+    vfs.write_file("/b", &data(&vfs, 2, 2)).unwrap();
+    vfs.unlink("/b").unwrap();
+    let oid = vfs.open("/a").unwrap();
+    vfs.seek(oid, 2 * vfs.statfs().block_size).unwrap();
+    vfs.write(oid, &data(&vfs, 2, 3)).unwrap();
+    vfs.close(oid).unwrap();
+    assert_eq!(vfs.compact().unwrap().moved, 0);
+}
+
+#[test]
+fn appends_skip_blocks_taken_by_other_files() {
+    let mut vfs = Vfs::new();
+    vfs.write_file("/a", &data(&vfs, 2, 1)).unwrap();
+    vfs.write_file("/b", &data(&vfs, 2, 2)).unwrap();
+    let oid = vfs.open("/a").unwrap();
+    vfs.seek(oid, 2 * vfs.statfs().block_size).unwrap();
+    vfs.write(oid, &data(&vfs, 2, 3)).unwrap();
+    vfs.close(oid).unwrap();
+    assert_eq!(vfs.read_file("/b").unwrap(), data(&vfs, 2, 2));
+    assert_eq!(vfs.compact().unwrap().moved, 4);
+    assert_eq!(vfs.compact().unwrap().moved, 0);
+}
+
+#[test]
+fn freed_blocks_are_reused_when_the_store_is_full() {
+    let block_size = Vfs::new().statfs().block_size;
+    let mut vfs = VfsBuilder::new().size(6 * block_size).build();
+    vfs.write_file("/a", &data(&vfs, 4, 1)).unwrap();
+    vfs.write_file("/b", &data(&vfs, 2, 2)).unwrap();
+    assert_eq!(vfs.statfs().blocks_free, 0);
+    vfs.unlink("/a").unwrap();
+    assert_eq!(vfs.statfs().blocks_free, 4);
+    vfs.write_file("/c", &data(&vfs, 4, 3)).unwrap();
+    assert_eq!(vfs.statfs().blocks_free, 0);
+    assert_eq!(vfs.read_file("/b").unwrap(), data(&vfs, 2, 2));
+    assert_eq!(vfs.read_file("/c").unwrap(), data(&vfs, 4, 3));
+    assert_eq!(vfs.compact().unwrap().reclaimed_bytes, 0);
+}
@@ -0,0 +1,54 @@
+use vfs::{ErrorKind, MountOptions, Op, Renamed, Reply, Vfs};
+
+fn ops(script: &str) -> Vec<Op> {
+    script.lines().map(|line| line.parse().unwrap()).collect()
+}
+
+#[test]
+fn apply_returns_what_each_op_produced() {
+    let mut vfs = Vfs::new();
+    let results = vfs.apply(&ops(concat!(
+        "create \"/a\"\n",
+        "open \"/a\"\n",
+        "write 0 68656c6c6f\n",
+        "seek 0 0\n",
+        "read 0 16\n",
+        "stat \"/a\"\n",
+        "rename \"/a\" \"/b\"\n",
+        "open \"/missing\"\n",
+    )));
+    assert!(matches!(results[0], Ok(Reply::Done)));
+    assert!(matches!(results[1], Ok(Reply::Fd(0))));
+    assert!(matches!(results[2], Ok(Reply::Written(5))));
+    assert!(matches!(results[3], Ok(Reply::Done)));
+    assert!(matches!(&results[4], Ok(Reply::Data(data)) if data == b"hello"));
+    assert!(matches!(&results[5], Ok(Reply::Stat(stat)) if stat.size() == 5));
+    assert!(matches!(results[6], Ok(Reply::Renamed(Renamed::Moved))));
+    assert_eq!(results[7].as_ref().unwrap_err().kind, ErrorKind::NotFound);
+}
+
+#[test]
+fn read_and_stat_round_trip() {
+    for line in ["read 3 512", "stat \"/a b\""] {
+        let op: Op = line.parse().unwrap();
+        assert_eq!(op.to_string(), line);
+    }
+}
+
+#[test]
+fn mount_rejects_bad_options() {
+    assert!("mount \"/image\" \"/mnt\" bogus".parse::<Op>().is_err());
+    assert!("mount \"/image\" \"/mnt\" ro,bogus".parse::<Op>().is_err());
+}
+
+#[test]
+fn mount_round_trips_its_options() {
+    let op: Op = "mount \"/image\" \"/mnt\" ro,noatime".parse().unwrap();
+    assert_eq!(op.to_string().parse::<Op>().unwrap(), op);
+    let op: Op = "mount \"/image\" \"/mnt\"".parse().unwrap();
+    match &op {
+        Op::Mount { options, .. } => assert_eq!(*options, MountOptions::default()),
+        other => panic!("parsed {:?}", other),
+    }
+    assert_eq!(op.to_string().parse::<Op>().unwrap(), op);
+}
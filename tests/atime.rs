@@ -0,0 +1,50 @@
+use vfs::{AtimePolicy, Vfs, VfsBuilder};
+
+fn vfs_with(policy: AtimePolicy) -> Vfs {
+    let mut vfs = VfsBuilder::new().seed(1).atime(policy).build();
+    vfs.write_file("/file", b"data").unwrap();
+    vfs
+}
+
+/// The access time of `/file` before and after reading it through a
+/// descriptor.
+fn atimes_around_read(vfs: &mut Vfs) -> (i64, i64) {
+    let before = vfs.stat("/file").unwrap().atime().sec;
+    let fd = vfs.open("/file").unwrap();
+    vfs.read(fd, 4).unwrap();
+    vfs.close(fd).unwrap();
+    (before, vfs.stat("/file").unwrap().atime().sec)
+}
+
+#[test]
+fn strictatime_updates_on_every_read() {
+    let mut vfs = vfs_with(AtimePolicy::StrictAtime);
+    let (before, after) = atimes_around_read(&mut vfs);
+    assert!(after > before);
+    let (before, after) = atimes_around_read(&mut vfs);
+    assert!(after > before);
+}
+
+#[test]
+fn relatime_updates_once_after_a_change() {
+    let mut vfs = vfs_with(AtimePolicy::Relatime);
+    let (before, after) = atimes_around_read(&mut vfs);
+    assert!(after > before);
+    let (before, after) = atimes_around_read(&mut vfs);
+    assert_eq!(after, before);
+}
+
+#[test]
+fn noatime_never_updates() {
+    let mut vfs = vfs_with(AtimePolicy::NoAtime);
+    let (before, after) = atimes_around_read(&mut vfs);
+    assert_eq!(after, before);
+}
+
+#[test]
+fn path_based_reads_leave_atime_alone() {
+    let vfs = vfs_with(AtimePolicy::StrictAtime);
+    let before = vfs.stat("/file").unwrap().atime();
+    vfs.read_file("/file").unwrap();
+    assert_eq!(vfs.stat("/file").unwrap().atime(), before);
+}
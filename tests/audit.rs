@@ -0,0 +1,83 @@
+use vfs::{AuditRecord, ErrorKind, Vfs, VfsBuilder};
+
+fn session(vfs: &mut Vfs) {
+    vfs.mkdir("/dir").unwrap();
+    vfs.write_file("/dir/a", b"hello").unwrap();
+    vfs.link("/dir/a", "/b").unwrap();
+    vfs.rename("/dir/a", "/dir/c").unwrap();
+    let fd = vfs.open("/b").unwrap();
+    vfs.seek(fd, 5).unwrap();
+    vfs.write(fd, b" world").unwrap();
+    vfs.close(fd).unwrap();
+    assert!(vfs.mkdir("/dir").is_err());
+    vfs.symlink("/dir/c", "/link").unwrap();
+    vfs.unlink("/b").unwrap();
+}
+
+#[test]
+fn replay_rebuilds_the_same_tree() {
+    let mut vfs = Vfs::new();
+    vfs.set_audit(true);
+    session(&mut vfs);
+    let mut replayed = Vfs::new();
+    replayed.replay(vfs.audit_log()).unwrap();
+    assert!(vfs.diff(&replayed).is_empty(), "{}", vfs.diff(&replayed));
+    assert_eq!(replayed.read_file("/dir/c").unwrap(), b"hello world");
+}
+
+#[test]
+fn records_round_trip_through_text() {
+    let mut vfs = Vfs::new();
+    vfs.set_audit(true);
+    session(&mut vfs);
+    let text: String = vfs
+        .audit_log()
+        .iter()
+        .map(|record| format!("{}\n", record))
+        .collect();
+    let records: Vec<AuditRecord> = text.lines().map(|line| line.parse().unwrap()).collect();
+    assert_eq!(records, vfs.audit_log());
+    let mut replayed = Vfs::new();
+    replayed.replay(&records).unwrap();
+    assert_eq!(replayed.read_file("/dir/c").unwrap(), b"hello world");
+}
+
+#[test]
+fn failures_are_recorded_and_replayed_as_failures() {
+    let mut vfs = Vfs::new();
+    vfs.set_audit(true);
+    session(&mut vfs);
+    let failed: Vec<_> = vfs
+        .audit_log()
+        .iter()
+        .filter(|record| record.result.is_err())
+        .collect();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].op.to_string(), "mkdir \"/dir\"");
+}
+
+#[test]
+fn replay_stops_where_the_outcome_diverges() {
+    let mut vfs = Vfs::new();
+    vfs.set_audit(true);
+    session(&mut vfs);
+    let mut replayed = Vfs::new();
+    replayed.mkdir("/link").unwrap();
+    let err = replayed.replay(vfs.audit_log()).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Other);
+    assert!(err.message.contains("symlink"), "{}", err);
+    assert!(err.message.contains("expected success"), "{}", err);
+}
+
+#[test]
+fn seeded_timestamps_count_records() {
+    let mut vfs = VfsBuilder::new().seed(1).build();
+    vfs.set_audit(true);
+    session(&mut vfs);
+    let timestamps: Vec<_> = vfs
+        .audit_log()
+        .iter()
+        .map(|record| record.timestamp)
+        .collect();
+    assert_eq!(timestamps, (0..timestamps.len() as u64).collect::<Vec<_>>());
+}
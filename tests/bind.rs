@@ -0,0 +1,110 @@
+use vfs::{BindOptions, ErrorKind, Vfs};
+
+/// `/src` with a file, and `/dst` with a file of its own.
+fn tree() -> Vfs {
+    let mut vfs = Vfs::new();
+    vfs.mkdir("/src").unwrap();
+    vfs.write_file("/src/file", b"source").unwrap();
+    vfs.mkdir("/dst").unwrap();
+    vfs.write_file("/dst/hidden", b"hidden").unwrap();
+    vfs
+}
+
+#[test]
+fn the_target_shows_the_source_until_unmounted() {
+    let mut vfs = tree();
+    vfs.bind_mount("/src", "/dst").unwrap();
+    assert_eq!(vfs.read_file("/dst/file").unwrap(), b"source");
+    assert_eq!(
+        vfs.stat("/dst/file").unwrap().inode(),
+        vfs.stat("/src/file").unwrap().inode()
+    );
+    assert_eq!(
+        vfs.stat("/dst/hidden").unwrap_err().kind,
+        ErrorKind::NotFound
+    );
+    vfs.write_file("/dst/new", b"through the target").unwrap();
+    assert_eq!(vfs.read_file("/src/new").unwrap(), b"through the target");
+    assert_eq!(vfs.read_file("/dst/../src/file").unwrap(), b"source");
+    let binds: Vec<_> = vfs.binds().collect();
+    assert_eq!(binds, [("/src", "/dst", BindOptions::default())]);
+    vfs.unmount("/dst").unwrap();
+    assert_eq!(vfs.read_file("/dst/hidden").unwrap(), b"hidden");
+    assert_eq!(vfs.stat("/dst/new").unwrap_err().kind, ErrorKind::NotFound);
+    assert_eq!(vfs.binds().count(), 0);
+}
+
+#[test]
+fn a_read_only_bind_refuses_changes_through_the_target() {
+    let mut vfs = tree();
+    let options: BindOptions = "bind,ro".parse().unwrap();
+    vfs.bind_mount_with("/src", "/dst", options).unwrap();
+    for err in [
+        vfs.write_file("/dst/file", b"changed").unwrap_err(),
+        vfs.create("/dst/new").unwrap_err(),
+        vfs.mkdir("/dst/dir").unwrap_err(),
+        vfs.unlink("/dst/file").unwrap_err(),
+        vfs.rename("/dst/file", "/dst/moved").unwrap_err(),
+    ] {
+        assert_eq!(err.kind, ErrorKind::ReadOnly, "{}", err);
+    }
+    assert_eq!(vfs.read_file("/dst/file").unwrap(), b"source");
+    vfs.write_file("/src/file", b"changed").unwrap();
+    assert_eq!(vfs.read_file("/dst/file").unwrap(), b"changed");
+}
+
+#[test]
+fn only_a_recursive_bind_carries_the_binds_below_the_source() {
+    let mut vfs = tree();
+    vfs.mkdir("/src/inner").unwrap();
+    vfs.write_file("/src/inner/under", b"under").unwrap();
+    vfs.mkdir("/other").unwrap();
+    vfs.write_file("/other/over", b"over").unwrap();
+    vfs.bind_mount("/other", "/src/inner").unwrap();
+    vfs.mkdir("/rdst").unwrap();
+    vfs.bind_mount("/src", "/dst").unwrap();
+    vfs.bind_mount_with(
+        "/src",
+        "/rdst",
+        BindOptions {
+            recursive: true,
+            read_only: false,
+        },
+    )
+    .unwrap();
+    assert_eq!(vfs.read_file("/src/inner/over").unwrap(), b"over");
+    assert_eq!(vfs.read_file("/dst/inner/under").unwrap(), b"under");
+    assert_eq!(vfs.read_file("/rdst/inner/over").unwrap(), b"over");
+}
+
+#[test]
+fn bound_directories_are_busy() {
+    let mut vfs = tree();
+    vfs.mkdir("/src/sub").unwrap();
+    vfs.bind_mount("/src", "/dst").unwrap();
+    for err in [
+        vfs.rmdir("/dst").unwrap_err(),
+        vfs.rename("/src", "/moved").unwrap_err(),
+        vfs.bind_mount("/src", "/").unwrap_err(),
+    ] {
+        assert_eq!(err.kind, ErrorKind::Busy, "{}", err);
+    }
+    let recursive = "rbind".parse().unwrap();
+    let err = vfs
+        .bind_mount_with("/src", "/src/sub", recursive)
+        .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+    vfs.cd("/dst").unwrap();
+    assert_eq!(vfs.unmount("/dst").unwrap_err().kind, ErrorKind::Busy);
+    vfs.cd("/").unwrap();
+    vfs.unmount("/dst").unwrap();
+}
+
+#[test]
+fn options_round_trip_through_their_text_form() {
+    let options: BindOptions = "rbind,ro".parse().unwrap();
+    assert!(options.recursive && options.read_only);
+    assert_eq!(options.to_string(), "rbind,ro");
+    assert_eq!(BindOptions::default().to_string(), "bind");
+    assert!("bind,noexec".parse::<BindOptions>().is_err());
+}
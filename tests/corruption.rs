@@ -0,0 +1,38 @@
+use vfs::{ErrorKind, FaultPlan, Vfs};
+
+fn corrupted_file() -> Vfs {
+    let mut vfs = Vfs::new();
+    vfs.write_file("/file", &[1; 1024]).unwrap();
+    vfs.set_faults(FaultPlan::new().corrupt_block(1));
+    vfs
+}
+
+#[test]
+fn write_into_a_corrupted_block_fails() {
+    let mut vfs = corrupted_file();
+    let fd = vfs.open("/file").unwrap();
+    vfs.seek(fd, 10).unwrap();
+    let err = vfs.write(fd, b"patch").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::DataCorruption);
+    assert_eq!(vfs.scrub().corrupted, [1]);
+}
+
+#[test]
+fn corruption_survives_later_writes() {
+    let mut vfs = corrupted_file();
+    let fd = vfs.open("/file").unwrap();
+    vfs.seek(fd, 600).unwrap();
+    vfs.write(fd, b"patch").unwrap();
+    let err = vfs.read_file("/file").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::DataCorruption);
+}
+
+#[test]
+fn truncate_into_a_corrupted_block_fails() {
+    let mut vfs = corrupted_file();
+    vfs.truncate("/file", 10).unwrap();
+    assert_eq!(
+        vfs.truncate("/file", 100).unwrap_err().kind,
+        ErrorKind::DataCorruption
+    );
+}
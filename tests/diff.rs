@@ -0,0 +1,130 @@
+use vfs::{Change, ChangeKind, TreeOptions, Vfs};
+
+fn before() -> Vfs {
+    let mut vfs = Vfs::new();
+    vfs.mkdir("/dir").unwrap();
+    vfs.write_file("/dir/kept", b"kept").unwrap();
+    vfs.write_file("/dir/edited", b"old").unwrap();
+    vfs.write_file("/gone", b"gone").unwrap();
+    vfs
+}
+
+fn change(path: &str, kind: ChangeKind) -> Change {
+    Change {
+        path: path.to_string(),
+        kind,
+    }
+}
+
+#[test]
+fn a_snapshot_diffs_empty_against_itself() {
+    let vfs = before();
+    let snapshot = vfs.clone();
+    assert!(vfs.diff(&snapshot).is_empty());
+    assert!(vfs.diff_with(&snapshot, TreeOptions::default()).is_empty());
+}
+
+#[test]
+fn changes_are_reported_from_the_newer_tree() {
+    let snapshot = before();
+    let mut vfs = snapshot.clone();
+    vfs.write_file("/dir/edited", b"new").unwrap();
+    vfs.unlink("/gone").unwrap();
+    vfs.mkdir("/dir/sub").unwrap();
+    vfs.write_file("/dir/sub/added", b"added").unwrap();
+    let changes = vfs.diff(&snapshot);
+    assert_eq!(
+        changes.changes,
+        [
+            change(
+                "/dir/edited",
+                ChangeKind::Modified {
+                    content: true,
+                    metadata: false
+                }
+            ),
+            change("/dir/sub", ChangeKind::Added),
+            change("/dir/sub/added", ChangeKind::Added),
+            change("/gone", ChangeKind::Removed),
+        ]
+    );
+    assert_eq!(
+        changes.to_string(),
+        "M /dir/edited (content)\nA /dir/sub\nA /dir/sub/added\nD /gone"
+    );
+    let reverse = snapshot.diff(&vfs);
+    assert_eq!(reverse.changes[1], change("/dir/sub", ChangeKind::Removed));
+    assert_eq!(reverse.changes[3], change("/gone", ChangeKind::Added));
+}
+
+#[test]
+fn a_rename_is_a_removal_and_an_addition() {
+    let snapshot = before();
+    let mut vfs = snapshot.clone();
+    vfs.rename("/gone", "/dir/moved").unwrap();
+    assert_eq!(
+        vfs.diff(&snapshot).changes,
+        [
+            change("/dir/moved", ChangeKind::Added),
+            change("/gone", ChangeKind::Removed),
+        ]
+    );
+}
+
+#[test]
+fn metadata_is_compared_only_when_asked() {
+    let snapshot = before();
+    let mut vfs = snapshot.clone();
+    vfs.chmod("/dir/kept", 0o600).unwrap();
+    assert!(vfs.diff(&snapshot).is_empty());
+    let options = TreeOptions {
+        metadata: true,
+        ..TreeOptions::default()
+    };
+    assert_eq!(
+        vfs.diff_with(&snapshot, options).changes,
+        [change(
+            "/dir/kept",
+            ChangeKind::Modified {
+                content: false,
+                metadata: true
+            }
+        )]
+    );
+}
+
+#[test]
+fn a_new_hard_link_changes_the_link_count() {
+    let snapshot = before();
+    let mut vfs = snapshot.clone();
+    vfs.link("/dir/kept", "/alias").unwrap();
+    assert_eq!(
+        vfs.diff(&snapshot).changes,
+        [
+            change("/alias", ChangeKind::Added),
+            change(
+                "/dir/kept",
+                ChangeKind::Modified {
+                    content: false,
+                    metadata: true
+                }
+            ),
+        ]
+    );
+}
+
+#[test]
+fn trees_built_differently_compare_equal() {
+    let mut left = Vfs::new();
+    left.write_file("/a", b"1").unwrap();
+    left.write_file("/b", b"2").unwrap();
+    let mut right = Vfs::new();
+    right.write_file("/tmp", b"2").unwrap();
+    right.write_file("/a", b"1").unwrap();
+    right.rename("/tmp", "/b").unwrap();
+    assert!(left.diff(&right).is_empty());
+    assert_eq!(
+        left.tree_digest(TreeOptions::default()),
+        right.tree_digest(TreeOptions::default())
+    );
+}
@@ -0,0 +1,39 @@
+use vfs::{ErrorKind, Vfs, VfsBuilder};
+
+const KEY: [u8; 32] = [7; 32];
+const SECRET: &[u8] = b"attack at dawn, attack at dawn";
+
+fn encrypted_image() -> Vec<u8> {
+    let mut vfs = VfsBuilder::new().encryption_key(KEY).build();
+    vfs.write_file("/secret", SECRET).unwrap();
+    vfs.to_image().unwrap()
+}
+
+#[test]
+fn image_of_encrypted_volume_holds_only_ciphertext() {
+    let image = encrypted_image();
+    assert!(!image.windows(SECRET.len()).any(|window| window == SECRET));
+}
+
+#[test]
+fn encrypted_image_loads_with_its_key() {
+    let image = encrypted_image();
+    let vfs = VfsBuilder::new()
+        .encryption_key(KEY)
+        .build_from_image(&image)
+        .unwrap();
+    assert_eq!(vfs.read_file("/secret").unwrap(), SECRET);
+    assert_eq!(vfs.to_image().unwrap(), image);
+}
+
+#[test]
+fn encrypted_image_needs_the_right_key() {
+    let image = encrypted_image();
+    let err = Vfs::from_image(&image).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+    let err = VfsBuilder::new()
+        .encryption_key([8; 32])
+        .build_from_image(&image)
+        .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::DataCorruption);
+}
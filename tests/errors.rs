@@ -0,0 +1,62 @@
+use std::io;
+
+use vfs::{ErrorKind, SetTime, Vfs, ATTR_IMMUTABLE};
+
+#[test]
+fn kind_comes_from_the_failure_not_the_path() {
+    let mut vfs = Vfs::new();
+    vfs.write_file("/file exists", b"data").unwrap();
+    vfs.set_attrs("/file exists", ATTR_IMMUTABLE).unwrap();
+    let err = vfs.unlink("/file exists").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::NotPermitted);
+
+    let err = vfs.unlink("/permission denied").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::NotFound);
+}
+
+#[test]
+fn kinds_map_to_specific_io_kinds() {
+    let mut vfs = Vfs::new();
+    vfs.symlink("/loop", "/loop").unwrap();
+    let err = vfs.read_file("/loop/file").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Loop);
+    assert_ne!(io::Error::from(err).kind(), io::ErrorKind::Other);
+}
+
+#[test]
+fn converted_calls_report_kinds() {
+    let mut vfs = Vfs::new();
+    assert_eq!(
+        vfs.walk("/missing").err().unwrap().kind,
+        ErrorKind::NotFound
+    );
+    assert_eq!(
+        vfs.remove_all("/missing").unwrap_err().kind,
+        ErrorKind::NotFound
+    );
+    assert_eq!(
+        vfs.mkring("/", 4096).unwrap_err().kind,
+        ErrorKind::AlreadyExists
+    );
+}
+
+#[test]
+fn utimens_reports_the_missing_component() {
+    let mut vfs = Vfs::new();
+    vfs.mkdir("/dir").unwrap();
+    let err = vfs
+        .utimens("/dir/missing/file", SetTime::Now, SetTime::Now, true)
+        .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::NotFound);
+    assert_eq!(err.component.unwrap().name, "missing");
+}
+
+#[test]
+fn read_file_as_names_the_command() {
+    let vfs = Vfs::new();
+    let err = vfs.read_file_as("cat", "/missing").unwrap_err();
+    assert!(err.message.starts_with("cat: "), "{}", err);
+    assert_eq!(err.kind, ErrorKind::NotFound);
+    let err = vfs.walk_as("ls", "/missing").err().unwrap();
+    assert!(err.message.starts_with("ls: "), "{}", err);
+}
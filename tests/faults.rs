@@ -0,0 +1,12 @@
+use vfs::{ErrorKind, FaultPlan, Vfs};
+
+#[test]
+fn write_crossing_the_limit_returns_what_it_stored() {
+    let mut vfs = Vfs::new();
+    vfs.write_file("/file", b"").unwrap();
+    let fd = vfs.open("/file").unwrap();
+    vfs.set_faults(FaultPlan::new().fail_writes_after(3));
+    assert_eq!(vfs.write(fd, b"hello").unwrap(), 3);
+    assert_eq!(vfs.write(fd, b"lo").unwrap_err().kind, ErrorKind::Other);
+    assert_eq!(vfs.read_file("/file").unwrap(), b"hel");
+}
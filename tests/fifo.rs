@@ -0,0 +1,24 @@
+use vfs::{ErrorKind, Vfs, O_NONBLOCK};
+
+#[test]
+fn empty_fifo_reads_fail_by_blocking_mode() {
+    let mut vfs = Vfs::new();
+    vfs.mkfifo("/fifo").unwrap();
+    let reader = vfs.open("/fifo").unwrap();
+    let writer = vfs.open("/fifo").unwrap();
+    assert_eq!(vfs.read(reader, 4).unwrap_err().kind, ErrorKind::Deadlock);
+
+    vfs.set_fd_flags(reader, O_NONBLOCK).unwrap();
+    assert_eq!(vfs.read(reader, 4).unwrap_err().kind, ErrorKind::WouldBlock);
+
+    vfs.write(writer, b"data").unwrap();
+    assert_eq!(vfs.read(reader, 4).unwrap(), b"data");
+}
+
+#[test]
+fn empty_fifo_without_writers_reads_end_of_file() {
+    let mut vfs = Vfs::new();
+    vfs.mkfifo("/fifo").unwrap();
+    let reader = vfs.open("/fifo").unwrap();
+    assert!(vfs.read(reader, 4).unwrap().is_empty());
+}
@@ -0,0 +1,97 @@
+use vfs::{ErrorKind, Vfs};
+
+/// A tree with a file of several blocks, a small file and a directory.
+fn fixture() -> Vfs {
+    let mut vfs = Vfs::new();
+    let block_size = vfs.statfs().block_size;
+    vfs.mkdir("/dir").unwrap();
+    vfs.write_file("/dir/big", &vec![b'x'; 4 * block_size])
+        .unwrap();
+    vfs.write_file("/small", b"small").unwrap();
+    vfs
+}
+
+/// Change every part of `vfs` the fixture has: blocks in place, file
+/// sizes, directory entries and metadata.
+fn mutate(vfs: &mut Vfs) {
+    let block_size = vfs.statfs().block_size;
+    let oid = vfs.open("/dir/big").unwrap();
+    vfs.seek(oid, block_size + 1).unwrap();
+    vfs.write(oid, b"changed").unwrap();
+    vfs.close(oid).unwrap();
+    vfs.truncate("/small", 2).unwrap();
+    vfs.write_file("/dir/new", b"new").unwrap();
+    vfs.rename("/dir", "/moved").unwrap();
+    vfs.chmod("/moved/big", 0o600).unwrap();
+}
+
+fn assert_untouched(vfs: &Vfs, original: &Vfs) {
+    assert!(vfs.diff(original).is_empty(), "{}", vfs.diff(original));
+    let block_size = vfs.statfs().block_size;
+    assert_eq!(
+        vfs.read_file("/dir/big").unwrap(),
+        vec![b'x'; 4 * block_size]
+    );
+    assert_eq!(vfs.read_file("/small").unwrap(), b"small");
+    assert_eq!(vfs.stat("/dir/new").unwrap_err().kind, ErrorKind::NotFound);
+    assert_eq!(
+        vfs.stat("/dir/big").unwrap().mode(),
+        original.stat("/dir/big").unwrap().mode()
+    );
+}
+
+#[test]
+fn changes_to_a_fork_leave_the_original_alone() {
+    let original = fixture();
+    let mut fork = original.fork();
+    mutate(&mut fork);
+    assert_untouched(&original, &fixture());
+    assert_eq!(fork.read_file("/moved/new").unwrap(), b"new");
+}
+
+#[test]
+fn changes_to_the_original_leave_the_fork_alone() {
+    let mut original = fixture();
+    let fork = original.fork();
+    mutate(&mut original);
+    assert_untouched(&fork, &fixture());
+    assert_eq!(original.read_file("/small").unwrap(), b"sm");
+}
+
+#[test]
+fn sibling_forks_are_isolated_from_each_other() {
+    let original = fixture();
+    let mut first = original.fork();
+    let mut second = original.fork();
+    first.write_file("/small", b"first").unwrap();
+    second.write_file("/small", b"second").unwrap();
+    let mut third = second.fork();
+    third.unlink("/small").unwrap();
+    assert_eq!(original.read_file("/small").unwrap(), b"small");
+    assert_eq!(first.read_file("/small").unwrap(), b"first");
+    assert_eq!(second.read_file("/small").unwrap(), b"second");
+    assert!(third.stat("/small").is_err());
+}
+
+#[test]
+fn descriptors_are_copied_with_their_offsets() {
+    let mut original = fixture();
+    let oid = original.open("/small").unwrap();
+    original.seek(oid, 2).unwrap();
+    let mut fork = original.fork();
+    assert_eq!(fork.read(oid, 3).unwrap(), b"all");
+    fork.close(oid).unwrap();
+    assert_eq!(original.read(oid, 1).unwrap(), b"a");
+    original.close(oid).unwrap();
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn overwrites_through_a_shared_vfs_copy_shared_blocks() {
+    let original = fixture();
+    let fork = original.fork();
+    let shared = vfs::SharedVfs::new(original);
+    assert_eq!(shared.write_at("/dir/big", 1, b"changed").unwrap(), 7);
+    assert_eq!(&shared.read_file("/dir/big").unwrap()[..8], b"xchanged");
+    assert_untouched(&fork, &fixture());
+}
@@ -0,0 +1,75 @@
+use vfs::{ErrorKind, FileHandle, Vfs};
+
+#[test]
+fn a_handle_names_the_inode_and_generation_stat_reports() {
+    let mut vfs = Vfs::new();
+    vfs.write_file("/file", b"data").unwrap();
+    let stat = vfs.stat("/file").unwrap();
+    let handle = vfs.handle("/file").unwrap();
+    assert_eq!(handle.inode, stat.inode());
+    assert_eq!(handle.generation, stat.generation());
+    let oid = vfs.open_by_handle(handle).unwrap();
+    assert_eq!(vfs.read(oid, 4).unwrap(), b"data");
+    let by_inode = vfs.stat_by_inode(handle.inode, handle.generation).unwrap();
+    assert_eq!(by_inode.name(), "/file");
+}
+
+#[test]
+fn reusing_an_inode_number_bumps_its_generation() {
+    let mut vfs = Vfs::new();
+    vfs.create("/old").unwrap();
+    let old = vfs.handle("/old").unwrap();
+    vfs.unlink("/old").unwrap();
+    vfs.create("/new").unwrap();
+    let new = vfs.handle("/new").unwrap();
+    assert_eq!(new.inode, old.inode);
+    assert_ne!(new.generation, old.generation);
+    assert_eq!(vfs.stat("/new").unwrap().generation(), new.generation);
+    assert!(vfs.open_by_handle(new).is_ok());
+}
+
+#[test]
+fn handles_to_freed_or_reused_inodes_are_stale() {
+    let mut vfs = Vfs::new();
+    vfs.create("/old").unwrap();
+    let old = vfs.handle("/old").unwrap();
+    vfs.unlink("/old").unwrap();
+    let err = vfs.open_by_handle(old).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Stale);
+    vfs.create("/new").unwrap();
+    for err in [
+        vfs.open_by_handle(old).unwrap_err(),
+        vfs.open_by_inode(old.inode, old.generation, 0).unwrap_err(),
+        vfs.stat_by_inode(old.inode, old.generation).unwrap_err(),
+    ] {
+        assert_eq!(err.kind, ErrorKind::Stale);
+        assert!(err.message.ends_with("Stale file handle"), "{}", err);
+    }
+}
+
+#[test]
+fn an_unlinked_file_stays_live_while_it_is_open() {
+    let mut vfs = Vfs::new();
+    vfs.write_file("/file", b"data").unwrap();
+    let handle = vfs.handle("/file").unwrap();
+    let oid = vfs.open("/file").unwrap();
+    vfs.unlink("/file").unwrap();
+    let stat = vfs.stat_by_inode(handle.inode, handle.generation).unwrap();
+    assert_eq!(stat.name(), handle.to_string());
+    vfs.close(oid).unwrap();
+    let err = vfs
+        .stat_by_inode(handle.inode, handle.generation)
+        .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Stale);
+}
+
+#[test]
+fn handles_round_trip_through_their_string_form() {
+    let handle = FileHandle {
+        inode: 7,
+        generation: 3,
+    };
+    assert_eq!(handle.to_string(), "7:3");
+    assert_eq!("7:3".parse::<FileHandle>().unwrap(), handle);
+    assert!("7".parse::<FileHandle>().is_err());
+}
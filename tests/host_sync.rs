@@ -0,0 +1,81 @@
+#![cfg(all(unix, feature = "std"))]
+
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    process,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use vfs::{SetTime, Timespec, Vfs};
+
+fn host_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("vfs-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn set_mtime(path: &PathBuf, sec: u64) {
+    let file = fs::File::options().write(true).open(path).unwrap();
+    file.set_modified(UNIX_EPOCH + Duration::from_secs(sec))
+        .unwrap();
+}
+
+#[test]
+fn sync_from_host_keeps_modes_and_mtimes() {
+    let dir = host_dir("sync-in");
+    let script = dir.join("run.sh");
+    fs::write(&script, b"#!/bin/sh\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o750)).unwrap();
+    set_mtime(&script, 1_000_000);
+
+    let mut vfs = Vfs::new();
+    let stats = vfs.sync_from_host(&dir, "/sync").unwrap();
+    assert_eq!(stats.copied, 1);
+    let stat = vfs.stat("/sync/run.sh").unwrap();
+    assert_eq!(stat.mode() & 0o7777, 0o750);
+    assert_eq!(stat.mtime(), Timespec::new(1_000_000, 0));
+
+    // Same size and time: left alone, even with other contents.
+    fs::write(&script, b"#!/bin/zh\n").unwrap();
+    set_mtime(&script, 1_000_000);
+    let stats = vfs.sync_from_host(&dir, "/sync").unwrap();
+    assert_eq!((stats.copied, stats.unchanged), (0, 1));
+    assert_eq!(vfs.read_file("/sync/run.sh").unwrap(), b"#!/bin/sh\n");
+
+    set_mtime(&script, 2_000_000);
+    let stats = vfs.sync_from_host(&dir, "/sync").unwrap();
+    assert_eq!(stats.copied, 1);
+    assert_eq!(vfs.read_file("/sync/run.sh").unwrap(), b"#!/bin/zh\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn sync_to_host_keeps_modes_and_mtimes() {
+    let dir = host_dir("sync-out");
+    let mut vfs = Vfs::new();
+    vfs.mkdir("/out").unwrap();
+    vfs.write_file("/out/key", b"secret").unwrap();
+    vfs.chmod("/out/key", 0o600).unwrap();
+    let mtime = SetTime::At(Timespec::new(1_000_000, 0));
+    vfs.utimens("/out/key", SetTime::Omit, mtime, false)
+        .unwrap();
+
+    let stats = vfs.sync_to_host("/out", &dir).unwrap();
+    assert_eq!(stats.copied, 1);
+    let metadata = fs::metadata(dir.join("key")).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o7777, 0o600);
+    assert_eq!(
+        metadata.modified().unwrap(),
+        UNIX_EPOCH + Duration::from_secs(1_000_000)
+    );
+
+    vfs.chmod("/out/key", 0o640).unwrap();
+    let stats = vfs.sync_to_host("/out", &dir).unwrap();
+    assert_eq!((stats.copied, stats.unchanged), (0, 1));
+    let metadata = fs::metadata(dir.join("key")).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o7777, 0o640);
+    fs::remove_dir_all(&dir).unwrap();
+}
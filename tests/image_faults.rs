@@ -0,0 +1,35 @@
+use vfs::{ErrorKind, FaultPlan, Vfs};
+
+fn vfs_with_image() -> Vfs {
+    let mut vfs = Vfs::new();
+    vfs.mkdir("/tree").unwrap();
+    vfs.write_file("/tree/a", &[1; 2048]).unwrap();
+    vfs.write_file("/tree/b", &[2; 2048]).unwrap();
+    vfs.create_image("/tree", "/tree.img").unwrap();
+    vfs.mkdir("/mnt").unwrap();
+    vfs
+}
+
+#[test]
+fn mount_image_reports_failed_allocation() {
+    let mut vfs = vfs_with_image();
+    let used = vfs.dedup_stats().physical_blocks;
+    vfs.set_faults(FaultPlan::new().fail_alloc(6));
+    let err = vfs.mount_image("/tree.img", "/mnt").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::NoSpace);
+    assert_eq!(vfs.dedup_stats().physical_blocks, used);
+    assert_eq!(vfs.mounts().count(), 0);
+
+    vfs.mount_image("/tree.img", "/mnt").unwrap();
+    assert_eq!(vfs.read_file("/mnt/b").unwrap(), [2; 2048]);
+}
+
+#[test]
+fn mount_image_fails_on_first_allocation() {
+    let mut vfs = vfs_with_image();
+    vfs.set_faults(FaultPlan::new().fail_alloc(1));
+    let err = vfs.mount_image("/tree.img", "/mnt").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::NoSpace);
+    vfs.mount_image("/tree.img", "/mnt").unwrap();
+    assert_eq!(vfs.read_file("/mnt/a").unwrap(), [1; 2048]);
+}
@@ -0,0 +1,23 @@
+//! Run with `cargo test --features testing --test model`.
+#![cfg(feature = "testing")]
+
+use vfs::model::{check_against_model, ops_from_bytes, random_ops};
+
+#[test]
+fn random_ops_match_the_model() {
+    for seed in 0..200 {
+        let ops = random_ops(seed, 200);
+        if let Err(err) = check_against_model(&ops) {
+            panic!("seed {}: {}", seed, err);
+        }
+    }
+}
+
+#[test]
+fn decoded_ops_match_the_model() {
+    for data in [&b""[..], &[0; 64], &[0xff; 64], b"create, write, unlink"] {
+        if let Err(err) = check_against_model(&ops_from_bytes(data)) {
+            panic!("input {:?}: {}", data, err);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use vfs::{ErrorKind, ProjectQuota, ProjectUsage, Session, Vfs};
+
+const PROJECT: u32 = 7;
+
+/// `/proj` assigned to `PROJECT` with a file of 10 bytes, and an
+/// unassigned `/other`.
+fn tree() -> Vfs {
+    let mut vfs = Vfs::new();
+    vfs.mkdir("/proj").unwrap();
+    vfs.mkdir("/other").unwrap();
+    vfs.set_project("/proj", PROJECT).unwrap();
+    vfs.write_file("/proj/file", b"0123456789").unwrap();
+    vfs
+}
+
+#[test]
+fn new_files_inherit_the_project_of_their_directory() {
+    let mut vfs = tree();
+    vfs.mkdir("/proj/sub").unwrap();
+    vfs.write_file("/proj/sub/nested", b"abc").unwrap();
+    vfs.write_file("/other/file", b"outside").unwrap();
+    assert_eq!(vfs.project("/proj/sub/nested").unwrap(), PROJECT);
+    assert_eq!(vfs.project("/other/file").unwrap(), 0);
+    assert_eq!(
+        vfs.project_usage(PROJECT),
+        ProjectUsage {
+            bytes: 13,
+            inodes: 4
+        }
+    );
+}
+
+#[test]
+fn a_file_renamed_out_stays_charged_to_its_project() {
+    let mut vfs = tree();
+    let before = vfs.project_usage(PROJECT);
+    vfs.rename("/proj/file", "/other/file").unwrap();
+    assert_eq!(vfs.project("/other/file").unwrap(), PROJECT);
+    assert_eq!(vfs.project_usage(PROJECT), before);
+    assert_eq!(vfs.project_usage(0).bytes, 0);
+}
+
+#[test]
+fn a_file_renamed_in_keeps_its_own_project() {
+    let mut vfs = tree();
+    vfs.write_file("/other/file", b"outside").unwrap();
+    vfs.rename("/other/file", "/proj/moved").unwrap();
+    assert_eq!(vfs.project("/proj/moved").unwrap(), 0);
+    assert_eq!(vfs.project_usage(PROJECT).bytes, 10);
+    vfs.write_file("/proj/created", b"").unwrap();
+    assert_eq!(vfs.project("/proj/created").unwrap(), PROJECT);
+}
+
+#[test]
+fn the_byte_limit_applies_after_a_rename() {
+    let mut vfs = tree();
+    vfs.set_project_quota(
+        PROJECT,
+        Some(ProjectQuota {
+            max_bytes: Some(12),
+            max_inodes: None,
+        }),
+    );
+    vfs.rename("/proj/file", "/other/file").unwrap();
+    let oid = vfs.open("/other/file").unwrap();
+    vfs.seek(oid, 10).unwrap();
+    assert_eq!(vfs.write(oid, b"ab").unwrap(), 2);
+    let err = vfs.write(oid, b"c").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::QuotaExceeded);
+    vfs.close(oid).unwrap();
+    vfs.write_file("/other/unassigned", &[0; 64]).unwrap();
+    assert_eq!(vfs.project_usage(PROJECT).bytes, 12);
+}
+
+#[test]
+fn the_inode_limit_counts_the_whole_project() {
+    let mut vfs = tree();
+    vfs.set_project_quota(
+        PROJECT,
+        Some(ProjectQuota {
+            max_bytes: None,
+            max_inodes: Some(3),
+        }),
+    );
+    vfs.mkdir("/proj/sub").unwrap();
+    let err = vfs.create("/proj/sub/file").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::QuotaExceeded);
+    vfs.rename("/proj/file", "/other/file").unwrap();
+    assert_eq!(
+        vfs.create("/proj/sub/file").unwrap_err().kind,
+        ErrorKind::QuotaExceeded
+    );
+    vfs.unlink("/other/file").unwrap();
+    vfs.create("/proj/sub/file").unwrap();
+    vfs.set_project_quota(PROJECT, None);
+    assert_eq!(vfs.project_quota(PROJECT), None);
+    vfs.create("/proj/sub/more").unwrap();
+}
+
+#[test]
+fn only_the_superuser_assigns_projects() {
+    let mut vfs = tree();
+    vfs.switch_session(Session::new().with_identity(1000, 1000));
+    let err = vfs.set_project("/other", PROJECT).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::NotPermitted);
+}
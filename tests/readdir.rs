@@ -0,0 +1,106 @@
+use vfs::{DirCursor, DirPage, ErrorKind, Vfs};
+
+fn directory(names: &[&str]) -> Vfs {
+    let mut vfs = Vfs::new();
+    vfs.mkdir("/dir").unwrap();
+    for name in names {
+        vfs.create(&format!("/dir/{}", name)).unwrap();
+    }
+    vfs
+}
+
+/// Page through `/dir`, calling `between` after each page, and return the
+/// names listed.
+fn list<F>(vfs: &mut Vfs, limit: usize, mut between: F) -> Vec<String>
+where
+    F: FnMut(&mut Vfs, usize),
+{
+    let mut names = Vec::new();
+    let mut cursor = DirCursor::start();
+    for page in 0.. {
+        let DirPage { entries, next } = vfs.readdir_page("/dir", &cursor, limit).unwrap();
+        assert!(entries.len() <= limit);
+        names.extend(entries.into_iter().map(|entry| entry.name));
+        let Some(next) = next else {
+            break;
+        };
+        between(vfs, page);
+        cursor = next;
+    }
+    names
+}
+
+#[test]
+fn pages_list_every_entry_once_in_name_order() {
+    let mut vfs = directory(&["c", "a", "e", "b", "d"]);
+    let names = list(&mut vfs, 2, |_, _| {});
+    assert_eq!(names, [".", "..", "a", "b", "c", "d", "e"]);
+    let all: Vec<_> = vfs
+        .read_dir("/dir")
+        .unwrap()
+        .map(|entry| entry.name)
+        .collect();
+    assert_eq!(names, all);
+}
+
+#[test]
+fn entries_added_between_pages_show_up_only_ahead_of_the_cursor() {
+    let mut vfs = directory(&["b", "d", "f", "h"]);
+    let names = list(&mut vfs, 3, |vfs, page| {
+        if page == 0 {
+            vfs.create("/dir/a").unwrap();
+            vfs.create("/dir/e").unwrap();
+        }
+    });
+    // After the first page (".", "..", "b") "a" is behind the cursor and
+    // "e" ahead of it.
+    assert_eq!(names, [".", "..", "b", "d", "e", "f", "h"]);
+}
+
+#[test]
+fn entries_removed_between_pages_do_not_shift_the_others() {
+    let mut vfs = directory(&["a", "b", "c", "d", "e", "f"]);
+    let names = list(&mut vfs, 3, |vfs, page| {
+        if page == 0 {
+            vfs.unlink("/dir/a").unwrap();
+            vfs.unlink("/dir/c").unwrap();
+        }
+    });
+    assert_eq!(names, [".", "..", "a", "b", "d", "e", "f"]);
+}
+
+#[test]
+fn a_cursor_at_a_removed_entry_still_resumes_after_it() {
+    let mut vfs = directory(&["a", "b", "c", "d"]);
+    let names = list(&mut vfs, 4, |vfs, _| vfs.unlink("/dir/b").unwrap());
+    assert_eq!(names, [".", "..", "a", "b", "c", "d"]);
+}
+
+#[test]
+fn cursors_round_trip_through_their_string_form() {
+    let vfs = directory(&["a", "b", "c"]);
+    let page = vfs.readdir_page("/dir", &DirCursor::start(), 3).unwrap();
+    let next = page.next.unwrap();
+    let resumed: DirCursor = next.to_string().parse().unwrap();
+    assert_eq!(resumed, next);
+    let page = vfs.readdir_page("/dir", &resumed, 3).unwrap();
+    let names: Vec<_> = page
+        .entries
+        .iter()
+        .map(|entry| entry.name.as_str())
+        .collect();
+    assert_eq!(names, ["b", "c"]);
+    assert_eq!(page.next, None);
+    assert_eq!("".parse::<DirCursor>().unwrap(), DirCursor::start());
+    assert!("zz".parse::<DirCursor>().is_err());
+}
+
+#[test]
+fn paging_a_file_fails() {
+    let mut vfs = Vfs::new();
+    vfs.create("/file").unwrap();
+    let err = vfs
+        .readdir_page("/file", &DirCursor::start(), 1)
+        .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::NotADirectory);
+}
@@ -0,0 +1,64 @@
+#[cfg(feature = "std")]
+use std::{fs, process};
+
+use vfs::{ErrorKind, Vfs};
+
+fn wide_tree(vfs: &mut Vfs) {
+    vfs.mkdir("/tree").unwrap();
+    for d in 0..4 {
+        vfs.mkdir(&format!("/tree/{}", d)).unwrap();
+        for f in 0..8 {
+            vfs.write_file(&format!("/tree/{}/{}", d, f), &[(d * 8 + f) as u8; 700])
+                .unwrap();
+        }
+    }
+    vfs.symlink("0", "/tree/link").unwrap();
+}
+
+#[test]
+fn copy_recursive_copies_every_file() {
+    let mut vfs = Vfs::new();
+    wide_tree(&mut vfs);
+    vfs.copy_recursive("/tree", "/copy").unwrap();
+    for d in 0..4 {
+        for f in 0..8 {
+            assert_eq!(
+                vfs.read_file(&format!("/copy/{}/{}", d, f)).unwrap(),
+                [(d * 8 + f) as u8; 700]
+            );
+        }
+    }
+    assert_eq!(vfs.read_file("/copy/link/1").unwrap(), [1; 700]);
+}
+
+#[test]
+fn copy_recursive_into_itself_fails() {
+    let mut vfs = Vfs::new();
+    wide_tree(&mut vfs);
+    let err = vfs.copy_recursive("/tree", "/tree/0/copy").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn import_dir_copies_every_file() {
+    let host = std::env::temp_dir().join(format!("vfs-import-wide-{}", process::id()));
+    for d in 0..4 {
+        fs::create_dir_all(host.join(d.to_string())).unwrap();
+        for f in 0..8 {
+            fs::write(host.join(d.to_string()).join(f.to_string()), [f as u8; 700]).unwrap();
+        }
+    }
+    let mut vfs = Vfs::new();
+    let stats = vfs.import_dir(&host, "/import");
+    fs::remove_dir_all(&host).unwrap();
+    assert_eq!(stats.unwrap().copied, 32);
+    for d in 0..4 {
+        for f in 0..8 {
+            assert_eq!(
+                vfs.read_file(&format!("/import/{}/{}", d, f)).unwrap(),
+                [f as u8; 700]
+            );
+        }
+    }
+}
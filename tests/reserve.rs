@@ -0,0 +1,73 @@
+use vfs::{ErrorKind, FaultPlan, Vfs, VfsBuilder};
+
+const BLOCK_SIZE: usize = 512;
+
+#[test]
+fn writes_within_a_reservation_never_run_out_of_space() {
+    let mut vfs = VfsBuilder::new().size(16 * BLOCK_SIZE).build();
+    vfs.create("/reserved").unwrap();
+    let reserved = vfs.open("/reserved").unwrap();
+    vfs.reserve(reserved, 4 * BLOCK_SIZE).unwrap();
+    assert_eq!(vfs.stat("/reserved").unwrap().size(), 4 * BLOCK_SIZE);
+
+    vfs.create("/filler").unwrap();
+    let filler = vfs.open("/filler").unwrap();
+    let err = loop {
+        if let Err(err) = vfs.write(filler, &[1; BLOCK_SIZE]) {
+            break err;
+        }
+    };
+    assert_eq!(err.kind, ErrorKind::NoSpace);
+
+    assert_eq!(
+        vfs.write(reserved, &[2; 4 * BLOCK_SIZE]).unwrap(),
+        4 * BLOCK_SIZE
+    );
+    vfs.close(reserved).unwrap();
+    assert_eq!(vfs.read_file("/reserved").unwrap(), [2; 4 * BLOCK_SIZE]);
+}
+
+#[test]
+fn reserving_a_shared_block_copies_it() {
+    let mut vfs = Vfs::new();
+    vfs.set_dedup(true);
+    vfs.write_file("/a", &[3; BLOCK_SIZE]).unwrap();
+    vfs.write_file("/b", &[3; BLOCK_SIZE]).unwrap();
+    assert_eq!(vfs.dedup_stats().physical_blocks, 1);
+    let fd = vfs.open("/b").unwrap();
+    vfs.reserve(fd, BLOCK_SIZE).unwrap();
+    assert_eq!(vfs.dedup_stats().physical_blocks, 2);
+    assert_eq!(vfs.read_file("/b").unwrap(), [3; BLOCK_SIZE]);
+}
+
+#[test]
+fn dedup_releases_a_reservation_written_to_match() {
+    let mut vfs = Vfs::new();
+    vfs.set_dedup(true);
+    vfs.write_file("/a", &[7; BLOCK_SIZE]).unwrap();
+    vfs.create("/b").unwrap();
+    let fd = vfs.open("/b").unwrap();
+    vfs.reserve(fd, BLOCK_SIZE).unwrap();
+    assert_eq!(vfs.dedup_stats().physical_blocks, 2);
+    vfs.write(fd, &[7; BLOCK_SIZE]).unwrap();
+    assert_eq!(vfs.dedup_stats().physical_blocks, 1);
+}
+
+#[test]
+fn reserving_a_corrupted_shared_block_fails() {
+    let mut vfs = Vfs::new();
+    vfs.set_dedup(true);
+    vfs.write_file("/a", &[5; BLOCK_SIZE]).unwrap();
+    vfs.write_file("/b", &[5; BLOCK_SIZE]).unwrap();
+    vfs.set_faults(FaultPlan::new().corrupt_block(1));
+    let fd = vfs.open("/b").unwrap();
+    let err = vfs.reserve(fd, BLOCK_SIZE).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::DataCorruption);
+    for pathname in ["/a", "/b"] {
+        assert_eq!(
+            vfs.read_file(pathname).unwrap_err().kind,
+            ErrorKind::DataCorruption
+        );
+    }
+    assert_eq!(vfs.scrub().corrupted, [1]);
+}
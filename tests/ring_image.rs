@@ -0,0 +1,43 @@
+use vfs::{ErrorKind, Vfs, VfsBuilder};
+
+fn ring_with(vfs: &mut Vfs, data: &[u8]) {
+    vfs.mkring("/ring", 8).unwrap();
+    let fd = vfs.open("/ring").unwrap();
+    vfs.write(fd, data).unwrap();
+    vfs.close(fd).unwrap();
+}
+
+#[test]
+fn images_keep_ring_buffers() {
+    let mut vfs = Vfs::new();
+    ring_with(&mut vfs, b"0123456789");
+    let mut vfs = Vfs::from_image(&vfs.to_image().unwrap()).unwrap();
+    assert_eq!(vfs.ring_capacity("/ring"), Some(8));
+    assert_eq!(vfs.read_file("/ring").unwrap(), b"23456789");
+    let fd = vfs.open("/ring").unwrap();
+    vfs.write(fd, b"ab").unwrap();
+    assert_eq!(vfs.read_file("/ring").unwrap(), b"456789ab");
+}
+
+#[test]
+fn backups_keep_ring_buffers() {
+    let mut vfs = Vfs::new();
+    ring_with(&mut vfs, b"before");
+    let mut backup = Vec::new();
+    vfs.backup(&mut backup).unwrap();
+    let fd = vfs.open("/ring").unwrap();
+    vfs.write(fd, b"after").unwrap();
+    vfs.close(fd).unwrap();
+    vfs.apply_incremental(&backup[..]).unwrap();
+    assert_eq!(vfs.read_file("/ring").unwrap(), b"before");
+}
+
+#[test]
+fn encrypted_images_refuse_ring_contents() {
+    let mut vfs = VfsBuilder::new().encryption_key([3; 32]).build();
+    vfs.mkring("/empty", 8).unwrap();
+    vfs.to_image().unwrap();
+    ring_with(&mut vfs, b"secret");
+    let err = vfs.to_image().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+}
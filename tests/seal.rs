@@ -0,0 +1,118 @@
+use std::fmt;
+
+use vfs::{
+    parse_seals, seals_string, ErrorKind, Vfs, VfsError, F_SEAL_GROW, F_SEAL_SEAL, F_SEAL_SHRINK,
+    F_SEAL_WRITE,
+};
+
+/// `/file` holding 8 bytes, open as the returned descriptor.
+fn sealed(seals: u32) -> (Vfs, usize) {
+    let mut vfs = Vfs::new();
+    vfs.write_file("/file", b"01234567").unwrap();
+    let oid = vfs.open("/file").unwrap();
+    vfs.add_seals(oid, seals).unwrap();
+    (vfs, oid)
+}
+
+#[track_caller]
+fn assert_sealed<T: fmt::Debug>(result: Result<T, VfsError>) {
+    let err = result.unwrap_err();
+    assert_eq!(err.kind, ErrorKind::NotPermitted, "{}", err);
+}
+
+#[test]
+fn the_write_seal_forbids_writes_through_every_descriptor() {
+    let (mut vfs, oid) = sealed(F_SEAL_WRITE);
+    let other = vfs.open("/file").unwrap();
+    assert_sealed(vfs.write(oid, b"x"));
+    assert_sealed(vfs.write(other, b"x"));
+    vfs.seek(other, 8).unwrap();
+    assert_sealed(vfs.write(other, b"x"));
+    assert_eq!(vfs.read_file("/file").unwrap(), b"01234567");
+    vfs.truncate("/file", 4).unwrap();
+    vfs.truncate("/file", 6).unwrap();
+    assert_eq!(vfs.read_file("/file").unwrap(), b"0123\0\0");
+}
+
+#[test]
+fn the_shrink_seal_forbids_only_getting_smaller() {
+    let (mut vfs, oid) = sealed(F_SEAL_SHRINK);
+    assert_sealed(vfs.truncate("/file", 4));
+    assert_sealed(vfs.write_file("/file", b"short"));
+    vfs.write(oid, b"ab").unwrap();
+    vfs.truncate("/file", 10).unwrap();
+    vfs.truncate("/file", 10).unwrap();
+    assert_eq!(vfs.read_file("/file").unwrap(), b"ab234567\0\0");
+}
+
+#[test]
+fn the_grow_seal_forbids_only_getting_larger() {
+    let (mut vfs, oid) = sealed(F_SEAL_GROW);
+    vfs.seek(oid, 6).unwrap();
+    assert_sealed(vfs.write(oid, b"abc"));
+    assert_sealed(vfs.truncate("/file", 9));
+    assert_sealed(vfs.reserve(oid, 3));
+    vfs.write(oid, b"ab").unwrap();
+    vfs.truncate("/file", 4).unwrap();
+    assert_eq!(vfs.read_file("/file").unwrap(), b"0123");
+}
+
+#[test]
+fn seals_accumulate_until_sealed() {
+    let (mut vfs, oid) = sealed(F_SEAL_SHRINK);
+    let other = vfs.open("/file").unwrap();
+    vfs.add_seals(other, F_SEAL_GROW).unwrap();
+    assert_eq!(vfs.seals(oid).unwrap(), F_SEAL_SHRINK | F_SEAL_GROW);
+    vfs.add_seals(oid, F_SEAL_SEAL).unwrap();
+    assert_sealed(vfs.add_seals(other, F_SEAL_WRITE));
+    assert_sealed(vfs.add_seals(other, 0));
+    assert_eq!(
+        vfs.seals(other).unwrap(),
+        F_SEAL_SEAL | F_SEAL_SHRINK | F_SEAL_GROW
+    );
+}
+
+#[test]
+fn seals_belong_to_the_file_not_the_path() {
+    let (mut vfs, oid) = sealed(F_SEAL_WRITE);
+    vfs.close(oid).unwrap();
+    let reopened = vfs.open("/file").unwrap();
+    assert_eq!(vfs.seals(reopened).unwrap(), F_SEAL_WRITE);
+    vfs.close(reopened).unwrap();
+    vfs.unlink("/file").unwrap();
+    vfs.write_file("/file", b"fresh").unwrap();
+    let fresh = vfs.open("/file").unwrap();
+    assert_eq!(vfs.seals(fresh).unwrap(), 0);
+    vfs.write(fresh, b"F").unwrap();
+}
+
+#[test]
+fn invalid_seals_and_descriptors_are_refused() {
+    let (mut vfs, oid) = sealed(0);
+    assert_eq!(vfs.seals(oid).unwrap(), 0);
+    let err = vfs.add_seals(oid, 0x100).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+    vfs.close(oid).unwrap();
+    assert_eq!(vfs.seals(oid).unwrap_err().kind, ErrorKind::BadDescriptor);
+    let err = vfs.add_seals(oid, F_SEAL_WRITE).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::BadDescriptor);
+}
+
+#[test]
+fn seal_names_round_trip() {
+    let seals = F_SEAL_SHRINK | F_SEAL_GROW;
+    assert_eq!(seals_string(seals), "shrink,grow");
+    assert_eq!(parse_seals("shrink,grow"), Some(seals));
+    assert_eq!(seals_string(0), "-");
+    assert_eq!(parse_seals("-"), Some(0));
+    assert_eq!(parse_seals("shrink,nope"), None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn shared_overwrites_respect_the_write_seal() {
+    let (vfs, _) = sealed(F_SEAL_WRITE);
+    let shared = vfs::SharedVfs::new(vfs);
+    assert_sealed(shared.write_at("/file", 0, b"x"));
+    assert_eq!(shared.read_file("/file").unwrap(), b"01234567");
+}
@@ -0,0 +1,145 @@
+#![cfg(feature = "std")]
+
+use std::{sync::mpsc, thread, time::Duration};
+
+use vfs::{SharedVfs, Vfs};
+
+const READERS: usize = 4;
+const ROUNDS: usize = 200;
+const FILE_SIZE: usize = 16 * 1024;
+
+/// Readers of their own files and of a file being rewritten never see a
+/// torn or missing file while a writer replaces contents and churns the
+/// directory under the exclusive lock.
+#[test]
+fn concurrent_reads_see_whole_writes() {
+    let mut vfs = Vfs::new();
+    for i in 0..READERS {
+        vfs.write_file(&format!("/{}", i), &vec![i as u8; FILE_SIZE])
+            .unwrap();
+    }
+    vfs.write_file("/hot", &[0; FILE_SIZE]).unwrap();
+    let vfs = SharedVfs::new(vfs);
+    thread::scope(|scope| {
+        for i in 0..READERS {
+            let vfs = vfs.clone();
+            scope.spawn(move || {
+                let pathname = format!("/{}", i);
+                for _ in 0..ROUNDS {
+                    assert_eq!(vfs.read_file(&pathname).unwrap(), [i as u8; FILE_SIZE]);
+                    let hot = vfs.read_file("/hot").unwrap();
+                    assert_eq!(hot.len(), FILE_SIZE);
+                    assert!(hot.iter().all(|&byte| byte == hot[0]), "torn read");
+                    assert_eq!(vfs.stat(&pathname).unwrap().size(), FILE_SIZE);
+                }
+            });
+        }
+        let vfs = vfs.clone();
+        scope.spawn(move || {
+            for round in 0..ROUNDS {
+                vfs.write(|vfs| {
+                    vfs.write_file("/hot", &[round as u8; FILE_SIZE]).unwrap();
+                    let pathname = format!("/tmp{}", round % 8);
+                    match vfs.stat(&pathname) {
+                        Ok(_) => vfs.unlink(&pathname).unwrap(),
+                        Err(_) => vfs.create(&pathname).unwrap(),
+                    }
+                });
+            }
+        });
+    });
+    let vfs = vfs.into_inner().unwrap();
+    assert_eq!(
+        vfs.read_file("/hot").unwrap(),
+        [(ROUNDS - 1) as u8; FILE_SIZE]
+    );
+}
+
+/// Writers overwriting their own files in place and readers of all of them
+/// never see a torn file, and every file ends with its last write.
+#[test]
+fn concurrent_overwrites_are_whole() {
+    let mut vfs = Vfs::new();
+    for i in 0..READERS {
+        vfs.write_file(&format!("/{}", i), &[0; FILE_SIZE]).unwrap();
+    }
+    let vfs = SharedVfs::new(vfs);
+    thread::scope(|scope| {
+        for i in 0..READERS {
+            let writer = vfs.clone();
+            scope.spawn(move || {
+                let pathname = format!("/{}", i);
+                for round in 0..ROUNDS {
+                    let data = [(round + i) as u8; FILE_SIZE];
+                    assert_eq!(writer.write_at(&pathname, 0, &data).unwrap(), FILE_SIZE);
+                }
+            });
+            let vfs = vfs.clone();
+            scope.spawn(move || {
+                for _ in 0..ROUNDS {
+                    for j in 0..READERS {
+                        let data = vfs.read_file(&format!("/{}", j)).unwrap();
+                        assert_eq!(data.len(), FILE_SIZE);
+                        assert!(data.iter().all(|&byte| byte == data[0]), "torn read");
+                    }
+                }
+            });
+        }
+    });
+    let vfs = vfs.into_inner().unwrap();
+    for i in 0..READERS {
+        assert_eq!(
+            vfs.read_file(&format!("/{}", i)).unwrap(),
+            [(ROUNDS - 1 + i) as u8; FILE_SIZE]
+        );
+    }
+}
+
+/// An overwrite in place only needs the shared tree lock, so it finishes
+/// while another thread holds that lock.
+#[test]
+fn overwrites_do_not_wait_for_readers_of_the_tree() {
+    let mut vfs = Vfs::new();
+    vfs.write_file("/file", &[0; FILE_SIZE]).unwrap();
+    let vfs = SharedVfs::new(vfs);
+    vfs.read(|_| {
+        let (done, finished) = mpsc::channel();
+        let writer = vfs.clone();
+        thread::spawn(move || {
+            done.send(writer.write_at("/file", 100, b"middle").unwrap())
+                .unwrap()
+        });
+        assert_eq!(finished.recv_timeout(Duration::from_secs(10)), Ok(6));
+    });
+    let data = vfs.read_file("/file").unwrap();
+    assert_eq!(&data[100..106], b"middle");
+    assert_eq!(data.len(), FILE_SIZE);
+}
+
+/// Writes that grow the file take the exclusive path and still land.
+#[test]
+fn write_at_past_the_end_grows_the_file() {
+    let mut vfs = Vfs::new();
+    vfs.write_file("/file", b"head").unwrap();
+    let vfs = SharedVfs::new(vfs);
+    assert_eq!(vfs.write_at("/file", 2, b"tail").unwrap(), 4);
+    assert_eq!(vfs.read_file("/file").unwrap(), b"hetail");
+    assert!(vfs.write_at("/missing", 0, b"data").is_err());
+}
+
+/// The times and counters of overwrites reach the `Vfs`.
+#[test]
+fn overwrites_update_times_and_counters() {
+    let mut vfs = Vfs::new();
+    vfs.write_file("/file", &[0; FILE_SIZE]).unwrap();
+    let before = vfs.stat("/file").unwrap().mtime();
+    let writes = vfs.io_stats().writes;
+    let vfs = SharedVfs::new(vfs);
+    thread::sleep(Duration::from_millis(10));
+    vfs.write_at("/file", 0, b"new").unwrap();
+    let during = vfs.stat("/file").unwrap().mtime();
+    assert!(during > before);
+    let vfs = vfs.into_inner().unwrap();
+    assert_eq!(vfs.stat("/file").unwrap().mtime(), during);
+    assert_eq!(vfs.io_stats().writes, writes + 1);
+}
@@ -0,0 +1,38 @@
+#[cfg(feature = "std")]
+use std::{fs, process};
+
+use vfs::{ErrorKind, FaultPlan, Vfs};
+
+#[cfg(feature = "std")]
+#[test]
+fn upload_fails_on_a_short_final_write() {
+    let host = std::env::temp_dir().join(format!("vfs-short-upload-{}", process::id()));
+    fs::write(&host, [7; 700]).unwrap();
+    let mut vfs = Vfs::new();
+    vfs.set_faults(FaultPlan::new().fail_writes_after(600));
+    let result = vfs.upload(&host, "/file");
+    fs::remove_file(&host).unwrap();
+    assert_eq!(result.unwrap_err().kind, ErrorKind::Other);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn upload_counts_every_byte() {
+    let host = std::env::temp_dir().join(format!("vfs-full-upload-{}", process::id()));
+    fs::write(&host, [7; 700]).unwrap();
+    let mut vfs = Vfs::new();
+    let result = vfs.upload(&host, "/file");
+    fs::remove_file(&host).unwrap();
+    assert_eq!(result.unwrap(), 700);
+    assert_eq!(vfs.read_file("/file").unwrap(), [7; 700]);
+}
+
+#[test]
+fn append_log_fails_on_a_short_write() {
+    let mut vfs = Vfs::new();
+    vfs.append_log("/log", b"first").unwrap();
+    vfs.set_faults(FaultPlan::new().fail_writes_after(3));
+    let err = vfs.append_log("/log", b"second").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Other);
+    assert_eq!(vfs.read_file("/log").unwrap(), b"first\nsec");
+}
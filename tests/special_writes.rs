@@ -0,0 +1,61 @@
+use vfs::{BindOptions, ErrorKind, Null, Vfs, F_SEAL_WRITE};
+
+#[test]
+fn ring_write_on_read_only_filesystem() {
+    let mut vfs = Vfs::new();
+    vfs.mkring("/ring", 64).unwrap();
+    let fd = vfs.open("/ring").unwrap();
+    vfs.set_read_only(true);
+    assert_eq!(
+        vfs.write(fd, b"data").unwrap_err().kind,
+        ErrorKind::ReadOnly
+    );
+    assert_eq!(vfs.read_file("/ring").unwrap(), b"");
+}
+
+#[test]
+fn ring_write_through_read_only_bind() {
+    let mut vfs = Vfs::new();
+    vfs.mkdir("/src").unwrap();
+    vfs.mkdir("/dst").unwrap();
+    vfs.mkring("/src/ring", 64).unwrap();
+    let options = BindOptions {
+        read_only: true,
+        ..BindOptions::default()
+    };
+    vfs.bind_mount_with("/src", "/dst", options).unwrap();
+    let fd = vfs.open("/dst/ring").unwrap();
+    assert_eq!(
+        vfs.write(fd, b"data").unwrap_err().kind,
+        ErrorKind::ReadOnly
+    );
+    assert_eq!(vfs.read_file("/src/ring").unwrap(), b"");
+}
+
+#[test]
+fn fifo_and_device_writes_on_read_only_filesystem() {
+    let mut vfs = Vfs::new();
+    vfs.mkfifo("/fifo").unwrap();
+    vfs.mknod("/null", Box::new(Null)).unwrap();
+    let fifo = vfs.open("/fifo").unwrap();
+    let null = vfs.open("/null").unwrap();
+    vfs.set_read_only(true);
+    assert_eq!(
+        vfs.write(fifo, b"data").unwrap_err().kind,
+        ErrorKind::ReadOnly
+    );
+    assert_eq!(
+        vfs.write(null, b"data").unwrap_err().kind,
+        ErrorKind::ReadOnly
+    );
+}
+
+#[test]
+fn rings_cannot_be_sealed() {
+    let mut vfs = Vfs::new();
+    vfs.mkring("/ring", 64).unwrap();
+    let fd = vfs.open("/ring").unwrap();
+    let err = vfs.add_seals(fd, F_SEAL_WRITE).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+    assert_eq!(vfs.write(fd, b"data").unwrap(), 4);
+}
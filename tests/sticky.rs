@@ -0,0 +1,91 @@
+use vfs::{mode_string, ErrorKind, Session, Vfs, S_ISVTX};
+
+const DIR_OWNER: u32 = 500;
+const ALICE: u32 = 1000;
+const BOB: u32 = 2000;
+
+/// A world-writable `/tmp` owned by `DIR_OWNER` holding a file and a
+/// directory of Alice's, with the sticky bit if `sticky`.
+fn tmp(sticky: bool) -> Vfs {
+    let mut vfs = Vfs::new();
+    vfs.mkdir("/tmp").unwrap();
+    vfs.chown("/tmp", Some(DIR_OWNER), None).unwrap();
+    vfs.chmod("/tmp", if sticky { 0o1777 } else { 0o777 })
+        .unwrap();
+    as_user(&mut vfs, ALICE, |vfs| {
+        vfs.create("/tmp/alice").unwrap();
+        vfs.mkdir("/tmp/alice.d").unwrap();
+    });
+    vfs
+}
+
+fn as_user<T, F>(vfs: &mut Vfs, uid: u32, f: F) -> T
+where
+    F: FnOnce(&mut Vfs) -> T,
+{
+    let previous = vfs.switch_session(Session::new().with_identity(uid, uid));
+    let result = f(vfs);
+    vfs.switch_session(previous);
+    result
+}
+
+#[test]
+fn others_cannot_remove_or_rename_entries_of_a_sticky_directory() {
+    let mut vfs = tmp(true);
+    as_user(&mut vfs, BOB, |vfs| {
+        for err in [
+            vfs.unlink("/tmp/alice").unwrap_err(),
+            vfs.rmdir("/tmp/alice.d").unwrap_err(),
+            vfs.rename("/tmp/alice", "/tmp/bob").unwrap_err(),
+        ] {
+            assert_eq!(err.kind, ErrorKind::NotPermitted, "{}", err);
+        }
+        vfs.create("/tmp/bob").unwrap();
+        let err = vfs.rename("/tmp/bob", "/tmp/alice").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::NotPermitted);
+        vfs.unlink("/tmp/bob").unwrap();
+    });
+    assert!(vfs.stat("/tmp/alice").is_ok());
+    assert!(vfs.stat("/tmp/alice.d").is_ok());
+}
+
+#[test]
+fn the_entry_owner_may_remove_and_rename_it() {
+    let mut vfs = tmp(true);
+    as_user(&mut vfs, ALICE, |vfs| {
+        vfs.rename("/tmp/alice", "/tmp/renamed").unwrap();
+        vfs.unlink("/tmp/renamed").unwrap();
+        vfs.rmdir("/tmp/alice.d").unwrap();
+    });
+}
+
+#[test]
+fn the_directory_owner_and_the_superuser_may_remove_any_entry() {
+    let mut vfs = tmp(true);
+    as_user(&mut vfs, DIR_OWNER, |vfs| {
+        vfs.unlink("/tmp/alice").unwrap();
+    });
+    vfs.rmdir("/tmp/alice.d").unwrap();
+}
+
+#[test]
+fn without_the_sticky_bit_write_permission_is_enough() {
+    let mut vfs = tmp(false);
+    as_user(&mut vfs, BOB, |vfs| {
+        vfs.rename("/tmp/alice", "/tmp/bob").unwrap();
+        vfs.unlink("/tmp/bob").unwrap();
+        vfs.rmdir("/tmp/alice.d").unwrap();
+    });
+}
+
+#[test]
+fn chmod_sets_and_clears_the_sticky_bit() {
+    let mut vfs = tmp(true);
+    let mode = vfs.stat("/tmp").unwrap().mode();
+    assert_ne!(mode & S_ISVTX, 0);
+    assert_eq!(mode_string(mode), "rwxrwxrwt");
+    assert_eq!(mode_string(0o1776), "rwxrwxrwT");
+    vfs.chmod("/tmp", 0o777).unwrap();
+    assert_eq!(vfs.stat("/tmp").unwrap().mode() & S_ISVTX, 0);
+    as_user(&mut vfs, BOB, |vfs| vfs.unlink("/tmp/alice")).unwrap();
+}
@@ -0,0 +1,37 @@
+//! Run with `cargo test --features testing --test torture_truncate`.
+#![cfg(feature = "testing")]
+
+use vfs::model::torture_truncate;
+
+/// Deterministic byte strings of every length up to `max_len`.
+fn inputs(count: usize, max_len: usize) -> impl Iterator<Item = Vec<u8>> {
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    (0..count).map(move |i| {
+        let len = i % (max_len + 1);
+        (0..len).map(|_| next() as u8).collect()
+    })
+}
+
+#[test]
+fn truncate_torture_matches_the_model() {
+    for data in inputs(500, 256) {
+        if let Err(err) = torture_truncate(&data) {
+            panic!("{} for input {:?}", err, data);
+        }
+    }
+}
+
+#[test]
+fn truncate_torture_handles_degenerate_inputs() {
+    for data in [vec![], vec![0; 64], vec![0xff; 64]] {
+        if let Err(err) = torture_truncate(&data) {
+            panic!("{} for input {:?}", err, data);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use vfs::{ErrorKind, Op, Vfs};
+
+#[test]
+fn rollback_discards_every_change_since_begin() {
+    let mut vfs = Vfs::new();
+    vfs.mkdir("/keep").unwrap();
+    vfs.write_file("/keep/file", b"before").unwrap();
+    vfs.begin().unwrap();
+    assert!(vfs.in_transaction());
+    vfs.write_file("/keep/file", b"during").unwrap();
+    vfs.write_file("/new", b"new").unwrap();
+    vfs.mkdir("/dir").unwrap();
+    vfs.rename("/keep", "/moved").unwrap();
+    vfs.rollback().unwrap();
+    assert!(!vfs.in_transaction());
+    assert_eq!(vfs.read_file("/keep/file").unwrap(), b"before");
+    for pathname in ["/new", "/dir", "/moved"] {
+        assert_eq!(vfs.stat(pathname).unwrap_err().kind, ErrorKind::NotFound);
+    }
+}
+
+#[test]
+fn rollback_restores_descriptors_and_the_working_directory() {
+    let mut vfs = Vfs::new();
+    vfs.mkdir("/home").unwrap();
+    vfs.write_file("/file", b"0123456789").unwrap();
+    let before = vfs.open("/file").unwrap();
+    vfs.seek(before, 2).unwrap();
+    vfs.begin().unwrap();
+    vfs.seek(before, 8).unwrap();
+    let during = vfs.open("/file").unwrap();
+    vfs.cd("/home").unwrap();
+    vfs.rollback().unwrap();
+    assert_eq!(vfs.cwd(), "/");
+    assert_eq!(vfs.read(before, 3).unwrap(), b"234");
+    assert_eq!(
+        vfs.close(during).unwrap_err().kind,
+        ErrorKind::BadDescriptor
+    );
+}
+
+#[test]
+fn commit_keeps_the_changes() {
+    let mut vfs = Vfs::new();
+    vfs.begin().unwrap();
+    vfs.write_file("/file", b"kept").unwrap();
+    vfs.commit().unwrap();
+    assert_eq!(vfs.rollback().unwrap_err().kind, ErrorKind::InvalidInput);
+    assert_eq!(vfs.read_file("/file").unwrap(), b"kept");
+}
+
+#[test]
+fn transactions_do_not_nest() {
+    let mut vfs = Vfs::new();
+    assert_eq!(vfs.commit().unwrap_err().kind, ErrorKind::InvalidInput);
+    vfs.begin().unwrap();
+    assert_eq!(vfs.begin().unwrap_err().kind, ErrorKind::Busy);
+    vfs.create("/file").unwrap();
+    vfs.rollback().unwrap();
+    assert_eq!(vfs.stat("/file").unwrap_err().kind, ErrorKind::NotFound);
+}
+
+#[test]
+fn the_audit_log_keeps_rolled_back_operations() {
+    let mut vfs = Vfs::new();
+    vfs.set_audit(true);
+    vfs.begin().unwrap();
+    vfs.create("/file").unwrap();
+    vfs.rollback().unwrap();
+    let ops: Vec<_> = vfs.audit_log().iter().map(|record| &record.op).collect();
+    assert_eq!(
+        ops,
+        [
+            &Op::Begin,
+            &Op::Create {
+                pathname: "/file".to_string()
+            },
+            &Op::Rollback,
+        ]
+    );
+}
@@ -0,0 +1,91 @@
+//! Undo and redo live in the `vfs` shell, so these tests run the binary on
+//! an rc file.
+#![cfg(feature = "cli")]
+
+use std::{
+    fs,
+    process::{self, Command, Stdio},
+};
+
+/// Run `script` in the shell and return what it printed to stdout and
+/// stderr.
+fn shell(name: &str, script: &str) -> (String, String) {
+    let rc = std::env::temp_dir().join(format!("vfs-{}-{}", name, process::id()));
+    fs::write(&rc, script).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_vfs"))
+        .arg("--rc")
+        .arg(&rc)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    fs::remove_file(&rc).unwrap();
+    assert!(output.status.success());
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn undo_and_redo_step_through_mutations() {
+    let (stdout, stderr) = shell(
+        "undo-steps",
+        concat!(
+            "write-file /a first\n",
+            "write-file /a second\n",
+            "undo\n",
+            "cat /a\n",
+            "redo\n",
+            "cat /a\n",
+            "undo\n",
+            "undo\n",
+            "cat /a\n",
+        ),
+    );
+    let lines: Vec<_> = stdout.lines().skip(2).collect();
+    assert_eq!(lines, ["first", "second"]);
+    assert!(
+        stderr.contains("cat: cannot read '/a': No such file"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn a_new_mutation_clears_redo() {
+    let (_, stderr) = shell(
+        "undo-clear",
+        concat!(
+            "mkdir /d\n",
+            "undo\n",
+            "mkdir /e\n",
+            "redo\n",
+            "undo\n",
+            "undo\n",
+        ),
+    );
+    assert_eq!(
+        stderr.lines().collect::<Vec<_>>(),
+        ["redo: nothing to redo", "undo: nothing to undo"]
+    );
+}
+
+#[test]
+fn reads_are_not_recorded() {
+    let (stdout, stderr) = shell(
+        "undo-reads",
+        concat!(
+            "write-file /a data\n",
+            "cat /a\n",
+            "stat /a\n",
+            "undo\n",
+            "cat /a\n",
+        ),
+    );
+    assert_eq!(stdout.lines().nth(2), Some("data"));
+    assert!(
+        stderr.contains("cat: cannot read '/a': No such file"),
+        "{}",
+        stderr
+    );
+}
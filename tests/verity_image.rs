@@ -0,0 +1,60 @@
+use vfs::{ErrorKind, Vfs};
+
+/// CRC32 (IEEE) as stored at the end of an image.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn image_with(contents: &[u8], verity: bool) -> Vec<u8> {
+    let mut vfs = Vfs::new();
+    vfs.write_file("/file", contents).unwrap();
+    if verity {
+        vfs.enable_verity(vfs.merkle_root()).unwrap();
+    }
+    vfs.to_image().unwrap()
+}
+
+#[test]
+fn verity_images_load_back_in_verity_mode() {
+    let mut vfs = Vfs::from_image(&image_with(b"trusted", true)).unwrap();
+    assert!(vfs.is_verity());
+    assert_eq!(vfs.read_file("/file").unwrap(), b"trusted");
+    assert_eq!(
+        vfs.write_file("/file", b"changed").unwrap_err().kind,
+        ErrorKind::ReadOnly
+    );
+}
+
+#[test]
+fn plain_images_load_writable() {
+    let mut vfs = Vfs::from_image(&image_with(b"plain", false)).unwrap();
+    assert!(!vfs.is_verity());
+    vfs.write_file("/file", b"changed").unwrap();
+}
+
+#[test]
+fn tampered_blocks_fail_the_merkle_root_check() {
+    let mut image = image_with(b"trusted", true);
+    let at = image
+        .windows(7)
+        .position(|window| window == b"trusted")
+        .unwrap();
+    image[at] = b'T';
+    let end = image.len() - 4;
+    let crc = crc32(&image[..end]);
+    image[end..].copy_from_slice(&crc.to_le_bytes());
+    let err = Vfs::from_image(&image).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::DataCorruption);
+    assert!(err.message.contains("Merkle root mismatch"), "{}", err);
+}
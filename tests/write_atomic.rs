@@ -0,0 +1,46 @@
+use vfs::{ErrorKind, Vfs};
+
+#[test]
+fn write_atomic_replaces_the_symlink_target() {
+    let mut vfs = Vfs::new();
+    vfs.mkdir("/data").unwrap();
+    vfs.write_file("/data/config", b"old").unwrap();
+    vfs.chmod("/data/config", 0o600).unwrap();
+    vfs.symlink("data/config", "/config").unwrap();
+    vfs.write_atomic("/config", b"new").unwrap();
+    assert_eq!(vfs.stat("/config").unwrap().type_char(), 'l');
+    let target = vfs.stat("/data/config").unwrap();
+    assert_eq!(target.mode() & 0o777, 0o600);
+    assert_eq!(vfs.read_file("/data/config").unwrap(), b"new");
+    let names: Vec<_> = vfs.ls("/").unwrap().map(|entry| entry.name).collect();
+    assert!(names.iter().all(|name| !name.starts_with(".config")));
+}
+
+#[test]
+fn write_atomic_creates_a_dangling_symlink_target() {
+    let mut vfs = Vfs::new();
+    vfs.symlink("/target", "/link").unwrap();
+    vfs.write_atomic("/link", b"data").unwrap();
+    assert_eq!(vfs.read_file("/target").unwrap(), b"data");
+}
+
+#[test]
+fn write_atomic_rejects_a_trailing_slash() {
+    let mut vfs = Vfs::new();
+    vfs.write_file("/file", b"old").unwrap();
+    let err = vfs.write_atomic("/file/", b"new").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::IsADirectory);
+    let err = vfs.write_atomic("/new/", b"new").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::IsADirectory);
+    assert_eq!(vfs.read_file("/file").unwrap(), b"old");
+}
+
+#[test]
+fn write_atomic_reports_symlink_loops() {
+    let mut vfs = Vfs::new();
+    vfs.symlink("/loop", "/loop").unwrap();
+    assert_eq!(
+        vfs.write_atomic("/loop", b"data").unwrap_err().kind,
+        ErrorKind::Loop
+    );
+}